@@ -1,3 +1,4 @@
+use rand::{CryptoRng, Rng};
 
 pub trait Encryptable<T: Crypto<Self>>
 where
@@ -11,6 +12,16 @@ pub trait Crypto<Item>{
     fn decrypto(&self, s_key: &Self::SecretKey, rep: Self::Representation) -> Item;
 }
 
+/// 乱数生成器を呼び出し側から渡して暗号化する。`Crypto::encrypto`は`secure_rng()`を使う
+pub trait CryptoWithRng<Item>: Crypto<Item> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: Item,
+        rng: &mut R,
+    ) -> Self::Representation;
+}
+
 pub struct Cryptor;
 impl Cryptor {
     #[inline]
@@ -22,6 +33,19 @@ impl Cryptor {
         strategy.encrypto(s_key, item)
     }
     #[inline]
+    pub fn encrypto_with_rng<
+        Item: Encryptable<Strategy>,
+        Strategy: CryptoWithRng<Item>,
+        R: Rng + CryptoRng,
+    >(
+        strategy: Strategy,
+        s_key: &Strategy::SecretKey,
+        item: Item,
+        rng: &mut R,
+    ) -> Strategy::Representation {
+        strategy.encrypto_with_rng(s_key, item, rng)
+    }
+    #[inline]
     pub fn decrypto<Item: Encryptable<Strategy>, Strategy: Crypto<Item>>(
         strategy: Strategy,
         s_key: &Strategy::SecretKey,
@@ -0,0 +1,94 @@
+/*!
+wasm-bindgenでJavaScriptから使うためのクライアント側のAPI。
+
+ブラウザでは鍵生成・暗号化・復号だけを行い、評価鍵(CompressedServerKey)と暗号文は
+`wire`の形式のバイト列(Uint8Array)でサーバーに送る。
+
+```sh
+cargo build -p hom_nand --target wasm32-unknown-unknown --features wasm
+```
+ */
+use crate::digest::Cryptor;
+use crate::tfhe::CompressedServerKey;
+use crate::tlwe::{TLWEHelper, TLWERep, TLWE};
+use crate::trlwe::TRLWEHelper;
+use crate::wire::WireFormat;
+use utils::math::{Binary, BinaryDistribution, Random};
+use wasm_bindgen::prelude::*;
+
+const TLWE_N: usize = TLWEHelper::N;
+const TRLWE_N: usize = TRLWEHelper::N;
+
+#[wasm_bindgen]
+pub struct ClientKey {
+    s_key_tlwelv0: [Binary; TLWE_N],
+    s_key_tlwelv1: [Binary; TRLWE_N],
+}
+
+#[wasm_bindgen]
+impl ClientKey {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ClientKey {
+        let mut unif = BinaryDistribution::uniform();
+        ClientKey {
+            s_key_tlwelv0: unif.gen_n(),
+            s_key_tlwelv1: unif.gen_n(),
+        }
+    }
+
+    /// TLWEで暗号化し、wire形式のバイト列で返す
+    pub fn encrypt(&self, item: bool) -> Vec<u8> {
+        let rep = Cryptor::encrypto(TLWE, &self.s_key_tlwelv0, Binary::from(item as u32));
+        let mut bytes = Vec::new();
+        rep.write_to(&mut bytes).unwrap();
+        bytes
+    }
+    pub fn decrypt(&self, bytes: &[u8]) -> Result<bool, JsValue> {
+        let rep = TLWERep::<TLWE_N>::read_from(&mut &bytes[..])
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let res: Binary = Cryptor::decrypto(TLWE, &self.s_key_tlwelv0, rep);
+        Ok(res == Binary::One)
+    }
+
+    /// サーバーに渡す評価鍵。KeySwitchingKeyは圧縮しておく
+    #[wasm_bindgen(js_name = serverKey)]
+    pub fn server_key(&self) -> Vec<u8> {
+        let key = CompressedServerKey::new(self.s_key_tlwelv0, self.s_key_tlwelv1);
+        let mut bytes = Vec::new();
+        key.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// 秘密鍵を保存するためのバイト列。1係数を1バイトで並べる
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.s_key_tlwelv0
+            .iter()
+            .chain(self.s_key_tlwelv1.iter())
+            .map(|&s| s as u8)
+            .collect()
+    }
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<ClientKey, JsValue> {
+        if bytes.len() != TLWE_N + TRLWE_N {
+            return Err(JsValue::from_str(&format!(
+                "client key must be {} bytes, found {}",
+                TLWE_N + TRLWE_N,
+                bytes.len()
+            )));
+        }
+        if bytes.iter().any(|&b| b > 1) {
+            return Err(JsValue::from_str("key coefficient must be 0 or 1"));
+        }
+        let (lv0, lv1) = bytes.split_at(TLWE_N);
+        Ok(ClientKey {
+            s_key_tlwelv0: utils::mem::array_create_enumerate(|i| Binary::from(lv0[i])),
+            s_key_tlwelv1: utils::mem::array_create_enumerate(|i| Binary::from(lv1[i])),
+        })
+    }
+}
+impl Default for ClientKey {
+    fn default() -> Self {
+        ClientKey::new()
+    }
+}
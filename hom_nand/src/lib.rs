@@ -7,10 +7,20 @@ extern crate debug_print;
 extern crate utils;
 
 pub mod digest;
+#[cfg(feature = "wasm")]
+pub mod js;
+pub mod lut;
+pub mod pir;
+pub mod selftest;
+pub mod stats;
 pub mod tlwe;
 pub mod trgsw;
 pub mod trlwe;
 pub mod tfhe;
+pub mod tfhe_lib;
+pub mod tfhe_rs;
+pub mod threshold;
+pub mod wire;
 
 
 #[cfg(test)]
@@ -0,0 +1,225 @@
+/*!
+暗号化した添字で平文の表を引く(CMux tree)。
+
+表の値はbit列で、1つの値を自明なTRLWE(x^jの係数がjbit目)にする。
+添字のbitごとにCMuxで2つの候補から1つを選ぶことを、下の桁から繰り返す。
+k bitの添字なら`2^k - 1`回のCMuxだけで済み、bootstrapは使わない。同じ表を論理式で書くより大幅に安い。
+
+添字の各bitはクライアントがTRGSWで暗号化して送る(`EncryptedIndex`)。
+
+# 未実装: 回路bootstrap
+要望にあったTLWEからTRGSWを作る回路bootstrap(circuit bootstrapping)は実装していない。
+別の要望として残してあり、このモジュールはCMux treeの部分だけを提供する。
+そのためゲートの出力をそのまま添字には使えず、添字は秘密鍵を持つ側が暗号化する。
+回路bootstrapには、lv1より精度の高いパラメータ(lv2)のbootstrapping keyと
+秘密鍵を掛ける関数付きのkey switching key(private functional key switching)が要るが、
+`TFHE`はどちらも持っていない。今のTorus32のlv1のパラメータでは雑音が大きすぎて作れない。
+
+```ignore
+let table = LookupTable::new(&sbox); // sbox: Vec<Vec<Binary>>, 256個
+let index = EncryptedIndex::encrypt(&pol!(s_key_tlwelv1), 0x53, 8);
+let res: Vec<TLWERep<TLWE_N>> = tfhe.hom_lookup(&table, &index)?;
+```
+ */
+use crate::digest::Cryptor;
+use crate::tfhe::{BootstrappingKeyRows, TFHE};
+use crate::tlwe::TLWERep;
+use crate::trgsw::{TRGSWRepF, TRGSW};
+use crate::trlwe::{TRLWEHelper, TRLWERep};
+use std::fmt::Display;
+use utils::math::{Binary, Polynomial};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookupError {
+    /// 添字の桁数が表と違う
+    IndexBits { expected: usize, found: usize },
+}
+impl Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupError::IndexBits { expected, found } => {
+                write!(f, "index must have {} bits, found {}", expected, found)
+            }
+        }
+    }
+}
+impl std::error::Error for LookupError {}
+
+/// 添字のbitをTRGSWで暗号化したもの。0番目が最下位bit
+pub struct EncryptedIndex<const N: usize>(Vec<TRGSWRepF<N>>);
+impl<const N: usize> EncryptedIndex<N> {
+    /// 桁数の上限。これより多い桁の表はメモリに載らない
+    pub const MAX_BITS: usize = usize::BITS as usize;
+
+    pub fn new(bits: Vec<TRGSWRepF<N>>) -> Self {
+        EncryptedIndex(bits)
+    }
+    /// indexの下位bits桁を暗号化する。s_keyはTRLWE(lv1)の秘密鍵
+    pub fn encrypt(s_key: &Polynomial<Binary, N>, index: usize, bits: usize) -> Self {
+        let bits = (0..bits)
+            .map(|i| {
+                let bit = Binary::from(((index >> i) & 1) as u32);
+                TRGSWRepF::from(Cryptor::encrypto(TRGSW, s_key, bit))
+            })
+            .collect();
+        Self::new(bits)
+    }
+    /// 添字の桁数
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, TRGSWRepF<N>> {
+        self.0.iter()
+    }
+}
+
+pub struct LookupTable<const N: usize> {
+    entries: Vec<TRLWERep<N>>,
+    width: usize,
+}
+impl<const N: usize> LookupTable<N> {
+    /// `table[i]`は添字iの値のbit列
+    /// # Panic
+    /// - 表の長さが2のべきでない
+    /// - 値の長さがそろっていないか、Nより長い
+    pub fn new(table: &[Vec<Binary>]) -> Self {
+        assert!(
+            table.len().is_power_of_two(),
+            "table length must be a power of two, len={}",
+            table.len()
+        );
+        let width = table[0].len();
+        assert!(
+            table.iter().all(|v| v.len() == width),
+            "all entries must have the same length"
+        );
+        let entries = table
+            .iter()
+            .map(|v| {
+                let pol = TRLWEHelper::pack_binary::<N>(v);
+                TRLWERep::trivial(TRLWEHelper::binary_pol2torus_pol(pol))
+            })
+            .collect();
+        LookupTable { entries, width }
+    }
+    /// 添字の桁数
+    pub fn index_bits(&self) -> usize {
+        self.entries.len().trailing_zeros() as usize
+    }
+    /// 値のbit数
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// 値をx^jの係数に入れたTRLWE。添字は他人から受け取ることがあるので、桁数の違いはエラーで返す
+    pub fn lookup_trlwe(&self, index: &EncryptedIndex<N>) -> Result<TRLWERep<N>, LookupError> {
+        if index.len() != self.index_bits() {
+            return Err(LookupError::IndexBits {
+                expected: self.index_bits(),
+                found: index.len(),
+            });
+        }
+        let mut level = self.entries.clone();
+        // 下の桁から、隣り合う2つのうち1つを選ぶ
+        for bit in &index.0 {
+            level = level
+                .chunks(2)
+                .map(|pair| bit.cmux(pair[1].clone(), pair[0].clone()))
+                .collect();
+        }
+        Ok(level.pop().unwrap())
+    }
+    /// 値の各bitのTLWE(lv1)
+    pub fn lookup(&self, index: &EncryptedIndex<N>) -> Result<Vec<TLWERep<N>>, LookupError> {
+        let trlwe = self.lookup_trlwe(index)?;
+        Ok((0..self.width)
+            .map(|j| trlwe.sample_extract_index(j))
+            .collect())
+    }
+}
+
+impl<const TLWE_N: usize, const TRLWE_N: usize, BK: BootstrappingKeyRows<TRLWE_N>>
+    TFHE<TLWE_N, TRLWE_N, BK>
+{
+    /// 表を引いて、ゲートに渡せるTLWE(lv0)にする
+    pub fn hom_lookup(
+        &self,
+        table: &LookupTable<TRLWE_N>,
+        index: &EncryptedIndex<TRLWE_N>,
+    ) -> Result<Vec<TLWERep<TLWE_N>>, LookupError> {
+        Ok(table
+            .lookup(index)?
+            .into_iter()
+            .map(|tlwe| tlwe.identity_key_switch(self.ksk()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tlwe::TLWE;
+    use utils::math::{BinaryDistribution, Random};
+    use utils::pol;
+
+    fn bits(x: usize, width: usize) -> Vec<Binary> {
+        (0..width)
+            .map(|j| Binary::from(((x >> j) & 1) as u32))
+            .collect()
+    }
+    fn value(bits: &[Binary]) -> usize {
+        bits.iter()
+            .enumerate()
+            .map(|(j, &b)| (b as usize) << j)
+            .sum()
+    }
+
+    #[test]
+    fn lookup_table() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        let values = [3, 14, 15, 9, 2, 6, 5, 0];
+        let table: Vec<Vec<Binary>> = values.iter().map(|&v| bits(v, 4)).collect();
+        let table = LookupTable::<TRLWE_N>::new(&table);
+        assert_eq!((table.index_bits(), table.width()), (3, 4));
+        for (i, &expect) in values.iter().enumerate() {
+            let index = EncryptedIndex::encrypt(&pol!(s_key_tlwelv1), i, 3);
+            let lv1: Vec<Binary> = table
+                .lookup(&index)
+                .unwrap()
+                .into_iter()
+                .map(|t| Cryptor::decrypto(TLWE, &s_key_tlwelv1, t))
+                .collect();
+            assert_eq!(value(&lv1), expect, "table[{}]", i);
+            let lv0: Vec<Binary> = tfhe
+                .hom_lookup(&table, &index)
+                .unwrap()
+                .into_iter()
+                .map(|t| Cryptor::decrypto(TLWE, &s_key_tlwelv0, t))
+                .collect();
+            assert_eq!(value(&lv0), expect, "table[{}]", i);
+        }
+        let index = EncryptedIndex::encrypt(&pol!(s_key_tlwelv1), 0, 2);
+        assert_eq!(
+            table.lookup_trlwe(&index).err(),
+            Some(LookupError::IndexBits {
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn lookup_table_length() {
+        LookupTable::<16>::new(&[vec![Binary::One], vec![Binary::Zero], vec![Binary::One]]);
+    }
+}
@@ -0,0 +1,197 @@
+/*!
+1つのサーバーで行う計算型の秘匿情報検索(PIR)。
+
+サーバーは平文のレコードを`PirDatabase`として持ち、クライアントが暗号化した添字(`EncryptedIndex`)で`lut`のCMux treeを引く。
+サーバーにはどのレコードを返したか分からない。返すのはTRLWE(lv1)で、1つにNbitずつ入る。
+
+```text
+client                               server
+  query = client.query(i, db.index_bits())
+          ---- EncryptedIndex ---->
+                                     response = db.respond(&query)
+          <---- Vec<TRLWERep> -----
+  record = client.decode(&response, db.record_len())
+```
+`EncryptedIndex`と`TRLWERep`は`wire::WireFormat`で送れる。
+ */
+use crate::digest::Cryptor;
+use crate::lut::{EncryptedIndex, LookupError, LookupTable};
+use crate::trlwe::{TRLWERep, TRLWE};
+use utils::math::{Binary, Polynomial};
+
+/// サーバーが持つレコードの表
+pub struct PirDatabase<const N: usize> {
+    /// レコードのNbitごとの表
+    tables: Vec<LookupTable<N>>,
+    records: usize,
+    record_len: usize,
+}
+impl<const N: usize> PirDatabase<N> {
+    /// 短いレコードは後ろを0で埋めて、一番長いものにそろえる。
+    /// 件数も2のべきになるまで空のレコードで埋める
+    /// # Panic
+    /// - recordsが空
+    pub fn new(records: &[Vec<u8>]) -> Self {
+        assert!(
+            !records.is_empty(),
+            "database must have at least one record"
+        );
+        let record_len = records.iter().map(Vec::len).max().unwrap();
+        let rows = records.len().next_power_of_two();
+        let bits: Vec<Vec<Binary>> = (0..rows)
+            .map(|i| {
+                let record = records.get(i).map_or(&[][..], |r| &r[..]);
+                (0..record_len * 8)
+                    .map(|j| {
+                        let byte = record.get(j / 8).copied().unwrap_or(0);
+                        Binary::from(((byte >> (j % 8)) & 1) as u32)
+                    })
+                    .collect()
+            })
+            .collect();
+        // 長さ0のレコードでも表は1つ作る
+        let chunks = (record_len * 8).div_ceil(N).max(1);
+        let tables = (0..chunks)
+            .map(|c| {
+                let chunk: Vec<Vec<Binary>> = bits
+                    .iter()
+                    .map(|b| b[(c * N).min(b.len())..((c + 1) * N).min(b.len())].to_vec())
+                    .collect();
+                LookupTable::new(&chunk)
+            })
+            .collect();
+        PirDatabase {
+            tables,
+            records: records.len(),
+            record_len,
+        }
+    }
+    /// レコードの数
+    pub fn len(&self) -> usize {
+        self.records
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+    /// 1つのレコードのbyte数
+    pub fn record_len(&self) -> usize {
+        self.record_len
+    }
+    /// 添字の桁数
+    pub fn index_bits(&self) -> usize {
+        self.tables[0].index_bits()
+    }
+    /// 添字のレコードを暗号化したまま取り出す。
+    /// queryはクライアントから届いたものなので、桁数が`index_bits`と違えばエラーを返す
+    pub fn respond(&self, query: &EncryptedIndex<N>) -> Result<Vec<TRLWERep<N>>, LookupError> {
+        self.tables
+            .iter()
+            .map(|table| table.lookup_trlwe(query))
+            .collect()
+    }
+}
+
+/// 添字を暗号化し、返ってきたレコードを復号する
+pub struct PirClient<const N: usize> {
+    s_key: Polynomial<Binary, N>,
+}
+impl<const N: usize> PirClient<N> {
+    /// s_keyはTRLWE(lv1)の秘密鍵
+    pub fn new(s_key: Polynomial<Binary, N>) -> Self {
+        PirClient { s_key }
+    }
+    /// # Panic
+    /// - indexがindex_bits桁に入らない
+    pub fn query(&self, index: usize, index_bits: usize) -> EncryptedIndex<N> {
+        assert!(
+            index_bits >= usize::BITS as usize || index >> index_bits == 0,
+            "index {} does not fit in {} bits",
+            index,
+            index_bits
+        );
+        EncryptedIndex::encrypt(&self.s_key, index, index_bits)
+    }
+    /// 先頭からrecord_len byteを取り出す
+    pub fn decode(&self, response: &[TRLWERep<N>], record_len: usize) -> Vec<u8> {
+        let bits: Vec<Binary> = response
+            .iter()
+            .flat_map(|rep| {
+                let pol: Polynomial<Binary, N> = Cryptor::decrypto(TRLWE, &self.s_key, rep.clone());
+                pol.coefs().to_vec()
+            })
+            .collect();
+        (0..record_len)
+            .map(|i| (0..8).map(|j| (bits[i * 8 + j] as u8) << j).sum())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::{WireError, WireFormat};
+    use utils::math::{BinaryDistribution, Random};
+    use utils::pol;
+
+    #[test]
+    fn pir_roundtrip() {
+        const N: usize = 1024;
+        let client = PirClient::<N>::new(pol!(BinaryDistribution::uniform().gen_n::<N>()));
+        // 1つのTRLWEに入るのは128byteまで
+        let records: Vec<Vec<u8>> = vec![
+            b"alice".to_vec(),
+            b"bob".to_vec(),
+            (0..200).map(|i| i as u8).collect(),
+            vec![],
+            b"eve".to_vec(),
+        ];
+        let db = PirDatabase::<N>::new(&records);
+        assert_eq!((db.len(), db.record_len(), db.index_bits()), (5, 200, 3));
+        for (i, record) in records.iter().enumerate() {
+            let query = client.query(i, db.index_bits());
+            // 送るときはバイト列にする
+            let query = EncryptedIndex::<N>::from_bytes(&query.to_bytes()).unwrap();
+            let response: Vec<TRLWERep<N>> = db
+                .respond(&query)
+                .unwrap()
+                .iter()
+                .map(|r| TRLWERep::from_bytes(&r.to_bytes()).unwrap())
+                .collect();
+            assert_eq!(response.len(), 2);
+            let res = client.decode(&response, db.record_len());
+            let mut expect = record.clone();
+            expect.resize(db.record_len(), 0);
+            assert_eq!(res, expect, "record {}", i);
+        }
+    }
+
+    #[test]
+    fn pir_bad_query() {
+        const N: usize = 1024;
+        let client = PirClient::<N>::new(pol!(BinaryDistribution::uniform().gen_n::<N>()));
+        let db = PirDatabase::<N>::new(&[b"a".to_vec(), b"b".to_vec()]);
+        let query = client.query(0, 2);
+        assert_eq!(
+            db.respond(&query).err(),
+            Some(LookupError::IndexBits {
+                expected: 1,
+                found: 2
+            })
+        );
+        // 桁数(ヘッダの直後のu32)が大きすぎるときはTRGSWを読まない
+        let mut bytes = query.to_bytes();
+        bytes[8 + 4 * 3..8 + 4 * 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        match EncryptedIndex::<N>::from_bytes(&bytes) {
+            Err(WireError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            _ => panic!("桁数が大きすぎるのに読めてしまった"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn pir_query_out_of_range() {
+        const N: usize = 16;
+        let client = PirClient::<N>::new(pol!(BinaryDistribution::uniform().gen_n::<N>()));
+        client.query(4, 2);
+    }
+}
@@ -0,0 +1,167 @@
+/*!
+読み込んだ鍵とパラメータでゲートが正しく動くかを、起動時に確かめる。
+
+`TFHE::self_test`は各ゲートの入力の組を全て暗号化して評価し、復号した値を真理値表と比べる。
+あわせて、復号する前の位相が判定の境目(0と1/2)からどれだけ離れているか(余裕)を測る。
+余裕はノイズがないときの1/8を1とした割合で、0に近いほど誤りやすい。
+
+評価した26回のゲートは`TFHE::stats()`にも数える。
+
+```ignore
+let report = tfhe.self_test(&s_key_tlwelv0);
+if !report.is_ok() {
+    panic!("{}", report);
+}
+```
+ */
+use crate::digest::Cryptor;
+use crate::stats::Gate;
+use crate::tfhe::{BootstrappingKeyRows, TFHE};
+use crate::tlwe::{TLWERep, TLWE};
+use std::fmt::Display;
+use utils::math::{Binary, Centered, Torus32};
+
+/// 真理値表と違う値になったゲート
+#[derive(Debug, Clone, PartialEq)]
+pub struct GateMismatch {
+    pub gate: Gate,
+    pub inputs: Vec<Binary>,
+    pub expect: Binary,
+    pub actual: Binary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestReport {
+    pub mismatches: Vec<GateMismatch>,
+    /// ゲートごとの余裕の最小値。`Gate::ALL`の順
+    pub margins: [f64; Gate::ALL.len()],
+}
+impl SelfTestReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+    pub fn margin(&self, gate: Gate) -> f64 {
+        self.margins[gate as usize]
+    }
+    /// 全てのゲートの余裕の最小値
+    pub fn min_margin(&self) -> f64 {
+        self.margins.iter().cloned().fold(f64::INFINITY, f64::min)
+    }
+}
+impl Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = if self.is_ok() { "ok" } else { "FAILED" };
+        write!(f, "self test {}: ", status)?;
+        for gate in Gate::ALL {
+            write!(f, "{}={:.3} ", gate.name(), self.margin(gate))?;
+        }
+        for m in self.mismatches.iter() {
+            let inputs: Vec<u32> = m.inputs.iter().map(|&b| b as u32).collect();
+            write!(
+                f,
+                "\n  {}{:?}: expected {} but got {}",
+                m.gate.name(),
+                inputs,
+                m.expect as u32,
+                m.actual as u32
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 位相が判定の境目からどれだけ離れているか。1/8を1とする
+fn margin(phase: Torus32) -> f64 {
+    let t = phase.centered().abs();
+    t.min(0.5 - t) * 8.0
+}
+
+impl<const TLWE_N: usize, const TRLWE_N: usize, BK: BootstrappingKeyRows<TRLWE_N>>
+    TFHE<TLWE_N, TRLWE_N, BK>
+{
+    /// s_keyはTLWE(lv0)の秘密鍵。`hom_*`の各ゲートを入力の全ての組で評価する
+    pub fn self_test(&self, s_key: &[Binary; TLWE_N]) -> SelfTestReport {
+        let mut report = SelfTestReport {
+            mismatches: Vec::new(),
+            margins: [f64::INFINITY; Gate::ALL.len()],
+        };
+        for gate in Gate::ALL {
+            let arity = match gate {
+                Gate::Not => 1,
+                Gate::Mux => 3,
+                _ => 2,
+            };
+            for bits in 0..(1u32 << arity) {
+                let inputs: Vec<Binary> =
+                    (0..arity).map(|i| Binary::from((bits >> i) & 1)).collect();
+                let enc: Vec<TLWERep<TLWE_N>> = inputs
+                    .iter()
+                    .map(|&b| Cryptor::encrypto(TLWE, s_key, b))
+                    .collect();
+                let (x, y) = (inputs[0] as u32, *inputs.last().unwrap() as u32);
+                let (expect, res) = match gate {
+                    Gate::Nand => (1 ^ (x & y), self.hom_nand(enc[0].clone(), enc[1].clone())),
+                    Gate::And => (x & y, self.hom_and(enc[0].clone(), enc[1].clone())),
+                    Gate::Or => (x | y, self.hom_or(enc[0].clone(), enc[1].clone())),
+                    Gate::Xor => (x ^ y, self.hom_xor(enc[0].clone(), enc[1].clone())),
+                    Gate::Not => (1 ^ x, self.hom_not(enc[0].clone())),
+                    // control ? input_1 : input_0
+                    Gate::Mux => {
+                        let (c, i0, i1) = (x, inputs[1] as u32, y);
+                        let res = self.hom_mux(enc[0].clone(), enc[1].clone(), enc[2].clone());
+                        (if c == 1 { i1 } else { i0 }, res)
+                    }
+                };
+                let phase: Torus32 = Cryptor::decrypto(TLWE, s_key, res.clone());
+                let actual: Binary = Cryptor::decrypto(TLWE, s_key, res);
+                let m = &mut report.margins[gate as usize];
+                *m = m.min(margin(phase));
+                let expect = Binary::from(expect);
+                if actual != expect {
+                    report.mismatches.push(GateMismatch {
+                        gate,
+                        inputs,
+                        expect,
+                        actual,
+                    });
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::math::{BinaryDistribution, Random};
+
+    #[test]
+    fn self_test() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        let report = tfhe.self_test(&s_key_tlwelv0);
+        assert!(report.is_ok(), "{}", report);
+        assert!(report.min_margin() > 0.5, "{}", report);
+        assert_eq!(tfhe.stats().total_gates(), 4 * 4 + 2 + 8);
+
+        // 鍵が合っていなければ気づく
+        let wrong = s_key_tlwelv0.map(|b| Binary::from(b as u32 ^ 1));
+        let report = tfhe.self_test(&wrong);
+        assert!(!report.is_ok());
+        assert!(report.to_string().starts_with("self test FAILED"));
+    }
+
+    #[test]
+    fn margin_of_phase() {
+        assert_eq!(margin(Torus32::from_bits(0x2000_0000)), 1.0);
+        assert_eq!(margin(Torus32::from_bits(0xE000_0000)), 1.0);
+        assert_eq!(margin(Torus32::from_bits(0)), 0.0);
+        assert_eq!(margin(Torus32::from_bits(0x8000_0000)), 0.0);
+    }
+}
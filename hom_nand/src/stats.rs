@@ -0,0 +1,123 @@
+/*!
+評価にかかった量の集計。
+
+`TFHE`はゲートを評価するたびに、ゲートの種類ごとの回数・bootstrapの回数・FFT(IFFT)の回数・
+ゲートの中で過ごした時間を足していく。`TFHE::stats()`で取り出して、同じ関数の実装同士を比べるのに使う。
+カウンタはatomicなので、複数のスレッドから同じ`TFHE`を使ってもよい。
+ */
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use utils::mem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gate {
+    Nand,
+    And,
+    Or,
+    Xor,
+    Not,
+    Mux,
+}
+impl Gate {
+    pub const ALL: [Gate; 6] = [
+        Gate::Nand,
+        Gate::And,
+        Gate::Or,
+        Gate::Xor,
+        Gate::Not,
+        Gate::Mux,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Gate::Nand => "nand",
+            Gate::And => "and",
+            Gate::Or => "or",
+            Gate::Xor => "xor",
+            Gate::Not => "not",
+            Gate::Mux => "mux",
+        }
+    }
+}
+
+/// 評価器が持つカウンタ
+#[derive(Debug, Default)]
+pub struct Stats {
+    gates: [AtomicU64; Gate::ALL.len()],
+    bootstraps: AtomicU64,
+    ffts: AtomicU64,
+    nanos: AtomicU64,
+}
+impl Stats {
+    /// ゲートをcount個評価する間の時間とFFTの回数を、戻り値をdropしたときに足す
+    pub fn gate(&self, gate: Gate, count: u64) -> GateRecord<'_> {
+        self.gates[gate as usize].fetch_add(count, Ordering::Relaxed);
+        GateRecord {
+            stats: self,
+            start: Instant::now(),
+            ffts: utils::math::fft_count(),
+        }
+    }
+    pub fn bootstrap(&self) {
+        self.bootstraps.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            gates: mem::array_create_enumerate(|i| self.gates[i].load(Ordering::Relaxed)),
+            bootstraps: self.bootstraps.load(Ordering::Relaxed),
+            ffts: self.ffts.load(Ordering::Relaxed),
+            wall_time: Duration::from_nanos(self.nanos.load(Ordering::Relaxed)),
+        }
+    }
+    pub fn reset(&self) {
+        self.gates
+            .iter()
+            .chain([&self.bootstraps, &self.ffts, &self.nanos])
+            .for_each(|c| c.store(0, Ordering::Relaxed));
+    }
+}
+
+pub struct GateRecord<'a> {
+    stats: &'a Stats,
+    start: Instant,
+    ffts: u64,
+}
+impl Drop for GateRecord<'_> {
+    fn drop(&mut self) {
+        // FFTの回数はスレッドごとに数えているので、同じスレッドの中での差分を取る
+        let ffts = utils::math::fft_count() - self.ffts;
+        self.stats.ffts.fetch_add(ffts, Ordering::Relaxed);
+        let nanos = self.start.elapsed().as_nanos() as u64;
+        self.stats.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+/// ある時点でのカウンタの値
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsSnapshot {
+    pub gates: [u64; Gate::ALL.len()],
+    pub bootstraps: u64,
+    pub ffts: u64,
+    /// ゲートの評価にかかった時間の合計。複数のスレッドで評価したときは実時間より長くなる
+    pub wall_time: Duration,
+}
+impl StatsSnapshot {
+    pub fn gate(&self, gate: Gate) -> u64 {
+        self.gates[gate as usize]
+    }
+    pub fn total_gates(&self) -> u64 {
+        self.gates.iter().sum()
+    }
+}
+impl Display for StatsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for gate in Gate::ALL {
+            write!(f, "{}={} ", gate.name(), self.gate(gate))?;
+        }
+        write!(
+            f,
+            "bootstraps={} ffts={} time={:?}",
+            self.bootstraps, self.ffts, self.wall_time
+        )
+    }
+}
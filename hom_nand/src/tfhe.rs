@@ -1,14 +1,22 @@
 use crate::digest::Cryptor;
-use crate::tlwe::KeySwitchingKey;
+use crate::stats::{Gate, Stats, StatsSnapshot};
+use crate::tlwe::{CompressedKeySwitchingKey, KeySwitchingKey, TLWE};
+use crate::trgsw::ExternalProductScratch;
 use crate::trgsw::TRGSW;
 use crate::{digest::Encrypted, tlwe::TLWERep, trgsw::TRGSWRepF, trlwe::TRLWERep};
 use num::ToPrimitive;
-use utils::math::{Binary, Polynomial, Torus32};
+use rand::{CryptoRng, Rng};
+use std::fmt::Debug;
+use std::io;
+use utils::math::{secure_rng, Binary, BinaryDistribution, Polynomial, Random, Torus32};
 use utils::{pol, torus};
 
-pub struct TFHE<const TLWE_N: usize, const TRLWE_N: usize> {
-    bk: BootstrappingKey<TLWE_N, TRLWE_N>,
+/// BKはメモリ上に展開したBootstrappingKeyの他に、
+/// ファイルから1行ずつ読む`wire::StreamingBootstrappingKey`なども使える
+pub struct TFHE<const TLWE_N: usize, const TRLWE_N: usize, BK = BootstrappingKey<TLWE_N, TRLWE_N>> {
+    bk: BK,
     ksk: KeySwitchingKey<TRLWE_N, TLWE_N>,
+    stats: Stats,
 }
 
 pub struct TFHEHelper;
@@ -19,78 +27,271 @@ impl TFHEHelper {
 
 impl<const TLWE_N: usize, const TRLWE_N: usize> TFHE<TLWE_N, TRLWE_N> {
     pub fn new(s_key_tlwelv0: [Binary; TLWE_N], s_key_tlwelv1: [Binary; TRLWE_N]) -> Self {
-        let ksk = KeySwitchingKey::new(s_key_tlwelv1, &s_key_tlwelv0);
-        let bk = BootstrappingKey::new(s_key_tlwelv0, &pol!(s_key_tlwelv1));
-        TFHE { bk, ksk }
+        Self::new_with_rng(s_key_tlwelv0, s_key_tlwelv1, &mut secure_rng())
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "info", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn new_with_rng<R: Rng + CryptoRng>(
+        s_key_tlwelv0: [Binary; TLWE_N],
+        s_key_tlwelv1: [Binary; TRLWE_N],
+        rng: &mut R,
+    ) -> Self {
+        let ksk = KeySwitchingKey::new_with_rng(s_key_tlwelv1, &s_key_tlwelv0, rng);
+        let bk = BootstrappingKey::new_with_rng(s_key_tlwelv0, &pol!(s_key_tlwelv1), rng);
+        TFHE::from_keys(bk, ksk)
+    }
+}
+/// KeySwitchingKeyを圧縮したままの評価鍵。保存や送信に使い、計算の前にTFHEへ展開する
+pub struct CompressedServerKey<const TLWE_N: usize, const TRLWE_N: usize> {
+    bk: BootstrappingKey<TLWE_N, TRLWE_N>,
+    ksk: CompressedKeySwitchingKey<TRLWE_N, TLWE_N>,
+}
+impl<const TLWE_N: usize, const TRLWE_N: usize> CompressedServerKey<TLWE_N, TRLWE_N> {
+    pub fn new(s_key_tlwelv0: [Binary; TLWE_N], s_key_tlwelv1: [Binary; TRLWE_N]) -> Self {
+        Self::new_with_rng(s_key_tlwelv0, s_key_tlwelv1, &mut secure_rng())
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "info", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn new_with_rng<R: Rng + CryptoRng>(
+        s_key_tlwelv0: [Binary; TLWE_N],
+        s_key_tlwelv1: [Binary; TRLWE_N],
+        rng: &mut R,
+    ) -> Self {
+        let ksk = CompressedKeySwitchingKey::new_with_rng(s_key_tlwelv1, &s_key_tlwelv0, rng);
+        let bk = BootstrappingKey::new_with_rng(s_key_tlwelv0, &pol!(s_key_tlwelv1), rng);
+        CompressedServerKey { bk, ksk }
+    }
+    pub fn from_keys(
+        bk: BootstrappingKey<TLWE_N, TRLWE_N>,
+        ksk: CompressedKeySwitchingKey<TRLWE_N, TLWE_N>,
+    ) -> Self {
+        CompressedServerKey { bk, ksk }
+    }
+    pub fn bk(&self) -> &BootstrappingKey<TLWE_N, TRLWE_N> {
+        &self.bk
+    }
+    pub fn ksk(&self) -> &CompressedKeySwitchingKey<TRLWE_N, TLWE_N> {
+        &self.ksk
+    }
+    pub fn decompress(self) -> TFHE<TLWE_N, TRLWE_N> {
+        let ksk = self.ksk.decompress();
+        TFHE::from_keys(self.bk, ksk)
+    }
+}
+/// 秘密鍵の組。暗号化・復号と評価鍵の生成に使う。`wire`の形式でファイルに保存できる
+#[derive(Clone, PartialEq)]
+pub struct SecretKey<const TLWE_N: usize, const TRLWE_N: usize> {
+    pub tlwelv0: [Binary; TLWE_N],
+    pub tlwelv1: [Binary; TRLWE_N],
+}
+impl<const TLWE_N: usize, const TRLWE_N: usize> SecretKey<TLWE_N, TRLWE_N> {
+    pub fn new() -> Self {
+        let mut unif = BinaryDistribution::uniform();
+        SecretKey {
+            tlwelv0: unif.gen_n(),
+            tlwelv1: unif.gen_n(),
+        }
+    }
+    pub fn server_key(&self) -> TFHE<TLWE_N, TRLWE_N> {
+        TFHE::new(self.tlwelv0, self.tlwelv1)
+    }
+    pub fn compressed_server_key(&self) -> CompressedServerKey<TLWE_N, TRLWE_N> {
+        CompressedServerKey::new(self.tlwelv0, self.tlwelv1)
+    }
+    pub fn encrypt(&self, item: Binary) -> TLWERep<TLWE_N> {
+        Cryptor::encrypto(TLWE, &self.tlwelv0, item)
+    }
+    pub fn decrypt(&self, rep: TLWERep<TLWE_N>) -> Binary {
+        Cryptor::decrypto(TLWE, &self.tlwelv0, rep)
+    }
+}
+/// ログに鍵が出ないように、係数は表示しない
+impl<const TLWE_N: usize, const TRLWE_N: usize> Debug for SecretKey<TLWE_N, TRLWE_N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretKey")
+            .field("tlwe_n", &TLWE_N)
+            .field("trlwe_n", &TRLWE_N)
+            .finish_non_exhaustive()
+    }
+}
+impl<const TLWE_N: usize, const TRLWE_N: usize> Default for SecretKey<TLWE_N, TRLWE_N> {
+    fn default() -> Self {
+        SecretKey::new()
+    }
+}
+impl<const TLWE_N: usize, const TRLWE_N: usize> From<CompressedServerKey<TLWE_N, TRLWE_N>>
+    for TFHE<TLWE_N, TRLWE_N>
+{
+    fn from(key: CompressedServerKey<TLWE_N, TRLWE_N>) -> Self {
+        key.decompress()
+    }
+}
+impl<const TLWE_N: usize, const TRLWE_N: usize, BK: BootstrappingKeyRows<TRLWE_N>>
+    TFHE<TLWE_N, TRLWE_N, BK>
+{
+    pub fn from_keys(bk: BK, ksk: KeySwitchingKey<TRLWE_N, TLWE_N>) -> Self {
+        TFHE {
+            bk,
+            ksk,
+            stats: Stats::default(),
+        }
+    }
+    pub fn bk(&self) -> &BK {
+        &self.bk
+    }
+    pub fn ksk(&self) -> &KeySwitchingKey<TRLWE_N, TLWE_N> {
+        &self.ksk
+    }
+    /// これまでに評価したゲートの数、bootstrapやFFTの回数、かかった時間
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+    pub fn reset_stats(&self) {
+        self.stats.reset()
     }
     /// (input_1&control)|(input_0&!control)
+    /// # Panic
+    /// - 鍵の行を読めなかった。`try_hom_mux`ならエラーを返す
     pub fn hom_mux(
         &self,
         control: TLWERep<TLWE_N>,
         input_0: TLWERep<TLWE_N>,
         input_1: TLWERep<TLWE_N>,
     ) -> TLWERep<TLWE_N> {
-        let i_1 = self.hom_and(control.clone(), input_1);
-        let i_0 = self.hom_and(-control, input_0);
-        Self::bootstrap(
-            i_1 + i_0 + TLWERep::trivial(torus!(TFHEHelper::COEF)),
-            &self.bk,
-            &self.ksk,
-        )
+        Self::expect_key(self.try_hom_mux(control, input_0, input_1))
     }
+    /// # Panic
+    /// - 鍵の行を読めなかった。`try_hom_nand`ならエラーを返す
     pub fn hom_nand(&self, input_0: TLWERep<TLWE_N>, input_1: TLWERep<TLWE_N>) -> TLWERep<TLWE_N> {
-        Self::bootstrap(
-            TLWERep::trivial(torus!(TFHEHelper::COEF)) - (input_0 + input_1),
-            &self.bk,
-            &self.ksk,
-        )
+        Self::expect_key(self.try_hom_nand(input_0, input_1))
     }
+    /// # Panic
+    /// - 鍵の行を読めなかった。`try_hom_and`ならエラーを返す
     pub fn hom_and(&self, input_0: TLWERep<TLWE_N>, input_1: TLWERep<TLWE_N>) -> TLWERep<TLWE_N> {
-        Self::bootstrap(
-            (input_0 + input_1) - TLWERep::trivial(torus!(TFHEHelper::COEF)),
-            &self.bk,
-            &self.ksk,
-        )
+        Self::expect_key(self.try_hom_and(input_0, input_1))
     }
+    /// # Panic
+    /// - 鍵の行を読めなかった。`try_hom_or`ならエラーを返す
     pub fn hom_or(&self, input_0: TLWERep<TLWE_N>, input_1: TLWERep<TLWE_N>) -> TLWERep<TLWE_N> {
-        Self::bootstrap(
-            (input_0 + input_1) + TLWERep::trivial(torus!(TFHEHelper::COEF)),
-            &self.bk,
-            &self.ksk,
-        )
+        Self::expect_key(self.try_hom_or(input_0, input_1))
     }
+    /// # Panic
+    /// - 鍵の行を読めなかった。`try_hom_xor`ならエラーを返す
     pub fn hom_xor(&self, input_0: TLWERep<TLWE_N>, input_1: TLWERep<TLWE_N>) -> TLWERep<TLWE_N> {
-        Self::bootstrap(
-            (input_0 + input_1) * 2 + TLWERep::trivial(torus!(2.0 * TFHEHelper::COEF)),
-            &self.bk,
-            &self.ksk,
-        )
+        Self::expect_key(self.try_hom_xor(input_0, input_1))
     }
+    /// # Panic
+    /// - 鍵の行を読めなかった。`try_hom_not`ならエラーを返す
     pub fn hom_not(&self, input: TLWERep<TLWE_N>) -> TLWERep<TLWE_N> {
-        Self::bootstrap(-input, &self.bk, &self.ksk)
+        Self::expect_key(self.try_hom_not(input))
+    }
+    /// メモリ上の鍵(`BootstrappingKey`)は常にOkを返すので、ここでpanicすることはない
+    fn expect_key<T>(res: io::Result<T>) -> T {
+        res.unwrap_or_else(|e| panic!("failed to read bootstrapping key: {}", e))
     }
 
-    fn bootstrap(
-        tlwelv0: TLWERep<TLWE_N>,
-        bk: &BootstrappingKey<TLWE_N, TRLWE_N>,
-        ks: &KeySwitchingKey<TRLWE_N, TLWE_N>,
-    ) -> TLWERep<TLWE_N> {
-        let tlwelv1 = Self::gate_bootstrapping_tlwe2tlwe(tlwelv0, bk);
-        tlwelv1.identity_key_switch(ks)
+    /// `hom_mux`と同じ。鍵の行を読めなければエラーを返す
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn try_hom_mux(
+        &self,
+        control: TLWERep<TLWE_N>,
+        input_0: TLWERep<TLWE_N>,
+        input_1: TLWERep<TLWE_N>,
+    ) -> io::Result<TLWERep<TLWE_N>> {
+        let _record = self.stats.gate(Gate::Mux, 1);
+        let and = |a: TLWERep<TLWE_N>, b| {
+            self.bootstrap((a + b) - TLWERep::trivial(torus!(TFHEHelper::COEF)))
+        };
+        let i_1 = and(control.clone(), input_1)?;
+        let i_0 = and(-control, input_0)?;
+        self.bootstrap(i_1 + i_0 + TLWERep::trivial(torus!(TFHEHelper::COEF)))
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn try_hom_nand(
+        &self,
+        input_0: TLWERep<TLWE_N>,
+        input_1: TLWERep<TLWE_N>,
+    ) -> io::Result<TLWERep<TLWE_N>> {
+        let _record = self.stats.gate(Gate::Nand, 1);
+        self.bootstrap(TLWERep::trivial(torus!(TFHEHelper::COEF)) - (input_0 + input_1))
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn try_hom_and(
+        &self,
+        input_0: TLWERep<TLWE_N>,
+        input_1: TLWERep<TLWE_N>,
+    ) -> io::Result<TLWERep<TLWE_N>> {
+        let _record = self.stats.gate(Gate::And, 1);
+        self.bootstrap((input_0 + input_1) - TLWERep::trivial(torus!(TFHEHelper::COEF)))
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn try_hom_or(
+        &self,
+        input_0: TLWERep<TLWE_N>,
+        input_1: TLWERep<TLWE_N>,
+    ) -> io::Result<TLWERep<TLWE_N>> {
+        let _record = self.stats.gate(Gate::Or, 1);
+        self.bootstrap((input_0 + input_1) + TLWERep::trivial(torus!(TFHEHelper::COEF)))
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn try_hom_xor(
+        &self,
+        input_0: TLWERep<TLWE_N>,
+        input_1: TLWERep<TLWE_N>,
+    ) -> io::Result<TLWERep<TLWE_N>> {
+        let _record = self.stats.gate(Gate::Xor, 1);
+        self.bootstrap((input_0 + input_1) * 2 + TLWERep::trivial(torus!(2.0 * TFHEHelper::COEF)))
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
+    pub fn try_hom_not(&self, input: TLWERep<TLWE_N>) -> io::Result<TLWERep<TLWE_N>> {
+        let _record = self.stats.gate(Gate::Not, 1);
+        self.bootstrap(-input)
+    }
+
+    fn bootstrap(&self, tlwelv0: TLWERep<TLWE_N>) -> io::Result<TLWERep<TLWE_N>> {
+        self.stats.bootstrap();
+        let tlwelv1 = Self::gate_bootstrapping_tlwe2tlwe(tlwelv0, &self.bk)?;
+        Ok(tlwelv1.identity_key_switch(&self.ksk))
     }
     fn gate_bootstrapping_tlwe2tlwe(
         rep_tlwe: TLWERep<TLWE_N>,
-        bk: &BootstrappingKey<TLWE_N, TRLWE_N>,
-    ) -> TLWERep<TRLWE_N> {
+        bk: &BK,
+    ) -> io::Result<TLWERep<TRLWE_N>> {
         let testvec = TRLWERep::trivial(pol!([torus!(TFHEHelper::COEF); TRLWE_N]));
-        let trlwe = TFHE::blind_rotate(rep_tlwe, bk, testvec);
-        trlwe.sample_extract_index(0)
+        let trlwe = Self::blind_rotate(rep_tlwe, bk, testvec)?;
+        Ok(trlwe.sample_extract_index(0))
     }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(tlwe_n = TLWE_N, trlwe_n = TRLWE_N))
+    )]
     fn blind_rotate(
         rep_tlwe: TLWERep<TLWE_N>,
-        bk: &BootstrappingKey<TLWE_N, TRLWE_N>,
+        bk: &BK,
         base: TRLWERep<TRLWE_N>,
-    ) -> TRLWERep<TRLWE_N> {
+    ) -> io::Result<TRLWERep<TRLWE_N>> {
         const NBIT: u32 = TFHEHelper::NBIT;
         const BITS: u32 = u32::BITS;
         let (b, a) = rep_tlwe.get_and_drop();
@@ -100,30 +301,74 @@ impl<const TLWE_N: usize, const TRLWE_N: usize> TFHE<TLWE_N, TRLWE_N> {
         };
 
         // 計算 X^{-2bg(b-a*s)}*base = X^{(2bg*a)*s-(2bg*b)}*base where bg = 2^{NBIT}
-        let trlwe = a
-            .iter()
-            .zip(bk.iter())
-            .fold(rotate(&base, -b), |trlwe, (a_i, bk_i)| {
+        // 外積の作業領域はスレッドごとに使い回す
+        ExternalProductScratch::with(|scratch| {
+            bk.fold_rows(rotate(&base, -b), |trlwe, i, bk_i| {
+                let a_i = a[i];
                 let a =
                     (a_i.inner().wrapping_add(1 << (BITS - NBIT - 2)) >> (BITS - NBIT - 1)) as i32; // a_i.rounnd() * 2^(NBIT)
-                bk_i.cmux(rotate(&trlwe, a), trlwe)
-            });
-
-        trlwe
+                bk_i.cmux_with(rotate(&trlwe, a), trlwe, scratch)
+            })
+        })
     }
 }
 
+/// blind rotationに使う鍵の行(TRGSW(s_i))を先頭から順に渡す
+pub trait BootstrappingKeyRows<const N: usize> {
+    /// f(acc, i, TRGSW(s_i)) を i=0,1,.. の順に畳み込む。行を読めなければエラーを返す
+    fn fold_rows<A, F: FnMut(A, usize, &TRGSWRepF<N>) -> A>(
+        &self,
+        init: A,
+        f: F,
+    ) -> io::Result<A>;
+}
+
+/// TRGSW(s_i)は鍵生成や読み込みのときにFFTした形(TRGSWRepF)で持つ。
+/// 外積のたびに鍵を変換しなくて済む代わりに、係数1つにf64を使うのでメモリはTorus32の2倍になる
 pub struct BootstrappingKey<const PRE_N: usize, const N: usize>(Vec<TRGSWRepF<N>>);
+impl<const PRE_N: usize, const N: usize> BootstrappingKeyRows<N> for BootstrappingKey<PRE_N, N> {
+    fn fold_rows<A, F: FnMut(A, usize, &TRGSWRepF<N>) -> A>(
+        &self,
+        init: A,
+        mut f: F,
+    ) -> io::Result<A> {
+        Ok(self
+            .iter()
+            .enumerate()
+            .fold(init, |acc, (i, bk_i)| f(acc, i, bk_i)))
+    }
+}
 
 impl<const PRE_N: usize, const N: usize> BootstrappingKey<PRE_N, N> {
     pub fn new(s_key_tlwe: [Binary; PRE_N], s_key: &Polynomial<Binary, N>) -> Self {
+        Self::new_with_rng(s_key_tlwe, s_key, &mut secure_rng())
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "info", skip_all, fields(tlwe_n = PRE_N, trlwe_n = N))
+    )]
+    pub fn new_with_rng<R: Rng + CryptoRng>(
+        s_key_tlwe: [Binary; PRE_N],
+        s_key: &Polynomial<Binary, N>,
+        rng: &mut R,
+    ) -> Self {
         let mut vec = Vec::<TRGSWRepF<N>>::with_capacity(PRE_N);
         for s_i in s_key_tlwe {
-            let trgsw_ = Cryptor::encrypto(TRGSW, s_key, s_i);
+            let trgsw_ = Cryptor::encrypto_with_rng(TRGSW, s_key, s_i, rng);
             vec.push(TRGSWRepF::<N>::from(trgsw_));
         }
         BootstrappingKey(vec)
     }
+    /// 事前にFFTしておいたTRGSWを並べたものから作る
+    pub fn from_vec(vec: Vec<TRGSWRepF<N>>) -> Self {
+        assert_eq!(
+            vec.len(),
+            PRE_N,
+            "BootstrappingKey needs PRE_N={} rows",
+            PRE_N
+        );
+        BootstrappingKey(vec)
+    }
     #[inline]
     pub fn iter(&self) -> std::slice::Iter<'_, TRGSWRepF<N>> {
         self.0.iter()
@@ -142,8 +387,88 @@ mod tests {
 
     use super::*;
     use crate::tlwe::{TLWEHelper, TLWE};
+    use crate::trlwe::TRLWEHelper;
     use test::Bencher;
 
+    #[test]
+    fn tfhe_new_with_rng() {
+        use crate::wire::WireFormat;
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = TRLWEHelper::N;
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let mut unif = BinaryDistribution::uniform_with(&mut rng);
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+
+        let key_bytes = |seed: u64| {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            let tfhe = TFHE::new_with_rng(s_key_tlwelv0, s_key_tlwelv1, &mut rng);
+            let mut bytes = Vec::new();
+            tfhe.write_to(&mut bytes).unwrap();
+            bytes
+        };
+        assert!(key_bytes(1) == key_bytes(1), "同じseedなら同じ評価鍵");
+        assert!(key_bytes(1) != key_bytes(2));
+    }
+
+    #[test]
+    fn tfhe_seeded_run() {
+        use crate::wire::WireFormat;
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = TRLWEHelper::N;
+        // 鍵生成から評価までをseedだけで再現する
+        let run = |seed: u64| {
+            utils::math::seed_secure_rng(seed);
+            let mut unif = BinaryDistribution::uniform();
+            let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+            let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+            let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+            let x = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::One);
+            let y = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::Zero);
+            let res = tfhe.hom_nand(x, y);
+            let mut bytes = Vec::new();
+            res.write_to(&mut bytes).unwrap();
+            let res: Binary = Cryptor::decrypto(TLWE, &s_key_tlwelv0, res);
+            assert_eq!(res, Binary::One);
+            bytes
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn tfhe_stats() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = TRLWEHelper::N;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+        assert_eq!(tfhe.stats(), StatsSnapshot::default());
+
+        let x = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::One);
+        let y = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::Zero);
+        let z = tfhe.hom_nand(x.clone(), y.clone());
+        let _ = tfhe.hom_mux(z, x.clone(), y);
+        let _ = tfhe.hom_not(x);
+
+        let stats = tfhe.stats();
+        assert_eq!(stats.gate(Gate::Nand), 1);
+        assert_eq!(stats.gate(Gate::Mux), 1);
+        assert_eq!(stats.gate(Gate::Not), 1);
+        assert_eq!(stats.gate(Gate::And), 0, "muxの中のandは数えない");
+        assert_eq!(stats.total_gates(), 3);
+        assert_eq!(stats.bootstraps, 1 + 3 + 1);
+        assert!(stats.ffts > 0);
+        assert!(stats.wall_time > time::Duration::ZERO);
+        assert!(stats.to_string().starts_with("nand=1 and=0 "));
+
+        tfhe.reset_stats();
+        assert_eq!(tfhe.stats(), StatsSnapshot::default());
+    }
+
     #[bench]
     //#[ignore = "a little late, for about 1 minute"]
     fn tfhe_hom_nand(_: &mut Bencher) {
@@ -0,0 +1,483 @@
+/*!
+TFHEの参照実装(tfhe-lib)のバイナリ形式との相互変換。
+
+tfhe-libの`tfhe_io.cpp`と同じく、各オブジェクトはint32の型IDに続けて中身をネイティブエンディアン
+(ここではリトルエンディアンのみ対応)で書く。
+鍵の前にはパラメータの記録(LweParams, TLweParams)がテキスト形式で入る。
+
+```text
+LweSample : uid(i32) a[n](i32) b(i32) current_variance(f64)
+LweParams : "-----BEGIN LWEPARAMS-----\n" "alpha_max = …\n" "alpha_min = …\n" "n = …\n" "-----END LWEPARAMS-----\n"
+LweKey    : LweParams uid(i32) key[n](i32)
+TLweParams: "-----BEGIN TLWEPARAMS-----\n" "N = …\n" "alpha_max = …\n" "alpha_min = …\n" "k = …\n" "-----END TLWEPARAMS-----\n"
+TLweKey   : TLweParams uid(i32) key[N](i32)   (k = 1)
+```
+Torus32はどちらも2^32倍した整数なので値はそのまま使える。ゲートの符号化(±1/8)と位相 b - a・s の向きも同じ。
+n や N は型で与え、鍵を読むときにパラメータの記録の値と同じか確かめる。
+パラメータの記録の実数は10進と16進(`0x1p-15`)のどちらも読める。
+
+tfhe-libで作った鍵から評価鍵を作る場合は、`read_lwe_key`と`read_tlwe_key`で読んだ鍵を
+`TFHE::new`に渡す。tfhe-libの評価鍵(BootstrappingKey)はTRGSWの分解パラメータが違うので読み込めない。
+ */
+use crate::digest::Encrypted;
+use crate::tlwe::{TLWEHelper, TLWERep};
+use crate::trlwe::TRLWEHelper;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use utils::math::{Binary, Torus32};
+use utils::mem;
+
+/// tfhe_io.cppの型ID
+pub const LWE_SAMPLE_TYPE_UID: i32 = 32;
+pub const LWE_KEY_TYPE_UID: i32 = 33;
+pub const TLWE_KEY_TYPE_UID: i32 = 43;
+/// tfhe_io.cppのパラメータの記録の見出し
+const LWE_PARAMS_TITLE: &str = "LWEPARAMS";
+const TLWE_PARAMS_TITLE: &str = "TLWEPARAMS";
+/// パラメータの記録の行数と1行の長さの上限。壊れた入力を読み続けないようにする
+const MAX_PARAMS_LINES: usize = 16;
+const MAX_PARAMS_LINE_LEN: usize = 256;
+
+#[derive(Debug)]
+pub enum TfheLibError {
+    Io(io::Error),
+    TypeMismatch {
+        expected: i32,
+        found: i32,
+    },
+    /// 秘密鍵の係数が0,1ではない
+    NotBinaryKey(i32),
+    /// パラメータの記録が読めない
+    InvalidParams(String),
+    /// パラメータの記録の値が型と違う
+    ParamMismatch {
+        name: &'static str,
+        expected: i64,
+        found: i64,
+    },
+}
+impl From<io::Error> for TfheLibError {
+    fn from(e: io::Error) -> Self {
+        TfheLibError::Io(e)
+    }
+}
+impl Display for TfheLibError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TfheLibError::Io(e) => write!(f, "io error: {}", e),
+            TfheLibError::TypeMismatch { expected, found } => {
+                write!(
+                    f,
+                    "type uid mismatch: expected {}, found {}",
+                    expected, found
+                )
+            }
+            TfheLibError::NotBinaryKey(k) => write!(f, "key coefficient must be 0 or 1: {}", k),
+            TfheLibError::InvalidParams(msg) => write!(f, "invalid params record: {}", msg),
+            TfheLibError::ParamMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "param {} mismatch: expected {}, found {}",
+                name, expected, found
+            ),
+        }
+    }
+}
+impl std::error::Error for TfheLibError {}
+
+/// current_varianceには新しく暗号化したときの分散を書く
+pub fn write_lwe_sample<W: Write, const N: usize>(w: &mut W, rep: &TLWERep<N>) -> io::Result<()> {
+    let (b, a) = rep.get_ref();
+    write_i32(w, LWE_SAMPLE_TYPE_UID)?;
+    a.iter().try_for_each(|a_i| write_torus(w, *a_i))?;
+    write_torus(w, *b)?;
+    let variance = (TLWEHelper::ALPHA as f64).powi(2);
+    w.write_all(&variance.to_le_bytes())
+}
+/// current_varianceは読み捨てる
+pub fn read_lwe_sample<R: Read, const N: usize>(r: &mut R) -> Result<TLWERep<N>, TfheLibError> {
+    expect_uid(r, LWE_SAMPLE_TYPE_UID)?;
+    let mut a = [Torus32::from_bits(0); N];
+    for a_i in a.iter_mut() {
+        *a_i = read_torus(r)?;
+    }
+    let b = read_torus(r)?;
+    let mut variance = [0_u8; 8];
+    r.read_exact(&mut variance)?;
+    Ok(TLWERep::new(b, a))
+}
+
+/// alpha_minとalpha_maxにはどちらもTLWEHelper::ALPHAを書く
+pub fn write_lwe_key<W: Write, const N: usize>(w: &mut W, s_key: &[Binary; N]) -> io::Result<()> {
+    let alpha = TLWEHelper::ALPHA as f64;
+    write_params(
+        w,
+        LWE_PARAMS_TITLE,
+        &[
+            ("alpha_max", format!("{:e}", alpha)),
+            ("alpha_min", format!("{:e}", alpha)),
+            ("n", N.to_string()),
+        ],
+    )?;
+    write_i32(w, LWE_KEY_TYPE_UID)?;
+    s_key.iter().try_for_each(|&s| write_i32(w, s as i32))
+}
+/// LweParamsのnがNと同じか確かめてから鍵を読む
+pub fn read_lwe_key<R: Read, const N: usize>(r: &mut R) -> Result<[Binary; N], TfheLibError> {
+    let params = read_params(r, LWE_PARAMS_TITLE)?;
+    expect_param(&params, "n", N)?;
+    expect_alpha(&params)?;
+    expect_uid(r, LWE_KEY_TYPE_UID)?;
+    read_binary_key(r)
+}
+/// k=1のTLweKey。TFHE::newのs_key_tlwelv1に使う。
+/// alpha_minとalpha_maxにはどちらもTRLWEHelper::ALPHAを書く
+pub fn write_tlwe_key<W: Write, const N: usize>(w: &mut W, s_key: &[Binary; N]) -> io::Result<()> {
+    let alpha = TRLWEHelper::ALPHA as f64;
+    write_params(
+        w,
+        TLWE_PARAMS_TITLE,
+        &[
+            ("N", N.to_string()),
+            ("alpha_max", format!("{:e}", alpha)),
+            ("alpha_min", format!("{:e}", alpha)),
+            ("k", 1.to_string()),
+        ],
+    )?;
+    write_i32(w, TLWE_KEY_TYPE_UID)?;
+    s_key.iter().try_for_each(|&s| write_i32(w, s as i32))
+}
+/// TLweParamsのNがNと同じで、kが1か確かめてから鍵を読む
+pub fn read_tlwe_key<R: Read, const N: usize>(r: &mut R) -> Result<[Binary; N], TfheLibError> {
+    let params = read_params(r, TLWE_PARAMS_TITLE)?;
+    expect_param(&params, "N", N)?;
+    expect_param(&params, "k", 1)?;
+    expect_alpha(&params)?;
+    expect_uid(r, TLWE_KEY_TYPE_UID)?;
+    read_binary_key(r)
+}
+
+/// tfhe_io.cppのTextModePropertiesと同じく、見出しの行の間に`名前 = 値`を1行ずつ書く
+fn write_params<W: Write>(w: &mut W, title: &str, props: &[(&str, String)]) -> io::Result<()> {
+    writeln!(w, "-----BEGIN {}-----", title)?;
+    for (name, value) in props {
+        writeln!(w, "{} = {}", name, value)?;
+    }
+    writeln!(w, "-----END {}-----", title)
+}
+/// 名前と値の区切りは`=`と`:`のどちらも受け付ける
+fn read_params<R: Read>(r: &mut R, title: &str) -> Result<HashMap<String, String>, TfheLibError> {
+    let begin = read_line(r)?;
+    if begin.trim() != format!("-----BEGIN {}-----", title) {
+        return Err(TfheLibError::InvalidParams(format!(
+            "expected {} but found {:?}",
+            title, begin
+        )));
+    }
+    let end = format!("-----END {}-----", title);
+    let mut props = HashMap::new();
+    for _ in 0..MAX_PARAMS_LINES {
+        let line = read_line(r)?;
+        let line = line.trim();
+        if line == end {
+            return Ok(props);
+        }
+        let (name, value) = line
+            .split_once(['=', ':'])
+            .ok_or_else(|| TfheLibError::InvalidParams(format!("invalid line {:?}", line)))?;
+        props.insert(name.trim().to_string(), value.trim().to_string());
+    }
+    Err(TfheLibError::InvalidParams(format!(
+        "{} is not closed",
+        title
+    )))
+}
+/// '\n'までの1行。'\n'は含めない
+fn read_line<R: Read>(r: &mut R) -> Result<String, TfheLibError> {
+    let mut line = Vec::new();
+    loop {
+        let mut c = [0_u8; 1];
+        r.read_exact(&mut c)?;
+        if c[0] == b'\n' {
+            break;
+        }
+        if line.len() >= MAX_PARAMS_LINE_LEN {
+            return Err(TfheLibError::InvalidParams("line too long".to_string()));
+        }
+        line.push(c[0]);
+    }
+    String::from_utf8(line).map_err(|_| TfheLibError::InvalidParams("not utf-8".to_string()))
+}
+fn param<'a>(params: &'a HashMap<String, String>, name: &str) -> Result<&'a str, TfheLibError> {
+    params
+        .get(name)
+        .map(String::as_str)
+        .ok_or_else(|| TfheLibError::InvalidParams(format!("missing {}", name)))
+}
+fn expect_param(
+    params: &HashMap<String, String>,
+    name: &'static str,
+    expected: usize,
+) -> Result<(), TfheLibError> {
+    let value = param(params, name)?;
+    let found: i64 = value
+        .parse()
+        .map_err(|_| TfheLibError::InvalidParams(format!("{} = {}", name, value)))?;
+    if found != expected as i64 {
+        return Err(TfheLibError::ParamMismatch {
+            name,
+            expected: expected as i64,
+            found,
+        });
+    }
+    Ok(())
+}
+/// alpha_min, alpha_maxは使わないが、0 < alpha_min <= alpha_max であることは確かめる
+fn expect_alpha(params: &HashMap<String, String>) -> Result<(), TfheLibError> {
+    let alpha = |name| {
+        let value = param(params, name)?;
+        parse_double(value)
+            .ok_or_else(|| TfheLibError::InvalidParams(format!("{} = {}", name, value)))
+    };
+    let (min, max) = (alpha("alpha_min")?, alpha("alpha_max")?);
+    if !(0.0 < min && min <= max && max.is_finite()) {
+        return Err(TfheLibError::InvalidParams(format!(
+            "alpha_min = {}, alpha_max = {}",
+            min, max
+        )));
+    }
+    Ok(())
+}
+/// 10進か、Cの`%a`の16進(`0x1.8p-15`)の実数
+fn parse_double(s: &str) -> Option<f64> {
+    let (sign, body) = match s.strip_prefix('-') {
+        Some(body) => (-1.0, body),
+        None => (1.0, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let hex = match body.strip_prefix("0x").or_else(|| body.strip_prefix("0X")) {
+        Some(hex) => hex,
+        None => return s.parse().ok(),
+    };
+    let (mantissa, exp) = hex.split_once(['p', 'P'])?;
+    let exp: i32 = exp.parse().ok()?;
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    // %aの仮数は高々53bitなので、14桁までならu64で正確に持てる
+    if int.len() + frac.len() > 14 || int.len() + frac.len() == 0 {
+        return None;
+    }
+    let digits = u64::from_str_radix(&format!("{}{}", int, frac), 16).ok()?;
+    Some(sign * digits as f64 * 2_f64.powi(exp - 4 * frac.len() as i32))
+}
+
+fn read_binary_key<R: Read, const N: usize>(r: &mut R) -> Result<[Binary; N], TfheLibError> {
+    let mut key = [0_i32; N];
+    for k in key.iter_mut() {
+        *k = read_i32(r)?;
+        if *k != 0 && *k != 1 {
+            return Err(TfheLibError::NotBinaryKey(*k));
+        }
+    }
+    Ok(mem::array_create_enumerate(|i| Binary::from(key[i])))
+}
+fn expect_uid<R: Read>(r: &mut R, expected: i32) -> Result<(), TfheLibError> {
+    let found = read_i32(r)?;
+    if found != expected {
+        return Err(TfheLibError::TypeMismatch { expected, found });
+    }
+    Ok(())
+}
+#[inline]
+fn write_i32<W: Write>(w: &mut W, i: i32) -> io::Result<()> {
+    w.write_all(&i.to_le_bytes())
+}
+#[inline]
+fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut buf = [0_u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+/// tfhe-libのTorus32はint32なので、ビット列をそのまま使う
+#[inline]
+fn write_torus<W: Write>(w: &mut W, t: Torus32) -> io::Result<()> {
+    write_i32(w, t.inner() as i32)
+}
+#[inline]
+fn read_torus<R: Read>(r: &mut R) -> io::Result<Torus32> {
+    Ok(Torus32::from_bits(read_i32(r)? as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::Cryptor;
+    use crate::tlwe::TLWE;
+    use utils::math::{BinaryDistribution, Random};
+
+    #[test]
+    fn tfhe_lib_lwe_sample() {
+        // tfhe-libのデフォルトのパラメータ(n=630)
+        const N: usize = 630;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let rep = Cryptor::encrypto(TLWE, &s_key, Binary::One);
+
+        let mut bytes = Vec::new();
+        write_lwe_sample(&mut bytes, &rep).unwrap();
+        assert_eq!(bytes.len(), 4 + 4 * N + 4 + 8);
+        assert_eq!(&bytes[0..4], &LWE_SAMPLE_TYPE_UID.to_le_bytes());
+        assert_eq!(&bytes[4..8], &rep.p_key()[0].inner().to_le_bytes());
+        assert_eq!(
+            &bytes[4 + 4 * N..8 + 4 * N],
+            &rep.cipher().inner().to_le_bytes()
+        );
+
+        let res: TLWERep<N> = read_lwe_sample(&mut &bytes[..]).unwrap();
+        let res: Binary = Cryptor::decrypto(TLWE, &s_key, res);
+        assert_eq!(res, Binary::One);
+
+        // tfhe-libのbootsSymEncryptと同じく true は 1/8
+        let trivial = TLWERep::<N>::trivial(Torus32::from_bits(1 << 29));
+        let mut bytes = Vec::new();
+        write_lwe_sample(&mut bytes, &trivial).unwrap();
+        let res: Binary = Cryptor::decrypto(
+            TLWE,
+            &s_key,
+            read_lwe_sample::<_, N>(&mut &bytes[..]).unwrap(),
+        );
+        assert_eq!(res, Binary::One);
+    }
+
+    #[test]
+    fn tfhe_lib_keys() {
+        const N: usize = 630;
+        const M: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let lwe_key: [Binary; N] = unif.gen_n();
+        let tlwe_key: [Binary; M] = unif.gen_n();
+
+        let mut bytes = Vec::new();
+        write_lwe_key(&mut bytes, &lwe_key).unwrap();
+        write_tlwe_key(&mut bytes, &tlwe_key).unwrap();
+        let mut r = &bytes[..];
+        assert_eq!(read_lwe_key::<_, N>(&mut r).unwrap(), lwe_key);
+        assert_eq!(read_tlwe_key::<_, M>(&mut r).unwrap(), tlwe_key);
+
+        assert!(matches!(
+            read_tlwe_key::<_, N>(&mut &bytes[..]),
+            Err(TfheLibError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            read_lwe_key::<_, 500>(&mut &bytes[..]),
+            Err(TfheLibError::ParamMismatch {
+                name: "n",
+                expected: 500,
+                found: 630
+            })
+        ));
+        let key_start = bytes
+            .windows(4)
+            .position(|w| w == LWE_KEY_TYPE_UID.to_le_bytes())
+            .unwrap();
+        let mut bad = bytes.clone();
+        bad[key_start..key_start + 4].copy_from_slice(&TLWE_KEY_TYPE_UID.to_le_bytes());
+        assert!(matches!(
+            read_lwe_key::<_, N>(&mut &bad[..]),
+            Err(TfheLibError::TypeMismatch {
+                expected: LWE_KEY_TYPE_UID,
+                found: TLWE_KEY_TYPE_UID
+            })
+        ));
+        let mut bad = bytes.clone();
+        bad[key_start + 4..key_start + 8].copy_from_slice(&(-1_i32).to_le_bytes());
+        assert!(matches!(
+            read_lwe_key::<_, N>(&mut &bad[..]),
+            Err(TfheLibError::NotBinaryKey(-1))
+        ));
+    }
+
+    /// tfhe-libの`export_lweKey_toFile`と`export_tLweKey_toFile`が書く並び
+    /// (TextModePropertiesのパラメータの記録、型ID、係数)に合わせて組み立てたバイト列。
+    /// alphaはtfhe-libの既定のパラメータの値をCの`%a`で書いたもので、nとNだけ小さくしてある。
+    /// tfhe-lib自体では作っていない
+    const TFHE_LIB_KEYS: &[u8] = b"-----BEGIN LWEPARAMS-----\n\
+        alpha_max = 0x1.9884c6a3bddfdp-7\n\
+        alpha_min = 0x1p-15\n\
+        n = 8\n\
+        -----END LWEPARAMS-----\n\
+        \x21\x00\x00\x00\
+        \x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\
+        -----BEGIN TLWEPARAMS-----\n\
+        N = 16\n\
+        alpha_max = 0x1.9884c6a3bddfdp-7\n\
+        alpha_min = 0x1p-25\n\
+        k = 1\n\
+        -----END TLWEPARAMS-----\n\
+        \x2b\x00\x00\x00\
+        \x00\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\
+        \x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+        \x01\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00";
+
+    #[test]
+    fn tfhe_lib_keys_fixture() {
+        let bits = |l: &[u32]| -> Vec<Binary> { l.iter().map(|&b| Binary::from(b)).collect() };
+        let mut r = TFHE_LIB_KEYS;
+        let lwe_key = read_lwe_key::<_, 8>(&mut r).unwrap();
+        assert_eq!(lwe_key.to_vec(), bits(&[1, 0, 1, 1, 0, 0, 1, 0]));
+        let tlwe_key = read_tlwe_key::<_, 16>(&mut r).unwrap();
+        assert_eq!(
+            tlwe_key.to_vec(),
+            bits(&[0, 1, 1, 0, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 0, 1])
+        );
+        assert!(r.is_empty());
+
+        // 書き直しても同じ鍵が読める
+        let mut bytes = Vec::new();
+        write_lwe_key(&mut bytes, &lwe_key).unwrap();
+        write_tlwe_key(&mut bytes, &tlwe_key).unwrap();
+        let mut r = &bytes[..];
+        assert_eq!(read_lwe_key::<_, 8>(&mut r).unwrap(), lwe_key);
+        assert_eq!(read_tlwe_key::<_, 16>(&mut r).unwrap(), tlwe_key);
+    }
+
+    #[test]
+    fn tfhe_lib_params() {
+        assert_eq!(parse_double("0x1p-15"), Some(2_f64.powi(-15)));
+        assert_eq!(parse_double("0x1.8p+1"), Some(3.0));
+        assert_eq!(parse_double("-0X1.8P1"), Some(-3.0));
+        assert_eq!(parse_double("0x1.9884c6a3bddfdp-7"), Some(0.012467));
+        assert_eq!(parse_double("3.0517578125e-05"), Some(2_f64.powi(-15)));
+        assert_eq!(parse_double("0xp1"), None);
+        assert_eq!(parse_double("0x1.8"), None);
+
+        let read = |text: &str| read_params(&mut text.as_bytes(), LWE_PARAMS_TITLE);
+        let params = read("-----BEGIN LWEPARAMS-----\nn: 630\n-----END LWEPARAMS-----\n").unwrap();
+        assert_eq!(params["n"], "630");
+        assert!(matches!(
+            read("-----BEGIN TLWEPARAMS-----\n"),
+            Err(TfheLibError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            read("-----BEGIN LWEPARAMS-----\nn 630\n"),
+            Err(TfheLibError::InvalidParams(_))
+        ));
+        // 閉じない記録や長すぎる行は途中でやめる
+        let unclosed = format!("-----BEGIN LWEPARAMS-----\n{}", "n = 1\n".repeat(100));
+        assert!(matches!(
+            read(&unclosed),
+            Err(TfheLibError::InvalidParams(_))
+        ));
+        let long = format!("-----BEGIN LWEPARAMS-----\n{}\n", "n".repeat(1000));
+        assert!(matches!(read(&long), Err(TfheLibError::InvalidParams(_))));
+
+        let swapped = "-----BEGIN LWEPARAMS-----\nalpha_max = 0x1p-15\nalpha_min = 0x1p-7\nn = 8\n-----END LWEPARAMS-----\n";
+        assert!(matches!(
+            read_lwe_key::<_, 8>(&mut swapped.as_bytes()),
+            Err(TfheLibError::InvalidParams(_))
+        ));
+    }
+}
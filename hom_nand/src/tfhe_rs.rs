@@ -0,0 +1,170 @@
+/*!
+tfhe-rsの`boolean`モジュールとの相互変換。
+
+tfhe-rsのLWE暗号文は`LweCiphertext<Vec<u32>>`で、コンテナは mask(n個) の後に body を並べたもの。
+秘密鍵`LweSecretKey<Vec<u32>>`のコンテナは0,1の列。どちらも
+`into_container()` / `from_container(..)` でVec<u32>と行き来できるので、ここではそのVec<u32>を扱う。
+
+```text
+boolean::Ciphertext::Encrypted(ct) <-> BooleanCiphertext::Encrypted(ct.into_container())
+boolean::Ciphertext::Trivial(b)    <-> BooleanCiphertext::Trivial(b)
+```
+平文の符号化(true = 1/8, false = -1/8)と位相 body - <mask, s> の向きは同じなので、値は変換しない。
+tfhe-rsの暗号文はネイティブの法(2^32)で作ったものだけを扱う。
+nは型で与えるので、tfhe-rs側のlwe_dimensionと揃えること。
+ */
+use crate::digest::Encrypted;
+use crate::tlwe::{TLWEHelper, TLWERep};
+use std::convert::TryInto;
+use std::fmt::Display;
+use utils::math::{Binary, Torus32};
+use utils::mem;
+
+#[derive(Debug, PartialEq)]
+pub enum TfheRsError {
+    /// コンテナの長さがn(+1)と合わない
+    LengthMismatch { expected: usize, found: usize },
+    /// 秘密鍵の係数が0,1ではない
+    NotBinaryKey(u32),
+}
+impl Display for TfheRsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TfheRsError::LengthMismatch { expected, found } => {
+                write!(
+                    f,
+                    "container length mismatch: expected {}, found {}",
+                    expected, found
+                )
+            }
+            TfheRsError::NotBinaryKey(k) => write!(f, "key coefficient must be 0 or 1: {}", k),
+        }
+    }
+}
+impl std::error::Error for TfheRsError {}
+
+/// tfhe-rsの`boolean::ciphertext::Ciphertext`に対応する
+#[derive(Debug, Clone, PartialEq)]
+pub enum BooleanCiphertext {
+    Encrypted(Vec<u32>),
+    Trivial(bool),
+}
+impl BooleanCiphertext {
+    pub fn from_tlwe<const N: usize>(rep: &TLWERep<N>) -> Self {
+        BooleanCiphertext::Encrypted(to_lwe_container(rep))
+    }
+    /// Trivialはmaskが0の暗号文にする
+    pub fn to_tlwe<const N: usize>(&self) -> Result<TLWERep<N>, TfheRsError> {
+        match self {
+            BooleanCiphertext::Encrypted(container) => from_lwe_container(container),
+            BooleanCiphertext::Trivial(b) => Ok(TLWERep::trivial(TLWEHelper::binary2torus(
+                Binary::from(*b as u32),
+            ))),
+        }
+    }
+}
+
+/// mask(n個), body の順に並べる
+pub fn to_lwe_container<const N: usize>(rep: &TLWERep<N>) -> Vec<u32> {
+    let (b, a) = rep.get_ref();
+    a.iter()
+        .chain(std::iter::once(b))
+        .map(|t| t.inner())
+        .collect()
+}
+pub fn from_lwe_container<const N: usize>(container: &[u32]) -> Result<TLWERep<N>, TfheRsError> {
+    if container.len() != N + 1 {
+        return Err(TfheRsError::LengthMismatch {
+            expected: N + 1,
+            found: container.len(),
+        });
+    }
+    let (a, b) = container.split_at(N);
+    let a: [Torus32; N] = mem::array_create_enumerate(|i| Torus32::from_bits(a[i]));
+    Ok(TLWERep::new(Torus32::from_bits(b[0]), a))
+}
+
+pub fn to_lwe_secret_key_container<const N: usize>(s_key: &[Binary; N]) -> Vec<u32> {
+    s_key.iter().map(|&s| s as u32).collect()
+}
+pub fn from_lwe_secret_key_container<const N: usize>(
+    container: &[u32],
+) -> Result<[Binary; N], TfheRsError> {
+    if let Some(&k) = container.iter().find(|&&k| k > 1) {
+        return Err(TfheRsError::NotBinaryKey(k));
+    }
+    let key: Vec<Binary> = container.iter().map(|&k| Binary::from(k)).collect();
+    let found = key.len();
+    key.try_into()
+        .map_err(|_| TfheRsError::LengthMismatch { expected: N, found })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::Cryptor;
+    use crate::tlwe::TLWE;
+    use utils::math::{BinaryDistribution, Random};
+
+    #[test]
+    fn tfhe_rs_lwe_container() {
+        const N: usize = TLWEHelper::N;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        for &item in [Binary::One, Binary::Zero].iter() {
+            let rep = Cryptor::encrypto(TLWE, &s_key, item);
+            let ct = BooleanCiphertext::from_tlwe(&rep);
+            match &ct {
+                BooleanCiphertext::Encrypted(c) => {
+                    assert_eq!(c.len(), N + 1);
+                    assert_eq!(c[N], rep.cipher().inner(), "bodyは最後");
+                    assert_eq!(c[0], rep.p_key()[0].inner());
+                }
+                _ => unreachable!(),
+            }
+            let res: Binary = Cryptor::decrypto(TLWE, &s_key, ct.to_tlwe::<N>().unwrap());
+            assert_eq!(res, item);
+        }
+
+        for &b in [true, false].iter() {
+            let res: Binary = Cryptor::decrypto(
+                TLWE,
+                &s_key,
+                BooleanCiphertext::Trivial(b).to_tlwe::<N>().unwrap(),
+            );
+            assert_eq!(res, Binary::from(b as u32));
+        }
+
+        assert!(matches!(
+            from_lwe_container::<N>(&[0; N]),
+            Err(TfheRsError::LengthMismatch {
+                expected: _,
+                found: N
+            })
+        ));
+    }
+
+    #[test]
+    fn tfhe_rs_secret_key() {
+        const N: usize = 16;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let container = to_lwe_secret_key_container(&s_key);
+        assert_eq!(
+            from_lwe_secret_key_container::<N>(&container).unwrap(),
+            s_key
+        );
+
+        assert_eq!(
+            from_lwe_secret_key_container::<N>(&container[1..]).unwrap_err(),
+            TfheRsError::LengthMismatch {
+                expected: N,
+                found: N - 1
+            }
+        );
+        let mut bad = container;
+        bad[3] = 2;
+        assert_eq!(
+            from_lwe_secret_key_container::<N>(&bad).unwrap_err(),
+            TfheRsError::NotBinaryKey(2)
+        );
+    }
+}
@@ -0,0 +1,330 @@
+/*!
+TLWEの秘密鍵を分散して持ち、t-of-nで復号する。
+
+秘密鍵sをZ_{2^32}上の加法シェアに分ける(replicated secret sharing)。
+大きさt-1の参加者集合Aごとにシェアr_Aを作り、Aに入らない参加者全員に持たせる。
+Σ_A r_A = s なので、t人集まれば全てのr_Aがそろうが、t-1人では必ず1つ欠ける。
+シェアの数はC(n,t-1)になるので、nは小さいことを想定している。
+
+復号は各参加者が a・r_A の和に誤差を足した値(DecryptionShare)を出し、
+combineで b - Σ(部分復号) = m + e + (足した誤差の和) を得る。
+
+# 安全性
+部分復号に足す誤差(`ThresholdHelper::SMUDGING_STD`)は統計的なsmudgingではない。
+TLWEの誤差eを統計的に隠すにはeより2^{40}倍程度大きい誤差が要るが、
+Torus32でBinaryを復号できる範囲(1/8)にはそれだけの余裕がない。
+そのため復号した結果から平文mを引いた値 e + (誤差の和) からeの情報が漏れる。
+同じ暗号文を何度も復号すると足した誤差は平均で消えてeが求まり、
+eが分かれば b - m - e = a・s という秘密鍵sの線形式が得られる。
+N本集まればsが求まるので、同じ暗号文の復号を繰り返したり、
+復号結果と平文を信頼できない参加者に渡したりしてはいけない。
+ */
+use crate::digest::Encrypted;
+use crate::tlwe::{TLWEHelper, TLWERep};
+use num::Zero;
+use rand::Rng;
+use std::fmt::Display;
+use utils::math::{Binary, ModDistribution, Random, Torus32};
+use utils::mem;
+
+pub struct ThresholdHelper;
+impl ThresholdHelper {
+    /// 参加者数の上限。シェアの数がC(n,t-1)で増えるため
+    pub const MAX_PARTIES: usize = 16;
+    /// 部分復号に足す誤差の標準偏差。t人分足してもBinaryの復号(1/8)を壊さない大きさ。
+    /// TLWEの誤差(2^{-15})を統計的には隠せない(モジュールの説明を参照)
+    pub const SMUDGING_STD: f32 = 1.0 / (2_u32.pow(9) as f32); // 2^{-9}
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ThresholdError {
+    /// 閾値に届かない
+    NotEnoughShares { threshold: usize, found: usize },
+    /// 同じ参加者のシェアが複数ある
+    DuplicateParty(usize),
+    /// 部分復号が別の参加者集合を前提に作られている
+    PartiesMismatch,
+    /// 参加者の番号が参加者数以上
+    UnknownParty(usize),
+    /// 部分復号する参加者が、復号する参加者集合に入っていない
+    NotInParties(usize),
+}
+impl Display for ThresholdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThresholdError::NotEnoughShares { threshold, found } => {
+                write!(f, "need {} decryption shares, found {}", threshold, found)
+            }
+            ThresholdError::DuplicateParty(p) => write!(f, "duplicate share from party {}", p),
+            ThresholdError::PartiesMismatch => {
+                write!(
+                    f,
+                    "decryption shares were made for different sets of parties"
+                )
+            }
+            ThresholdError::UnknownParty(p) => write!(f, "unknown party {}", p),
+            ThresholdError::NotInParties(p) => {
+                write!(f, "party {} is not in the decrypting parties", p)
+            }
+        }
+    }
+}
+impl std::error::Error for ThresholdError {}
+
+/// 参加者partyが持つ秘密鍵のシェア
+pub struct KeyShare<const N: usize> {
+    party: usize,
+    threshold: usize,
+    /// 参加者数n
+    n: usize,
+    /// (t-1人の集合Aのビットマスク, r_A)。party ∉ A
+    shares: Vec<(u32, [Torus32; N])>,
+}
+/// 部分復号の結果
+#[derive(Debug, Clone)]
+pub struct DecryptionShare {
+    party: usize,
+    parties: u32,
+    value: Torus32,
+}
+impl DecryptionShare {
+    pub fn party(&self) -> usize {
+        self.party
+    }
+}
+
+/// 秘密鍵をn人に分け、t人で復号できるようにする
+pub fn split_key<const N: usize>(s_key: &[Binary; N], t: usize, n: usize) -> Vec<KeyShare<N>> {
+    assert!(
+        1 <= t && t <= n && n <= ThresholdHelper::MAX_PARTIES,
+        "invalid threshold: t={}, n={}",
+        t,
+        n
+    );
+    let subsets = subsets_of_size(n, t - 1);
+    let mut rng = utils::math::secure_rng();
+    let (last, rest) = subsets.split_last().unwrap();
+
+    // r_A を一様に選び、最後のシェアで和がsになるように合わせる
+    let mut sum = [Torus32::zero(); N];
+    let mut r: Vec<(u32, [Torus32; N])> = rest
+        .iter()
+        .map(|&a| {
+            let r_a: [Torus32; N] = mem::array_create_enumerate(|_| Torus32::from_bits(rng.gen()));
+            sum.iter_mut().zip(r_a.iter()).for_each(|(x, &y)| *x += y);
+            (a, r_a)
+        })
+        .collect();
+    let r_last = mem::array_create_enumerate(|i| Torus32::from_bits(s_key[i] as u32) - sum[i]);
+    r.push((*last, r_last));
+
+    (0..n)
+        .map(|party| KeyShare {
+            party,
+            threshold: t,
+            n,
+            shares: r
+                .iter()
+                .filter(|(a, _)| a & (1 << party) == 0)
+                .cloned()
+                .collect(),
+        })
+        .collect()
+}
+
+impl<const N: usize> KeyShare<N> {
+    pub fn party(&self) -> usize {
+        self.party
+    }
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+    /// partiesで復号するときの、この参加者の部分復号
+    ///
+    /// 各r_Aはpartiesの中でAに入らない最小の参加者だけが使う。
+    /// partiesは他の参加者から受け取るので、不正なら`ThresholdError`を返す
+    pub fn partial_decrypt(
+        &self,
+        rep: &TLWERep<N>,
+        parties: &[usize],
+    ) -> Result<DecryptionShare, ThresholdError> {
+        let mask = parties_mask(parties, self.threshold, self.n)?;
+        if mask & (1 << self.party) == 0 {
+            return Err(ThresholdError::NotInParties(self.party));
+        }
+        let (_, a) = rep.get_ref();
+        let mut norm = ModDistribution::gaussian(ThresholdHelper::SMUDGING_STD);
+        let value = self
+            .shares
+            .iter()
+            .filter(|(subset, _)| (mask & !subset).trailing_zeros() as usize == self.party)
+            .fold(norm.gen(), |acc, (_, r_a)| {
+                a.iter()
+                    .zip(r_a.iter())
+                    .fold(acc, |acc, (&a_i, r_i)| acc + a_i * r_i.inner())
+            });
+        Ok(DecryptionShare {
+            party: self.party,
+            parties: mask,
+            value,
+        })
+    }
+}
+
+/// 部分復号を集めて平文(誤差つき)を得る
+pub fn combine<const N: usize>(
+    rep: &TLWERep<N>,
+    shares: &[DecryptionShare],
+    threshold: usize,
+) -> Result<Torus32, ThresholdError> {
+    let parties: Vec<usize> = shares.iter().map(|s| s.party).collect();
+    let mask = parties_mask(&parties, threshold, ThresholdHelper::MAX_PARTIES)?;
+    if shares.iter().any(|s| s.parties != mask) {
+        return Err(ThresholdError::PartiesMismatch);
+    }
+    let b = *rep.cipher();
+    Ok(shares.iter().fold(b, |acc, s| acc - s.value))
+}
+pub fn combine_binary<const N: usize>(
+    rep: &TLWERep<N>,
+    shares: &[DecryptionShare],
+    threshold: usize,
+) -> Result<Binary, ThresholdError> {
+    combine(rep, shares, threshold).map(TLWEHelper::torus2binary)
+}
+
+/// n人のうちpartiesの集合のビットマスク
+fn parties_mask(parties: &[usize], threshold: usize, n: usize) -> Result<u32, ThresholdError> {
+    let mut mask = 0_u32;
+    for &p in parties {
+        if p >= n {
+            return Err(ThresholdError::UnknownParty(p));
+        }
+        if mask & (1 << p) != 0 {
+            return Err(ThresholdError::DuplicateParty(p));
+        }
+        mask |= 1 << p;
+    }
+    if parties.len() < threshold {
+        return Err(ThresholdError::NotEnoughShares {
+            threshold,
+            found: parties.len(),
+        });
+    }
+    Ok(mask)
+}
+
+/// {0,..,n-1}の大きさkの部分集合をビットマスクで列挙する
+fn subsets_of_size(n: usize, k: usize) -> Vec<u32> {
+    (0..1_u32 << n)
+        .filter(|m| m.count_ones() as usize == k)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::Cryptor;
+    use crate::tlwe::TLWE;
+    use utils::math::BinaryDistribution;
+
+    #[test]
+    fn threshold_decrypt() {
+        const N: usize = TLWEHelper::N;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let (t, n) = (3, 5);
+        let key_shares = split_key(&s_key, t, n);
+        assert_eq!(key_shares.len(), n);
+        assert!(
+            key_shares.iter().all(|k| k.shares.len() == 6),
+            "C(4,2)個のr_Aを持つ"
+        );
+
+        for &item in [Binary::One, Binary::Zero].iter() {
+            let rep = Cryptor::encrypto(TLWE, &s_key, item);
+            for parties in [vec![0, 1, 2], vec![4, 2, 0], vec![1, 2, 3, 4]].iter() {
+                let shares: Vec<_> = parties
+                    .iter()
+                    .map(|&p| key_shares[p].partial_decrypt(&rep, parties).unwrap())
+                    .collect();
+                let res = combine_binary(&rep, &shares, t).unwrap();
+                assert_eq!(res, item, "parties={:?}", parties);
+            }
+        }
+    }
+
+    #[test]
+    fn threshold_errors() {
+        const N: usize = 16;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let key_shares = split_key(&s_key, 2, 3);
+        let rep = Cryptor::encrypto(TLWE, &s_key, Binary::One);
+
+        assert_eq!(
+            key_shares[0].partial_decrypt(&rep, &[0]).unwrap_err(),
+            ThresholdError::NotEnoughShares {
+                threshold: 2,
+                found: 1
+            }
+        );
+        let s0 = key_shares[0].partial_decrypt(&rep, &[0, 1]).unwrap();
+        let s1 = key_shares[1].partial_decrypt(&rep, &[0, 1]).unwrap();
+        let s1_ = key_shares[1].partial_decrypt(&rep, &[1, 2]).unwrap();
+        assert_eq!(
+            combine(&rep, &[s0.clone(), s0.clone()], 2).unwrap_err(),
+            ThresholdError::DuplicateParty(0)
+        );
+        assert_eq!(
+            combine(&rep, &[s0.clone(), s1_], 2).unwrap_err(),
+            ThresholdError::PartiesMismatch
+        );
+        assert_eq!(combine_binary(&rep, &[s0, s1], 2).unwrap(), Binary::One);
+
+        // 他の参加者から来る参加者集合が不正でもpanicしない
+        assert_eq!(
+            key_shares[0].partial_decrypt(&rep, &[0, 3]).unwrap_err(),
+            ThresholdError::UnknownParty(3)
+        );
+        assert_eq!(
+            key_shares[0].partial_decrypt(&rep, &[0, 1000]).unwrap_err(),
+            ThresholdError::UnknownParty(1000)
+        );
+        assert_eq!(
+            key_shares[0].partial_decrypt(&rep, &[1, 2]).unwrap_err(),
+            ThresholdError::NotInParties(0)
+        );
+        let forged = DecryptionShare {
+            party: 64,
+            parties: 0b11,
+            value: Torus32::zero(),
+        };
+        assert_eq!(
+            combine(&rep, &[forged], 1).unwrap_err(),
+            ThresholdError::UnknownParty(64)
+        );
+    }
+
+    #[test]
+    fn threshold_shares_sum_to_key() {
+        const N: usize = 16;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let key_shares = split_key(&s_key, 2, 4);
+        // t=2なのでA={j}で、r_{j}はj以外の全員が持つ
+        let mut sum = [Torus32::zero(); N];
+        for j in 0..4 {
+            let (_, r) = key_shares[(j + 1) % 4]
+                .shares
+                .iter()
+                .find(|(a, _)| *a == 1 << j)
+                .unwrap();
+            sum.iter_mut().zip(r.iter()).for_each(|(x, &y)| *x += y);
+        }
+        for (x, &s) in sum.iter().zip(s_key.iter()) {
+            assert_eq!(x.inner(), s as u32);
+        }
+        assert!(
+            key_shares[0].shares.iter().all(|(a, _)| a & 1 == 0),
+            "自分を含むAのシェアは持たない"
+        );
+    }
+}
@@ -1,8 +1,10 @@
-use super::digest::{Crypto, Cryptor, Encryptable, Encrypted};
+use super::digest::{Crypto, CryptoWithRng, Cryptor, Encryptable, Encrypted};
 use num::Zero;
+use rand::{CryptoRng, Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
 use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
 use utils::{
-    math::{Binary, ModDistribution, Random, Torus32},
+    math::{secure_rng, Binary, ModDistribution, Random, Torus32},
     mem, torus,
     traits::AsLogic,
 };
@@ -40,6 +42,10 @@ impl<const N: usize> TLWERep<N> {
         TLWERep { cipher, p_key }
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(n = N, m = M))
+    )]
     pub fn identity_key_switch<const M: usize>(self, ks: &KeySwitchingKey<N, M>) -> TLWERep<M> {
         const BASEBIT: u32 = TLWEHelper::IKS_BASEBIT;
         const IKS_L: usize = TLWEHelper::IKS_L;
@@ -84,7 +90,22 @@ impl<const N: usize> AsLogic for TLWERep<N> {
     fn logic_false() -> Self {
         Self::trivial(TLWEHelper::binary2torus(Binary::Zero))
     }
+    /// logic_true(),logic_false()で作った自明な暗号文だけ値が分かる
+    fn as_const(&self) -> Option<bool> {
+        if !self.p_key.iter().all(Zero::is_zero) {
+            None
+        } else if self.cipher == TLWEHelper::binary2torus(Binary::One) {
+            Some(true)
+        } else if self.cipher == TLWEHelper::binary2torus(Binary::Zero) {
+            Some(false)
+        } else {
+            None
+        }
+    }
 }
+/// 平文の和を暗号化したものになる(bootstrapなし)
+/// # Noise
+/// 誤差の分散は両辺の和になる。fresh同士なら標準偏差はsqrt(2)*ALPHA
 impl<const N: usize> Add for TLWERep<N> {
     type Output = Self;
     fn add(self, rhs: Self) -> Self::Output {
@@ -111,6 +132,9 @@ impl<const N: usize> AddAssign<Self> for TLWERep<N> {
         self.add_assign(&rhs);
     }
 }
+/// 平文の差を暗号化したものになる(bootstrapなし)
+/// # Noise
+/// 誤差の分散は両辺の和になる。fresh同士なら標準偏差はsqrt(2)*ALPHA
 impl<const N: usize> Sub for TLWERep<N> {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self::Output {
@@ -137,6 +161,9 @@ impl<const N: usize> SubAssign<Self> for TLWERep<N> {
         self.sub_assign(&rhs);
     }
 }
+/// 平文の符号を反転したものになる(bootstrapなし)
+/// # Noise
+/// 誤差の符号が反転するだけで、大きさは変わらない
 impl<const N: usize> Neg for TLWERep<N> {
     type Output = Self;
     fn neg(self) -> Self::Output {
@@ -146,6 +173,10 @@ impl<const N: usize> Neg for TLWERep<N> {
         TLWERep::new(b, a)
     }
 }
+/// 平文を整数倍したものになる(bootstrapなし)
+/// # Noise
+/// 誤差の標準偏差も|rhs|倍される。
+/// 例えば`hom_xor`では2倍してからbootstrapしている
 impl<Int: Copy, const N: usize> Mul<Int> for TLWERep<N>
 where
     Torus32: Mul<Int, Output = Torus32>,
@@ -178,22 +209,109 @@ impl TLWEHelper {
     pub const IKS_L: usize = 8;
     pub const IKS_BASEBIT: u32 = 2;
     pub const IKS_T: usize = 2_usize.pow(Self::IKS_BASEBIT);
+    /// 1 -> 1/8, 0 -> -1/8。値による分岐をしない
     pub fn binary2torus(bin: Binary) -> Torus32 {
-        torus!(match bin {
-            Binary::One => 1.0 / 8.0,
-            Binary::Zero => -1.0 / 8.0,
-        })
+        Torus32::from_bits(0x2000_0000 | (bin as u32 ^ 1).wrapping_mul(0xC000_0000))
     }
+    /// [0,1/2) -> 1, [1/2,1) -> 0。最上位bitだけを見るので分岐しない
     pub fn torus2binary(torus: Torus32) -> Binary {
-        let f: f32 = torus.into();
-        if f < 0.5 {
-            Binary::One
-        } else {
-            Binary::Zero
-        }
+        Binary::from_bit(!(torus.inner() >> 31))
+    }
+    /// <a, s>。秘密鍵の値で分岐しないように、0,1を掛けて足す
+    pub fn dot<const N: usize>(a: &[Torus32; N], s_key: &[Binary; N]) -> Torus32 {
+        a.iter()
+            .zip(s_key.iter())
+            .fold(Torus32::zero(), |s, (&x, &b)| s + x * b)
     }
 }
 impl<const N: usize> TLWE<N> {}
+
+/**
+Z_pの値をトーラス上に配置する。
+m in Z_p を m/(p*2^padding) に置く。paddingの分だけ上位ビットを空けておくことで、
+線形演算やprogrammable bootstrappingで符号が反転する領域(negacyclic)を避けられる。
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZpEncoding {
+    p: u32,
+    padding: u32,
+}
+impl ZpEncoding {
+    /// # Panic
+    /// - p < 2
+    /// - p*2^padding > 2^31
+    pub fn new(p: u32, padding: u32) -> Self {
+        assert!(p >= 2, "p must be greater than 1, p={}", p);
+        assert!(
+            (p as u64) << padding <= 1 << 31,
+            "p*2^padding is too large, p={},padding={}",
+            p,
+            padding
+        );
+        ZpEncoding { p, padding }
+    }
+    #[inline]
+    pub fn p(&self) -> u32 {
+        self.p
+    }
+    #[inline]
+    pub fn padding(&self) -> u32 {
+        self.padding
+    }
+    /// トーラスを分割する数 = p*2^padding
+    #[inline]
+    fn slots(&self) -> u64 {
+        (self.p as u64) << self.padding
+    }
+    /// m mod p を m/(p*2^padding) に置く
+    pub fn encode(&self, m: u32) -> Torus32 {
+        let m = (m % self.p) as u64;
+        Torus32::from_bits(((m << u32::BITS) / self.slots()) as u32)
+    }
+    /// round(t*p*2^padding) mod p
+    pub fn decode(&self, t: Torus32) -> u32 {
+        const HALF: u64 = 1 << (u32::BITS - 1);
+        let slot = (t.inner() as u64 * self.slots() + HALF) >> u32::BITS;
+        (slot % self.p as u64) as u32
+    }
+}
+
+/// Z_pの値をZpEncodingでトーラスに置いてからTLWEで暗号化する
+pub struct TLWEZp<const N: usize> {
+    encoding: ZpEncoding,
+}
+impl<const N: usize> TLWEZp<N> {
+    pub fn new(encoding: ZpEncoding) -> Self {
+        TLWEZp { encoding }
+    }
+    pub fn encoding(&self) -> &ZpEncoding {
+        &self.encoding
+    }
+}
+impl<const N: usize> Encryptable<TLWEZp<N>> for u32 {}
+impl<const N: usize> Crypto<u32> for TLWEZp<N> {
+    type SecretKey = [Binary; N];
+    type Representation = TLWERep<N>;
+
+    fn encrypto(&self, s_key: &Self::SecretKey, item: u32) -> Self::Representation {
+        Cryptor::encrypto(TLWE, s_key, self.encoding.encode(item))
+    }
+
+    fn decrypto(&self, s_key: &Self::SecretKey, rep: Self::Representation) -> u32 {
+        let t: Torus32 = Cryptor::decrypto(TLWE, s_key, rep);
+        self.encoding.decode(t)
+    }
+}
+impl<const N: usize> CryptoWithRng<u32> for TLWEZp<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: u32,
+        rng: &mut R,
+    ) -> Self::Representation {
+        Cryptor::encrypto_with_rng(TLWE, s_key, self.encoding.encode(item), rng)
+    }
+}
 impl<const N: usize> Crypto<Binary> for TLWE<N> {
     type SecretKey = [Binary; N];
     type Representation = TLWERep<N>;
@@ -206,54 +324,68 @@ impl<const N: usize> Crypto<Binary> for TLWE<N> {
         TLWEHelper::torus2binary(self.decrypto(s_key, rep))
     }
 }
+impl<const N: usize> CryptoWithRng<Binary> for TLWE<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        key: &Self::SecretKey,
+        item: Binary,
+        rng: &mut R,
+    ) -> Self::Representation {
+        self.encrypto_with_rng(key, TLWEHelper::binary2torus(item), rng)
+    }
+}
 impl<const N: usize> Crypto<Torus32> for TLWE<N> {
     type SecretKey = [Binary; N];
     type Representation = TLWERep<N>;
 
     fn encrypto(&self, key: &Self::SecretKey, item: Torus32) -> Self::Representation {
-        let mut unif = ModDistribution::uniform();
-        let mut norm = ModDistribution::gaussian(TLWEHelper::ALPHA);
-
-        let a: [Torus32; N] = unif.gen_n();
-        let m = item;
-        let e = norm.gen();
-        let b = a
-            .iter()
-            .zip(key.iter())
-            .filter(|(_, &b)| b == Binary::One)
-            .fold(Torus32::zero(), |s, (&x, _)| s + x)
-            + e
-            + m;
-        TLWERep::new(b, a)
+        self.encrypto_with_rng(key, item, &mut secure_rng())
     }
 
     fn decrypto(&self, s_key: &Self::SecretKey, rep: Self::Representation) -> Torus32 {
         let (cipher, p_key) = rep.get_and_drop();
-        let a_cross_s = p_key
-            .iter()
-            .zip(s_key.iter())
-            .filter(|(_, &b)| b == Binary::One)
-            .fold(Torus32::zero(), |s, (&x, _)| s + x);
+        let a_cross_s = TLWEHelper::dot(&p_key, s_key);
         let m_with_e = cipher - a_cross_s;
 
         m_with_e
     }
 }
+impl<const N: usize> CryptoWithRng<Torus32> for TLWE<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        key: &Self::SecretKey,
+        item: Torus32,
+        rng: &mut R,
+    ) -> Self::Representation {
+        let a: [Torus32; N] = ModDistribution::uniform_with(&mut *rng).gen_n();
+        let e = ModDistribution::gaussian_with(TLWEHelper::ALPHA, &mut *rng).gen();
+        let b = TLWEHelper::dot(&a, key) + e + item;
+        TLWERep::new(b, a)
+    }
+}
 
 pub struct KeySwitchingKey<const N: usize, const M: usize>(
     Vec<[[TLWERep<M>; TLWEHelper::IKS_T]; TLWEHelper::IKS_L]>,
 );
 impl<const N: usize, const M: usize> KeySwitchingKey<N, M> {
     pub fn new(pre_s_key: [Binary; N], next_s_key: &[Binary; M]) -> Self {
-        const BASEBIT: i32 = TLWEHelper::IKS_BASEBIT as i32;
+        Self::new_with_rng(pre_s_key, next_s_key, &mut secure_rng())
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "info", skip_all, fields(n = N, m = M))
+    )]
+    pub fn new_with_rng<R: Rng + CryptoRng>(
+        pre_s_key: [Binary; N],
+        next_s_key: &[Binary; M],
+        rng: &mut R,
+    ) -> Self {
         const T: usize = TLWEHelper::IKS_T;
         const L: usize = TLWEHelper::IKS_L;
 
-        let culc_tlwe = |s_i: Binary, l: u32, t: u32| {
-            let s_i: f32 = s_i.into();
-            // t*s_i/2^{basebit * l}
-            let item: Torus32 = torus!(s_i * 0.5_f32.powi(BASEBIT * l as i32) * t as f32);
-            let tlwe = Cryptor::encrypto(TLWE, next_s_key, item);
+        let mut culc_tlwe = |s_i: Binary, l: u32, t: u32| {
+            let item = Self::item(s_i, l, t);
+            let tlwe = Cryptor::encrypto_with_rng(TLWE, next_s_key, item, rng);
             tlwe
         };
 
@@ -275,6 +407,26 @@ impl<const N: usize, const M: usize> KeySwitchingKey<N, M> {
         }
         KeySwitchingKey(ks)
     }
+    /// KS\[i\]\[l-1\]\[t-1\]の平文 t\*s_i/2^{basebit\*l}
+    fn item(s_i: Binary, l: u32, t: u32) -> Torus32 {
+        const BASEBIT: i32 = TLWEHelper::IKS_BASEBIT as i32;
+        torus!(0.5_f32.powi(BASEBIT * l as i32) * t as f32) * s_i
+    }
+    /// 全てtrivialな0の1行。Vecに入れてから中身を書き込むときに使う
+    pub fn zero_row() -> [[TLWERep<M>; TLWEHelper::IKS_T]; TLWEHelper::IKS_L] {
+        mem::array_init(|_| mem::array_init(|_| TLWERep::zero()))
+    }
+    /// KS\[i\]\[l\]\[t-1\] を並べたものから作る
+    pub fn from_vec(ks: Vec<[[TLWERep<M>; TLWEHelper::IKS_T]; TLWEHelper::IKS_L]>) -> Self {
+        assert_eq!(ks.len(), N, "KeySwitchingKey needs N={} rows", N);
+        KeySwitchingKey(ks)
+    }
+    #[inline]
+    pub fn iter(
+        &self,
+    ) -> std::slice::Iter<'_, [[TLWERep<M>; TLWEHelper::IKS_T]; TLWEHelper::IKS_L]> {
+        self.0.iter()
+    }
     /// 引数についての境界チェックあり
     /// # Return
     /// get(i,l,t) = KS\[i\]\[l\]\[t-1\] = TLWE::encrypto(t\*s_i/(2^{bit\*(l+1)}))
@@ -292,6 +444,134 @@ impl<const N: usize, const M: usize> KeySwitchingKey<N, M> {
     }
 }
 
+/// 同じ長さの秘密鍵の間で暗号文を付け替える鍵
+///
+/// `RotationKey::new(old_s_key, &new_s_key)`で作る。鍵を更新するときに、
+/// 保存済みの暗号文を復号せずに新しい鍵の暗号文にできる。
+/// 評価鍵(TFHE)は新しい鍵で作り直すこと。
+pub type RotationKey<const N: usize> = KeySwitchingKey<N, N>;
+impl<const N: usize, const M: usize> KeySwitchingKey<N, M> {
+    /// 復号せずに次の鍵の暗号文にする
+    /// # Noise
+    /// 1回ごとにkey switchingの誤差(分散はおよそN\*IKS_L\*ALPHA^2)が加わる。
+    /// 何度も繰り返す場合は間でbootstrapして誤差を戻すこと
+    #[inline]
+    pub fn rekey(&self, rep: TLWERep<N>) -> TLWERep<M> {
+        rep.identity_key_switch(self)
+    }
+}
+impl<const N: usize> RotationKey<N> {
+    /// 暗号文の集まりを置き換えながら付け替える
+    pub fn rekey_all<'a, I>(&self, store: I)
+    where
+        I: IntoIterator<Item = &'a mut TLWERep<N>>,
+    {
+        for rep in store {
+            let old = std::mem::replace(rep, TLWERep::trivial(Torus32::zero()));
+            *rep = self.rekey(old);
+        }
+    }
+}
+
+/// KeySwitchingKeyの圧縮表現
+///
+/// 各TLWEのマスクaはseedから作る乱数列で決まるので、bだけを持てばよい。
+/// 大きさはおよそ1/(M+1)になる。計算に使うときは`decompress`で展開する。
+pub struct CompressedKeySwitchingKey<const N: usize, const M: usize> {
+    seed: [u8; 32],
+    bodies: Vec<[[Torus32; TLWEHelper::IKS_T]; TLWEHelper::IKS_L]>,
+}
+impl<const N: usize, const M: usize> CompressedKeySwitchingKey<N, M> {
+    pub fn new(pre_s_key: [Binary; N], next_s_key: &[Binary; M]) -> Self {
+        Self::new_with_rng(pre_s_key, next_s_key, &mut secure_rng())
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "info", skip_all, fields(n = N, m = M))
+    )]
+    pub fn new_with_rng<R: Rng + CryptoRng>(
+        pre_s_key: [Binary; N],
+        next_s_key: &[Binary; M],
+        rng: &mut R,
+    ) -> Self {
+        let seed: [u8; 32] = rng.gen();
+        let mut masks = MaskStream::new(seed);
+        let mut norm = ModDistribution::gaussian_with(TLWEHelper::ALPHA, rng);
+
+        let bodies = pre_s_key
+            .iter()
+            .map(|&s_i| {
+                mem::array_create_enumerate(|l| {
+                    mem::array_create_enumerate(|t| {
+                        // KeySwitchingKey::newと同じ値をマスクだけ決めて暗号化する
+                        let a: [Torus32; M] = masks.next_mask();
+                        let m = KeySwitchingKey::<N, M>::item(s_i, 1 + l as u32, 1 + t as u32);
+                        TLWEHelper::dot(&a, next_s_key) + norm.gen() + m
+                    })
+                })
+            })
+            .collect();
+        CompressedKeySwitchingKey { seed, bodies }
+    }
+    /// seedとKS\[i\]\[l\]\[t-1\]のbを並べたものから作る
+    pub fn from_parts(
+        seed: [u8; 32],
+        bodies: Vec<[[Torus32; TLWEHelper::IKS_T]; TLWEHelper::IKS_L]>,
+    ) -> Self {
+        assert_eq!(bodies.len(), N, "KeySwitchingKey needs N={} rows", N);
+        CompressedKeySwitchingKey { seed, bodies }
+    }
+    pub fn seed(&self) -> &[u8; 32] {
+        &self.seed
+    }
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, [[Torus32; TLWEHelper::IKS_T]; TLWEHelper::IKS_L]> {
+        self.bodies.iter()
+    }
+    /// マスクを作り直してKeySwitchingKeyに戻す。
+    /// 1行(IKS_L*IKS_T個のTLWE)が大きいので、Vecに入れてからマスクを書き込む
+    pub fn decompress(&self) -> KeySwitchingKey<N, M> {
+        let mut masks = MaskStream::new(self.seed);
+        let mut ks = Vec::with_capacity(N);
+        for b_i in self.bodies.iter() {
+            ks.push(KeySwitchingKey::<N, M>::zero_row());
+            let ks_i = ks.last_mut().unwrap();
+            for (ks_i_l, b_i_l) in ks_i.iter_mut().zip(b_i.iter()) {
+                for (ks_i_l_t, &b) in ks_i_l.iter_mut().zip(b_i_l.iter()) {
+                    let (b_, a_) = ks_i_l_t.get_mut_ref();
+                    *b_ = b;
+                    masks.fill_mask(a_);
+                }
+            }
+        }
+        KeySwitchingKey::from_vec(ks)
+    }
+}
+impl<const N: usize, const M: usize> From<&CompressedKeySwitchingKey<N, M>>
+    for KeySwitchingKey<N, M>
+{
+    fn from(cksk: &CompressedKeySwitchingKey<N, M>) -> Self {
+        cksk.decompress()
+    }
+}
+
+/// seedからマスクを順に作る。圧縮と展開で同じ順に呼ぶこと
+struct MaskStream(ChaCha20Rng);
+impl MaskStream {
+    fn new(seed: [u8; 32]) -> Self {
+        MaskStream(ChaCha20Rng::from_seed(seed))
+    }
+    fn next_mask<const M: usize>(&mut self) -> [Torus32; M] {
+        let mut a = [Torus32::zero(); M];
+        self.fill_mask(&mut a);
+        a
+    }
+    fn fill_mask(&mut self, a: &mut [Torus32]) {
+        a.iter_mut()
+            .for_each(|a_j| *a_j = Torus32::from_bits(self.0.next_u32()));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{digest::Cryptor, trlwe::TRLWEHelper};
@@ -325,6 +605,86 @@ mod tests {
         assert!(res.p_key[1].is_in(torus!(0.0), 1e-9));
     }
 
+    #[test]
+    fn tlwerep_linear_op() {
+        const N: usize = TLWEHelper::N;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let strategy = || TLWEZp::<N>::new(ZpEncoding::new(8, 1));
+        let enc = |m: u32| Cryptor::encrypto(strategy(), &s_key, m);
+        let dec = |rep: TLWERep<N>| -> u32 { Cryptor::decrypto(strategy(), &s_key, rep) };
+
+        assert_eq!(dec(enc(3) + enc(4)), 7, "3+4");
+        assert_eq!(dec(enc(3) - enc(5)), 6, "3-5 = -2 mod 8");
+        assert_eq!(dec(-enc(3)), 5, "-3 mod 8");
+        assert_eq!(dec(enc(3) * 2), 6, "3*2");
+        assert_eq!(dec(enc(3) * -3), 7, "3*(-3) = -9 mod 8");
+        assert_eq!(
+            dec(enc(1) + TLWERep::trivial(ZpEncoding::new(8, 1).encode(2))),
+            3,
+            "平文との和"
+        );
+    }
+
+    #[test]
+    fn tlwe_branchless_helpers() {
+        // ±1/8 をちょうど表す
+        let one = TLWEHelper::binary2torus(Binary::One);
+        assert_eq!(one, Torus32::from_bits(1 << 29));
+        assert_eq!(TLWEHelper::binary2torus(Binary::Zero), -one);
+        for &(t, b) in [
+            (0_u32, Binary::One),
+            (0x7fff_ffff, Binary::One),
+            (0x8000_0000, Binary::Zero),
+            (0xffff_ffff, Binary::Zero),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                TLWEHelper::torus2binary(Torus32::from_bits(t)),
+                b,
+                "{:x}",
+                t
+            );
+        }
+
+        const N: usize = 64;
+        let a: [Torus32; N] = ModDistribution::uniform().gen_n();
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let expect = a
+            .iter()
+            .zip(s_key.iter())
+            .filter(|(_, &b)| b == Binary::One)
+            .fold(Torus32::zero(), |s, (&x, _)| s + x);
+        assert_eq!(TLWEHelper::dot(&a, &s_key), expect);
+    }
+
+    #[test]
+    fn tlwe_encrypt_with_rng() {
+        const N: usize = TLWEHelper::N;
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let s_key: [Binary; N] = BinaryDistribution::uniform_with(&mut rng).gen_n();
+        let rep = |seed: u64| {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            Cryptor::encrypto_with_rng(TLWE, &s_key, Binary::One, &mut rng)
+        };
+        assert_eq!(rep(1).get_ref(), rep(1).get_ref(), "同じseedなら同じ暗号文");
+        assert_ne!(rep(1).get_ref(), rep(2).get_ref());
+        let res: Binary = Cryptor::decrypto(TLWE, &s_key, rep(1));
+        assert_eq!(res, Binary::One);
+    }
+
+    #[test]
+    fn tlwe_as_const() {
+        const N: usize = TLWEHelper::N;
+        assert_eq!(TLWERep::<N>::logic_true().as_const(), Some(true));
+        assert_eq!(TLWERep::<N>::logic_false().as_const(), Some(false));
+        // 鍵で暗号化したものは値が分からない
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let rep = Cryptor::encrypto(TLWE, &s_key, Binary::One);
+        assert_eq!(rep.as_const(), None);
+        assert_eq!(TLWERep::<N>::trivial(torus!(0.5)).as_const(), None);
+    }
+
     #[test]
     fn tlwe_test() {
         const N: usize = TLWEHelper::N;
@@ -343,6 +703,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tlwe_zp_encoding() {
+        for p in 2..=8 {
+            for padding in 0..=2 {
+                let enc = ZpEncoding::new(p, padding);
+                for m in 0..p {
+                    assert_eq!(enc.decode(enc.encode(m)), m, "p={},padding={}", p, padding);
+                }
+                assert_eq!(enc.encode(p + 1), enc.encode(1), "mod pで同じ値");
+            }
+        }
+
+        let enc = ZpEncoding::new(4, 1);
+        assert_eq!(enc.encode(1), Torus32::from_bits(1 << 29), "1/8");
+        assert_eq!(enc.decode(enc.encode(1) + enc.encode(2)), 3, "線形性");
+    }
+
+    #[test]
+    fn tlwe_zp_crypto() {
+        const N: usize = TLWEHelper::N;
+        let mut b_uniform = BinaryDistribution::uniform();
+        let s_key: [Binary; N] = b_uniform.gen_n();
+
+        for p in 2..=8 {
+            let strategy = || TLWEZp::<N>::new(ZpEncoding::new(p, 1));
+            for m in 0..p {
+                let rep = Cryptor::encrypto(strategy(), &s_key, m);
+                let res: u32 = Cryptor::decrypto(strategy(), &s_key, rep);
+                assert_eq!(res, m, "p={}", p);
+            }
+        }
+    }
+
     #[test]
     fn tlwe_identity_key_switching() {
         {
@@ -394,4 +787,72 @@ mod tests {
             test(Binary::Zero);
         }
     }
+
+    #[test]
+    fn tlwe_compressed_key_switching() {
+        const N: usize = 256;
+        const M: usize = 60;
+        let mut b_uniform = BinaryDistribution::uniform();
+        let s_key_tlwelv1 = b_uniform.gen_n::<N>();
+        let s_key_tlwelv0 = b_uniform.gen_n::<M>();
+
+        let cksk = CompressedKeySwitchingKey::new(s_key_tlwelv1, &s_key_tlwelv0);
+        let ks = cksk.decompress();
+        for (b_i, ks_i) in cksk.iter().zip(ks.iter()) {
+            for (b_i_l, ks_i_l) in b_i.iter().zip(ks_i.iter()) {
+                for (b, tlwe) in b_i_l.iter().zip(ks_i_l.iter()) {
+                    assert_eq!(b, tlwe.cipher(), "bはそのまま");
+                }
+            }
+        }
+        let again = CompressedKeySwitchingKey::<N, M>::from_parts(
+            *cksk.seed(),
+            cksk.iter().cloned().collect(),
+        )
+        .decompress();
+        assert!(
+            again.iter().zip(ks.iter()).all(|(x, y)| x
+                .iter()
+                .flatten()
+                .zip(y.iter().flatten())
+                .all(|(x, y)| x.p_key() == y.p_key())),
+            "同じseedからは同じマスクが出る"
+        );
+
+        for &item in [Binary::One, Binary::Zero].iter() {
+            let rep_tlwelv1 = Cryptor::encrypto(TLWE, &s_key_tlwelv1, item);
+            let rep_tlwelv0 = rep_tlwelv1.identity_key_switch(&ks);
+            let result: Binary = Cryptor::decrypto(TLWE, &s_key_tlwelv0, rep_tlwelv0);
+            assert_eq!(result, item, "展開した鍵でkey switchできる, item={}", item);
+        }
+    }
+
+    #[test]
+    fn tlwe_key_rotation() {
+        const N: usize = TLWEHelper::N;
+        let mut b_uniform = BinaryDistribution::uniform();
+        let s_key_old = b_uniform.gen_n::<N>();
+        let s_key_new = b_uniform.gen_n::<N>();
+        let s_key_newer = b_uniform.gen_n::<N>();
+
+        let items: [Binary; 8] = b_uniform.gen_n();
+        let mut store: Vec<TLWERep<N>> = items
+            .iter()
+            .map(|&item| Cryptor::encrypto(TLWE, &s_key_old, item))
+            .collect();
+
+        let rk = RotationKey::new(s_key_old, &s_key_new);
+        rk.rekey_all(store.iter_mut());
+        for (rep, &item) in store.iter().zip(items.iter()) {
+            let res: Binary = Cryptor::decrypto(TLWE, &s_key_new, rep.clone());
+            assert_eq!(res, item, "新しい鍵で復号できる");
+        }
+
+        let rk = RotationKey::new(s_key_new, &s_key_newer);
+        rk.rekey_all(&mut store);
+        for (rep, &item) in store.iter().zip(items.iter()) {
+            let res: Binary = Cryptor::decrypto(TLWE, &s_key_newer, rep.clone());
+            assert_eq!(res, item, "2回付け替えても復号できる");
+        }
+    }
 }
@@ -1,10 +1,13 @@
-use super::digest::{Crypto, Cryptor, Encryptable, Encrypted};
+use super::digest::{Crypto, CryptoWithRng, Cryptor, Encryptable, Encrypted};
 use super::tlwe::TLWE;
 use super::trlwe::TRLWE;
-use crate::trlwe::TRLWERep;
+use crate::trlwe::{TRLWERep, TRLWERepF};
 use num::{ToPrimitive, Zero};
-use std::mem::MaybeUninit;
-use utils::math::{Binary, Cross, Polynomial, Torus32};
+use rand::{CryptoRng, Rng};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use utils::math::{secure_rng, Binary, Cross, Polynomial, Torus32, FFT_MAP};
 use utils::spqlios::FrrSeries;
 use utils::{mem, torus};
 
@@ -80,6 +83,12 @@ impl<const N: usize> From<TRGSWRep<N>> for TRGSWRepF<N> {
     }
 }
 impl<const N: usize> TRGSWRepF<N> {
+    pub fn new(
+        cipher_f: [FrrSeries<N>; TRGSWHelper::L * 2],
+        pkey_f: [FrrSeries<N>; TRGSWHelper::L * 2],
+    ) -> Self {
+        TRGSWRepF { cipher_f, pkey_f }
+    }
     #[allow(dead_code)]
     fn cipher_f(&self) -> &[FrrSeries<N>; 2 * TRGSWHelper::L] {
         &self.cipher_f
@@ -88,7 +97,7 @@ impl<const N: usize> TRGSWRepF<N> {
     fn p_key(&self) -> &[FrrSeries<N>; 2 * TRGSWHelper::L] {
         &self.pkey_f
     }
-    fn get_ref(
+    pub fn get_ref(
         &self,
     ) -> (
         &[FrrSeries<N>; 2 * TRGSWHelper::L],
@@ -96,6 +105,21 @@ impl<const N: usize> TRGSWRepF<N> {
     ) {
         (&self.cipher_f, &self.pkey_f)
     }
+    /// 全て0。読み込みなどで、後から中身を書き込むときに使う
+    pub fn zero() -> Self {
+        TRGSWRepF {
+            cipher_f: mem::array_init(|_| FrrSeries::zero()),
+            pkey_f: mem::array_init(|_| FrrSeries::zero()),
+        }
+    }
+    pub fn get_mut_ref(
+        &mut self,
+    ) -> (
+        &mut [FrrSeries<N>; 2 * TRGSWHelper::L],
+        &mut [FrrSeries<N>; 2 * TRGSWHelper::L],
+    ) {
+        (&mut self.cipher_f, &mut self.pkey_f)
+    }
     #[allow(dead_code)]
     fn get_and_drop(
         self,
@@ -115,26 +139,20 @@ impl TRGSWHelper {
     pub const L: usize = 3;
 }
 impl<const N: usize> TRGSW<N> {
-    fn create_zero_encrypted_pols<const M: usize>(
+    fn create_zero_encrypted_pols<const M: usize, R: Rng + CryptoRng>(
         s_key: &<TRGSW<N> as Crypto<Polynomial<i32, N>>>::SecretKey,
+        rng: &mut R,
     ) -> ([Polynomial<Torus32, N>; M], [Polynomial<Torus32, N>; M]) {
-        let mut cipher: [MaybeUninit<Polynomial<Torus32, N>>; M] =
-            unsafe { MaybeUninit::uninit().assume_init() };
-        let mut p_key: [MaybeUninit<Polynomial<Torus32, N>>; M] =
-            unsafe { MaybeUninit::uninit().assume_init() };
+        let mut p_key: [Polynomial<Torus32, N>; M] = mem::array_init(|_| Polynomial::zero());
         // TODO:　並列化
-        for (b_, a_) in cipher.iter_mut().zip(p_key.iter_mut()) {
+        let cipher = mem::array_init(|i| {
             let (b, a) =
-                Cryptor::encrypto(TRLWE, s_key, Polynomial::<Torus32, N>::zero()).get_and_drop();
-            *b_ = MaybeUninit::new(b);
-            *a_ = MaybeUninit::new(a);
-        }
-        // std::mem::transmute have problem
-        // issue:https://github.com/rust-lang/rust/issues/61956
-        (
-            utils::mem::transmute::<_, [Polynomial<Torus32, N>; M]>(cipher),
-            utils::mem::transmute::<_, [Polynomial<Torus32, N>; M]>(p_key),
-        )
+                Cryptor::encrypto_with_rng(TRLWE, s_key, Polynomial::<Torus32, N>::zero(), rng)
+                    .get_and_drop();
+            p_key[i] = a;
+            b
+        });
+        (cipher, p_key)
     }
 }
 
@@ -143,18 +161,7 @@ impl<const N: usize> Crypto<Polynomial<i32, N>> for TRGSW<N> {
     type Representation = TRGSWRep<N>;
 
     fn encrypto(&self, s_key: &Self::SecretKey, item: Polynomial<i32, N>) -> Self::Representation {
-        const L: usize = TRGSWHelper::L;
-        let (mut cipher, mut p_key) = Self::create_zero_encrypted_pols::<{ 2 * L }>(s_key);
-        {
-            const BG_INV: f32 = TRGSWHelper::BG_INV;
-            for i in 0..L {
-                let bg_inv_pow_i = BG_INV.powi(1 + i as i32);
-                let p = item.map(|&x| torus!(x as f32 * bg_inv_pow_i));
-                cipher[i] += &p;
-                p_key[i + L] += &p;
-            }
-        }
-        TRGSWRep::new(cipher, p_key)
+        self.encrypto_with_rng(s_key, item, &mut secure_rng())
     }
 
     fn decrypto(&self, s_key: &Self::SecretKey, rep: Self::Representation) -> Polynomial<i32, N> {
@@ -176,6 +183,27 @@ impl<const N: usize> Crypto<Polynomial<i32, N>> for TRGSW<N> {
         })
     }
 }
+impl<const N: usize> CryptoWithRng<Polynomial<i32, N>> for TRGSW<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: Polynomial<i32, N>,
+        rng: &mut R,
+    ) -> Self::Representation {
+        const L: usize = TRGSWHelper::L;
+        let (mut cipher, mut p_key) = Self::create_zero_encrypted_pols::<{ 2 * L }, R>(s_key, rng);
+        {
+            const BG_INV: f32 = TRGSWHelper::BG_INV;
+            for i in 0..L {
+                let bg_inv_pow_i = BG_INV.powi(1 + i as i32);
+                let p = item.map(|&x| torus!(x as f32 * bg_inv_pow_i));
+                cipher[i] += &p;
+                p_key[i + L] += &p;
+            }
+        }
+        TRGSWRep::new(cipher, p_key)
+    }
+}
 impl<const N: usize> Crypto<Polynomial<u32, N>> for TRGSW<N> {
     type SecretKey = Polynomial<Binary, N>;
     type Representation = TRGSWRep<N>;
@@ -189,6 +217,16 @@ impl<const N: usize> Crypto<Polynomial<u32, N>> for TRGSW<N> {
         res.map(|&x| x as u32)
     }
 }
+impl<const N: usize> CryptoWithRng<Polynomial<u32, N>> for TRGSW<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: Polynomial<u32, N>,
+        rng: &mut R,
+    ) -> Self::Representation {
+        self.encrypto_with_rng(s_key, item.map(|&x| x as i32), rng)
+    }
+}
 impl<const N: usize> Crypto<Polynomial<Binary, N>> for TRGSW<N> {
     type SecretKey = Polynomial<Binary, N>;
     type Representation = TRGSWRep<N>;
@@ -210,22 +248,22 @@ impl<const N: usize> Crypto<Polynomial<Binary, N>> for TRGSW<N> {
         res.map(|&x| Binary::from(x))
     }
 }
+impl<const N: usize> CryptoWithRng<Polynomial<Binary, N>> for TRGSW<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: Polynomial<Binary, N>,
+        rng: &mut R,
+    ) -> Self::Representation {
+        self.encrypto_with_rng(s_key, item.map(|&x| x as i32), rng)
+    }
+}
 impl<const N: usize> Crypto<i32> for TRGSW<N> {
     type SecretKey = Polynomial<Binary, N>;
     type Representation = TRGSWRep<N>;
 
     fn encrypto(&self, s_key: &Self::SecretKey, item: i32) -> Self::Representation {
-        const L: usize = TRGSWHelper::L;
-        let (mut cipher, mut p_key) = Self::create_zero_encrypted_pols::<{ 2 * L }>(s_key);
-        {
-            const BG_INV: f32 = TRGSWHelper::BG_INV;
-            for i in 0..L {
-                let p = torus!(item.to_f32().unwrap() * BG_INV.powi(1 + i as i32));
-                cipher[i].add_constant(p);
-                p_key[i + L].add_constant(p);
-            }
-        }
-        TRGSWRep::new(cipher, p_key)
+        self.encrypto_with_rng(s_key, item, &mut secure_rng())
     }
 
     fn decrypto(&self, s_key: &Self::SecretKey, rep: Self::Representation) -> i32 {
@@ -247,6 +285,26 @@ impl<const N: usize> Crypto<i32> for TRGSW<N> {
         }
     }
 }
+impl<const N: usize> CryptoWithRng<i32> for TRGSW<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: i32,
+        rng: &mut R,
+    ) -> Self::Representation {
+        const L: usize = TRGSWHelper::L;
+        let (mut cipher, mut p_key) = Self::create_zero_encrypted_pols::<{ 2 * L }, R>(s_key, rng);
+        {
+            const BG_INV: f32 = TRGSWHelper::BG_INV;
+            for i in 0..L {
+                let p = torus!(item.to_f32().unwrap() * BG_INV.powi(1 + i as i32));
+                cipher[i].add_constant(p);
+                p_key[i + L].add_constant(p);
+            }
+        }
+        TRGSWRep::new(cipher, p_key)
+    }
+}
 impl<const N: usize> Crypto<Binary> for TRGSW<N> {
     type SecretKey = Polynomial<Binary, N>;
     type Representation = TRGSWRep<N>;
@@ -260,10 +318,26 @@ impl<const N: usize> Crypto<Binary> for TRGSW<N> {
         Binary::from(res)
     }
 }
+impl<const N: usize> CryptoWithRng<Binary> for TRGSW<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: Binary,
+        rng: &mut R,
+    ) -> Self::Representation {
+        self.encrypto_with_rng(s_key, item as i32, rng)
+    }
+}
 
 impl<const N: usize> Cross<TRLWERep<N>> for TRGSWRepF<N> {
     type Output = TRLWERep<N>;
     fn cross(&self, rhs: &TRLWERep<N>) -> Self::Output {
+        TRLWERep::from(self.cross_f(rhs))
+    }
+}
+impl<const N: usize> TRGSWRepF<N> {
+    /// 外積の結果を逆変換せずに返す
+    pub fn cross_f(&self, rhs: &TRLWERep<N>) -> TRLWERepF<N> {
         const L: usize = TRGSWHelper::L;
         const BGBIT: u32 = TRGSWHelper::BGBIT;
         const DECOMP_MASK: u32 = Torus32::make_decomp_mask(L as u32, BGBIT);
@@ -271,20 +345,8 @@ impl<const N: usize> Cross<TRLWERep<N>> for TRGSWRepF<N> {
         let a_decomp = rhs.p_key().decomposition_i32_::<L>(BGBIT, DECOMP_MASK);
         let (b_trgsw_f, a_trgsw_f) = self.get_ref();
 
-        let b_decomp_f: [FrrSeries<N>; L] = unsafe {
-            mem::array_create(
-                b_decomp
-                    .iter()
-                    .map(|b_decomp_i| FrrSeries::<N>::from(b_decomp_i)),
-            )
-        };
-        let a_decomp_f: [FrrSeries<N>; L] = unsafe {
-            mem::array_create(
-                a_decomp
-                    .iter()
-                    .map(|a_decomp_i| FrrSeries::<N>::from(a_decomp_i)),
-            )
-        };
+        let b_decomp_f: [FrrSeries<N>; L] = mem::array_init(|i| FrrSeries::from(&b_decomp[i]));
+        let a_decomp_f: [FrrSeries<N>; L] = mem::array_init(|i| FrrSeries::from(&a_decomp[i]));
 
         // (cipher,p_key) = C*(b,a) = (b.decomp[0],..,,a.decomp[0],..)*(b_trgsw,a_trgsw)
         let cipher_f = b_trgsw_f
@@ -298,10 +360,7 @@ impl<const N: usize> Cross<TRLWERep<N>> for TRGSWRepF<N> {
             .map(|(l, r)| l.hadamard(r))
             .fold(FrrSeries::zero(),|s,lr| s + lr);
 
-        let cipher: Polynomial<Torus32, N> = Polynomial::<Torus32, N>::from(cipher_f);
-        let p_key: Polynomial<Torus32, N> = Polynomial::<Torus32, N>::from(p_key_f);
-
-        TRLWERep::new(cipher, p_key)
+        TRLWERepF::new(cipher_f, p_key_f)
     }
 }
 impl<const N: usize> Cross<TRLWERep<N>> for TRGSWRep<N> {
@@ -317,7 +376,102 @@ impl<const N: usize> TRGSWRepF<N> {
     /// let i: Binary;
     /// TRGSW(i).cmux(rep_1,rep_0) = rep_i;
     pub fn cmux(&self, rep_1: TRLWERep<N>, rep_0: TRLWERep<N>) -> TRLWERep<N> {
-        self.cross(&(rep_1 - &rep_0)) + rep_0
+        ExternalProductScratch::with(|scratch| self.cmux_with(rep_1, rep_0, scratch))
+    }
+    /// cmuxと同じ。分解やFFTの途中結果はscratchに書き込む
+    pub fn cmux_with(
+        &self,
+        rep_1: TRLWERep<N>,
+        rep_0: TRLWERep<N>,
+        scratch: &mut ExternalProductScratch<N>,
+    ) -> TRLWERep<N> {
+        let mut res = rep_1 - &rep_0;
+        self.cross_to(&res, scratch);
+        let (cipher, p_key) = res.get_mut_ref();
+        FFT_MAP.with(|m| {
+            let mut m = m.borrow_mut();
+            m.get_fft_proc(N)
+                .fft_torus_to(&scratch.acc_f[0], cipher.coefs_mut());
+            m.get_fft_proc(N)
+                .fft_torus_to(&scratch.acc_f[1], p_key.coefs_mut());
+        });
+        res + &rep_0
+    }
+    /// cross_fの結果をscratch.acc_fに書き込む
+    fn cross_to(&self, rhs: &TRLWERep<N>, scratch: &mut ExternalProductScratch<N>) {
+        const L: usize = TRGSWHelper::L;
+        const BGBIT: u32 = TRGSWHelper::BGBIT;
+        const DECOMP_MASK: u32 = Torus32::make_decomp_mask(L as u32, BGBIT);
+        let ExternalProductScratch {
+            decomp_b,
+            decomp_a,
+            decomp_f,
+            acc_f,
+        } = scratch;
+        rhs.cipher()
+            .decomposition_i32_to::<L>(BGBIT, DECOMP_MASK, decomp_b);
+        rhs.p_key()
+            .decomposition_i32_to::<L>(BGBIT, DECOMP_MASK, decomp_a);
+        FFT_MAP.with(|m| {
+            let mut m = m.borrow_mut();
+            for (d, d_f) in decomp_b
+                .iter()
+                .chain(decomp_a.iter())
+                .zip(decomp_f.iter_mut())
+            {
+                m.get_fft_proc(N).ifft_int_to(d.coefs(), d_f);
+            }
+        });
+
+        // (cipher,p_key) = C*(b,a) = (b.decomp[0],..,,a.decomp[0],..)*(b_trgsw,a_trgsw)
+        let (b_trgsw_f, a_trgsw_f) = self.get_ref();
+        for (acc, trgsw_f) in acc_f.iter_mut().zip([b_trgsw_f, a_trgsw_f]) {
+            acc.coefs_mut().fill(0.0);
+            trgsw_f
+                .iter()
+                .zip(decomp_f.iter())
+                .for_each(|(l, r)| acc.hadamard_add(l, r));
+        }
+    }
+}
+
+/// 外積(cmux)の途中で使う作業領域
+///
+/// 1回の外積でも分解した多項式とそのFFTで数十KBになるので、bootstrapのたびに確保し直さず、
+/// `with`でスレッドごとに1つを使い回す
+pub struct ExternalProductScratch<const N: usize> {
+    decomp_b: [Polynomial<i32, N>; TRGSWHelper::L],
+    decomp_a: [Polynomial<i32, N>; TRGSWHelper::L],
+    decomp_f: [FrrSeries<N>; 2 * TRGSWHelper::L],
+    acc_f: [FrrSeries<N>; 2],
+}
+thread_local! {
+    static SCRATCH_MAP: RefCell<HashMap<usize, Box<dyn Any>>> = Default::default();
+}
+impl<const N: usize> ExternalProductScratch<N> {
+    pub fn new() -> Self {
+        ExternalProductScratch {
+            decomp_b: mem::array_create_enumerate(|_| Polynomial::zero()),
+            decomp_a: mem::array_create_enumerate(|_| Polynomial::zero()),
+            decomp_f: mem::array_create_enumerate(|_| FrrSeries::zero()),
+            acc_f: mem::array_create_enumerate(|_| FrrSeries::zero()),
+        }
+    }
+    /// このスレッドの作業領域を借りてfを呼ぶ。入れ子に呼んだときは新しく作る
+    pub fn with<T, F: FnOnce(&mut Self) -> T>(f: F) -> T {
+        let scratch = SCRATCH_MAP.with(|m| m.borrow_mut().remove(&N));
+        let mut scratch = match scratch.map(|s| s.downcast::<Self>()) {
+            Some(Ok(s)) => s,
+            _ => Box::new(Self::new()),
+        };
+        let res = f(&mut scratch);
+        SCRATCH_MAP.with(|m| m.borrow_mut().insert(N, scratch));
+        res
+    }
+}
+impl<const N: usize> Default for ExternalProductScratch<N> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 impl<const N: usize> TRGSWRep<N> {
@@ -389,6 +543,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn trgsw_cross_fourier() {
+        const N: usize = TRLWEHelper::N;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key = pol!(unif.gen_n::<N>());
+        let pol_0: Polynomial<Binary, N> = pol!(unif.gen_n::<N>());
+        let pol_1: Polynomial<Binary, N> = pol!(unif.gen_n::<N>());
+        let rep_0 = Cryptor::encrypto(TRLWE, &s_key, pol_0.clone());
+        let rep_1 = Cryptor::encrypto(TRLWE, &s_key, pol_1.clone());
+
+        // 1*rep_1 + 0*rep_0 を周波数領域で足し合わせ、最後に1回だけ逆変換する
+        let one = TRGSWRepF::from(Cryptor::encrypto(TRGSW, &s_key, 1));
+        let zero = TRGSWRepF::from(Cryptor::encrypto(TRGSW, &s_key, 0));
+        let mut acc = TRLWERepF::zero();
+        acc += &one.cross_f(&rep_1);
+        acc += &zero.cross_f(&rep_0);
+        let res: Polynomial<Binary, N> = Cryptor::decrypto(TRLWE, &s_key, TRLWERep::from(acc));
+        assert_eq!(res, pol_1);
+
+        // cmux: rep_0 + TRGSW(1)*(rep_1 - rep_0)
+        let acc = TRLWERepF::from(&rep_0) + one.cross_f(&(rep_1 - &rep_0));
+        let res: Polynomial<Binary, N> = Cryptor::decrypto(TRLWE, &s_key, TRLWERep::from(acc));
+        assert_eq!(res, pol_1);
+    }
+
+    #[test]
+    fn trgsw_cmux_scratch() {
+        const N: usize = TRLWEHelper::N;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key = pol!(unif.gen_n::<N>());
+        let rep_0 = Cryptor::encrypto(TRLWE, &s_key, pol!(unif.gen_n::<N>()));
+        let rep_1 = Cryptor::encrypto(TRLWE, &s_key, pol!(unif.gen_n::<N>()));
+
+        let mut scratch = ExternalProductScratch::new();
+        for &item in [0, 1].iter() {
+            let trgsw = TRGSWRepF::from(Cryptor::encrypto(TRGSW, &s_key, item));
+            let expect = TRLWERep::from(trgsw.cross_f(&(rep_1.clone() - &rep_0))) + &rep_0;
+            // 同じ作業領域を続けて使っても結果は変わらない
+            for _ in 0..2 {
+                let res = trgsw.cmux_with(rep_1.clone(), rep_0.clone(), &mut scratch);
+                assert_eq!(res.get_ref(), expect.get_ref());
+            }
+            let res = trgsw.cmux(rep_1.clone(), rep_0.clone());
+            assert_eq!(res.get_ref(), expect.get_ref());
+        }
+    }
+
     #[test]
     fn trgsw_cmux() {
         {
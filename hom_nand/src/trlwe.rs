@@ -1,10 +1,12 @@
-use super::digest::{Crypto, Encryptable, Encrypted};
-use crate::tlwe::TLWERep;
+use super::digest::{Crypto, CryptoWithRng, Cryptor, Encryptable, Encrypted};
+use crate::tlwe::{TLWEHelper, TLWERep};
 use num::Zero;
-use std::ops::{Add, Sub};
+use rand::{CryptoRng, Rng};
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use utils::spqlios::FrrSeries;
 use utils::{
-    math::{Binary, ModDistribution, Polynomial, Random, Torus32},
-    mem, pol, torus,
+    math::{secure_rng, Binary, ModDistribution, Polynomial, Random, Torus32},
+    mem, pol,
 };
 
 pub struct TRLWE<const N: usize>;
@@ -71,32 +73,108 @@ impl<const N: usize> Sub<&TRLWERep<N>> for TRLWERep<N> {
     }
 }
 
+/// TRLWERepをFFTしたもの
+///
+/// 外積(TRGSWRepF::cross_f)の結果をこのまま足し合わせれば、逆変換は最後の1回で済む
+#[derive(Clone)]
+pub struct TRLWERepF<const N: usize> {
+    cipher_f: FrrSeries<N>,
+    p_key_f: FrrSeries<N>,
+}
+impl<const N: usize> TRLWERepF<N> {
+    pub fn new(cipher_f: FrrSeries<N>, p_key_f: FrrSeries<N>) -> Self {
+        TRLWERepF { cipher_f, p_key_f }
+    }
+    pub fn get_ref(&self) -> (&FrrSeries<N>, &FrrSeries<N>) {
+        (&self.cipher_f, &self.p_key_f)
+    }
+}
+impl<const N: usize> From<&TRLWERep<N>> for TRLWERepF<N> {
+    fn from(t: &TRLWERep<N>) -> Self {
+        TRLWERepF::new(FrrSeries::from(t.cipher()), FrrSeries::from(t.p_key()))
+    }
+}
+impl<const N: usize> From<TRLWERep<N>> for TRLWERepF<N> {
+    fn from(t: TRLWERep<N>) -> Self {
+        Self::from(&t)
+    }
+}
+impl<const N: usize> From<&TRLWERepF<N>> for TRLWERep<N> {
+    fn from(t: &TRLWERepF<N>) -> Self {
+        TRLWERep::new(Polynomial::from(&t.cipher_f), Polynomial::from(&t.p_key_f))
+    }
+}
+impl<const N: usize> From<TRLWERepF<N>> for TRLWERep<N> {
+    fn from(t: TRLWERepF<N>) -> Self {
+        Self::from(&t)
+    }
+}
+impl<const N: usize> Zero for TRLWERepF<N> {
+    fn zero() -> Self {
+        TRLWERepF::new(FrrSeries::zero(), FrrSeries::zero())
+    }
+    fn is_zero(&self) -> bool {
+        self.cipher_f.is_zero() && self.p_key_f.is_zero()
+    }
+}
+impl<const N: usize> AddAssign<&Self> for TRLWERepF<N> {
+    fn add_assign(&mut self, rhs: &Self) {
+        self.cipher_f += &rhs.cipher_f;
+        self.p_key_f += &rhs.p_key_f;
+    }
+}
+impl<const N: usize> SubAssign<&Self> for TRLWERepF<N> {
+    fn sub_assign(&mut self, rhs: &Self) {
+        self.cipher_f -= &rhs.cipher_f;
+        self.p_key_f -= &rhs.p_key_f;
+    }
+}
+impl<const N: usize> Add for TRLWERepF<N> {
+    type Output = Self;
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += &rhs;
+        self
+    }
+}
+impl<const N: usize> Sub for TRLWERepF<N> {
+    type Output = Self;
+    fn sub(mut self, rhs: Self) -> Self::Output {
+        self -= &rhs;
+        self
+    }
+}
+
 pub struct TRLWEHelper;
 impl TRLWEHelper {
     pub const N: usize = 2_usize.pow(10);
     pub const ALPHA: f32 = 1.0 / (2_u32.pow(25) as f32); // 2^{-25}
+    pub const AUT_L: usize = 7;
+    pub const AUT_BGBIT: u32 = 4;
     pub fn binary_pol2torus_pol<const M: usize>(
         pol: Polynomial<Binary, M>,
     ) -> Polynomial<Torus32, M> {
-        let l = mem::array_create_enumerate(|i| {
-            torus!(match pol.coef_(i) {
-                Binary::One => 1.0 / 8.0,
-                Binary::Zero => -1.0 / 8.0,
-            })
-        });
+        let l = mem::array_create_enumerate(|i| TLWEHelper::binary2torus(pol.coef_(i)));
         pol!(l)
     }
+    /// bitを先頭の係数から順に詰める。余った係数はBinary::Zero
+    /// # Panic
+    /// - bits.len() > M
+    pub fn pack_binary<const M: usize>(bits: &[Binary]) -> Polynomial<Binary, M> {
+        assert!(
+            bits.len() <= M,
+            "too many bits to pack, len={},M={}",
+            bits.len(),
+            M
+        );
+        pol!(mem::array_create_enumerate(|i| bits
+            .get(i)
+            .copied()
+            .unwrap_or(Binary::Zero)))
+    }
     pub fn torus_pol2binary_pol<const M: usize>(
         pol: Polynomial<Torus32, M>,
     ) -> Polynomial<Binary, M> {
-        let l = mem::array_create_enumerate(|i| {
-            let f: f32 = pol.coef_(i).into();
-            if f < 0.5 {
-                Binary::One
-            } else {
-                Binary::Zero
-            }
-        });
+        let l = mem::array_create_enumerate(|i| TLWEHelper::torus2binary(pol.coef_(i)));
         pol!(l)
     }
 }
@@ -120,20 +198,66 @@ impl<const N: usize> TRLWERep<N> {
         TLWERep::new(b_, a_)
     }
 }
+/// X -> X^k で移した暗号文を、元の秘密鍵sでの暗号文に戻すための鍵。
+/// j番目は s(X^k)/Bg^{j+1} をsで暗号化したもの
+pub struct AutomorphismKey<const N: usize> {
+    k: usize,
+    ksk: [TRLWERep<N>; TRLWEHelper::AUT_L],
+}
+impl<const N: usize> AutomorphismKey<N> {
+    pub fn new(s_key: &Polynomial<Binary, N>, k: usize) -> Self {
+        Self::new_with_rng(s_key, k, &mut secure_rng())
+    }
+    /// # Panic
+    /// - kが偶数
+    pub fn new_with_rng<R: Rng + CryptoRng>(
+        s_key: &Polynomial<Binary, N>,
+        k: usize,
+        rng: &mut R,
+    ) -> Self {
+        let s_k = s_key.map(|&s| s as i32).automorphism(k);
+        let ksk = mem::array_init(|j| {
+            let item = Self::item(&s_k, 1 + j as u32);
+            Cryptor::encrypto_with_rng(TRLWE, s_key, item, &mut *rng)
+        });
+        AutomorphismKey { k, ksk }
+    }
+    pub fn k(&self) -> usize {
+        self.k
+    }
+    /// ksk\[l-1\]の平文 s(X^k)/2^{bgbit\*l}
+    fn item(s_k: &Polynomial<i32, N>, l: u32) -> Polynomial<Torus32, N> {
+        let unit = Torus32::from_bits(1 << (u32::BITS - TRLWEHelper::AUT_BGBIT * l));
+        s_k.map(|&s| unit * s)
+    }
+}
+impl<const N: usize> TRLWERep<N> {
+    /**
+    m(X)の暗号文から、同じ鍵でのm(X^k)の暗号文を作る。
+    (b(X^k), a(X^k))はs(X^k)での暗号文なので、a(X^k)を分解して鍵を切り替える。
+    */
+    pub fn automorphism(&self, key: &AutomorphismKey<N>) -> Self {
+        const L: usize = TRLWEHelper::AUT_L;
+
+        let (cipher, p_key) = self.get_ref();
+        let a_decomp: [Polynomial<i32, N>; L] = p_key
+            .automorphism(key.k)
+            .decomposition_i32(TRLWEHelper::AUT_BGBIT);
+        // sum(a_j * ksk_j) ~ a(X^k)s(X^k) をsで暗号化したもの
+        let mut b_ = Polynomial::<Torus32, N>::zero();
+        let mut a_ = Polynomial::<Torus32, N>::zero();
+        let terms = || key.ksk.iter().zip(a_decomp.iter());
+        b_.fft_mul_add_assign_many(terms().map(|(c, d)| (&c.cipher, d)));
+        a_.fft_mul_add_assign_many(terms().map(|(c, d)| (&c.p_key, d)));
+        TRLWERep::new(cipher.automorphism(key.k) - b_, -a_)
+    }
+}
 impl<const N: usize> Crypto<Polynomial<Torus32, N>> for TRLWE<N> {
     type SecretKey = Polynomial<Binary, N>;
     type Representation = TRLWERep<N>;
 
     fn encrypto(&self, key: &Self::SecretKey, rep: Polynomial<Torus32, N>) -> Self::Representation {
-        let mut unif = ModDistribution::uniform();
-        let mut norm = ModDistribution::gaussian(TRLWEHelper::ALPHA);
-
-        let a = pol!(unif.gen_n::<N>());
-        let e = pol!(norm.gen_n::<N>());
-
-        let b = a.fft_cross(key) + rep + e;
-
-        TRLWERep::new(b, a)
+        self.encrypto_with_rng(key, rep, &mut secure_rng())
     }
 
     fn decrypto(
@@ -146,6 +270,21 @@ impl<const N: usize> Crypto<Polynomial<Torus32, N>> for TRLWE<N> {
         m_with_e
     }
 }
+impl<const N: usize> CryptoWithRng<Polynomial<Torus32, N>> for TRLWE<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        key: &Self::SecretKey,
+        rep: Polynomial<Torus32, N>,
+        rng: &mut R,
+    ) -> Self::Representation {
+        let a = pol!(ModDistribution::uniform_with(&mut *rng).gen_n::<N>());
+        let e = pol!(ModDistribution::gaussian_with(TRLWEHelper::ALPHA, &mut *rng).gen_n::<N>());
+
+        let b = a.fft_cross(key) + rep + e;
+
+        TRLWERep::new(b, a)
+    }
+}
 impl<const N: usize> Crypto<Polynomial<Binary, N>> for TRLWE<N> {
     type SecretKey = Polynomial<Binary, N>;
     type Representation = TRLWERep<N>;
@@ -166,6 +305,16 @@ impl<const N: usize> Crypto<Polynomial<Binary, N>> for TRLWE<N> {
         TRLWEHelper::torus_pol2binary_pol(self.decrypto(s_key, rep))
     }
 }
+impl<const N: usize> CryptoWithRng<Polynomial<Binary, N>> for TRLWE<N> {
+    fn encrypto_with_rng<R: Rng + CryptoRng>(
+        &self,
+        s_key: &Self::SecretKey,
+        item: Polynomial<Binary, N>,
+        rng: &mut R,
+    ) -> Self::Representation {
+        self.encrypto_with_rng(s_key, TRLWEHelper::binary_pol2torus_pol(item), rng)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -174,6 +323,7 @@ mod tests {
 
     use super::*;
     use utils::math::*;
+    use utils::torus;
 
     #[test]
     fn trlwe_sample_extract_index() {
@@ -228,4 +378,25 @@ mod tests {
         let res: Polynomial<Torus32, N> = Cryptor::decrypto(TRLWE, &s_key, rep);
         assert_eq!(res, pol, "trivialな暗号文を複号してみた");
     }
+
+    #[test]
+    fn trlwe_automorphism() {
+        const N: usize = TRLWEHelper::N;
+        let mut b_unif = BinaryDistribution::uniform();
+        let s_key = pol!(b_unif.gen_n::<N>());
+
+        for k in [3, 5, 2 * N - 1] {
+            let key = AutomorphismKey::new(&s_key, k);
+            assert_eq!(key.k(), k);
+            for _ in 0..3 {
+                let item = pol!(b_unif.gen_n::<N>());
+                let rep = Cryptor::encrypto(TRLWE, &s_key, item.clone()).automorphism(&key);
+                let res: Polynomial<Torus32, N> = Cryptor::decrypto(TRLWE, &s_key, rep);
+
+                let expect = TRLWEHelper::binary_pol2torus_pol(item).automorphism(k);
+                let err = (res - expect).linf_norm();
+                assert!(err < 1.0 / 64.0, "k={} err={}", k, err);
+            }
+        }
+    }
 }
@@ -0,0 +1,959 @@
+/*!
+暗号文と評価鍵のバイナリ形式。
+
+```text
+magic   : b"HNND"
+version : u16
+kind    : u8
+n_params: u8
+params  : [u32; n_params]
+payload : kindごとのデータ
+```
+数値はすべてリトルエンディアン。トーラスはu32の生の値、FFT済みの値はf64のビット列で書く。
+paramsにはNやTRGSWの分解パラメータなどを入れておき、読み込み時に型の定数と一致するか確認する。
+
+| kind | 型 | params | payload |
+|---|---|---|---|
+| 1 | `TLWERep` | N | b, a\[N\] |
+| 2 | `TRLWERep` | N | b\[N\], a\[N\] |
+| 3 | `KeySwitchingKey` | N, M, IKS_L, IKS_BASEBIT | TLWE(lv0)の並び |
+| 4 | `BootstrappingKey` | PRE_N, N, L, BGBIT | FFT済みのTRGSWの並び |
+| 5 | `TFHE`(評価鍵) | TLWE_N, TRLWE_N, L, BGBIT, IKS_L, IKS_BASEBIT | BootstrappingKey, KeySwitchingKey |
+| 6 | `CompressedKeySwitchingKey` | 3と同じ | seed\[32\], bの並び |
+| 7 | `CompressedServerKey` | 5と同じ | BootstrappingKey, CompressedKeySwitchingKey |
+| 8 | `EncryptedIndex` | N, L, BGBIT | 桁数(u32, `MAX_BITS`以下), TRGSWの並び |
+| 9 | `SecretKey` | TLWE_N, TRLWE_N | 係数を1つ1バイト(0か1)で、lv0, lv1の順 |
+| 10 | `CiphertextBundle` | N | 個数(u32), (名前の長さ(u16), 名前(UTF-8), TLWE)の並び |
+
+形式を変えたときは`VERSION`を上げ、古いファイルは`UnsupportedVersion`で読めないようにする。
+ */
+use crate::digest::Encrypted;
+use crate::lut::EncryptedIndex;
+use crate::tfhe::{BootstrappingKey, BootstrappingKeyRows, CompressedServerKey, SecretKey, TFHE};
+use crate::tlwe::{CompressedKeySwitchingKey, KeySwitchingKey, TLWEHelper, TLWERep};
+use crate::trgsw::{TRGSWHelper, TRGSWRepF};
+use crate::trlwe::TRLWERep;
+use num::Zero;
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use utils::math::{Binary, Polynomial, Torus32};
+use utils::pol;
+use utils::spqlios::FrrSeries;
+
+pub const MAGIC: [u8; 4] = *b"HNND";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireKind {
+    TLWE = 1,
+    TRLWE = 2,
+    KeySwitchingKey = 3,
+    BootstrappingKey = 4,
+    ServerKey = 5,
+    CompressedKeySwitchingKey = 6,
+    CompressedServerKey = 7,
+    EncryptedIndex = 8,
+    SecretKey = 9,
+    CiphertextBundle = 10,
+}
+impl WireKind {
+    pub const ALL: [WireKind; 10] = [
+        WireKind::TLWE,
+        WireKind::TRLWE,
+        WireKind::KeySwitchingKey,
+        WireKind::BootstrappingKey,
+        WireKind::ServerKey,
+        WireKind::CompressedKeySwitchingKey,
+        WireKind::CompressedServerKey,
+        WireKind::EncryptedIndex,
+        WireKind::SecretKey,
+        WireKind::CiphertextBundle,
+    ];
+    pub fn from_u8(kind: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|&k| k as u8 == kind)
+    }
+}
+/// エラーの表示用。知らないkindは番号だけ
+fn kind_name(kind: u8) -> String {
+    match WireKind::from_u8(kind) {
+        Some(k) => format!("{:?} ({})", k, kind),
+        None => format!("unknown ({})", kind),
+    }
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Io(io::Error),
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u16),
+    KindMismatch { expected: u8, found: u8 },
+    ParamMismatch { expected: Vec<u32>, found: Vec<u32> },
+}
+impl From<io::Error> for WireError {
+    fn from(e: io::Error) -> Self {
+        WireError::Io(e)
+    }
+}
+impl Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::Io(e) => write!(f, "io error: {}", e),
+            WireError::BadMagic(m) => write!(f, "bad magic bytes: {:?}", m),
+            WireError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "unsupported format version: {} (expected {})",
+                    v, VERSION
+                )
+            }
+            WireError::KindMismatch { expected, found } => {
+                write!(
+                    f,
+                    "kind mismatch: expected {}, found {}",
+                    kind_name(*expected),
+                    kind_name(*found)
+                )
+            }
+            WireError::ParamMismatch { expected, found } => {
+                write!(
+                    f,
+                    "parameter mismatch: expected {:?}, found {:?}",
+                    expected, found
+                )
+            }
+        }
+    }
+}
+impl std::error::Error for WireError {}
+
+pub trait WireFormat: Sized {
+    const KIND: WireKind;
+    /// 秘密鍵のように持ち主以外に読ませてはいけないか。unixではsaveで作るファイルを0600にする
+    const SECRET: bool = false;
+    /// ヘッダに書くパラメータ
+    fn params() -> Vec<u32>;
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()>;
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self>;
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_header(w, Self::KIND, &Self::params())?;
+        self.write_payload(w)
+    }
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, WireError> {
+        read_header(r, Self::KIND, &Self::params())?;
+        Ok(Self::read_payload(r)?)
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf).expect("write to Vec never fails");
+        buf
+    }
+    fn from_bytes(mut bytes: &[u8]) -> Result<Self, WireError> {
+        Self::read_from(&mut bytes)
+    }
+    /// ファイルに書き出す。すでにあれば上書きする
+    fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(create_file(path.as_ref(), Self::SECRET)?);
+        self.write_to(&mut w)?;
+        w.flush()
+    }
+    fn load<P: AsRef<Path>>(path: P) -> Result<Self, WireError> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// secretなら、新しく作るときも上書きするときも持ち主だけが読み書きできるようにする
+fn create_file(path: &Path, secret: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        if secret {
+            let file = options.mode(0o600).open(path)?;
+            // modeは作るときにしか効かないので、前からあったファイルにも付け直す
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+            return Ok(file);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = secret;
+    options.open(path)
+}
+
+pub fn write_header<W: Write>(w: &mut W, kind: WireKind, params: &[u32]) -> io::Result<()> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&[kind as u8, params.len() as u8])?;
+    params.iter().try_for_each(|&p| write_u32(w, p))
+}
+pub fn read_header<R: Read>(r: &mut R, kind: WireKind, params: &[u32]) -> Result<(), WireError> {
+    let found_kind = read_kind(r)?;
+    if found_kind != kind as u8 {
+        return Err(WireError::KindMismatch {
+            expected: kind as u8,
+            found: found_kind,
+        });
+    }
+    read_params(r, params)
+}
+/// magicとversionを確認してkindを返す
+fn read_kind<R: Read>(r: &mut R) -> Result<u8, WireError> {
+    let mut magic = [0_u8; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(WireError::BadMagic(magic));
+    }
+    let mut version = [0_u8; 2];
+    r.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    if version != VERSION {
+        return Err(WireError::UnsupportedVersion(version));
+    }
+    let mut kind = [0_u8; 1];
+    r.read_exact(&mut kind)?;
+    Ok(kind[0])
+}
+fn read_params<R: Read>(r: &mut R, params: &[u32]) -> Result<(), WireError> {
+    let mut n_params = [0_u8; 1];
+    r.read_exact(&mut n_params)?;
+    let found = (0..n_params[0])
+        .map(|_| read_u32(r))
+        .collect::<io::Result<Vec<_>>>()?;
+    if found != params {
+        return Err(WireError::ParamMismatch {
+            expected: params.to_vec(),
+            found,
+        });
+    }
+    Ok(())
+}
+/// ServerKeyとCompressedServerKeyのどちらでも読み、TFHEとして返す
+pub fn read_server_key<R: Read, const TLWE_N: usize, const TRLWE_N: usize>(
+    r: &mut R,
+) -> Result<TFHE<TLWE_N, TRLWE_N>, WireError> {
+    match read_kind(r)? {
+        k if k == WireKind::ServerKey as u8 => {
+            read_params(r, &TFHE::<TLWE_N, TRLWE_N>::params())?;
+            Ok(TFHE::read_payload(r)?)
+        }
+        k if k == WireKind::CompressedServerKey as u8 => {
+            read_params(r, &CompressedServerKey::<TLWE_N, TRLWE_N>::params())?;
+            Ok(CompressedServerKey::read_payload(r)?.decompress())
+        }
+        found => Err(WireError::KindMismatch {
+            expected: WireKind::ServerKey as u8,
+            found,
+        }),
+    }
+}
+
+#[inline]
+fn write_u32<W: Write>(w: &mut W, u: u32) -> io::Result<()> {
+    w.write_all(&u.to_le_bytes())
+}
+#[inline]
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0_u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+fn write_torus_slice<W: Write>(w: &mut W, s: &[Torus32]) -> io::Result<()> {
+    s.iter().try_for_each(|t| write_u32(w, t.inner()))
+}
+fn read_torus_into<R: Read>(r: &mut R, s: &mut [Torus32]) -> io::Result<()> {
+    for t in s.iter_mut() {
+        *t = Torus32::from_bits(read_u32(r)?);
+    }
+    Ok(())
+}
+fn read_torus_array<R: Read, const N: usize>(r: &mut R) -> io::Result<[Torus32; N]> {
+    let mut arr = [Torus32::zero(); N];
+    read_torus_into(r, &mut arr)?;
+    Ok(arr)
+}
+fn write_frr<W: Write, const N: usize>(w: &mut W, s: &FrrSeries<N>) -> io::Result<()> {
+    s.coefs()
+        .iter()
+        .try_for_each(|f| w.write_all(&f.to_bits().to_le_bytes()))
+}
+fn read_frr_into<R: Read, const N: usize>(r: &mut R, s: &mut FrrSeries<N>) -> io::Result<()> {
+    let mut buf = [0_u8; 8];
+    for f in s.coefs_mut().iter_mut() {
+        r.read_exact(&mut buf)?;
+        *f = f64::from_bits(u64::from_le_bytes(buf));
+    }
+    Ok(())
+}
+/// BootstrappingKeyの1行分
+fn write_trgsw_f<W: Write, const N: usize>(w: &mut W, t: &TRGSWRepF<N>) -> io::Result<()> {
+    let (b, a) = t.get_ref();
+    b.iter().chain(a.iter()).try_for_each(|s| write_frr(w, s))
+}
+/// 1行はN=1024で96KBあるので、値で返さずにtへ直接読む
+fn read_trgsw_f_into<R: Read, const N: usize>(r: &mut R, t: &mut TRGSWRepF<N>) -> io::Result<()> {
+    let (b, a) = t.get_mut_ref();
+    b.iter_mut()
+        .chain(a.iter_mut())
+        .try_for_each(|s| read_frr_into(r, s))
+}
+/// TRGSWをlen行読む。各行はヒープ上のVecの中で組み立てる
+fn read_trgsw_f_rows<R: Read, const N: usize>(
+    r: &mut R,
+    len: usize,
+) -> io::Result<Vec<TRGSWRepF<N>>> {
+    let mut rows = Vec::new();
+    for _ in 0..len {
+        rows.push(TRGSWRepF::zero());
+        read_trgsw_f_into(r, rows.last_mut().unwrap())?;
+    }
+    Ok(rows)
+}
+/// 読み込みに失敗しうる要素で配列を作る
+fn read_array<R: Read, T, F, const N: usize>(r: &mut R, mut f: F) -> io::Result<[T; N]>
+where
+    F: FnMut(&mut R) -> io::Result<T>,
+{
+    let v = (0..N).map(|_| f(r)).collect::<io::Result<Vec<T>>>()?;
+    match v.try_into() {
+        Ok(arr) => Ok(arr),
+        Err(_) => unreachable!("length is always N"),
+    }
+}
+
+impl<const N: usize> WireFormat for TLWERep<N> {
+    const KIND: WireKind = WireKind::TLWE;
+    fn params() -> Vec<u32> {
+        vec![N as u32]
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (b, a) = self.get_ref();
+        write_u32(w, b.inner())?;
+        write_torus_slice(w, a)
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let b = Torus32::from_bits(read_u32(r)?);
+        let a = read_torus_array(r)?;
+        Ok(TLWERep::new(b, a))
+    }
+}
+
+impl<const N: usize> WireFormat for TRLWERep<N> {
+    const KIND: WireKind = WireKind::TRLWE;
+    fn params() -> Vec<u32> {
+        vec![N as u32]
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (b, a) = self.get_ref();
+        write_torus_slice(w, b.coefs())?;
+        write_torus_slice(w, a.coefs())
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let b: Polynomial<Torus32, N> = pol!(read_torus_array(r)?);
+        let a: Polynomial<Torus32, N> = pol!(read_torus_array(r)?);
+        Ok(TRLWERep::new(b, a))
+    }
+}
+
+impl<const N: usize, const M: usize> WireFormat for KeySwitchingKey<N, M> {
+    const KIND: WireKind = WireKind::KeySwitchingKey;
+    fn params() -> Vec<u32> {
+        vec![
+            N as u32,
+            M as u32,
+            TLWEHelper::IKS_L as u32,
+            TLWEHelper::IKS_BASEBIT,
+        ]
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.iter()
+            .flat_map(|ks_i| ks_i.iter())
+            .flat_map(|ks_i_l| ks_i_l.iter())
+            .try_for_each(|ks_i_l_t| ks_i_l_t.write_payload(w))
+    }
+    /// 1行(IKS_L*IKS_T個のTLWE)も大きいので、Vecの中で直接組み立てる
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut ks = Vec::with_capacity(N);
+        for _ in 0..N {
+            ks.push(KeySwitchingKey::<N, M>::zero_row());
+            let ks_i = ks.last_mut().unwrap();
+            for ks_i_l_t in ks_i.iter_mut().flat_map(|ks_i_l| ks_i_l.iter_mut()) {
+                let (b, a) = ks_i_l_t.get_mut_ref();
+                *b = Torus32::from_bits(read_u32(r)?);
+                read_torus_into(r, a)?;
+            }
+        }
+        Ok(KeySwitchingKey::from_vec(ks))
+    }
+}
+
+impl<const PRE_N: usize, const N: usize> WireFormat for BootstrappingKey<PRE_N, N> {
+    const KIND: WireKind = WireKind::BootstrappingKey;
+    fn params() -> Vec<u32> {
+        vec![
+            PRE_N as u32,
+            N as u32,
+            TRGSWHelper::L as u32,
+            TRGSWHelper::BGBIT,
+        ]
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.iter().try_for_each(|bk_i| write_trgsw_f(w, bk_i))
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        Ok(BootstrappingKey::from_vec(read_trgsw_f_rows(r, PRE_N)?))
+    }
+}
+
+impl<const N: usize> WireFormat for EncryptedIndex<N> {
+    const KIND: WireKind = WireKind::EncryptedIndex;
+    fn params() -> Vec<u32> {
+        vec![N as u32, TRGSWHelper::L as u32, TRGSWHelper::BGBIT]
+    }
+    /// 桁数(u32)の後に各桁のTRGSW
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u32(w, self.len() as u32)?;
+        self.iter().try_for_each(|bit| write_trgsw_f(w, bit))
+    }
+    /// 桁数が`EncryptedIndex::MAX_BITS`を超えるときは、TRGSWを読む前にエラーにする
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let len = read_u32(r)? as usize;
+        if len > EncryptedIndex::<N>::MAX_BITS {
+            return Err(invalid_data(format!(
+                "index has {} bits, at most {}",
+                len,
+                EncryptedIndex::<N>::MAX_BITS
+            )));
+        }
+        Ok(EncryptedIndex::new(read_trgsw_f_rows(r, len)?))
+    }
+}
+
+/// ファイルに書き出したBootstrappingKeyを展開せずに使う
+///
+/// openで開いたファイルを持ち続け、blind rotationのたびに先頭の行へ戻って
+/// TRGSW(s_i)を1行ずつ読んで渡す。メモリに載るのは1行分だけだが、ゲート1回ごとに鍵全体を読む。
+/// 読み込みは1つのファイルを共有するので、複数のスレッドから使うとゲートは1つずつ進む
+pub struct StreamingBootstrappingKey<const PRE_N: usize, const N: usize> {
+    path: PathBuf,
+    offset: u64,
+    reader: Mutex<BufReader<File>>,
+}
+impl<const PRE_N: usize, const N: usize> StreamingBootstrappingKey<PRE_N, N> {
+    const ROW_BYTES: u64 = (TRGSWHelper::L * 2 * 2 * N * 8) as u64;
+
+    /// ヘッダとファイル長だけを確認する
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WireError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path)?;
+        read_header(
+            &mut file,
+            WireKind::BootstrappingKey,
+            &BootstrappingKey::<PRE_N, N>::params(),
+        )?;
+        let offset = file.stream_position()?;
+        if file.metadata()?.len() < offset + PRE_N as u64 * Self::ROW_BYTES {
+            return Err(WireError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "bootstrapping key is truncated",
+            )));
+        }
+        Ok(StreamingBootstrappingKey {
+            path,
+            offset,
+            reader: Mutex::new(BufReader::new(file)),
+        })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+impl<const PRE_N: usize, const N: usize> BootstrappingKeyRows<N>
+    for StreamingBootstrappingKey<PRE_N, N>
+{
+    /// openの後にファイルが切り詰められたときなどはエラーを返す
+    fn fold_rows<A, F: FnMut(A, usize, &TRGSWRepF<N>) -> A>(
+        &self,
+        init: A,
+        mut f: F,
+    ) -> io::Result<A> {
+        // 途中でpanicしても、次は先頭にseekし直すのでそのまま使える
+        let mut r = self.reader.lock().unwrap_or_else(|e| e.into_inner());
+        r.seek(SeekFrom::Start(self.offset))?;
+        // 1行分の領域を使い回す
+        let mut bk_i = Box::new(TRGSWRepF::zero());
+        (0..PRE_N).try_fold(init, |acc, i| {
+            read_trgsw_f_into(&mut *r, &mut bk_i)?;
+            Ok(f(acc, i, &bk_i))
+        })
+    }
+}
+
+/// 評価鍵(BootstrappingKey + KeySwitchingKey)をまとめたもの
+impl<const TLWE_N: usize, const TRLWE_N: usize> WireFormat for TFHE<TLWE_N, TRLWE_N> {
+    const KIND: WireKind = WireKind::ServerKey;
+    fn params() -> Vec<u32> {
+        vec![
+            TLWE_N as u32,
+            TRLWE_N as u32,
+            TRGSWHelper::L as u32,
+            TRGSWHelper::BGBIT,
+            TLWEHelper::IKS_L as u32,
+            TLWEHelper::IKS_BASEBIT,
+        ]
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.bk().write_payload(w)?;
+        self.ksk().write_payload(w)
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let bk = BootstrappingKey::read_payload(r)?;
+        let ksk = KeySwitchingKey::read_payload(r)?;
+        Ok(TFHE::from_keys(bk, ksk))
+    }
+}
+
+/// seedとbだけを書く。マスクは読み込んだ側がseedから作り直す
+impl<const N: usize, const M: usize> WireFormat for CompressedKeySwitchingKey<N, M> {
+    const KIND: WireKind = WireKind::CompressedKeySwitchingKey;
+    fn params() -> Vec<u32> {
+        KeySwitchingKey::<N, M>::params()
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(self.seed())?;
+        self.iter()
+            .flat_map(|ks_i| ks_i.iter())
+            .try_for_each(|ks_i_l| write_torus_slice(w, ks_i_l))
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut seed = [0_u8; 32];
+        r.read_exact(&mut seed)?;
+        let bodies = (0..N)
+            .map(|_| read_array(r, read_torus_array))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(CompressedKeySwitchingKey::from_parts(seed, bodies))
+    }
+}
+
+impl<const TLWE_N: usize, const TRLWE_N: usize> WireFormat
+    for CompressedServerKey<TLWE_N, TRLWE_N>
+{
+    const KIND: WireKind = WireKind::CompressedServerKey;
+    fn params() -> Vec<u32> {
+        TFHE::<TLWE_N, TRLWE_N>::params()
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.bk().write_payload(w)?;
+        self.ksk().write_payload(w)
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let bk = BootstrappingKey::read_payload(r)?;
+        let ksk = CompressedKeySwitchingKey::read_payload(r)?;
+        Ok(CompressedServerKey::from_keys(bk, ksk))
+    }
+}
+
+/// 係数を1つ1バイトで書く。0,1以外は読み込みでエラーにする
+impl<const TLWE_N: usize, const TRLWE_N: usize> WireFormat for SecretKey<TLWE_N, TRLWE_N> {
+    const KIND: WireKind = WireKind::SecretKey;
+    const SECRET: bool = true;
+    fn params() -> Vec<u32> {
+        vec![TLWE_N as u32, TRLWE_N as u32]
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let bytes: Vec<u8> = self
+            .tlwelv0
+            .iter()
+            .chain(self.tlwelv1.iter())
+            .map(|&s| s as u8)
+            .collect();
+        w.write_all(&bytes)
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        fn read_key<R: Read, const N: usize>(r: &mut R) -> io::Result<[Binary; N]> {
+            read_array(r, |r| {
+                let mut buf = [0_u8; 1];
+                r.read_exact(&mut buf)?;
+                match buf[0] {
+                    0 => Ok(Binary::Zero),
+                    1 => Ok(Binary::One),
+                    b => Err(invalid_data(format!(
+                        "secret key coefficient must be 0 or 1, found {}",
+                        b
+                    ))),
+                }
+            })
+        }
+        Ok(SecretKey {
+            tlwelv0: read_key(r)?,
+            tlwelv1: read_key(r)?,
+        })
+    }
+}
+
+/// 名前を付けた暗号文(TLWE)の組。回路の入力や出力をまとめて1つのファイルにする
+#[derive(Clone, Default)]
+pub struct CiphertextBundle<const N: usize> {
+    entries: Vec<(String, TLWERep<N>)>,
+}
+impl<const N: usize> CiphertextBundle<N> {
+    pub fn new() -> Self {
+        CiphertextBundle {
+            entries: Vec::new(),
+        }
+    }
+    /// 同じ名前があれば置き換える
+    pub fn insert(&mut self, name: &str, rep: TLWERep<N>) {
+        match self.entries.iter_mut().find(|(n, _)| n == name) {
+            Some((_, r)) => *r = rep,
+            None => self.entries.push((name.to_string(), rep)),
+        }
+    }
+    pub fn get(&self, name: &str) -> Option<&TLWERep<N>> {
+        self.entries.iter().find(|(n, _)| n == name).map(|(_, r)| r)
+    }
+    /// 入れた順
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TLWERep<N>)> {
+        self.entries.iter().map(|(n, r)| (n.as_str(), r))
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+impl<const N: usize> FromIterator<(String, TLWERep<N>)> for CiphertextBundle<N> {
+    fn from_iter<I: IntoIterator<Item = (String, TLWERep<N>)>>(iter: I) -> Self {
+        let mut bundle = CiphertextBundle::new();
+        iter.into_iter()
+            .for_each(|(name, rep)| bundle.insert(&name, rep));
+        bundle
+    }
+}
+impl<const N: usize> WireFormat for CiphertextBundle<N> {
+    const KIND: WireKind = WireKind::CiphertextBundle;
+    fn params() -> Vec<u32> {
+        TLWERep::<N>::params()
+    }
+    fn write_payload<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u32(w, self.len() as u32)?;
+        self.entries.iter().try_for_each(|(name, rep)| {
+            let len: u16 = name.len().try_into().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "name is longer than 65535 bytes",
+                )
+            })?;
+            w.write_all(&len.to_le_bytes())?;
+            w.write_all(name.as_bytes())?;
+            rep.write_payload(w)
+        })
+    }
+    fn read_payload<R: Read>(r: &mut R) -> io::Result<Self> {
+        let len = read_u32(r)?;
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let mut name_len = [0_u8; 2];
+            r.read_exact(&mut name_len)?;
+            let mut name = vec![0_u8; u16::from_le_bytes(name_len) as usize];
+            r.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|e| invalid_data(format!("name is not UTF-8: {}", e)))?;
+            entries.push((name, TLWERep::read_payload(r)?));
+        }
+        Ok(CiphertextBundle { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::Cryptor;
+    use crate::tfhe::TFHEHelper;
+    use crate::tlwe::TLWE;
+    use crate::trlwe::TRLWE;
+    use utils::math::{Binary, BinaryDistribution, Random};
+
+    #[test]
+    fn wire_tlwe() {
+        const N: usize = TLWEHelper::N;
+        let s_key: [Binary; N] = BinaryDistribution::uniform().gen_n();
+        let rep = Cryptor::encrypto(TLWE, &s_key, Binary::One);
+
+        let bytes = rep.to_bytes();
+        assert_eq!(&bytes[0..4], b"HNND");
+        assert_eq!(&bytes[4..6], &[1, 0], "version 1, little endian");
+        assert_eq!(bytes[6], WireKind::TLWE as u8);
+        assert_eq!(bytes[7], 1, "params = [N]");
+        assert_eq!(&bytes[8..12], &(N as u32).to_le_bytes());
+        assert_eq!(&bytes[12..16], &rep.cipher().inner().to_le_bytes());
+        assert_eq!(bytes.len(), 12 + 4 * (N + 1));
+
+        let res = TLWERep::<N>::from_bytes(&bytes).unwrap();
+        assert_eq!(res.to_bytes(), bytes);
+        let b: Binary = Cryptor::decrypto(TLWE, &s_key, res);
+        assert_eq!(b, Binary::One);
+    }
+
+    #[test]
+    fn wire_errors() {
+        let rep = TLWERep::<4>::trivial(Torus32::from_bits(3));
+        let bytes = rep.to_bytes();
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(matches!(
+            TLWERep::<4>::from_bytes(&bad),
+            Err(WireError::BadMagic(_))
+        ));
+
+        let mut bad = bytes.clone();
+        bad[4] = 2;
+        assert!(matches!(
+            TLWERep::<4>::from_bytes(&bad),
+            Err(WireError::UnsupportedVersion(2))
+        ));
+
+        assert!(matches!(
+            TRLWERep::<4>::from_bytes(&bytes),
+            Err(WireError::KindMismatch {
+                expected: 2,
+                found: 1
+            })
+        ));
+        match TLWERep::<5>::from_bytes(&bytes) {
+            Err(WireError::ParamMismatch { expected, found }) => {
+                assert_eq!(expected, vec![5]);
+                assert_eq!(found, vec![4]);
+            }
+            _ => panic!("Nが違うのに読めてしまった"),
+        }
+        assert!(matches!(
+            TLWERep::<4>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(WireError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn wire_trlwe() {
+        const N: usize = 1024;
+        let s_key = pol!(BinaryDistribution::uniform().gen_n::<N>());
+        let item = pol!(BinaryDistribution::uniform().gen_n::<N>());
+        let rep = Cryptor::encrypto(TRLWE, &s_key, item.clone());
+
+        let bytes = rep.to_bytes();
+        let res = TRLWERep::<N>::from_bytes(&bytes).unwrap();
+        let res: Polynomial<Binary, N> = Cryptor::decrypto(TRLWE, &s_key, res);
+        assert_eq!(res, item);
+    }
+
+    #[test]
+    fn wire_server_key() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 2_usize.pow(TFHEHelper::NBIT);
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        let bytes = tfhe.to_bytes();
+        let loaded = TFHE::<TLWE_N, TRLWE_N>::from_bytes(&bytes).unwrap();
+        assert!(loaded.to_bytes() == bytes, "読み直しても同じバイト列");
+
+        let input_0 = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::One);
+        let input_1 = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::One);
+        let res: Binary =
+            Cryptor::decrypto(TLWE, &s_key_tlwelv0, loaded.hom_nand(input_0, input_1));
+        assert_eq!(res, Binary::Zero, "読み込んだ鍵で計算できる");
+
+        assert!(matches!(
+            TFHE::<8, TRLWE_N>::from_bytes(&bytes),
+            Err(WireError::ParamMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn wire_streaming_bk() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 2_usize.pow(TFHEHelper::NBIT);
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        let path = std::env::temp_dir().join(format!("hom_nand_bk_{}.bin", std::process::id()));
+        tfhe.bk()
+            .write_to(&mut File::create(&path).unwrap())
+            .unwrap();
+        let bk = StreamingBootstrappingKey::<TLWE_N, TRLWE_N>::open(&path).unwrap();
+        let ksk = KeySwitchingKey::from_bytes(&tfhe.ksk().to_bytes()).unwrap();
+        let streaming = TFHE::from_keys(bk, ksk);
+
+        for (x, y) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            let bin = |b| if b == 1 { Binary::One } else { Binary::Zero };
+            let input_0 = Cryptor::encrypto(TLWE, &s_key_tlwelv0, bin(x));
+            let input_1 = Cryptor::encrypto(TLWE, &s_key_tlwelv0, bin(y));
+            let res: Binary =
+                Cryptor::decrypto(TLWE, &s_key_tlwelv0, streaming.hom_nand(input_0, input_1));
+            assert_eq!(res, bin(1 - x * y), "streaming bk: nand({},{})", x, y);
+        }
+
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 1).unwrap();
+        // openの後に切り詰められたら、panicせずにエラーを返す
+        let input = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::One);
+        match streaming.try_hom_not(input) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            Ok(_) => panic!("切り詰めた鍵で計算できてしまった"),
+        }
+        assert!(matches!(
+            StreamingBootstrappingKey::<TLWE_N, TRLWE_N>::open(&path),
+            Err(WireError::Io(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wire_secret_key() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let key = SecretKey::<TLWE_N, TRLWE_N>::new();
+        let path = std::env::temp_dir().join(format!("hom_nand_sk_{}.bin", std::process::id()));
+        // 他人が読めるファイルがすでにあっても、上書きしたら持ち主だけになる
+        std::fs::write(&path, b"").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            key.save(&path).unwrap();
+            assert_eq!(mode(&path), 0o600);
+            std::fs::remove_file(&path).unwrap();
+            key.save(&path).unwrap();
+            assert_eq!(mode(&path), 0o600);
+            // 秘密でないものは今まで通り
+            let public = path.with_extension("pub");
+            key.encrypt(Binary::One).save(&public).unwrap();
+            assert_ne!(mode(&public), 0o600);
+            std::fs::remove_file(&public).unwrap();
+        }
+        key.save(&path).unwrap();
+        assert_eq!(SecretKey::load(&path).unwrap(), key);
+        std::fs::remove_file(&path).unwrap();
+        let debug = format!("{:?}", key);
+        assert_eq!(debug, "SecretKey { tlwe_n: 16, trlwe_n: 1024, .. }");
+
+        let bytes = key.to_bytes();
+        assert_eq!(bytes.len(), 8 + 4 * 2 + TLWE_N + TRLWE_N);
+        let mut bad = bytes.clone();
+        bad[16] = 2;
+        match SecretKey::<TLWE_N, TRLWE_N>::from_bytes(&bad) {
+            Err(WireError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            _ => panic!("係数が0,1以外なのに読めてしまった"),
+        }
+        assert!(matches!(
+            SecretKey::<TLWE_N, 512>::from_bytes(&bytes),
+            Err(WireError::ParamMismatch { .. })
+        ));
+        // 秘密鍵を評価鍵として読もうとしたとき
+        let err = TFHE::<TLWE_N, TRLWE_N>::from_bytes(&bytes).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "kind mismatch: expected ServerKey (5), found SecretKey (9)"
+        );
+        let mut bad = bytes;
+        bad[6] = 42;
+        let err = TFHE::<TLWE_N, TRLWE_N>::from_bytes(&bad).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "kind mismatch: expected ServerKey (5), found unknown (42)"
+        );
+    }
+
+    #[test]
+    fn wire_ciphertext_bundle() {
+        const TLWE_N: usize = 16;
+        let key = SecretKey::<TLWE_N, 1024>::new();
+        let mut bundle: CiphertextBundle<TLWE_N> = [("a", Binary::One), ("b", Binary::Zero)]
+            .iter()
+            .map(|&(n, b)| (n.to_string(), key.encrypt(b)))
+            .collect();
+        bundle.insert("a", key.encrypt(Binary::Zero));
+        bundle.insert("出力", key.encrypt(Binary::One));
+        assert_eq!(bundle.len(), 3);
+
+        let bytes = bundle.to_bytes();
+        let loaded = CiphertextBundle::<TLWE_N>::from_bytes(&bytes).unwrap();
+        let names: Vec<&str> = loaded.iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["a", "b", "出力"]);
+        let dec = |n| key.decrypt(loaded.get(n).unwrap().clone());
+        assert_eq!(dec("a"), Binary::Zero);
+        assert_eq!(dec("b"), Binary::Zero);
+        assert_eq!(dec("出力"), Binary::One);
+        assert!(loaded.get("c").is_none());
+
+        // 1つ目の名前"a"をUTF-8でないバイトにする
+        let mut bad = bytes.clone();
+        bad[8 + 4 + 4 + 2] = 0xff;
+        match CiphertextBundle::<TLWE_N>::from_bytes(&bad) {
+            Err(WireError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            _ => panic!("名前がUTF-8でないのに読めてしまった"),
+        }
+        assert!(matches!(
+            TLWERep::<TLWE_N>::from_bytes(&bytes),
+            Err(WireError::KindMismatch {
+                expected: 1,
+                found: 10
+            })
+        ));
+    }
+
+    #[test]
+    fn wire_compressed_server_key() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 2_usize.pow(TFHEHelper::NBIT);
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let key = CompressedServerKey::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        let cksk_bytes = key.ksk().to_bytes();
+        let ksk_bytes = key.ksk().decompress().to_bytes();
+        const HEADER: usize = 8 + 4 * 4;
+        assert_eq!(
+            (cksk_bytes.len() - HEADER - 32) * (TLWE_N + 1),
+            ksk_bytes.len() - HEADER,
+            "seed以外はbだけなので1/(M+1)"
+        );
+        let cksk = CompressedKeySwitchingKey::<TRLWE_N, TLWE_N>::from_bytes(&cksk_bytes).unwrap();
+        assert!(cksk.decompress().to_bytes() == ksk_bytes);
+
+        let bytes = key.to_bytes();
+        let loaded = read_server_key::<_, TLWE_N, TRLWE_N>(&mut &bytes[..]).unwrap();
+        let input_0 = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::One);
+        let input_1 = Cryptor::encrypto(TLWE, &s_key_tlwelv0, Binary::Zero);
+        let res: Binary =
+            Cryptor::decrypto(TLWE, &s_key_tlwelv0, loaded.hom_nand(input_0, input_1));
+        assert_eq!(res, Binary::One, "圧縮した鍵を展開して計算できる");
+
+        let plain = loaded.to_bytes();
+        assert!(read_server_key::<_, TLWE_N, TRLWE_N>(&mut &plain[..]).is_ok());
+        assert!(matches!(
+            read_server_key::<_, TLWE_N, TRLWE_N>(&mut &cksk_bytes[..]),
+            Err(WireError::KindMismatch {
+                expected: 5,
+                found: 6
+            })
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+/*!
+`Box`の代わりにノードの番号で子を指す論理式。
+
+ノードは1つの`Vec`に子から順に並ぶので、巨大な式でも確保は`Vec`の伸長だけで済み、走査もメモリを前から読むだけになる。
+ノードの形は`Circuit`と同じ`Node`で、`Circuit`と違って同じ形の部分式もまとめない。
+
+```ignore
+let mut arena = ExprArena::new();
+let (a, b) = (arena.var("a"), arena.var("b"));
+let t = arena.nand(a, b);
+let (at, bt) = (arena.nand(a, t), arena.nand(b, t));
+let root = arena.nand(at, bt); // xor
+let res = arena.eval_with(&PlainLogip, root, &vars)?;
+```
+ */
+use crate::circuit::Node;
+use crate::{EvalError, LogicExpr, Logip};
+use std::collections::HashMap;
+use utils::traits::AsLogic;
+
+pub struct ExprArena<R> {
+    nodes: Vec<Node<R>>,
+}
+impl<R> Default for ExprArena<R> {
+    fn default() -> Self {
+        ExprArena { nodes: Vec::new() }
+    }
+}
+impl<R: AsLogic> ExprArena<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_capacity(capacity: usize) -> Self {
+        ExprArena {
+            nodes: Vec::with_capacity(capacity),
+        }
+    }
+    /// 式を変換して、ノードの番号を表す根を返す
+    pub fn from_expr(exp: LogicExpr<R>) -> (Self, usize) {
+        let mut arena = Self::new();
+        let root = arena.push_expr(exp);
+        (arena, root)
+    }
+    pub fn nodes(&self) -> &[Node<R>] {
+        &self.nodes
+    }
+    pub(crate) fn into_nodes(self) -> Vec<Node<R>> {
+        self.nodes
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn leaf(&mut self, r: R) -> usize {
+        self.push(Node::Leaf(r))
+    }
+    pub fn var(&mut self, name: &str) -> usize {
+        self.push(Node::Var(name.to_string()))
+    }
+    pub fn not(&mut self, e: usize) -> usize {
+        self.push(Node::Not(e))
+    }
+    pub fn nand(&mut self, lhs: usize, rhs: usize) -> usize {
+        self.push(Node::Nand(lhs, rhs))
+    }
+    pub fn and(&mut self, lhs: usize, rhs: usize) -> usize {
+        self.push(Node::And(lhs, rhs))
+    }
+    pub fn or(&mut self, lhs: usize, rhs: usize) -> usize {
+        self.push(Node::Or(lhs, rhs))
+    }
+    pub fn xor(&mut self, lhs: usize, rhs: usize) -> usize {
+        self.push(Node::Xor(lhs, rhs))
+    }
+    /// # Panic
+    /// - 子がまだないノードを指している
+    fn push(&mut self, node: Node<R>) -> usize {
+        let id = self.nodes.len();
+        let valid = |i: &usize| *i < id;
+        assert!(
+            match &node {
+                Node::Leaf(_) | Node::Var(_) => true,
+                Node::Not(e) => valid(e),
+                Node::Nand(l, r) | Node::And(l, r) | Node::Or(l, r) | Node::Xor(l, r) => {
+                    valid(l) && valid(r)
+                }
+            },
+            "child node must be pushed before its parent"
+        );
+        self.nodes.push(node);
+        id
+    }
+
+    /// 式のノードを全て足して、根の番号を返す。再帰しない
+    pub fn push_expr(&mut self, exp: LogicExpr<R>) -> usize {
+        enum Task<R: AsLogic> {
+            Enter(LogicExpr<R>),
+            Build(fn(usize, usize) -> Node<R>),
+            Not,
+        }
+        let mut tasks = vec![Task::Enter(exp)];
+        let mut ids = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Enter(LogicExpr::Leaf(r)) => ids.push(self.leaf(r)),
+                Task::Enter(LogicExpr::Var(name)) => ids.push(self.push(Node::Var(name))),
+                Task::Enter(LogicExpr::Not(e)) => tasks.extend([Task::Not, Task::Enter(*e)]),
+                Task::Enter(LogicExpr::Nand(l, r)) => {
+                    tasks.extend([Task::Build(Node::Nand), Task::Enter(*r), Task::Enter(*l)])
+                }
+                Task::Enter(LogicExpr::And(l, r)) => {
+                    tasks.extend([Task::Build(Node::And), Task::Enter(*r), Task::Enter(*l)])
+                }
+                Task::Enter(LogicExpr::Or(l, r)) => {
+                    tasks.extend([Task::Build(Node::Or), Task::Enter(*r), Task::Enter(*l)])
+                }
+                Task::Enter(LogicExpr::Xor(l, r)) => {
+                    tasks.extend([Task::Build(Node::Xor), Task::Enter(*r), Task::Enter(*l)])
+                }
+                Task::Not => {
+                    let e = ids.pop().unwrap();
+                    ids.push(self.not(e));
+                }
+                Task::Build(node) => {
+                    let r = ids.pop().unwrap();
+                    let l = ids.pop().unwrap();
+                    ids.push(self.push(node(l, r)));
+                }
+            }
+        }
+        ids.pop().unwrap()
+    }
+
+    /// rootから辿れるノードの印
+    fn reachable(&self, root: usize) -> Vec<bool> {
+        let mut reach = vec![false; root + 1];
+        reach[root] = true;
+        // 子は親より前にあるので、後ろから一度見るだけでよい
+        for i in (0..=root).rev() {
+            if !reach[i] {
+                continue;
+            }
+            match &self.nodes[i] {
+                Node::Leaf(_) | Node::Var(_) => {}
+                Node::Not(e) => reach[*e] = true,
+                Node::Nand(l, r) | Node::And(l, r) | Node::Or(l, r) | Node::Xor(l, r) => {
+                    reach[*l] = true;
+                    reach[*r] = true;
+                }
+            }
+        }
+        reach
+    }
+}
+impl<R: AsLogic + Clone> ExprArena<R> {
+    /// rootを根とする部分を`Box`の式に戻す。共有しているノードは複製する
+    pub fn to_expr(&self, root: usize) -> LogicExpr<R> {
+        let mut exps: Vec<Option<LogicExpr<R>>> = Vec::with_capacity(root + 1);
+        let reach = self.reachable(root);
+        for (i, node) in self.nodes[..=root].iter().enumerate() {
+            if !reach[i] {
+                exps.push(None);
+                continue;
+            }
+            let e = |i: &usize| Box::new(exps[*i].clone().unwrap());
+            let exp = match node {
+                Node::Leaf(r) => LogicExpr::Leaf(r.clone()),
+                Node::Var(name) => LogicExpr::Var(name.clone()),
+                Node::Not(x) => LogicExpr::Not(e(x)),
+                Node::Nand(l, r) => LogicExpr::Nand(e(l), e(r)),
+                Node::And(l, r) => LogicExpr::And(e(l), e(r)),
+                Node::Or(l, r) => LogicExpr::Or(e(l), e(r)),
+                Node::Xor(l, r) => LogicExpr::Xor(e(l), e(r)),
+            };
+            exps.push(Some(exp));
+        }
+        exps.swap_remove(root).unwrap()
+    }
+    /// rootから辿れるノードだけを、番号の小さい方から1回ずつ評価する
+    pub fn eval_with<P: Logip<R = R>>(
+        &self,
+        pros: &P,
+        root: usize,
+        vars: &HashMap<String, R>,
+    ) -> Result<R, EvalError> {
+        let reach = self.reachable(root);
+        let mut values: Vec<Option<R>> = Vec::with_capacity(root + 1);
+        for (i, node) in self.nodes[..=root].iter().enumerate() {
+            if !reach[i] {
+                values.push(None);
+                continue;
+            }
+            let v = |i: &usize| values[*i].clone().unwrap();
+            let value = match node {
+                Node::Leaf(r) => r.clone(),
+                Node::Var(name) => match vars.get(name) {
+                    Some(r) => r.clone(),
+                    None => return Err(EvalError::UnboundVariable(name.clone())),
+                },
+                Node::Not(e) => pros.not(v(e)),
+                Node::Nand(l, r) => pros.nand(v(l), v(r)),
+                Node::And(l, r) => pros.and(v(l), v(r)),
+                Node::Or(l, r) => pros.or(v(l), v(r)),
+                Node::Xor(l, r) => pros.xor(v(l), v(r)),
+            };
+            values.push(Some(value));
+        }
+        Ok(values.swap_remove(root).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_logic_expr_with, parse_logic_expr, PlainLogip};
+    use utils::math::Binary;
+
+    fn vars(i: usize) -> HashMap<String, Binary> {
+        ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(j, name)| (name.to_string(), Binary::from((i >> j) & 1)))
+            .collect()
+    }
+
+    #[test]
+    fn arena_from_expr() {
+        let l = "!(a & b) ^ (c | 1) $ !a";
+        let (arena, root) = ExprArena::from_expr(parse_logic_expr::<Binary>(l).unwrap());
+        assert_eq!(arena.len(), 11);
+        assert_eq!(root, 10);
+        assert_eq!(arena.to_expr(root).to_string(), "!(a & b) ^ (c | 1) $ !a");
+        for i in 0..8 {
+            assert_eq!(
+                arena.eval_with(&PlainLogip, root, &vars(i)),
+                eval_logic_expr_with(&PlainLogip, parse_logic_expr(l).unwrap(), &vars(i))
+            );
+        }
+    }
+
+    #[test]
+    fn arena_builder() {
+        let mut arena = ExprArena::<Binary>::new();
+        let (a, b) = (arena.var("a"), arena.var("b"));
+        let t = arena.nand(a, b);
+        let (at, bt) = (arena.nand(a, t), arena.nand(b, t));
+        let xor = arena.nand(at, bt);
+        // xorを使わない枝は評価しない
+        let c = arena.var("c");
+        arena.and(c, xor);
+        assert_eq!(
+            arena.to_expr(xor).to_string(),
+            "a $ (a $ b) $ (b $ (a $ b))"
+        );
+        for i in 0..4 {
+            let res = arena.eval_with(&PlainLogip, xor, &vars(i));
+            assert_eq!(res, Ok(Binary::from((i & 1) ^ (i >> 1))));
+        }
+
+        // 長い鎖でもスタックを使わない
+        let mut arena = ExprArena::with_capacity(1_000_001);
+        let mut root = arena.var("a");
+        for _ in 0..1_000_000 {
+            root = arena.not(root);
+        }
+        assert_eq!(
+            arena.eval_with(&PlainLogip, root, &vars(1)),
+            Ok(Binary::One)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn arena_forward_reference() {
+        let mut arena = ExprArena::<Binary>::new();
+        arena.not(0);
+    }
+}
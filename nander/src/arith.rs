@@ -0,0 +1,921 @@
+/*!
+整数の演算回路。
+
+整数はbitの配列で、0番目が最下位bit(`parse_bus_expr`の`x[0]`と同じ)。
+どの関数も`Logip`のゲートだけで組むので、平文(`PlainLogip`)でもTFHEでも同じように使える。
+`kogge_stone_adder`のように`Circuit`を返すものは、`Circuit::par_eval`で同じ段のゲートを並列に評価できる。
+
+```ignore
+let a: [TLWERep<N>; 8] = encrypt_u8(&s_key, 100);
+let b: [TLWERep<N>; 8] = encrypt_u8(&s_key, 27);
+let (sum, carry) = arith::add(&tfhe, &a, &b);
+```
+ */
+use crate::arena::ExprArena;
+use crate::{bus_bit_name, Circuit, Logip};
+use std::collections::VecDeque;
+use utils::{mem, traits::AsLogic};
+
+/// 回路を評価しながら組むか、`ExprArena`に組むかを同じ手順で書くためのもの
+trait Gates {
+    type W: Clone;
+    fn and(&mut self, lhs: Self::W, rhs: Self::W) -> Self::W;
+    fn or(&mut self, lhs: Self::W, rhs: Self::W) -> Self::W;
+    fn xor(&mut self, lhs: Self::W, rhs: Self::W) -> Self::W;
+    fn zero(&mut self) -> Self::W;
+}
+/// その場で評価する
+struct Eval<'a, P>(&'a P);
+impl<P: Logip> Gates for Eval<'_, P> {
+    type W = P::R;
+    fn and(&mut self, lhs: P::R, rhs: P::R) -> P::R {
+        self.0.and(lhs, rhs)
+    }
+    fn or(&mut self, lhs: P::R, rhs: P::R) -> P::R {
+        self.0.or(lhs, rhs)
+    }
+    fn xor(&mut self, lhs: P::R, rhs: P::R) -> P::R {
+        self.0.xor(lhs, rhs)
+    }
+    fn zero(&mut self) -> P::R {
+        P::R::logic_false()
+    }
+}
+impl<R: AsLogic> Gates for ExprArena<R> {
+    type W = usize;
+    fn and(&mut self, lhs: usize, rhs: usize) -> usize {
+        ExprArena::and(self, lhs, rhs)
+    }
+    fn or(&mut self, lhs: usize, rhs: usize) -> usize {
+        ExprArena::or(self, lhs, rhs)
+    }
+    fn xor(&mut self, lhs: usize, rhs: usize) -> usize {
+        ExprArena::xor(self, lhs, rhs)
+    }
+    fn zero(&mut self) -> usize {
+        self.leaf(R::logic_false())
+    }
+}
+
+/// 入力が`a[0]`,...,`a[bits-1]`,`b[0]`,...,`b[bits-1]`の順の回路を組む。
+/// 出力はbuildが返すbitの順
+fn binary_circuit<R: AsLogic>(
+    bits: usize,
+    build: impl FnOnce(&mut ExprArena<R>, Vec<usize>, Vec<usize>) -> Vec<usize>,
+) -> Circuit<R> {
+    let mut arena = ExprArena::new();
+    let a: Vec<usize> = (0..bits)
+        .map(|i| arena.var(&bus_bit_name("a", i)))
+        .collect();
+    let b: Vec<usize> = (0..bits)
+        .map(|i| arena.var(&bus_bit_name("b", i)))
+        .collect();
+    let roots = build(&mut arena, a, b);
+    Circuit::from_arena(arena, &roots)
+}
+
+/// 桁上げを下の桁から順に伝える加算器。(和, 最上位からの桁上げ)
+///
+/// 全加算器をBITS個並べるので、ゲートは`5 * BITS - 3`個で段数もBITSに比例する。
+pub fn add<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], P::R) {
+    let mut carry: Option<P::R> = None;
+    let sum = mem::array_create_enumerate(|i| {
+        let (a, b) = (a[i].clone(), b[i].clone());
+        // 最下位は桁上げがないので半加算器でよい
+        let (s, c) = match carry.take() {
+            None => pros.half_add(a, b),
+            Some(carry) => pros.full_add(a, b, carry),
+        };
+        carry = Some(c);
+        s
+    });
+    (sum, carry.unwrap_or_else(P::R::logic_false))
+}
+
+/// 借りを下の桁から順に伝える減算器。(差, 最上位での借り)
+///
+/// 差は`a - b`を`2^BITS`で割った余りで、借りは`a < b`(符号なし)のときに1になる。
+pub fn sub<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], P::R) {
+    let (diff, borrow) = sub_bits(pros, a, b);
+    let mut diff = diff.into_iter();
+    (
+        mem::array_create_enumerate(|_| diff.next().unwrap()),
+        borrow,
+    )
+}
+/// bがaより短いときは、上の桁を0として引く
+fn sub_bits<P: Logip>(pros: &P, a: &[P::R], b: &[P::R]) -> (Vec<P::R>, P::R) {
+    let mut borrow: Option<P::R> = None;
+    let mut diff = Vec::with_capacity(a.len());
+    for (i, a) in a.iter().cloned().enumerate() {
+        let (d, next) = match (b.get(i).cloned(), borrow.take()) {
+            (None, None) => (a, None),
+            // 最下位はa < bのときだけ借りる
+            (Some(b), None) => {
+                let x = pros.xor(a.clone(), b.clone());
+                (x, Some(pros.and(pros.not(a), b)))
+            }
+            (None, Some(borrow)) => {
+                let d = pros.xor(a.clone(), borrow.clone());
+                (d, Some(pros.and(pros.not(a), borrow)))
+            }
+            // a != bならbの桁で借りが決まり、a == bなら下からの借りをそのまま伝える
+            (Some(b), Some(borrow)) => {
+                let x = pros.xor(a, b.clone());
+                (
+                    pros.xor(x.clone(), borrow.clone()),
+                    Some(pros.mux(x, borrow, b)),
+                )
+            }
+        };
+        borrow = next;
+        diff.push(d);
+    }
+    (diff, borrow.unwrap_or_else(P::R::logic_false))
+}
+
+/// 2の補数での加算。(和, あふれ)
+///
+/// あふれは同じ符号の数を足して和の符号が変わったときに1になる。
+pub fn overflowing_add_signed<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], P::R) {
+    let (sum, _) = add(pros, a, b);
+    let overflow = match (a.last(), b.last(), sum.last()) {
+        (Some(a), Some(b), Some(s)) => pros.and(
+            pros.xnor(a.clone(), b.clone()),
+            pros.xor(s.clone(), a.clone()),
+        ),
+        _ => P::R::logic_false(),
+    };
+    (sum, overflow)
+}
+/// 2の補数での減算。(差, あふれ)
+///
+/// あふれは違う符号の数を引いて差の符号が`a`と変わったときに1になる。
+pub fn overflowing_sub_signed<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], P::R) {
+    let (diff, _) = sub(pros, a, b);
+    let overflow = match (a.last(), b.last(), diff.last()) {
+        (Some(a), Some(b), Some(d)) => pros.and(
+            pros.xor(a.clone(), b.clone()),
+            pros.xor(d.clone(), a.clone()),
+        ),
+        _ => P::R::logic_false(),
+    };
+    (diff, overflow)
+}
+
+/// 2の補数での符号の反転。(-a, あふれ)
+///
+/// あふれは`a`が最小の負の数(最上位だけが1)のときに1になる。そのとき`-a == a`になる。
+pub fn neg<P: Logip, const BITS: usize>(pros: &P, a: &[P::R; BITS]) -> ([P::R; BITS], P::R) {
+    // 下の桁に1があれば、その上の桁は反転する
+    let mut lower: Option<P::R> = None;
+    let mut overflow = P::R::logic_false();
+    let res = mem::array_create_enumerate(|i| {
+        let a = a[i].clone();
+        let last = i + 1 == BITS;
+        match lower.take() {
+            None => {
+                if last {
+                    overflow = a.clone();
+                }
+                lower = Some(a.clone());
+                a
+            }
+            Some(l) => {
+                let r = pros.xor(a.clone(), l.clone());
+                if last {
+                    overflow = pros.and(a, pros.not(l));
+                } else {
+                    lower = Some(pros.or(l, a));
+                }
+                r
+            }
+        }
+    });
+    (res, overflow)
+}
+
+/// 乗算。積を`2^BITS`で割った余り
+///
+/// 上の桁に入る部分積は作らないので、`mul_wide`よりゲートが少ない。
+pub fn mul<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> [P::R; BITS] {
+    let mut product = shift_and_add(pros, a, b, BITS).into_iter();
+    mem::array_create_enumerate(|_| product.next().unwrap())
+}
+/// 切り捨てない乗算。(積の下位BITS桁, 上位BITS桁)
+pub fn mul_wide<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], [P::R; BITS]) {
+    let mut product = shift_and_add(pros, a, b, 2 * BITS).into_iter();
+    let low = mem::array_create_enumerate(|_| product.next().unwrap());
+    let high = mem::array_create_enumerate(|_| product.next().unwrap());
+    (low, high)
+}
+/// 符号なしの乗算。(積を`2^BITS`で割った余り, あふれ)
+pub fn overflowing_mul<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], P::R) {
+    let (low, high) = mul_wide(pros, a, b);
+    (low, pros.not(is_zero(pros, &high)))
+}
+/// 2の補数での乗算。(積を`2^BITS`で割った余り, あふれ)
+pub fn overflowing_mul_signed<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], P::R) {
+    let (low, high) = mul_wide(pros, a, b);
+    let (a_sign, b_sign) = match (a.last(), b.last()) {
+        (Some(a), Some(b)) => (a.clone(), b.clone()),
+        _ => return (low, P::R::logic_false()),
+    };
+    // 符号付きの積の上位は、符号なしの積の上位から負の数を掛けた分を引いたもの
+    let masked = |sign: &P::R, x: &[P::R; BITS]| -> [P::R; BITS] {
+        mem::array_create_enumerate(|i| pros.and(sign.clone(), x[i].clone()))
+    };
+    let (high, _) = sub(pros, &high, &masked(&a_sign, b));
+    let (high, _) = sub(pros, &high, &masked(&b_sign, a));
+    // 上位が全て下位の符号と同じなら収まっている
+    let sign = low[BITS - 1].clone();
+    let overflow = high
+        .iter()
+        .map(|h| pros.xor(h.clone(), sign.clone()))
+        .reduce(|l, r| pros.or(l, r))
+        .unwrap();
+    (low, overflow)
+}
+/// b[j]ごとの部分積`a << j`を順に足していく。積の下位width桁を返す
+fn shift_and_add<P: Logip>(pros: &P, a: &[P::R], b: &[P::R], width: usize) -> Vec<P::R> {
+    // accは積の下位の桁から、これまでに決まった分だけ持つ
+    let mut acc: Vec<P::R> = Vec::with_capacity(width);
+    for (j, b) in b.iter().enumerate().take(width) {
+        let mut carry: Option<P::R> = None;
+        for (k, a) in a.iter().take(width - j).enumerate() {
+            let p = pros.and(a.clone(), b.clone());
+            let (bit, next) = match (acc.get(j + k).cloned(), carry.take()) {
+                (None, None) => (p, None),
+                (Some(x), None) | (None, Some(x)) => {
+                    let (s, c) = pros.half_add(x, p);
+                    (s, Some(c))
+                }
+                (Some(x), Some(c)) => {
+                    let (s, c) = pros.full_add(x, p, c);
+                    (s, Some(c))
+                }
+            };
+            carry = next;
+            match acc.get_mut(j + k) {
+                Some(x) => *x = bit,
+                None => acc.push(bit),
+            }
+        }
+        // 行の上の桁はまだaccにないので、桁上げはそのまま次の桁になる
+        if let Some(c) = carry.filter(|_| acc.len() < width) {
+            acc.push(c);
+        }
+    }
+    acc.resize_with(width, P::R::logic_false);
+    acc
+}
+
+/// 引き戻し法(restoring)の除算。(商, 余り)
+///
+/// 上の桁から1桁ずつ、余りに次の桁を付けたものから`b`を引けるか試す。引けたら商の桁は1で、差を次の余りにする。
+/// `b == 0`のときは商の全ての桁が1で、余りは`a`になる。
+pub fn div_rem<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], [P::R; BITS]) {
+    // upper[k]はb[k..]のどれかが1か
+    let mut upper: Vec<Option<P::R>> = vec![None; BITS + 1];
+    for k in (1..BITS).rev() {
+        upper[k] = Some(match &upper[k + 1] {
+            Some(u) => pros.or(b[k].clone(), u.clone()),
+            None => b[k].clone(),
+        });
+    }
+    let mut rem: Vec<P::R> = Vec::with_capacity(BITS);
+    let mut quotient: Vec<P::R> = Vec::with_capacity(BITS);
+    for a in a.iter().rev() {
+        // rem * 2 + a。まだ上の桁がない間は短いまま
+        let mut shifted = Vec::with_capacity(rem.len() + 1);
+        shifted.push(a.clone());
+        shifted.extend(rem);
+        // shiftedの方が短ければ、bの上の桁が全て0のときだけ引ける
+        let (trial, borrow) = if shifted.len() < BITS {
+            let (trial, borrow) = sub_bits(pros, &shifted, &b[..shifted.len()]);
+            let high = upper[shifted.len()].clone().unwrap();
+            (trial, pros.or(borrow, high))
+        } else {
+            sub_bits(pros, &shifted, b)
+        };
+        let q = pros.not(borrow);
+        // 余りはbより小さいのでBITS桁に収まる
+        rem = shifted
+            .into_iter()
+            .zip(trial)
+            .take(BITS)
+            .map(|(s, t)| pros.mux(q.clone(), s, t))
+            .collect();
+        quotient.push(q);
+    }
+    let mut quotient = quotient.into_iter().rev();
+    let mut rem = rem.into_iter();
+    (
+        mem::array_create_enumerate(|_| quotient.next().unwrap()),
+        mem::array_create_enumerate(|_| rem.next().unwrap()),
+    )
+}
+
+/// `a == b`
+pub fn eq<P: Logip, const BITS: usize>(pros: &P, a: &[P::R; BITS], b: &[P::R; BITS]) -> P::R {
+    let same = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| pros.xnor(a.clone(), b.clone()))
+        .collect();
+    reduce_pairs(same, |l, r| pros.and(l, r)).unwrap_or_else(P::R::logic_true)
+}
+/// `a == 0`
+pub fn is_zero<P: Logip, const BITS: usize>(pros: &P, a: &[P::R; BITS]) -> P::R {
+    a.iter()
+        .cloned()
+        .reduce(|l, r| pros.or(l, r))
+        .map_or_else(P::R::logic_true, |any| pros.not(any))
+}
+/// 符号なしの`a < b`
+pub fn lt<P: Logip, const BITS: usize>(pros: &P, a: &[P::R; BITS], b: &[P::R; BITS]) -> P::R {
+    less_than(pros, a, b, false)
+}
+/// 符号なしの`a <= b`
+pub fn le<P: Logip, const BITS: usize>(pros: &P, a: &[P::R; BITS], b: &[P::R; BITS]) -> P::R {
+    pros.not(less_than(pros, b, a, false))
+}
+/// 2の補数での`a < b`
+pub fn lt_signed<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> P::R {
+    less_than(pros, a, b, true)
+}
+/// 2の補数での`a <= b`
+pub fn le_signed<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> P::R {
+    pros.not(less_than(pros, b, a, true))
+}
+/// `a - b`の借りだけを求める
+fn less_than<P: Logip>(pros: &P, a: &[P::R], b: &[P::R], signed: bool) -> P::R {
+    let bits = a.len();
+    let mut borrow: Option<P::R> = None;
+    for (i, (a, b)) in a.iter().zip(b).enumerate() {
+        // 符号付きでは最上位の桁の重みが負なので、aとbの役を入れ替える
+        let (a, b) = if signed && i + 1 == bits {
+            (b.clone(), a.clone())
+        } else {
+            (a.clone(), b.clone())
+        };
+        borrow = Some(match borrow {
+            None => pros.and(pros.not(a), b),
+            Some(borrow) => pros.mux(pros.xor(a, b.clone()), borrow, b),
+        });
+    }
+    borrow.unwrap_or_else(P::R::logic_false)
+}
+
+/// 符号なしで小さい方と大きい方。比較は1回だけする
+pub fn min_max<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], [P::R; BITS]) {
+    let a_lt_b = lt(pros, a, b);
+    let select = |i: usize, if_lt: &[P::R; BITS], if_ge: &[P::R; BITS]| {
+        pros.mux(a_lt_b.clone(), if_ge[i].clone(), if_lt[i].clone())
+    };
+    (
+        mem::array_create_enumerate(|i| select(i, a, b)),
+        mem::array_create_enumerate(|i| select(i, b, a)),
+    )
+}
+pub fn min<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> [P::R; BITS] {
+    let a_lt_b = lt(pros, a, b);
+    mem::array_create_enumerate(|i| pros.mux(a_lt_b.clone(), b[i].clone(), a[i].clone()))
+}
+pub fn max<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> [P::R; BITS] {
+    let a_lt_b = lt(pros, a, b);
+    mem::array_create_enumerate(|i| pros.mux(a_lt_b.clone(), a[i].clone(), b[i].clone()))
+}
+/// 全体の最小値。空ならNone
+///
+/// 2つずつ組にして比べるので、比較の段数は`log2(xs.len())`で済む。
+pub fn min_of<P: Logip, const BITS: usize>(pros: &P, xs: &[[P::R; BITS]]) -> Option<[P::R; BITS]> {
+    reduce_pairs(xs.to_vec(), |a, b| min(pros, &a, &b))
+}
+/// 全体の最大値。空ならNone
+pub fn max_of<P: Logip, const BITS: usize>(pros: &P, xs: &[[P::R; BITS]]) -> Option<[P::R; BITS]> {
+    reduce_pairs(xs.to_vec(), |a, b| max(pros, &a, &b))
+}
+/// n個を並べ替えるBatcherの奇偶マージソートの比較器。
+///
+/// 段ごとの`(i, j)`(`i < j`)の組で、同じ段の組は同じ位置を含まない。段の数は`log2(n)`の2乗程度。
+pub fn sorting_network(n: usize) -> Vec<Vec<(usize, usize)>> {
+    let mut layers = Vec::new();
+    let mut p = 1;
+    while p < n {
+        let mut k = p;
+        while k >= 1 {
+            let mut layer = Vec::new();
+            let mut j = k % p;
+            while j + k < n {
+                // 長さ2pの同じ区間にある組だけ比べる
+                for i in (0..k).take_while(|i| i + j + k < n) {
+                    if (i + j) / (2 * p) == (i + j + k) / (2 * p) {
+                        layer.push((i + j, i + j + k));
+                    }
+                }
+                j += 2 * k;
+            }
+            layers.push(layer);
+            k /= 2;
+        }
+        p *= 2;
+    }
+    layers
+}
+/// 符号なしで小さい順に並べ替える
+pub fn sort<P: Logip, const BITS: usize>(pros: &P, xs: &[[P::R; BITS]]) -> Vec<[P::R; BITS]> {
+    let mut xs = xs.to_vec();
+    for layer in sorting_network(xs.len()) {
+        for (i, j) in layer {
+            let (lo, hi) = min_max(pros, &xs[i], &xs[j]);
+            xs[i] = lo;
+            xs[j] = hi;
+        }
+    }
+    xs
+}
+/// `sort`の並列版。同じ段の比較器を並列に評価する
+#[cfg(feature = "rayon")]
+pub fn par_sort<P: Logip + Sync, const BITS: usize>(
+    pros: &P,
+    xs: &[[P::R; BITS]],
+) -> Vec<[P::R; BITS]>
+where
+    P::R: Send + Sync,
+{
+    use rayon::prelude::*;
+    let mut xs = xs.to_vec();
+    for layer in sorting_network(xs.len()) {
+        let swapped: Vec<_> = layer
+            .par_iter()
+            .map(|&(i, j)| min_max(pros, &xs[i], &xs[j]))
+            .collect();
+        for ((i, j), (lo, hi)) in layer.into_iter().zip(swapped) {
+            xs[i] = lo;
+            xs[j] = hi;
+        }
+    }
+    xs
+}
+/// 1のbitの数。下の桁から`usize::BITS - bits.len().leading_zeros()`桁
+///
+/// 同じ桁のbitを全加算器で3つずつ2つ(その桁と上の桁)に減らしていく(Wallace tree)。
+/// 早くできたbitから使うので、段数は`log(bits.len())`に比例する。
+pub fn popcount<P: Logip>(pros: &P, bits: &[P::R]) -> Vec<P::R> {
+    let width = (usize::BITS - bits.len().leading_zeros()) as usize;
+    let mut columns: Vec<VecDeque<P::R>> = vec![VecDeque::new(); width];
+    if let Some(first) = columns.first_mut() {
+        first.extend(bits.iter().cloned());
+    }
+    let mut res = Vec::with_capacity(width);
+    for k in 0..width {
+        while columns[k].len() > 1 {
+            let a = columns[k].pop_front().unwrap();
+            let b = columns[k].pop_front().unwrap();
+            let (sum, carry) = match columns[k].pop_front() {
+                Some(c) => pros.full_add(a, b, c),
+                None => pros.half_add(a, b),
+            };
+            columns[k].push_back(sum);
+            // 桁数は足りているので、最上位の桁からの桁上げは常に0
+            if k + 1 < width {
+                columns[k + 1].push_back(carry);
+            }
+        }
+        res.push(columns[k].pop_front().unwrap_or_else(P::R::logic_false));
+    }
+    res
+}
+
+/// 隣り合う2つずつをfでまとめることを1つになるまで繰り返す。段数は`log2(xs.len())`
+pub(crate) fn reduce_pairs<T>(mut xs: Vec<T>, mut f: impl FnMut(T, T) -> T) -> Option<T> {
+    while xs.len() > 1 {
+        let mut next = Vec::with_capacity(xs.len().div_ceil(2));
+        let mut iter = xs.into_iter();
+        while let Some(a) = iter.next() {
+            next.push(match iter.next() {
+                Some(b) => f(a, b),
+                None => a,
+            });
+        }
+        xs = next;
+    }
+    xs.pop()
+}
+
+/// Kogge-Stone型の並列プレフィックス加算器。(和, 最上位からの桁上げ)
+///
+/// 桁上げを距離1,2,4,...の順にまとめるので、段数は`2 * log2(BITS) + 1`程度で済む。
+/// ゲートは`add`より多いが、同じ段のゲートは互いに依存しない。
+pub fn add_kogge_stone<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+) -> ([P::R; BITS], P::R) {
+    let (mut sum, carry) = kogge_stone(&mut Eval(pros), a.to_vec(), b.to_vec());
+    (
+        mem::array_create_enumerate(|i| std::mem::replace(&mut sum[i], carry.clone())),
+        carry,
+    )
+}
+/// `add_kogge_stone`と同じ回路。出力は和の`bits`個のbitと桁上げ
+pub fn kogge_stone_adder<R: AsLogic>(bits: usize) -> Circuit<R> {
+    binary_circuit(bits, |arena, a, b| {
+        let (mut sum, carry) = kogge_stone(arena, a, b);
+        sum.push(carry);
+        sum
+    })
+}
+
+fn kogge_stone<G: Gates>(g: &mut G, a: Vec<G::W>, b: Vec<G::W>) -> (Vec<G::W>, G::W) {
+    let bits = a.len();
+    if bits == 0 {
+        return (Vec::new(), g.zero());
+    }
+    // i桁目で桁上げが生まれるか(generate)、下からの桁上げを伝えるか(propagate)
+    let mut gen: Vec<G::W> = Vec::with_capacity(bits);
+    let mut prop: Vec<G::W> = Vec::with_capacity(bits);
+    for (a, b) in a.into_iter().zip(b) {
+        gen.push(g.and(a.clone(), b.clone()));
+        prop.push(g.xor(a, b));
+    }
+    let half_sum = prop.clone();
+    // 距離dの段のあとで、gen[i]はi-2d+1..=i桁の範囲から上への桁上げ
+    let mut d = 1;
+    while d < bits {
+        let (prev_gen, prev_prop) = (gen.clone(), prop.clone());
+        for i in d..bits {
+            let carried = g.and(prev_prop[i].clone(), prev_gen[i - d].clone());
+            gen[i] = g.or(prev_gen[i].clone(), carried);
+            // 次の段で使うのは2d桁目から上だけ
+            if i >= 2 * d {
+                prop[i] = g.and(prev_prop[i].clone(), prev_prop[i - d].clone());
+            }
+        }
+        d *= 2;
+    }
+    let mut sum = Vec::with_capacity(bits);
+    sum.push(half_sum[0].clone());
+    for i in 1..bits {
+        sum.push(g.xor(half_sum[i].clone(), gen[i - 1].clone()));
+    }
+    (sum, gen[bits - 1].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlainLogip;
+    use hom_nand::{digest::Cryptor, tfhe::TFHE, tlwe::TLWE};
+    use utils::math::{Binary, BinaryDistribution, Random};
+
+    fn to_bits<const BITS: usize>(x: u64) -> [Binary; BITS] {
+        mem::array_create_enumerate(|i| Binary::from((x >> i) as u32 & 1))
+    }
+    fn from_bits(bits: &[Binary]) -> u64 {
+        bits.iter().enumerate().map(|(i, &b)| (b as u64) << i).sum()
+    }
+
+    #[test]
+    fn add_test() {
+        for (a, b) in [
+            (0, 0),
+            (1, 1),
+            (100, 27),
+            (200, 100),
+            (255, 255),
+            (0x5a, 0xa5),
+        ] {
+            let (sum, carry) = add(&PlainLogip, &to_bits::<8>(a), &to_bits::<8>(b));
+            assert_eq!(
+                from_bits(&sum) + ((carry as u64) << 8),
+                a + b,
+                "{}+{}",
+                a,
+                b
+            );
+        }
+        let (_, carry) = add::<_, 0>(&PlainLogip, &[], &[]);
+        assert_eq!(carry, Binary::Zero);
+    }
+
+    #[test]
+    fn add_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+        let enc = |x| to_bits::<4>(x).map(|b| Cryptor::encrypto(TLWE, &s_key_tlwelv0, b));
+        let (sum, carry) = add(&tfhe, &enc(11), &enc(6));
+        let mut res = sum.to_vec();
+        res.push(carry);
+        let res: Vec<Binary> = res
+            .into_iter()
+            .map(|r| Cryptor::decrypto(TLWE, &s_key_tlwelv0, r))
+            .collect();
+        assert_eq!(from_bits(&res), 17);
+        assert_eq!(tfhe.stats().total_gates(), 5 * 4 - 3);
+    }
+
+    #[test]
+    fn add_kogge_stone_test() {
+        for (a, b) in [
+            (0, 0),
+            (1, 1),
+            (100, 27),
+            (200, 100),
+            (255, 255),
+            (0x5a, 0xa5),
+            (0x7f, 1),
+        ] {
+            let (sum, carry) = add_kogge_stone(&PlainLogip, &to_bits::<8>(a), &to_bits::<8>(b));
+            assert_eq!(
+                from_bits(&sum) + ((carry as u64) << 8),
+                a + b,
+                "{}+{}",
+                a,
+                b
+            );
+        }
+        let (sum, carry) = add_kogge_stone(&PlainLogip, &to_bits::<3>(5), &to_bits::<3>(6));
+        assert_eq!(from_bits(&sum) + ((carry as u64) << 3), 11);
+
+        let circuit = kogge_stone_adder::<Binary>(32);
+        let (a, b) = (0x89ab_cdef_u64, 0xfedc_ba98_u64);
+        let inputs = [to_bits::<32>(a), to_bits::<32>(b)].concat();
+        assert_eq!(from_bits(&circuit.eval(&PlainLogip, &inputs)), a + b);
+        // 入力の段を除いて2 * log2(32) + 1段。ripple-carryなら60段を超える
+        assert_eq!(circuit.levels().len(), 1 + 2 * 5 + 1);
+    }
+
+    #[test]
+    fn sub_neg_test() {
+        for (a, b) in [(0, 0), (5, 3), (3, 5), (0, 255), (200, 100), (128, 1)] {
+            let (diff, borrow) = sub(&PlainLogip, &to_bits::<8>(a), &to_bits::<8>(b));
+            assert_eq!(from_bits(&diff), a.wrapping_sub(b) & 0xff, "{}-{}", a, b);
+            assert_eq!(borrow == Binary::One, a < b, "{}-{}", a, b);
+        }
+        for a in [0, 1, 2, 100, 127, 128, 129, 255] {
+            let (res, overflow) = neg(&PlainLogip, &to_bits::<8>(a));
+            assert_eq!(from_bits(&res), a.wrapping_neg() & 0xff, "-{}", a);
+            assert_eq!(overflow == Binary::One, a == 128, "-{}", a);
+        }
+        let (res, overflow) = neg(&PlainLogip, &[Binary::One]);
+        assert_eq!((res, overflow), ([Binary::One], Binary::One));
+    }
+
+    #[test]
+    fn mul_test() {
+        for (a, b) in [
+            (0, 0),
+            (1, 1),
+            (3, 5),
+            (12, 13),
+            (255, 255),
+            (200, 100),
+            (0x5a, 0xa5),
+        ] {
+            let (low, high) = mul_wide(&PlainLogip, &to_bits::<8>(a), &to_bits::<8>(b));
+            assert_eq!(
+                from_bits(&low) + (from_bits(&high) << 8),
+                a * b,
+                "{}*{}",
+                a,
+                b
+            );
+            let res = mul(&PlainLogip, &to_bits::<8>(a), &to_bits::<8>(b));
+            assert_eq!(from_bits(&res), (a * b) & 0xff, "{}*{}", a, b);
+        }
+        let (low, high) = mul_wide(&PlainLogip, &to_bits::<1>(1), &to_bits::<1>(1));
+        assert_eq!((from_bits(&low), from_bits(&high)), (1, 0));
+    }
+
+    #[test]
+    fn div_rem_test() {
+        for (a, b) in [
+            (0, 1),
+            (7, 2),
+            (100, 7),
+            (255, 1),
+            (255, 255),
+            (200, 201),
+            (128, 3),
+            (9, 0),
+        ] {
+            let (q, r) = div_rem(&PlainLogip, &to_bits::<8>(a), &to_bits::<8>(b));
+            let (q, r) = (from_bits(&q), from_bits(&r));
+            // 0で割ったときは商の全ての桁が1
+            let expect = a.checked_div(b).map_or((255, a), |q| (q, a % b));
+            assert_eq!((q, r), expect, "{}/{}", a, b);
+        }
+        // 全ての組
+        for a in 0..16 {
+            for b in 1..16 {
+                let (q, r) = div_rem(&PlainLogip, &to_bits::<4>(a), &to_bits::<4>(b));
+                assert_eq!(
+                    (from_bits(&q), from_bits(&r)),
+                    (a / b, a % b),
+                    "{}/{}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compare_test() {
+        let signed = |x: u64| ((x as i64) << 60) >> 60;
+        for a in 0..16 {
+            for b in 0..16 {
+                let (x, y) = (to_bits::<4>(a), to_bits::<4>(b));
+                let p = &PlainLogip;
+                let res = [eq(p, &x, &y), lt(p, &x, &y), le(p, &x, &y)];
+                let expect = [a == b, a < b, a <= b];
+                assert_eq!(res.map(|r| r == Binary::One), expect, "{} {}", a, b);
+                let res = [lt_signed(p, &x, &y), le_signed(p, &x, &y)];
+                let (sa, sb) = (signed(a), signed(b));
+                assert_eq!(
+                    res.map(|r| r == Binary::One),
+                    [sa < sb, sa <= sb],
+                    "{} {}",
+                    sa,
+                    sb
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn min_max_test() {
+        let p = &PlainLogip;
+        for (a, b) in [(0, 0), (3, 5), (5, 3), (255, 0), (128, 127)] {
+            let (x, y) = (to_bits::<8>(a), to_bits::<8>(b));
+            let (lo, hi) = min_max(p, &x, &y);
+            assert_eq!((from_bits(&lo), from_bits(&hi)), (a.min(b), a.max(b)));
+            assert_eq!(from_bits(&min(p, &x, &y)), a.min(b));
+            assert_eq!(from_bits(&max(p, &x, &y)), a.max(b));
+        }
+        let values = [42, 7, 199, 7, 63, 200, 1];
+        for n in 1..=values.len() {
+            let xs: Vec<_> = values[..n].iter().map(|&x| to_bits::<8>(x)).collect();
+            let lo = min_of(p, &xs).unwrap();
+            let hi = max_of(p, &xs).unwrap();
+            assert_eq!(from_bits(&lo), *values[..n].iter().min().unwrap());
+            assert_eq!(from_bits(&hi), *values[..n].iter().max().unwrap());
+        }
+        assert!(min_of::<_, 8>(p, &[]).is_none());
+    }
+
+    #[test]
+    fn overflow_flags_test() {
+        let p = &PlainLogip;
+        let flag = |b: Binary| b == Binary::One;
+        for a in 0..=255u64 {
+            for b in [0u64, 1, 2, 3, 15, 16, 100, 127, 128, 200, 255] {
+                let (x, y) = (to_bits::<8>(a), to_bits::<8>(b));
+                let (sa, sb) = (a as u8 as i8, b as u8 as i8);
+                let (res, overflow) = overflowing_add_signed(p, &x, &y);
+                let (expect, o) = sa.overflowing_add(sb);
+                assert_eq!((from_bits(&res), flag(overflow)), (expect as u8 as u64, o));
+                let (res, overflow) = overflowing_sub_signed(p, &x, &y);
+                let (expect, o) = sa.overflowing_sub(sb);
+                assert_eq!((from_bits(&res), flag(overflow)), (expect as u8 as u64, o));
+                let (res, overflow) = overflowing_mul(p, &x, &y);
+                let (expect, o) = (a as u8).overflowing_mul(b as u8);
+                assert_eq!((from_bits(&res), flag(overflow)), (expect as u64, o));
+                let (res, overflow) = overflowing_mul_signed(p, &x, &y);
+                let (expect, o) = sa.overflowing_mul(sb);
+                assert_eq!(
+                    (from_bits(&res), flag(overflow)),
+                    (expect as u8 as u64, o),
+                    "{}*{}",
+                    sa,
+                    sb
+                );
+            }
+            assert_eq!(flag(is_zero(p, &to_bits::<8>(a))), a == 0);
+        }
+        assert_eq!(is_zero::<_, 0>(p, &[]), Binary::One);
+    }
+
+    #[test]
+    fn popcount_test() {
+        let p = &PlainLogip;
+        for n in 0..40usize {
+            for pattern in [0u64, u64::MAX, 0x5555_5555_5555_5555, 0x8e3a_50f1_27c4_9bd6] {
+                let bits: Vec<Binary> = (0..n)
+                    .map(|i| Binary::from((pattern >> i) as u32 & 1))
+                    .collect();
+                let count = popcount(p, &bits);
+                assert_eq!(count.len(), (usize::BITS - n.leading_zeros()) as usize);
+                let expect = bits.iter().filter(|&&b| b == Binary::One).count() as u64;
+                assert_eq!(from_bits(&count), expect, "{} bits of {:x}", n, pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn sorting_network_test() {
+        for n in 0..=12usize {
+            let layers = sorting_network(n);
+            for layer in &layers {
+                let mut used: Vec<usize> = layer.iter().flat_map(|&(i, j)| [i, j]).collect();
+                used.sort_unstable();
+                used.dedup();
+                assert_eq!(used.len(), 2 * layer.len(), "n = {}", n);
+            }
+            // 0と1の全ての並びを並べ替えられれば、どの並びも並べ替えられる
+            for pattern in 0u32..1 << n {
+                let mut xs: Vec<u32> = (0..n).map(|i| pattern >> i & 1).collect();
+                for &(i, j) in layers.iter().flatten() {
+                    if xs[i] > xs[j] {
+                        xs.swap(i, j);
+                    }
+                }
+                assert!(xs.windows(2).all(|w| w[0] <= w[1]), "n = {}", n);
+            }
+        }
+        assert_eq!(sorting_network(8).len(), 6);
+    }
+
+    #[test]
+    fn sort_test() {
+        let values = [42u64, 7, 199, 7, 0, 255, 63, 1, 128];
+        let xs: Vec<_> = values.iter().map(|&x| to_bits::<8>(x)).collect();
+        let mut expect = values.to_vec();
+        expect.sort_unstable();
+        let sorted: Vec<u64> = sort(&PlainLogip, &xs)
+            .iter()
+            .map(|x| from_bits(x))
+            .collect();
+        assert_eq!(sorted, expect);
+        #[cfg(feature = "rayon")]
+        {
+            let sorted: Vec<u64> = par_sort(&PlainLogip, &xs)
+                .iter()
+                .map(|x| from_bits(x))
+                .collect();
+            assert_eq!(sorted, expect);
+        }
+    }
+}
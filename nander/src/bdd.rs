@@ -0,0 +1,366 @@
+/*!
+既約順序付き二分決定図(ROBDD)。
+
+変数の順序を決めると、同じ論理関数は同じBDDになる。
+式が恒真・充足不能かどうかや、2つの式が同じ関数かどうか(`equivalent`)をすぐに確かめられる。
+`simplify_with_bdd`はBDDから式を作り直し、元の式より小さければそちらを返す。制御回路のように同じ変数で場合分けを繰り返す式で効く。
+
+変数の順序は式の中で最初に出てきた順。値が分からない値(Leaf)は、それぞれを1つの変数として扱う。
+
+```ignore
+let exp = parse_logic_expr::<Binary>("(a & b) | (a & !b)")?;
+assert!(equivalent(&exp, &parse_logic_expr("a")?));
+```
+ */
+use crate::optimize::count_gates;
+use crate::LogicExpr;
+use std::collections::HashMap;
+use utils::traits::AsLogic;
+
+const FALSE: usize = 0;
+const TRUE: usize = 1;
+
+/// varで場合分けして、0ならlo、1ならhiに進む
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct BddNode {
+    var: usize,
+    lo: usize,
+    hi: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Op {
+    And,
+    Or,
+    Xor,
+}
+
+/// BDDの変数
+enum Input<R> {
+    Var(String),
+    Leaf(R),
+}
+
+pub struct Bdd<R> {
+    /// 0,1番目は定数
+    nodes: Vec<BddNode>,
+    unique: HashMap<BddNode, usize>,
+    computed: HashMap<(Op, usize, usize), usize>,
+    inputs: Vec<Input<R>>,
+    /// 変数の名前 -> その変数だけのBDD
+    vars: HashMap<String, usize>,
+    root: usize,
+}
+impl<R: AsLogic + Clone> Bdd<R> {
+    pub fn from_expr(exp: &LogicExpr<R>) -> Self {
+        let terminal = BddNode {
+            var: usize::MAX,
+            lo: FALSE,
+            hi: FALSE,
+        };
+        let mut bdd = Bdd {
+            nodes: vec![terminal, terminal],
+            unique: HashMap::new(),
+            computed: HashMap::new(),
+            inputs: Vec::new(),
+            vars: HashMap::new(),
+            root: FALSE,
+        };
+        bdd.root = bdd.build(exp);
+        bdd
+    }
+    /// expのBDDを作って根を返す。変数は今までのものと共有する
+    fn build(&mut self, exp: &LogicExpr<R>) -> usize {
+        // 子を評価してから親を評価する
+        let mut stack = vec![(exp, false)];
+        let mut res: Vec<usize> = Vec::new();
+        while let Some((exp, ready)) = stack.pop() {
+            let children: Vec<&LogicExpr<R>> = match exp {
+                LogicExpr::Nand(l, r)
+                | LogicExpr::And(l, r)
+                | LogicExpr::Or(l, r)
+                | LogicExpr::Xor(l, r) => vec![l, r],
+                LogicExpr::Not(e) => vec![e],
+                LogicExpr::Leaf(r) => {
+                    let node = match r.as_const() {
+                        Some(c) => c as usize,
+                        None => self.input(Input::Leaf(r.clone())),
+                    };
+                    res.push(node);
+                    continue;
+                }
+                LogicExpr::Var(name) => {
+                    let node = match self.vars.get(name) {
+                        Some(&node) => node,
+                        None => {
+                            let node = self.input(Input::Var(name.clone()));
+                            self.vars.insert(name.clone(), node);
+                            node
+                        }
+                    };
+                    res.push(node);
+                    continue;
+                }
+            };
+            if !ready {
+                stack.push((exp, true));
+                // 左から先に変数を見るように逆順に積む
+                stack.extend(children.into_iter().rev().map(|e| (e, false)));
+                continue;
+            }
+            let node = match exp {
+                LogicExpr::Not(_) => {
+                    let e = res.pop().unwrap();
+                    self.not(e)
+                }
+                _ => {
+                    let (r, l) = (res.pop().unwrap(), res.pop().unwrap());
+                    match exp {
+                        LogicExpr::Nand(_, _) => {
+                            let and = self.apply(Op::And, l, r);
+                            self.not(and)
+                        }
+                        LogicExpr::And(_, _) => self.apply(Op::And, l, r),
+                        LogicExpr::Or(_, _) => self.apply(Op::Or, l, r),
+                        _ => self.apply(Op::Xor, l, r),
+                    }
+                }
+            };
+            res.push(node);
+        }
+        res.pop().unwrap()
+    }
+
+    /// 新しい変数を最後の順位に加える
+    fn input(&mut self, input: Input<R>) -> usize {
+        self.inputs.push(input);
+        self.make(self.inputs.len() - 1, FALSE, TRUE)
+    }
+    fn make(&mut self, var: usize, lo: usize, hi: usize) -> usize {
+        if lo == hi {
+            return lo;
+        }
+        let node = BddNode { var, lo, hi };
+        if let Some(&id) = self.unique.get(&node) {
+            return id;
+        }
+        self.nodes.push(node);
+        self.unique.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+    fn not(&mut self, a: usize) -> usize {
+        self.apply(Op::Xor, a, TRUE)
+    }
+    /// 深さは変数の数までなので再帰する
+    fn apply(&mut self, op: Op, a: usize, b: usize) -> usize {
+        let (a, b) = (a.min(b), a.max(b));
+        match (op, a, b) {
+            (Op::And, FALSE, _) => return FALSE,
+            (Op::And, TRUE, _) | (Op::Or, FALSE, _) | (Op::Xor, FALSE, _) => return b,
+            (Op::Or, TRUE, _) => return TRUE,
+            (Op::And, _, _) | (Op::Or, _, _) if a == b => return a,
+            (Op::Xor, _, _) if a == b => return FALSE,
+            _ => {}
+        }
+        if let Some(&res) = self.computed.get(&(op, a, b)) {
+            return res;
+        }
+        let (na, nb) = (self.nodes[a], self.nodes[b]);
+        let var = na.var.min(nb.var);
+        let cofactor = |n: BddNode, id: usize| {
+            if n.var == var {
+                (n.lo, n.hi)
+            } else {
+                (id, id)
+            }
+        };
+        let ((alo, ahi), (blo, bhi)) = (cofactor(na, a), cofactor(nb, b));
+        let lo = self.apply(op, alo, blo);
+        let hi = self.apply(op, ahi, bhi);
+        let res = self.make(var, lo, hi);
+        self.computed.insert((op, a, b), res);
+        res
+    }
+
+    /// どの入力でも1
+    pub fn is_tautology(&self) -> bool {
+        self.root == TRUE
+    }
+    /// どの入力でも0
+    pub fn is_unsatisfiable(&self) -> bool {
+        self.root == FALSE
+    }
+    /// 1になる入力の1つ。場合分けしない変数は0にする。Leafの変数の値は含まない
+    pub fn satisfy(&self) -> Option<HashMap<String, bool>> {
+        if self.root == FALSE {
+            return None;
+        }
+        let mut assign: HashMap<String, bool> =
+            self.vars.keys().map(|v| (v.clone(), false)).collect();
+        // 既約なので、FALSE以外のノードからは必ずTRUEに辿り着く
+        let mut id = self.root;
+        while id != TRUE {
+            let BddNode { var, lo, hi } = self.nodes[id];
+            let value = lo == FALSE;
+            if let Input::Var(name) = &self.inputs[var] {
+                assign.insert(name.clone(), value);
+            }
+            id = if value { hi } else { lo };
+        }
+        Some(assign)
+    }
+    /// 根から辿れる場合分けのノードの数
+    pub fn size(&self) -> usize {
+        self.reachable().len()
+    }
+
+    /// 場合分けのノードを子から順に並べる
+    fn reachable(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut seen = vec![false; self.nodes.len()];
+        let mut stack = vec![(self.root, false)];
+        while let Some((id, ready)) = stack.pop() {
+            if id <= TRUE || (seen[id] && !ready) {
+                continue;
+            }
+            if ready {
+                order.push(id);
+                continue;
+            }
+            seen[id] = true;
+            let node = self.nodes[id];
+            stack.extend([(id, true), (node.hi, false), (node.lo, false)]);
+        }
+        order
+    }
+    /// 場合分けをゲートに直した式
+    pub fn to_expr(&self) -> LogicExpr<R> {
+        let mut exprs: HashMap<usize, LogicExpr<R>> = HashMap::new();
+        let constant = |c: usize| {
+            LogicExpr::Leaf(if c == TRUE {
+                R::logic_true()
+            } else {
+                R::logic_false()
+            })
+        };
+        if self.root <= TRUE {
+            return constant(self.root);
+        }
+        for id in self.reachable() {
+            let BddNode { var, lo, hi } = self.nodes[id];
+            let v = || match &self.inputs[var] {
+                Input::Var(name) => LogicExpr::Var(name.clone()),
+                Input::Leaf(r) => LogicExpr::Leaf(r.clone()),
+            };
+            let not_v = || LogicExpr::Not(Box::new(v()));
+            let child = |id: usize| Box::new(exprs[&id].clone());
+            let exp = match (lo, hi) {
+                (FALSE, TRUE) => v(),
+                (TRUE, FALSE) => not_v(),
+                (FALSE, _) => LogicExpr::And(Box::new(v()), child(hi)),
+                (_, FALSE) => LogicExpr::And(Box::new(not_v()), child(lo)),
+                (_, TRUE) => LogicExpr::Or(Box::new(v()), child(lo)),
+                (TRUE, _) => LogicExpr::Or(Box::new(not_v()), child(hi)),
+                _ => LogicExpr::Or(
+                    Box::new(LogicExpr::And(Box::new(v()), child(hi))),
+                    Box::new(LogicExpr::And(Box::new(not_v()), child(lo))),
+                ),
+            };
+            exprs.insert(id, exp);
+        }
+        exprs.remove(&self.root).unwrap()
+    }
+}
+
+/// 同じ論理関数か。Leafは値が分かる定数でなければ別々の変数として扱う
+pub fn equivalent<R: AsLogic + Clone>(lhs: &LogicExpr<R>, rhs: &LogicExpr<R>) -> bool {
+    // 同じBDDの中なら、同じ関数は同じノードになる
+    let mut bdd = Bdd::from_expr(lhs);
+    bdd.build(rhs) == bdd.root
+}
+
+/// BDDから作り直した式がbootstrapの少ない式なら、そちらを返す
+pub fn simplify_with_bdd<R: AsLogic + Clone>(exp: &LogicExpr<R>) -> LogicExpr<R> {
+    let resynth = Bdd::from_expr(exp).to_expr();
+    let cost = |exp: &LogicExpr<R>| {
+        let count = count_gates(exp);
+        (count.bootstraps(), count.total())
+    };
+    if cost(&resynth) < cost(exp) {
+        resynth
+    } else {
+        exp.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_logic_expr_with, parse_logic_expr, PlainLogip};
+    use utils::math::Binary;
+
+    fn bdd(s: &str) -> Bdd<Binary> {
+        Bdd::from_expr(&parse_logic_expr(s).unwrap())
+    }
+
+    #[test]
+    fn bdd_check() {
+        assert!(bdd("a | !a").is_tautology());
+        assert!(bdd("(a $ b) | (a & b)").is_tautology());
+        assert!(bdd("a & !a").is_unsatisfiable());
+        assert!(!bdd("a & b").is_unsatisfiable());
+        let exp = |s: &str| parse_logic_expr::<Binary>(s).unwrap();
+        assert!(equivalent(&exp("!(a & b)"), &exp("!a | !b")));
+        // 変数の出てくる順が違っても同じ関数
+        assert!(equivalent(&exp("a ^ b ^ c"), &exp("c ^ (b ^ a)")));
+        assert!(!equivalent(&exp("a ^ b"), &exp("a | b")));
+        assert_eq!(bdd("(a & b) | (a & !b)").size(), 1);
+        assert_eq!(bdd("a ^ b ^ c").size(), 5);
+    }
+
+    #[test]
+    fn bdd_satisfy() {
+        assert_eq!(bdd("a & !a").satisfy(), None);
+        let exp = parse_logic_expr::<Binary>("a & !b & (c | d)").unwrap();
+        let assign = Bdd::from_expr(&exp).satisfy().unwrap();
+        assert_eq!(assign.len(), 4);
+        let vars = assign
+            .iter()
+            .map(|(k, &v)| (k.clone(), Binary::from(v as u32)))
+            .collect();
+        assert_eq!(
+            eval_logic_expr_with(&PlainLogip, exp, &vars),
+            Ok(Binary::One)
+        );
+    }
+
+    #[test]
+    fn simplify_with_bdd_test() {
+        let cases = [
+            ("(a & b) | (a & !b)", 0),
+            ("(s & a) | (!s & a) | (s & b & !b)", 0),
+            ("((a & b) | (a & c)) | ((a & b) & c)", 2),
+            // 元の方が小さいときはそのまま
+            ("a ^ b", 1),
+        ];
+        for (s, bootstraps) in cases {
+            let exp = parse_logic_expr::<Binary>(s).unwrap();
+            let simple = simplify_with_bdd(&exp);
+            assert_eq!(count_gates(&simple).bootstraps(), bootstraps, "{}", s);
+            for i in 0..16 {
+                let vars: HashMap<String, Binary> = ["a", "b", "c", "s"]
+                    .iter()
+                    .enumerate()
+                    .map(|(j, v)| (v.to_string(), Binary::from((i >> j) as u32 & 1)))
+                    .collect();
+                assert_eq!(
+                    eval_logic_expr_with(&PlainLogip, simple.clone(), &vars),
+                    eval_logic_expr_with(&PlainLogip, exp.clone(), &vars),
+                    "{} at {}",
+                    s,
+                    i
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,520 @@
+/*!
+暗号化したbit列。
+
+`FheBits<P>`は長さが実行時に決まるbit列で、`&`,`|`,`^`,`!`をbitごとに評価する。
+`rayon`featureを有効にすると、`par_and`などでbitごとのゲートを並列に評価できる。1bitごとにbootstrapするTFHEでは、長いbit列ほど効く。
+
+```ignore
+let a = FheBits::encrypt(&tfhe, &s_key, &payload);
+let b = FheBits::encrypt(&tfhe, &s_key, &mask);
+let masked = a.par_and(&b);
+let header = masked.slice(..32).concat(&a.slice(32..));
+
+let name = FheBits::encrypt_str(&tfhe, &s_key, "alice", BitOrder::Lsb0);
+assert_eq!(name.decrypt_string(&s_key, BitOrder::Lsb0).unwrap(), "alice");
+```
+ */
+use crate::{
+    arith::{popcount, reduce_pairs},
+    Logip,
+};
+use hom_nand::{
+    digest::Cryptor,
+    tfhe::{BootstrappingKeyRows, TFHE},
+    tlwe::TLWE,
+};
+use std::ops::{BitAnd, BitOr, BitXor, Bound, Not, RangeBounds};
+use std::string::FromUtf8Error;
+use utils::{math::Binary, traits::AsLogic};
+
+/// 1byteをbit列にするときの順
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitOrder {
+    /// 最下位bitから(整数の`x[0]`と同じ)
+    #[default]
+    Lsb0,
+    /// 最上位bitから(ネットワークでの順)
+    Msb0,
+}
+impl BitOrder {
+    /// 1byteの中でi番目に並べるbitの桁
+    fn shift(self, i: usize) -> usize {
+        match self {
+            BitOrder::Lsb0 => i,
+            BitOrder::Msb0 => 7 - i,
+        }
+    }
+}
+
+/// 1byteを8bitずつ並べる
+pub fn bytes_to_bits(bytes: &[u8], order: BitOrder) -> Vec<Binary> {
+    bytes
+        .iter()
+        .flat_map(|&byte| (0..8).map(move |i| Binary::from((byte >> order.shift(i)) & 1)))
+        .collect()
+}
+/// `bytes_to_bits`の逆
+/// # Panic
+/// - 長さが8の倍数でない
+pub fn bits_to_bytes(bits: &[Binary], order: BitOrder) -> Vec<u8> {
+    assert_eq!(bits.len() % 8, 0, "length must be a multiple of 8");
+    bits.chunks(8)
+        .map(|byte| {
+            byte.iter()
+                .enumerate()
+                .map(|(i, &b)| (b as u8) << order.shift(i))
+                .sum()
+        })
+        .collect()
+}
+
+pub struct FheBits<'a, P: Logip> {
+    pros: &'a P,
+    bits: Vec<P::R>,
+}
+impl<'a, P: Logip> FheBits<'a, P> {
+    pub fn new(pros: &'a P, bits: Vec<P::R>) -> Self {
+        FheBits { pros, bits }
+    }
+    /// 暗号化していない値
+    pub fn trivial(pros: &'a P, bits: &[bool]) -> Self {
+        let bits = bits
+            .iter()
+            .map(|&b| {
+                if b {
+                    P::R::logic_true()
+                } else {
+                    P::R::logic_false()
+                }
+            })
+            .collect();
+        Self::new(pros, bits)
+    }
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+    pub fn bits(&self) -> &[P::R] {
+        &self.bits
+    }
+    pub fn into_bits(self) -> Vec<P::R> {
+        self.bits
+    }
+    pub fn pros(&self) -> &'a P {
+        self.pros
+    }
+    pub fn decrypt_with(&self, decrypt: impl Fn(&P::R) -> Binary) -> Vec<Binary> {
+        self.bits.iter().map(decrypt).collect()
+    }
+
+    /// rangeの部分のコピー
+    /// # Panic
+    /// - rangeが長さを超える
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Self {
+        let range: (Bound<usize>, Bound<usize>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        Self::new(self.pros, self.bits[range].to_vec())
+    }
+    /// selfの後ろにrhsを繋げる
+    pub fn concat(&self, rhs: &Self) -> Self {
+        Self::new(self.pros, [&self.bits[..], &rhs.bits[..]].concat())
+    }
+    /// (前のmid個, 残り)
+    /// # Panic
+    /// - `mid > self.len()`
+    pub fn split_at(&self, mid: usize) -> (Self, Self) {
+        (self.slice(..mid), self.slice(mid..))
+    }
+
+    pub fn and(&self, rhs: &Self) -> Self {
+        self.zip_with(rhs, P::and)
+    }
+    pub fn or(&self, rhs: &Self) -> Self {
+        self.zip_with(rhs, P::or)
+    }
+    pub fn xor(&self, rhs: &Self) -> Self {
+        self.zip_with(rhs, P::xor)
+    }
+    pub fn not(&self) -> Self {
+        let bits = self.bits.iter().map(|b| self.pros.not(b.clone())).collect();
+        Self::new(self.pros, bits)
+    }
+    /// condが1ならthen、0ならelse_。bitごとにMUXを使う
+    /// # Panic
+    /// - thenとelse_の長さが違う
+    pub fn select(cond: &P::R, then: &Self, else_: &Self) -> Self {
+        assert_eq!(then.len(), else_.len(), "length mismatch");
+        let bits = then
+            .bits
+            .iter()
+            .zip(&else_.bits)
+            .map(|(t, e)| then.pros.mux(cond.clone(), e.clone(), t.clone()))
+            .collect();
+        Self::new(then.pros, bits)
+    }
+
+    /// 同じbit列か。長さが違えば0
+    ///
+    /// bitごとのxnorを2つずつandでまとめるので、段数は`log2(len) + 1`で済む。
+    pub fn eq(&self, rhs: &Self) -> P::R {
+        if self.len() != rhs.len() {
+            return P::R::logic_false();
+        }
+        let same = self.zip_with(rhs, P::xnor).bits;
+        reduce_pairs(same, |l, r| self.pros.and(l, r)).unwrap_or_else(P::R::logic_true)
+    }
+
+    /// 1のbitの数。下の桁からのbit列
+    pub fn count_ones(&self) -> Self {
+        Self::new(self.pros, popcount(self.pros, &self.bits))
+    }
+    /// 違うbitの数(`(self ^ rhs).count_ones()`)
+    /// # Panic
+    /// - 長さが違う
+    pub fn hamming_distance(&self, rhs: &Self) -> Self {
+        self.xor(rhs).count_ones()
+    }
+
+    /// # Panic
+    /// - 長さが違う
+    fn zip_with(&self, rhs: &Self, gate: fn(&P, P::R, P::R) -> P::R) -> Self {
+        assert_eq!(self.len(), rhs.len(), "length mismatch");
+        let bits = self
+            .bits
+            .iter()
+            .zip(&rhs.bits)
+            .map(|(l, r)| gate(self.pros, l.clone(), r.clone()))
+            .collect();
+        Self::new(self.pros, bits)
+    }
+}
+#[cfg(feature = "rayon")]
+impl<'a, P: Logip + Sync> FheBits<'a, P>
+where
+    P::R: Send + Sync,
+{
+    /// `and`の並列版。使うプールは`rayon::ThreadPool::install`の中で呼んで選ぶ
+    pub fn par_and(&self, rhs: &Self) -> Self {
+        self.par_zip_with(rhs, P::and)
+    }
+    pub fn par_or(&self, rhs: &Self) -> Self {
+        self.par_zip_with(rhs, P::or)
+    }
+    pub fn par_xor(&self, rhs: &Self) -> Self {
+        self.par_zip_with(rhs, P::xor)
+    }
+    pub fn par_not(&self) -> Self {
+        use rayon::prelude::*;
+        let bits = self
+            .bits
+            .par_iter()
+            .map(|b| self.pros.not(b.clone()))
+            .collect();
+        Self::new(self.pros, bits)
+    }
+
+    /// `eq`の並列版。同じ段のandを並列に評価する
+    pub fn par_eq(&self, rhs: &Self) -> P::R {
+        use rayon::prelude::*;
+        if self.len() != rhs.len() {
+            return P::R::logic_false();
+        }
+        let mut same = self.par_zip_with(rhs, P::xnor).bits;
+        while same.len() > 1 {
+            same = same
+                .par_chunks(2)
+                .map(|pair| match pair {
+                    [l, r] => self.pros.and(l.clone(), r.clone()),
+                    _ => pair[0].clone(),
+                })
+                .collect();
+        }
+        same.pop().unwrap_or_else(P::R::logic_true)
+    }
+
+    /// `select`の並列版
+    pub fn par_select(cond: &P::R, then: &Self, else_: &Self) -> Self {
+        use rayon::prelude::*;
+        assert_eq!(then.len(), else_.len(), "length mismatch");
+        let bits = then
+            .bits
+            .par_iter()
+            .zip(&else_.bits)
+            .map(|(t, e)| then.pros.mux(cond.clone(), e.clone(), t.clone()))
+            .collect();
+        Self::new(then.pros, bits)
+    }
+
+    fn par_zip_with(&self, rhs: &Self, gate: fn(&P, P::R, P::R) -> P::R) -> Self {
+        use rayon::prelude::*;
+        assert_eq!(self.len(), rhs.len(), "length mismatch");
+        let bits = self
+            .bits
+            .par_iter()
+            .zip(&rhs.bits)
+            .map(|(l, r)| gate(self.pros, l.clone(), r.clone()))
+            .collect();
+        Self::new(self.pros, bits)
+    }
+}
+impl<'a, const N: usize, const M: usize, BK: BootstrappingKeyRows<M>> FheBits<'a, TFHE<N, M, BK>> {
+    /// s_keyはTLWE(lv0)の秘密鍵
+    pub fn encrypt(tfhe: &'a TFHE<N, M, BK>, s_key: &[Binary; N], bits: &[Binary]) -> Self {
+        let bits = bits
+            .iter()
+            .map(|&b| Cryptor::encrypto(TLWE, s_key, b))
+            .collect();
+        Self::new(tfhe, bits)
+    }
+    pub fn decrypt(&self, s_key: &[Binary; N]) -> Vec<Binary> {
+        self.decrypt_with(|b| Cryptor::decrypto(TLWE, s_key, b.clone()))
+    }
+    pub fn encrypt_bytes(
+        tfhe: &'a TFHE<N, M, BK>,
+        s_key: &[Binary; N],
+        bytes: &[u8],
+        order: BitOrder,
+    ) -> Self {
+        Self::encrypt(tfhe, s_key, &bytes_to_bits(bytes, order))
+    }
+    /// UTF-8のbyte列を暗号化する
+    pub fn encrypt_str(
+        tfhe: &'a TFHE<N, M, BK>,
+        s_key: &[Binary; N],
+        s: &str,
+        order: BitOrder,
+    ) -> Self {
+        Self::encrypt_bytes(tfhe, s_key, s.as_bytes(), order)
+    }
+    /// # Panic
+    /// - 長さが8の倍数でない
+    pub fn decrypt_bytes(&self, s_key: &[Binary; N], order: BitOrder) -> Vec<u8> {
+        bits_to_bytes(&self.decrypt(s_key), order)
+    }
+    /// # Panic
+    /// - 長さが8の倍数でない
+    pub fn decrypt_string(
+        &self,
+        s_key: &[Binary; N],
+        order: BitOrder,
+    ) -> Result<String, FromUtf8Error> {
+        String::from_utf8(self.decrypt_bytes(s_key, order))
+    }
+}
+impl<P: Logip> Clone for FheBits<'_, P> {
+    fn clone(&self) -> Self {
+        Self::new(self.pros, self.bits.clone())
+    }
+}
+
+macro_rules! impl_bitwise_op {
+    ($Op:ident, $op:ident, $method:ident) => {
+        impl<'a, P: Logip> $Op<&FheBits<'a, P>> for &FheBits<'a, P> {
+            type Output = FheBits<'a, P>;
+            fn $op(self, rhs: &FheBits<'a, P>) -> Self::Output {
+                self.$method(rhs)
+            }
+        }
+        impl<'a, P: Logip> $Op<FheBits<'a, P>> for &FheBits<'a, P> {
+            type Output = FheBits<'a, P>;
+            fn $op(self, rhs: FheBits<'a, P>) -> Self::Output {
+                self.$method(&rhs)
+            }
+        }
+        impl<'a, P: Logip> $Op<&FheBits<'a, P>> for FheBits<'a, P> {
+            type Output = FheBits<'a, P>;
+            fn $op(self, rhs: &FheBits<'a, P>) -> Self::Output {
+                self.$method(rhs)
+            }
+        }
+        impl<'a, P: Logip> $Op for FheBits<'a, P> {
+            type Output = FheBits<'a, P>;
+            fn $op(self, rhs: FheBits<'a, P>) -> Self::Output {
+                self.$method(&rhs)
+            }
+        }
+    };
+}
+impl_bitwise_op!(BitAnd, bitand, and);
+impl_bitwise_op!(BitOr, bitor, or);
+impl_bitwise_op!(BitXor, bitxor, xor);
+impl<'a, P: Logip> Not for &FheBits<'a, P> {
+    type Output = FheBits<'a, P>;
+    fn not(self) -> Self::Output {
+        FheBits::not(self)
+    }
+}
+impl<'a, P: Logip> Not for FheBits<'a, P> {
+    type Output = FheBits<'a, P>;
+    fn not(self) -> Self::Output {
+        FheBits::not(&self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlainLogip;
+    use utils::math::{BinaryDistribution, Random};
+
+    fn bools(bits: &FheBits<PlainLogip>) -> Vec<bool> {
+        bits.decrypt_with(|b| *b)
+            .into_iter()
+            .map(|b| b == Binary::One)
+            .collect()
+    }
+
+    #[test]
+    fn fhe_bits_ops() {
+        let p = &PlainLogip;
+        let xs = [true, false, true, true, false, false, true];
+        let ys = [false, false, true, false, true, true, true];
+        let (a, b) = (FheBits::trivial(p, &xs), FheBits::trivial(p, &ys));
+        let expect = |f: fn(bool, bool) -> bool| -> Vec<bool> {
+            xs.iter().zip(&ys).map(|(&x, &y)| f(x, y)).collect()
+        };
+        assert_eq!(bools(&(&a & &b)), expect(|x, y| x & y));
+        assert_eq!(bools(&(&a | &b)), expect(|x, y| x | y));
+        assert_eq!(bools(&(a.clone() ^ b.clone())), expect(|x, y| x ^ y));
+        assert_eq!(bools(&!&a), expect(|x, _| !x));
+
+        assert_eq!(bools(&a.slice(2..5)), xs[2..5].to_vec());
+        assert_eq!(bools(&a.slice(..=1)), xs[..=1].to_vec());
+        let (l, r) = a.split_at(3);
+        assert_eq!((l.len(), r.len()), (3, 4));
+        assert_eq!(bools(&l.concat(&r)), xs.to_vec());
+        assert!(a.slice(3..3).is_empty());
+
+        #[cfg(feature = "rayon")]
+        {
+            assert_eq!(bools(&a.par_and(&b)), expect(|x, y| x & y));
+            assert_eq!(bools(&a.par_or(&b)), expect(|x, y| x | y));
+            assert_eq!(bools(&a.par_xor(&b)), expect(|x, y| x ^ y));
+            assert_eq!(bools(&a.par_not()), expect(|x, _| !x));
+        }
+    }
+
+    #[test]
+    fn fhe_bits_eq() {
+        let p = &PlainLogip;
+        let bytes_of = |s: &str| FheBits::new(p, bytes_to_bits(s.as_bytes(), BitOrder::Lsb0));
+        let cases = [
+            ("", "", true),
+            ("abcde", "abcde", true),
+            ("abcde", "abcdf", false),
+            ("ab", "abc", false),
+        ];
+        for (l, r, expect) in cases {
+            let (a, b) = (bytes_of(l), bytes_of(r));
+            assert_eq!(a.eq(&b), Binary::from(expect as u32), "{} == {}", l, r);
+            #[cfg(feature = "rayon")]
+            assert_eq!(a.par_eq(&b), Binary::from(expect as u32), "{} == {}", l, r);
+        }
+    }
+
+    #[test]
+    fn fhe_bits_hamming_distance() {
+        let p = &PlainLogip;
+        let bytes_of = |b: &[u8]| FheBits::new(p, bytes_to_bits(b, BitOrder::Lsb0));
+        let value = |x: FheBits<PlainLogip>| -> u64 {
+            x.decrypt_with(|b| *b)
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| (b as u64) << i)
+                .sum()
+        };
+        let (a, b) = (bytes_of(&[0xff, 0x0f, 0x00]), bytes_of(&[0x0f, 0x0f, 0x81]));
+        let dist = a.hamming_distance(&b);
+        // 24bitなので5桁
+        assert_eq!(dist.len(), 5);
+        assert_eq!(value(dist), 4 + 2);
+        assert_eq!(value(a.count_ones()), 12);
+        assert_eq!(value(a.hamming_distance(&a)), 0);
+    }
+
+    #[test]
+    fn fhe_bits_select() {
+        let p = &PlainLogip;
+        let xs = [true, false, true, true, false];
+        let ys = [false, false, true, false, true];
+        let (a, b) = (FheBits::trivial(p, &xs), FheBits::trivial(p, &ys));
+        assert_eq!(bools(&FheBits::select(&Binary::One, &a, &b)), xs.to_vec());
+        assert_eq!(bools(&FheBits::select(&Binary::Zero, &a, &b)), ys.to_vec());
+        #[cfg(feature = "rayon")]
+        {
+            assert_eq!(
+                bools(&FheBits::par_select(&Binary::One, &a, &b)),
+                xs.to_vec()
+            );
+            assert_eq!(
+                bools(&FheBits::par_select(&Binary::Zero, &a, &b)),
+                ys.to_vec()
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "length mismatch")]
+    fn fhe_bits_length_mismatch() {
+        let p = &PlainLogip;
+        let _ = FheBits::trivial(p, &[true, false]) & FheBits::trivial(p, &[true]);
+    }
+
+    #[test]
+    fn bytes_bits() {
+        let bits = bytes_to_bits(&[0b0000_0110, 0x80], BitOrder::Lsb0);
+        assert_eq!(bits.len(), 16);
+        assert_eq!(
+            (bits[1], bits[2], bits[15]),
+            (Binary::One, Binary::One, Binary::One)
+        );
+        assert_eq!(bits.iter().filter(|&&b| b == Binary::One).count(), 3);
+        let msb = bytes_to_bits(&[0b0000_0110, 0x80], BitOrder::Msb0);
+        assert_eq!(
+            (msb[5], msb[6], msb[8]),
+            (Binary::One, Binary::One, Binary::One)
+        );
+        let bytes = "héllo, 世界".as_bytes();
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            assert_eq!(bits_to_bytes(&bytes_to_bits(bytes, order), order), bytes);
+        }
+    }
+
+    #[test]
+    fn fhe_bits_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+        let xs = unif.gen_n::<8>();
+        let ys = unif.gen_n::<8>();
+        let a = FheBits::encrypt(&tfhe, &s_key_tlwelv0, &xs);
+        let b = FheBits::encrypt(&tfhe, &s_key_tlwelv0, &ys);
+        let expect: Vec<Binary> = xs
+            .iter()
+            .zip(&ys)
+            .map(|(&x, &y)| PlainLogip.xor(x, y))
+            .collect();
+        assert_eq!((&a ^ &b).decrypt(&s_key_tlwelv0), expect);
+        #[cfg(feature = "rayon")]
+        assert_eq!(a.par_xor(&b).decrypt(&s_key_tlwelv0), expect);
+        let s = FheBits::encrypt_str(&tfhe, &s_key_tlwelv0, "ok?", BitOrder::Msb0);
+        assert_eq!(s.len(), 24);
+        assert_eq!(
+            s.decrypt_string(&s_key_tlwelv0, BitOrder::Msb0),
+            Ok("ok?".to_string())
+        );
+        assert_eq!(
+            s.decrypt_bytes(&s_key_tlwelv0, BitOrder::Lsb0)[0],
+            b'o'.reverse_bits()
+        );
+        assert_eq!(
+            tfhe.stats().total_gates(),
+            if cfg!(feature = "rayon") { 16 } else { 8 }
+        );
+    }
+}
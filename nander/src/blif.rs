@@ -0,0 +1,572 @@
+/*!
+BLIF形式のネットリストを読み込む。
+
+Yosysで合成した回路を評価するためのもの。
+```text
+yosys -p "synth; abc -g AND,NAND,OR,NOR,XOR,XNOR,ANDNOT,ORNOT,MUX; write_blif out.blif" in.v
+```
+読めるのは組み合わせ回路の1つの`.model`で、次の行を使う。
+- `.inputs`,`.outputs`
+- `.names` : 積和形のカバー。出力の列が0の行は否定(OFF-set)として読む
+- `.subckt`,`.gate` : Yosysの内部セル(`$_AND_`など)。`$_`と`_`を外した`AND`などでもよい
+- `.conn` : 別名(バッファ)
+
+`.latch`などの順序回路と、自分で定義したモデルの`.subckt`は読めない。
+`#`から行末までは注釈で、`\`で終わる行は次の行に続く。
+ */
+use crate::arena::ExprArena;
+use crate::circuit::Circuit;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use utils::traits::AsLogic;
+
+pub struct BlifModel<R> {
+    pub name: String,
+    /// 入力は`.inputs`の順
+    pub circuit: Circuit<R>,
+    /// 出力の名前。`circuit.outputs()`と同じ順
+    pub outputs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlifError {
+    /// 知らないセル
+    UnknownCell { cell: String, line: usize },
+    /// セルのポートの接続がない
+    MissingPort {
+        cell: String,
+        port: &'static str,
+        line: usize,
+    },
+    /// カバーの行が読めない
+    InvalidCover { line: usize },
+    /// 読めない命令(`.latch`など)
+    Unsupported { directive: String, line: usize },
+    /// 2箇所から値を決めているネット
+    MultipleDrivers { net: String, line: usize },
+    /// どこでも値を決めていないネット
+    UndrivenNet { net: String },
+    /// 自分自身に依存するネット
+    CombinationalLoop { net: String },
+}
+impl Display for BlifError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlifError::UnknownCell { cell, line } => {
+                write!(f, "unknown cell '{}' at line {}", cell, line)
+            }
+            BlifError::MissingPort { cell, port, line } => {
+                write!(f, "cell '{}' has no port {} at line {}", cell, port, line)
+            }
+            BlifError::InvalidCover { line } => write!(f, "invalid cover at line {}", line),
+            BlifError::Unsupported { directive, line } => {
+                write!(f, "unsupported directive '{}' at line {}", directive, line)
+            }
+            BlifError::MultipleDrivers { net, line } => {
+                write!(f, "net '{}' is driven again at line {}", net, line)
+            }
+            BlifError::UndrivenNet { net } => write!(f, "net '{}' is not driven", net),
+            BlifError::CombinationalLoop { net } => {
+                write!(f, "combinational loop through net '{}'", net)
+            }
+        }
+    }
+}
+impl std::error::Error for BlifError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    Buf,
+    Not,
+    And,
+    Nand,
+    Or,
+    Nor,
+    Xor,
+    Xnor,
+    /// A & !B
+    AndNot,
+    /// A | !B
+    OrNot,
+    /// S ? B : A
+    Mux,
+    /// !(S ? B : A)
+    Nmux,
+    /// !((A & B) | C)
+    Aoi3,
+    /// !((A | B) & C)
+    Oai3,
+    /// !((A & B) | (C & D))
+    Aoi4,
+    /// !((A | B) & (C | D))
+    Oai4,
+}
+impl Cell {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.strip_prefix("$_").unwrap_or(name);
+        let name = name.strip_suffix('_').unwrap_or(name);
+        Some(match name.to_ascii_uppercase().as_str() {
+            "BUF" => Cell::Buf,
+            "NOT" => Cell::Not,
+            "AND" => Cell::And,
+            "NAND" => Cell::Nand,
+            "OR" => Cell::Or,
+            "NOR" => Cell::Nor,
+            "XOR" => Cell::Xor,
+            "XNOR" => Cell::Xnor,
+            "ANDNOT" => Cell::AndNot,
+            "ORNOT" => Cell::OrNot,
+            "MUX" => Cell::Mux,
+            "NMUX" => Cell::Nmux,
+            "AOI3" => Cell::Aoi3,
+            "OAI3" => Cell::Oai3,
+            "AOI4" => Cell::Aoi4,
+            "OAI4" => Cell::Oai4,
+            _ => return None,
+        })
+    }
+    /// 入力のポート。出力はY
+    fn ports(self) -> &'static [&'static str] {
+        match self {
+            Cell::Buf | Cell::Not => &["A"],
+            Cell::Mux | Cell::Nmux => &["A", "B", "S"],
+            Cell::Aoi3 | Cell::Oai3 => &["A", "B", "C"],
+            Cell::Aoi4 | Cell::Oai4 => &["A", "B", "C", "D"],
+            _ => &["A", "B"],
+        }
+    }
+}
+
+/// ネットの値の決め方
+enum Driver {
+    Cell(Cell),
+    /// 入力の各値(0,1,-)と出力の値の組
+    Cover(Vec<(Vec<u8>, u8)>),
+}
+struct Def {
+    driver: Driver,
+    inputs: Vec<String>,
+    line: usize,
+}
+
+pub fn import_blif<R: AsLogic>(src: &str) -> Result<BlifModel<R>, BlifError> {
+    let mut name = String::new();
+    let mut inputs: Vec<String> = Vec::new();
+    let mut outputs: Vec<String> = Vec::new();
+    let mut defs: HashMap<String, Def> = HashMap::new();
+    // 読んでいる途中の.namesの出力
+    let mut cover: Option<String> = None;
+
+    let define = |defs: &mut HashMap<String, Def>, net: String, def: Def| {
+        if defs.contains_key(&net) {
+            return Err(BlifError::MultipleDrivers {
+                net,
+                line: def.line,
+            });
+        }
+        defs.insert(net, def);
+        Ok(())
+    };
+    for (line, tokens) in lines(src) {
+        let directive = tokens[0];
+        if !directive.starts_with('.') {
+            // カバーの行
+            let net = cover.as_ref().ok_or(BlifError::InvalidCover { line })?;
+            let def = defs.get_mut(net).unwrap();
+            let (row, out) = match tokens[..] {
+                [out] if def.inputs.is_empty() => ("", out),
+                [row, out] => (row, out),
+                _ => return Err(BlifError::InvalidCover { line }),
+            };
+            let valid_row =
+                row.len() == def.inputs.len() && row.bytes().all(|b| b"01-".contains(&b));
+            if !valid_row || !(out == "0" || out == "1") {
+                return Err(BlifError::InvalidCover { line });
+            }
+            match &mut def.driver {
+                Driver::Cover(rows) => rows.push((row.bytes().collect(), out.as_bytes()[0])),
+                Driver::Cell(_) => unreachable!(),
+            }
+            continue;
+        }
+        cover = None;
+        match directive {
+            ".model" => name = tokens.get(1).unwrap_or(&"").to_string(),
+            ".inputs" => inputs.extend(tokens[1..].iter().map(|s| s.to_string())),
+            ".outputs" => outputs.extend(tokens[1..].iter().map(|s| s.to_string())),
+            ".names" => {
+                let (net, ins) = match tokens[1..].split_last() {
+                    Some(split) => split,
+                    None => return Err(BlifError::InvalidCover { line }),
+                };
+                let def = Def {
+                    driver: Driver::Cover(Vec::new()),
+                    inputs: ins.iter().map(|s| s.to_string()).collect(),
+                    line,
+                };
+                define(&mut defs, net.to_string(), def)?;
+                cover = Some(net.to_string());
+            }
+            ".subckt" | ".gate" => {
+                let cell_name = tokens.get(1).copied().unwrap_or("");
+                let cell = Cell::from_name(cell_name).ok_or_else(|| BlifError::UnknownCell {
+                    cell: cell_name.to_string(),
+                    line,
+                })?;
+                let conns: HashMap<&str, &str> = tokens[2..]
+                    .iter()
+                    .filter_map(|conn| conn.split_once('='))
+                    .collect();
+                let port = |port: &'static str| {
+                    conns.get(port).map(|net| net.to_string()).ok_or_else(|| {
+                        BlifError::MissingPort {
+                            cell: cell_name.to_string(),
+                            port,
+                            line,
+                        }
+                    })
+                };
+                let def = Def {
+                    driver: Driver::Cell(cell),
+                    inputs: cell
+                        .ports()
+                        .iter()
+                        .map(|p| port(p))
+                        .collect::<Result<_, _>>()?,
+                    line,
+                };
+                define(&mut defs, port("Y")?, def)?;
+            }
+            ".conn" => match tokens[1..] {
+                [from, to] => {
+                    let def = Def {
+                        driver: Driver::Cell(Cell::Buf),
+                        inputs: vec![from.to_string()],
+                        line,
+                    };
+                    define(&mut defs, to.to_string(), def)?;
+                }
+                _ => {
+                    return Err(BlifError::Unsupported {
+                        directive: directive.to_string(),
+                        line,
+                    })
+                }
+            },
+            // Yosysが書く注釈
+            ".attr" | ".param" | ".cname" => {}
+            ".end" => break,
+            _ => {
+                return Err(BlifError::Unsupported {
+                    directive: directive.to_string(),
+                    line,
+                })
+            }
+        }
+    }
+
+    let mut arena = ExprArena::new();
+    // 入力を先に並べて、回路の入力の順を.inputsの順にする
+    let mut ids: HashMap<&str, usize> = inputs.iter().map(|i| (i.as_str(), arena.var(i))).collect();
+    let mut roots = Vec::with_capacity(outputs.len());
+    for output in outputs.iter() {
+        roots.push(build(&mut arena, &defs, &mut ids, output)?);
+    }
+    Ok(BlifModel {
+        name,
+        circuit: Circuit::from_arena(arena, &roots),
+        outputs,
+    })
+}
+
+/// 注釈と継続行を処理して、(行番号, 空白で区切った語)にする
+fn lines(src: &str) -> Vec<(usize, Vec<&str>)> {
+    let mut res = Vec::new();
+    let mut pending: Option<(usize, Vec<&str>)> = None;
+    for (i, raw) in src.lines().enumerate() {
+        let text = raw.split('#').next().unwrap();
+        let (text, continued) = match text.trim_end().strip_suffix('\\') {
+            Some(text) => (text, true),
+            None => (text, false),
+        };
+        let (line, mut tokens) = pending.take().unwrap_or((i + 1, Vec::new()));
+        tokens.extend(text.split_whitespace());
+        if continued {
+            pending = Some((line, tokens));
+        } else if !tokens.is_empty() {
+            res.push((line, tokens));
+        }
+    }
+    res.extend(pending.filter(|(_, tokens)| !tokens.is_empty()));
+    res
+}
+
+/// netのノードを作って番号を返す。再帰せずに、入力から順に作る
+fn build<'a, R: AsLogic>(
+    arena: &mut ExprArena<R>,
+    defs: &'a HashMap<String, Def>,
+    ids: &mut HashMap<&'a str, usize>,
+    net: &'a str,
+) -> Result<usize, BlifError> {
+    let mut visiting = HashSet::new();
+    let mut stack = vec![(net, false)];
+    while let Some((net, ready)) = stack.pop() {
+        if ids.contains_key(net) {
+            continue;
+        }
+        let (net, def) = defs
+            .get_key_value(net)
+            .ok_or_else(|| BlifError::UndrivenNet {
+                net: net.to_string(),
+            })?;
+        if ready {
+            let inputs: Vec<usize> = def.inputs.iter().map(|i| ids[i.as_str()]).collect();
+            let id = match &def.driver {
+                Driver::Cell(cell) => build_cell(arena, *cell, &inputs),
+                Driver::Cover(rows) => build_cover(arena, rows, &inputs),
+            };
+            ids.insert(net, id);
+            visiting.remove(net.as_str());
+            continue;
+        }
+        visiting.insert(net.as_str());
+        stack.push((net, true));
+        for input in def.inputs.iter() {
+            if visiting.contains(input.as_str()) {
+                return Err(BlifError::CombinationalLoop { net: input.clone() });
+            }
+            if !ids.contains_key(input.as_str()) {
+                stack.push((input, false));
+            }
+        }
+    }
+    Ok(ids[net])
+}
+
+fn build_cell<R: AsLogic>(arena: &mut ExprArena<R>, cell: Cell, inputs: &[usize]) -> usize {
+    let (a, b) = (inputs[0], *inputs.get(1).unwrap_or(&0));
+    match cell {
+        Cell::Buf => a,
+        Cell::Not => arena.not(a),
+        Cell::And => arena.and(a, b),
+        Cell::Nand => arena.nand(a, b),
+        Cell::Or => arena.or(a, b),
+        Cell::Nor => {
+            let or = arena.or(a, b);
+            arena.not(or)
+        }
+        Cell::Xor => arena.xor(a, b),
+        Cell::Xnor => {
+            let xor = arena.xor(a, b);
+            arena.not(xor)
+        }
+        Cell::AndNot => {
+            let not_b = arena.not(b);
+            arena.and(a, not_b)
+        }
+        Cell::OrNot => {
+            let not_b = arena.not(b);
+            arena.or(a, not_b)
+        }
+        Cell::Mux | Cell::Nmux => {
+            let s = inputs[2];
+            let not_s = arena.not(s);
+            let (a, b) = (arena.and(a, not_s), arena.and(b, s));
+            let mux = arena.or(a, b);
+            if cell == Cell::Nmux {
+                arena.not(mux)
+            } else {
+                mux
+            }
+        }
+        Cell::Aoi3 | Cell::Oai3 => {
+            let c = inputs[2];
+            let res = if cell == Cell::Aoi3 {
+                let ab = arena.and(a, b);
+                arena.or(ab, c)
+            } else {
+                let ab = arena.or(a, b);
+                arena.and(ab, c)
+            };
+            arena.not(res)
+        }
+        Cell::Aoi4 | Cell::Oai4 => {
+            let (c, d) = (inputs[2], inputs[3]);
+            let res = if cell == Cell::Aoi4 {
+                let (ab, cd) = (arena.and(a, b), arena.and(c, d));
+                arena.or(ab, cd)
+            } else {
+                let (ab, cd) = (arena.or(a, b), arena.or(c, d));
+                arena.and(ab, cd)
+            };
+            arena.not(res)
+        }
+    }
+}
+
+/// 各行を入力の積にして、その和を取る
+fn build_cover<R: AsLogic>(
+    arena: &mut ExprArena<R>,
+    rows: &[(Vec<u8>, u8)],
+    inputs: &[usize],
+) -> usize {
+    // 行がなければ常に0
+    let off_set = rows.first().is_some_and(|&(_, out)| out == b'0');
+    let mut sum = None;
+    for (row, _) in rows {
+        let mut product = None;
+        for (&lit, &input) in row.iter().zip(inputs) {
+            let lit = match lit {
+                b'1' => input,
+                b'0' => arena.not(input),
+                _ => continue,
+            };
+            product = Some(match product {
+                Some(p) => arena.and(p, lit),
+                None => lit,
+            });
+        }
+        let product = product.unwrap_or_else(|| arena.leaf(R::logic_true()));
+        sum = Some(match sum {
+            Some(s) => arena.or(s, product),
+            None => product,
+        });
+    }
+    let sum = sum.unwrap_or_else(|| arena.leaf(R::logic_false()));
+    if off_set {
+        arena.not(sum)
+    } else {
+        sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlainLogip;
+    use utils::math::Binary;
+
+    /// Yosysが書く形の全加算器
+    const FULL_ADDER: &str = r#"
+# Generated by Yosys
+.model full_adder
+.inputs a b \
+  cin
+.outputs sum cout
+.names $false
+.names $true
+1
+.subckt $_XOR_ A=a B=b Y=$abc$1
+.subckt $_XOR_ A=$abc$1 B=cin Y=sum
+.gate AND A=a B=b Y=$abc$2
+.names $abc$1 cin $abc$2 cout
+11- 1
+--1 1
+.end
+"#;
+
+    #[test]
+    fn import_blif_full_adder() {
+        let model = import_blif::<Binary>(FULL_ADDER).unwrap();
+        assert_eq!(model.name, "full_adder");
+        assert_eq!(model.outputs, ["sum", "cout"]);
+        assert_eq!(model.circuit.inputs(), ["a", "b", "cin"]);
+        for i in 0..8 {
+            let bit = |j: usize| Binary::from((i >> j) & 1);
+            let res = model.circuit.eval(&PlainLogip, &[bit(0), bit(1), bit(2)]);
+            let s = (i & 1) + ((i >> 1) & 1) + ((i >> 2) & 1);
+            assert_eq!(
+                res,
+                vec![Binary::from(s & 1), Binary::from(s >> 1)],
+                "{}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn import_blif_cells_and_covers() {
+        let src = "
+.model cells
+.inputs a b s
+.outputs mux nor andnot zero one off aoi
+.subckt $_MUX_ A=a B=b S=s Y=mux
+.subckt $_NOR_ A=a B=b Y=nor
+.subckt $_ANDNOT_ A=a B=b Y=andnot
+.names zero
+.names one
+1
+.names a b off
+00 0
+.subckt $_AOI3_ A=a B=b C=s Y=aoi
+.end
+";
+        let model = import_blif::<Binary>(src).unwrap();
+        for i in 0..8 {
+            let (a, b, s) = (i & 1, (i >> 1) & 1, (i >> 2) & 1);
+            let res = model.circuit.eval(
+                &PlainLogip,
+                &[Binary::from(a), Binary::from(b), Binary::from(s)],
+            );
+            let expect = [
+                if s == 1 { b } else { a },
+                1 ^ (a | b),
+                a & (1 ^ b),
+                0,
+                1,
+                a | b,
+                1 ^ ((a & b) | s),
+            ];
+            let expect: Vec<Binary> = expect.iter().map(|&v| Binary::from(v)).collect();
+            assert_eq!(res, expect, "a={} b={} s={}", a, b, s);
+        }
+    }
+
+    #[test]
+    fn import_blif_errors() {
+        let err = |src: &str| import_blif::<Binary>(src).err().unwrap();
+        assert_eq!(
+            err(".inputs a\n.outputs y\n.subckt $_DFF_P_ C=a D=a Q=y\n"),
+            BlifError::UnknownCell {
+                cell: "$_DFF_P_".to_string(),
+                line: 3
+            }
+        );
+        assert_eq!(
+            err(".inputs a\n.outputs y\n.subckt $_AND_ A=a Y=y\n"),
+            BlifError::MissingPort {
+                cell: "$_AND_".to_string(),
+                port: "B",
+                line: 3
+            }
+        );
+        assert_eq!(
+            err(".inputs a\n.outputs y\n.names a y\n12 1\n"),
+            BlifError::InvalidCover { line: 4 }
+        );
+        assert_eq!(
+            err(".inputs a\n.outputs y\n.latch a y re clk 0\n"),
+            BlifError::Unsupported {
+                directive: ".latch".to_string(),
+                line: 3
+            }
+        );
+        assert_eq!(
+            err(".inputs a\n.outputs y\n.names a y\n1 1\n.names a y\n0 1\n"),
+            BlifError::MultipleDrivers {
+                net: "y".to_string(),
+                line: 5
+            }
+        );
+        assert_eq!(
+            err(".inputs a\n.outputs y\n.subckt $_AND_ A=a B=b Y=y\n"),
+            BlifError::UndrivenNet {
+                net: "b".to_string()
+            }
+        );
+        assert!(matches!(
+            err(".inputs a\n.outputs y\n.subckt $_AND_ A=a B=t Y=y\n.subckt $_NOT_ A=y Y=t\n"),
+            BlifError::CombinationalLoop { .. }
+        ));
+    }
+}
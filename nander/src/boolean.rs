@@ -0,0 +1,197 @@
+/*!
+暗号化した真理値。
+
+`FheBool<P>`は暗号文と評価器への参照を持ち、`&`,`|`,`^`,`!`でその場でゲートを評価する。
+`pros.or(pros.and(a, pros.not(b)), c)`を`&a & !&b | &c`と書ける。
+
+```ignore
+let a = FheBool::encrypt(&tfhe, &s_key, true);
+let b = FheBool::encrypt(&tfhe, &s_key, false);
+let c = FheBool::encrypt(&tfhe, &s_key, false);
+let res = &a & !&b | &c;
+assert!(res.decrypt(&s_key));
+```
+ */
+use crate::Logip;
+use hom_nand::{
+    digest::Cryptor,
+    tfhe::{BootstrappingKeyRows, TFHE},
+    tlwe::TLWE,
+};
+use std::ops::{BitAnd, BitOr, BitXor, Not};
+use utils::{math::Binary, traits::AsLogic};
+
+pub struct FheBool<'a, P: Logip> {
+    pros: &'a P,
+    bit: P::R,
+}
+impl<'a, P: Logip> FheBool<'a, P> {
+    pub fn new(pros: &'a P, bit: P::R) -> Self {
+        FheBool { pros, bit }
+    }
+    /// 暗号化していない値
+    pub fn trivial(pros: &'a P, value: bool) -> Self {
+        let bit = if value {
+            P::R::logic_true()
+        } else {
+            P::R::logic_false()
+        };
+        Self::new(pros, bit)
+    }
+    pub fn bit(&self) -> &P::R {
+        &self.bit
+    }
+    pub fn into_bit(self) -> P::R {
+        self.bit
+    }
+    pub fn pros(&self) -> &'a P {
+        self.pros
+    }
+    pub fn decrypt_with(&self, decrypt: impl Fn(&P::R) -> Binary) -> bool {
+        decrypt(&self.bit) == Binary::One
+    }
+
+    pub fn nand(&self, rhs: &Self) -> Self {
+        self.gate(rhs, P::nand)
+    }
+    pub fn and(&self, rhs: &Self) -> Self {
+        self.gate(rhs, P::and)
+    }
+    pub fn or(&self, rhs: &Self) -> Self {
+        self.gate(rhs, P::or)
+    }
+    pub fn xor(&self, rhs: &Self) -> Self {
+        self.gate(rhs, P::xor)
+    }
+    pub fn nor(&self, rhs: &Self) -> Self {
+        self.gate(rhs, P::nor)
+    }
+    pub fn xnor(&self, rhs: &Self) -> Self {
+        self.gate(rhs, P::xnor)
+    }
+    /// !self | rhs
+    pub fn implies(&self, rhs: &Self) -> Self {
+        self.gate(rhs, P::implies)
+    }
+    pub fn not(&self) -> Self {
+        Self::new(self.pros, self.pros.not(self.bit.clone()))
+    }
+    /// selfが1ならthen、0ならelse_
+    pub fn select(&self, then: &Self, else_: &Self) -> Self {
+        let bit = self
+            .pros
+            .mux(self.bit.clone(), else_.bit.clone(), then.bit.clone());
+        Self::new(self.pros, bit)
+    }
+
+    fn gate(&self, rhs: &Self, gate: fn(&P, P::R, P::R) -> P::R) -> Self {
+        Self::new(
+            self.pros,
+            gate(self.pros, self.bit.clone(), rhs.bit.clone()),
+        )
+    }
+}
+impl<'a, const N: usize, const M: usize, BK: BootstrappingKeyRows<M>> FheBool<'a, TFHE<N, M, BK>> {
+    /// s_keyはTLWE(lv0)の秘密鍵
+    pub fn encrypt(tfhe: &'a TFHE<N, M, BK>, s_key: &[Binary; N], value: bool) -> Self {
+        Self::new(
+            tfhe,
+            Cryptor::encrypto(TLWE, s_key, Binary::from(value as u32)),
+        )
+    }
+    pub fn decrypt(&self, s_key: &[Binary; N]) -> bool {
+        self.decrypt_with(|b| Cryptor::decrypto(TLWE, s_key, b.clone()))
+    }
+}
+impl<P: Logip> Clone for FheBool<'_, P> {
+    fn clone(&self) -> Self {
+        Self::new(self.pros, self.bit.clone())
+    }
+}
+
+macro_rules! impl_bool_op {
+    ($Op:ident, $op:ident, $method:ident) => {
+        impl<'a, P: Logip> $Op<&FheBool<'a, P>> for &FheBool<'a, P> {
+            type Output = FheBool<'a, P>;
+            fn $op(self, rhs: &FheBool<'a, P>) -> Self::Output {
+                self.$method(rhs)
+            }
+        }
+        impl<'a, P: Logip> $Op<FheBool<'a, P>> for &FheBool<'a, P> {
+            type Output = FheBool<'a, P>;
+            fn $op(self, rhs: FheBool<'a, P>) -> Self::Output {
+                self.$method(&rhs)
+            }
+        }
+        impl<'a, P: Logip> $Op<&FheBool<'a, P>> for FheBool<'a, P> {
+            type Output = FheBool<'a, P>;
+            fn $op(self, rhs: &FheBool<'a, P>) -> Self::Output {
+                self.$method(rhs)
+            }
+        }
+        impl<'a, P: Logip> $Op for FheBool<'a, P> {
+            type Output = FheBool<'a, P>;
+            fn $op(self, rhs: FheBool<'a, P>) -> Self::Output {
+                self.$method(&rhs)
+            }
+        }
+    };
+}
+impl_bool_op!(BitAnd, bitand, and);
+impl_bool_op!(BitOr, bitor, or);
+impl_bool_op!(BitXor, bitxor, xor);
+impl<'a, P: Logip> Not for &FheBool<'a, P> {
+    type Output = FheBool<'a, P>;
+    fn not(self) -> Self::Output {
+        FheBool::not(self)
+    }
+}
+impl<'a, P: Logip> Not for FheBool<'a, P> {
+    type Output = FheBool<'a, P>;
+    fn not(self) -> Self::Output {
+        FheBool::not(&self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlainLogip;
+    use utils::math::{BinaryDistribution, Random};
+
+    #[test]
+    fn fhe_bool_ops() {
+        let p = &PlainLogip;
+        let value = |b: FheBool<PlainLogip>| b.decrypt_with(|b| *b);
+        for i in 0..8 {
+            let (x, y, z) = (i & 1 == 1, i & 2 == 2, i & 4 == 4);
+            let (a, b, c) = (
+                FheBool::trivial(p, x),
+                FheBool::trivial(p, y),
+                FheBool::trivial(p, z),
+            );
+            assert_eq!(value(&a & !&b | &c), x & !y | z);
+            assert_eq!(value(a.clone() ^ b.clone()), x ^ y);
+            assert_eq!(value(a.nand(&b)), !(x & y));
+            assert_eq!(value(a.nor(&b)), !(x | y));
+            assert_eq!(value(a.xnor(&b)), x == y);
+            assert_eq!(value(a.implies(&b)), !x | y);
+            assert_eq!(value(a.select(&b, &c)), if x { y } else { z });
+        }
+    }
+
+    #[test]
+    fn fhe_bool_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+        let a = FheBool::encrypt(&tfhe, &s_key_tlwelv0, true);
+        let b = FheBool::encrypt(&tfhe, &s_key_tlwelv0, false);
+        let c = FheBool::encrypt(&tfhe, &s_key_tlwelv0, false);
+        assert!((&a & !&b | &c).decrypt(&s_key_tlwelv0));
+        assert!(!(a ^ FheBool::trivial(&tfhe, true)).decrypt(&s_key_tlwelv0));
+    }
+}
@@ -0,0 +1,394 @@
+/*!
+ゲートの列で表した回路(IR)。
+
+ワイヤ(番号)ごとに1つのノード(入力の変数・値・ゲート)がある。ゲートの入力はそれより前のワイヤなので、前から順に評価すればよい。
+入力は変数の名前で、出力はいくつでも持てる。
+
+`from_exprs`は同じ形の部分式を1本のワイヤにまとめる(式をまたいでもまとめる)。
+`LogicExpr`は木なので同じ部分式が何度出てきてもその回数だけ評価するが、`Circuit`では1回ずつになる。
+
+`rayon`featureを有効にすると、`par_eval_with`で同じ段(`levels`)のゲートを並列に評価できる。
+bootstrapするゲートは1つ10ms程度かかり、同じ段のゲートは互いに依存しないので、段の幅の分だけ速くなる。
+
+```ignore
+let circuit = Circuit::from_exprs(parse_bus_expr("x[7:0] ^ y[7:0]")?);
+let res: Vec<_> = circuit.eval(&tfhe, &inputs); // inputsはcircuit.inputs()の順
+```
+ */
+use crate::arena::ExprArena;
+use crate::optimize::GateCount;
+use crate::{EvalError, LogicExpr, Logip};
+use std::collections::HashMap;
+use utils::traits::AsLogic;
+
+/// ワイヤの番号
+pub type Wire = usize;
+
+/// ゲートの入力は前にあるノードの番号
+pub enum Node<R> {
+    Leaf(R),
+    Var(String),
+    Not(usize),
+    Nand(usize, usize),
+    And(usize, usize),
+    Or(usize, usize),
+    Xor(usize, usize),
+}
+
+/// 同じ形のノードを探すためのキー。暗号文のように値が分からない値はまとめない
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Const(bool),
+    Var(String),
+    Not(Wire),
+    Nand(Wire, Wire),
+    And(Wire, Wire),
+    Or(Wire, Wire),
+    Xor(Wire, Wire),
+}
+
+pub struct Circuit<R> {
+    nodes: Vec<Node<R>>,
+    inputs: Vec<String>,
+    outputs: Vec<Wire>,
+}
+impl<R: AsLogic> Circuit<R> {
+    /// 出力が1つの回路
+    pub fn from_expr(exp: LogicExpr<R>) -> Self {
+        Self::from_exprs(vec![exp])
+    }
+    /// 式ごとに1つの出力を持つ回路。`parse_bus_expr`の結果ならbitごとの出力になる
+    pub fn from_exprs(exps: Vec<LogicExpr<R>>) -> Self {
+        // 一度番号で指す形にしてから、前から順にまとめる
+        let mut arena = ExprArena::new();
+        let roots: Vec<usize> = exps.into_iter().map(|e| arena.push_expr(e)).collect();
+        Self::from_arena(arena, &roots)
+    }
+    /// arenaのrootsを出力とする回路。arenaの中で共有しているノードはそのまま共有する
+    pub fn from_arena(arena: ExprArena<R>, roots: &[usize]) -> Self {
+        let mut circuit = Circuit {
+            nodes: Vec::with_capacity(arena.len()),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+        let mut keys = HashMap::new();
+        // arenaのノードの番号 -> ワイヤ
+        let mut wires: Vec<Wire> = Vec::with_capacity(arena.len());
+        for node in arena.into_nodes() {
+            // 二項演算子はどれも可換なので、入力の番号を並べ替えてからキーにする
+            let sorted = |l: usize, r: usize| (wires[l].min(wires[r]), wires[l].max(wires[r]));
+            let (key, node) = match node {
+                Node::Leaf(r) => (r.as_const().map(Key::Const), Node::Leaf(r)),
+                Node::Var(name) => (Some(Key::Var(name.clone())), Node::Var(name)),
+                Node::Not(e) => (Some(Key::Not(wires[e])), Node::Not(wires[e])),
+                Node::Nand(l, r) => {
+                    let (l, r) = sorted(l, r);
+                    (Some(Key::Nand(l, r)), Node::Nand(l, r))
+                }
+                Node::And(l, r) => {
+                    let (l, r) = sorted(l, r);
+                    (Some(Key::And(l, r)), Node::And(l, r))
+                }
+                Node::Or(l, r) => {
+                    let (l, r) = sorted(l, r);
+                    (Some(Key::Or(l, r)), Node::Or(l, r))
+                }
+                Node::Xor(l, r) => {
+                    let (l, r) = sorted(l, r);
+                    (Some(Key::Xor(l, r)), Node::Xor(l, r))
+                }
+            };
+            let wire = match key {
+                Some(key) => *keys.entry(key).or_insert_with(|| circuit.push(node)),
+                None => circuit.push(node),
+            };
+            wires.push(wire);
+        }
+        circuit.outputs = roots.iter().map(|&root| wires[root]).collect();
+        circuit
+    }
+    fn push(&mut self, node: Node<R>) -> Wire {
+        if let Node::Var(name) = &node {
+            self.inputs.push(name.clone());
+        }
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+    pub fn nodes(&self) -> &[Node<R>] {
+        &self.nodes
+    }
+    /// 入力の変数の名前。出てきた順
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+    pub fn outputs(&self) -> &[Wire] {
+        &self.outputs
+    }
+    /// ワイヤを段に分ける。入力と値が0段目で、ゲートは入力の段の最大より1つ後。
+    /// 同じ段のゲートは互いに依存しないので、同時に評価できる
+    pub fn levels(&self) -> Vec<Vec<Wire>> {
+        let mut depth = Vec::with_capacity(self.nodes.len());
+        let mut levels: Vec<Vec<Wire>> = Vec::new();
+        for (w, node) in self.nodes.iter().enumerate() {
+            let d = match node {
+                Node::Leaf(_) | Node::Var(_) => 0,
+                Node::Not(e) => depth[*e] + 1,
+                Node::Nand(l, r) | Node::And(l, r) | Node::Or(l, r) | Node::Xor(l, r) => {
+                    usize::max(depth[*l], depth[*r]) + 1
+                }
+            };
+            depth.push(d);
+            if levels.len() <= d {
+                levels.resize_with(d + 1, Vec::new);
+            }
+            levels[d].push(w);
+        }
+        levels
+    }
+    pub fn gate_count(&self) -> GateCount {
+        let mut count = GateCount::default();
+        for node in self.nodes.iter() {
+            match node {
+                Node::Nand(..) => count.nand += 1,
+                Node::And(..) => count.and += 1,
+                Node::Or(..) => count.or += 1,
+                Node::Xor(..) => count.xor += 1,
+                Node::Not(_) => count.not += 1,
+                Node::Leaf(_) | Node::Var(_) => {}
+            }
+        }
+        count
+    }
+}
+impl<R: AsLogic + Clone> Circuit<R> {
+    /// 各ゲートを1回ずつ評価して、出力の値を返す。変数にはvarsの値を使う
+    pub fn eval_with<P: Logip<R = R>>(
+        &self,
+        pros: &P,
+        vars: &HashMap<String, R>,
+    ) -> Result<Vec<R>, EvalError> {
+        let mut values: Vec<R> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let value = Self::eval_node(pros, node, vars, |i| values[i].clone())?;
+            values.push(value);
+        }
+        Ok(self.outputs.iter().map(|&o| values[o].clone()).collect())
+    }
+    /// ゲートの入力の値はvalueで読む
+    fn eval_node<P: Logip<R = R>>(
+        pros: &P,
+        node: &Node<R>,
+        vars: &HashMap<String, R>,
+        value: impl Fn(Wire) -> R,
+    ) -> Result<R, EvalError> {
+        Ok(match node {
+            Node::Leaf(r) => r.clone(),
+            Node::Var(name) => match vars.get(name) {
+                Some(r) => r.clone(),
+                None => return Err(EvalError::UnboundVariable(name.clone())),
+            },
+            Node::Not(e) => pros.not(value(*e)),
+            Node::Nand(l, r) => pros.nand(value(*l), value(*r)),
+            Node::And(l, r) => pros.and(value(*l), value(*r)),
+            Node::Or(l, r) => pros.or(value(*l), value(*r)),
+            Node::Xor(l, r) => pros.xor(value(*l), value(*r)),
+        })
+    }
+    /// 入力の値を`inputs()`の順に渡す
+    /// # Panic
+    /// - inputsの長さが入力の数と違う
+    pub fn eval<P: Logip<R = R>>(&self, pros: &P, inputs: &[R]) -> Vec<R> {
+        assert_eq!(inputs.len(), self.inputs.len(), "number of inputs");
+        let vars = self
+            .inputs
+            .iter()
+            .cloned()
+            .zip(inputs.iter().cloned())
+            .collect();
+        self.eval_with(pros, &vars).unwrap()
+    }
+}
+#[cfg(feature = "rayon")]
+impl<R: AsLogic + Clone + Send + Sync> Circuit<R> {
+    /// 同じ段(`levels`)のゲートをrayonのスレッドプールで並列に評価する。
+    /// 使うプールは`rayon::ThreadPool::install`の中で呼んで選ぶ
+    pub fn par_eval_with<P: Logip<R = R> + Sync>(
+        &self,
+        pros: &P,
+        vars: &HashMap<String, R>,
+    ) -> Result<Vec<R>, EvalError> {
+        use rayon::prelude::*;
+        let mut values: Vec<Option<R>> = vec![None; self.nodes.len()];
+        for level in self.levels() {
+            let results = level
+                .par_iter()
+                .map(|&w| {
+                    Self::eval_node(pros, &self.nodes[w], vars, |i| values[i].clone().unwrap())
+                })
+                .collect::<Result<Vec<R>, EvalError>>()?;
+            for (w, r) in level.into_iter().zip(results) {
+                values[w] = Some(r);
+            }
+        }
+        Ok(self
+            .outputs
+            .iter()
+            .map(|&o| values[o].clone().unwrap())
+            .collect())
+    }
+    /// `eval`の並列版
+    /// # Panic
+    /// - inputsの長さが入力の数と違う
+    pub fn par_eval<P: Logip<R = R> + Sync>(&self, pros: &P, inputs: &[R]) -> Vec<R> {
+        assert_eq!(inputs.len(), self.inputs.len(), "number of inputs");
+        let vars = self
+            .inputs
+            .iter()
+            .cloned()
+            .zip(inputs.iter().cloned())
+            .collect();
+        self.par_eval_with(pros, &vars).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::{lower_to_nand, NandForm};
+    use crate::{eval_logic_expr_with, parse_bus_expr, parse_logic_expr, PlainLogip};
+    use hom_nand::{digest::Cryptor, tfhe::TFHE, tlwe::TLWE};
+    use utils::math::{Binary, BinaryDistribution, Random};
+
+    fn vars(i: usize) -> HashMap<String, Binary> {
+        ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(j, name)| (name.to_string(), Binary::from((i >> j) & 1)))
+            .collect()
+    }
+
+    #[test]
+    fn circuit_shares_subexpressions() {
+        let exp = || parse_logic_expr::<Binary>("(a & b) ^ (b & a) | !(a & b) & 1 & 1").unwrap();
+        let circuit = Circuit::from_expr(exp());
+        // a,b,1,a&b,xor,not,and,and,or
+        assert_eq!(circuit.nodes().len(), 9);
+        assert_eq!(circuit.gate_count().and, 3);
+        for i in 0..4 {
+            assert_eq!(
+                circuit.eval_with(&PlainLogip, &vars(i)),
+                eval_logic_expr_with(&PlainLogip, exp(), &vars(i)).map(|r| vec![r])
+            );
+        }
+
+        // NANDにしたxorの共通部分(a $ b)は1つになる
+        let (lowered, count) = lower_to_nand(
+            parse_logic_expr::<Binary>("a ^ b").unwrap(),
+            NandForm::NandNot,
+        );
+        assert_eq!(count.nand, 5);
+        assert_eq!(Circuit::from_expr(lowered).gate_count().nand, 4);
+
+        let circuit = Circuit::from_expr(parse_logic_expr::<Binary>("a | c").unwrap());
+        assert_eq!(
+            circuit.eval_with(&PlainLogip, &HashMap::new()).err(),
+            Some(EvalError::UnboundVariable("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn circuit_multiple_outputs() {
+        // 2bitの加算器。出力は下位bitから
+        let exps = [
+            "x0 ^ y0",
+            "x1 ^ y1 ^ (x0 & y0)",
+            "x1 & y1 | (x1 ^ y1) & (x0 & y0)",
+        ]
+        .iter()
+        .map(|l| parse_logic_expr::<Binary>(l).unwrap())
+        .collect();
+        let circuit = Circuit::from_exprs(exps);
+        assert_eq!(circuit.outputs().len(), 3);
+        assert_eq!(circuit.inputs(), ["x0", "y0", "x1", "y1"]);
+        // x1 ^ y1とx0 & y0は出力の間で共有する
+        let count = circuit.gate_count();
+        assert_eq!((count.xor, count.and, count.or), (3, 3, 1));
+        for x in 0..4 {
+            for y in 0..4 {
+                let bit = |v: usize, i: usize| Binary::from((v >> i) & 1);
+                let res = circuit.eval(&PlainLogip, &[bit(x, 0), bit(y, 0), bit(x, 1), bit(y, 1)]);
+                let s = x + y;
+                assert_eq!(res, vec![bit(s, 0), bit(s, 1), bit(s, 2)], "{} + {}", x, y);
+            }
+        }
+
+        // バスの式はbitごとの出力になる
+        let circuit = Circuit::from_exprs(parse_bus_expr::<Binary>("x[1:0] & 2'b10").unwrap());
+        assert_eq!(circuit.outputs().len(), 2);
+        assert_eq!(
+            circuit.eval(&PlainLogip, &[Binary::One, Binary::One]),
+            vec![Binary::Zero, Binary::One]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn circuit_eval_missing_input() {
+        let circuit = Circuit::from_expr(parse_logic_expr::<Binary>("a & b").unwrap());
+        circuit.eval(&PlainLogip, &[Binary::One]);
+    }
+
+    #[test]
+    fn circuit_levels() {
+        let circuit = Circuit::from_expr(parse_logic_expr::<Binary>("(a & b) ^ !(c | a)").unwrap());
+        // a,b,c | a&b,c|a | not | xor
+        assert_eq!(
+            circuit.levels(),
+            vec![vec![0, 1, 3], vec![2, 4], vec![5], vec![6]]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn circuit_par_eval() {
+        let l = "(a ^ b) & !(c | a) $ (b & c ^ a) | (a $ c)";
+        let circuit = Circuit::from_expr(parse_logic_expr::<Binary>(l).unwrap());
+        for i in 0..8 {
+            assert_eq!(
+                circuit.par_eval_with(&PlainLogip, &vars(i)),
+                circuit.eval_with(&PlainLogip, &vars(i))
+            );
+        }
+        assert!(circuit.par_eval_with(&PlainLogip, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn circuit_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        let (a, b) = (Binary::One, Binary::Zero);
+        let vars: HashMap<_, _> = [("a", a), ("b", b)]
+            .iter()
+            .map(|&(k, v)| (k.to_string(), Cryptor::encrypto(TLWE, &s_key_tlwelv0, v)))
+            .collect();
+        let exp = parse_logic_expr("(a ^ b) & (b ^ a)").unwrap();
+        let circuit = Circuit::from_expr(exp);
+        let res = circuit.eval_with(&tfhe, &vars).unwrap().remove(0);
+        let res: Binary = Cryptor::decrypto(TLWE, &s_key_tlwelv0, res);
+        assert_eq!(res, Binary::One);
+        // xorは1回だけ評価する
+        assert_eq!(tfhe.stats().total_gates(), 2);
+
+        #[cfg(feature = "rayon")]
+        {
+            let res = circuit.par_eval_with(&tfhe, &vars).unwrap().remove(0);
+            let res: Binary = Cryptor::decrypto(TLWE, &s_key_tlwelv0, res);
+            assert_eq!(res, Binary::One);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+/*!
+スレッドごとの評価器(TFHEならサーバー鍵)。
+
+`FheBool`や`FheUint`は作るときに評価器を1度受け取れば、演算では渡さなくてよい。
+評価器をスレッドに登録しておけば、作る側の関数にも`&TFHE`を引き回さずに済む。
+
+登録した評価器はプログラムの終わりまで残る(`&'static`で返すため)。鍵を何度も作り直すなら、1度`set_server_key`したものを`with_server_key`で切り替える。
+rayonのworkerなど別のスレッドには引き継がれないので、そのスレッドでも登録する。
+
+```ignore
+set_server_key(TFHE::new(s_key_tlwelv0, s_key_tlwelv1));
+
+fn increment(x: &FheUint<'static, Tfhe, 8>) -> FheUint<'static, Tfhe, 8> {
+    x + FheUint::trivial(server_key(), 1)
+}
+```
+ */
+use crate::Logip;
+use std::any::Any;
+use std::cell::Cell;
+
+thread_local! {
+    static SERVER_KEY: Cell<Option<&'static dyn Any>> = Cell::new(None);
+}
+
+/// prosをこのスレッドの評価器にする。前に登録したものは解放しない
+pub fn set_server_key<P: Logip + 'static>(pros: P) -> &'static P {
+    let pros: &'static P = Box::leak(Box::new(pros));
+    SERVER_KEY.with(|key| key.set(Some(pros)));
+    pros
+}
+
+/// fの間だけprosをこのスレッドの評価器にする。fがpanicしても元に戻す
+pub fn with_server_key<P: Logip + 'static, T>(pros: &'static P, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<&'static dyn Any>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SERVER_KEY.with(|key| key.set(self.0));
+        }
+    }
+    let _restore = Restore(SERVER_KEY.with(|key| key.replace(Some(pros))));
+    f()
+}
+
+/// このスレッドの評価器。登録していないか、型が違えばNone
+pub fn try_server_key<P: Logip + 'static>() -> Option<&'static P> {
+    SERVER_KEY.with(|key| key.get()?.downcast_ref())
+}
+
+/// このスレッドの評価器
+/// # Panic
+/// - 登録していないか、型が違う
+pub fn server_key<P: Logip + 'static>() -> &'static P {
+    try_server_key().unwrap_or_else(|| {
+        panic!(
+            "no server key of type {} is set on this thread",
+            std::any::type_name::<P>()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoolLogip, FheBool, FheUint, PlainLogip};
+
+    #[test]
+    fn server_key_scope() {
+        assert!(try_server_key::<PlainLogip>().is_none());
+        let outer = set_server_key(PlainLogip);
+        assert!(std::ptr::eq(server_key::<PlainLogip>(), outer));
+        assert!(try_server_key::<BoolLogip>().is_none());
+
+        let inner: &'static BoolLogip = Box::leak(Box::new(BoolLogip));
+        let res = with_server_key(inner, || {
+            assert!(try_server_key::<PlainLogip>().is_none());
+            let a = FheBool::trivial(server_key::<BoolLogip>(), true);
+            (&a ^ &FheBool::trivial(server_key(), true)).into_bit()
+        });
+        assert!(!res);
+        assert!(std::ptr::eq(server_key::<PlainLogip>(), outer));
+
+        // 作るときにだけ使う
+        let add = |x: &FheUint<'static, PlainLogip, 4>| x + FheUint::trivial(server_key(), 1);
+        let x = FheUint::trivial(server_key(), 7);
+        assert_eq!(add(&x).decrypt_with(|b| *b), 8);
+
+        // 別のスレッドには引き継がない
+        std::thread::spawn(|| assert!(try_server_key::<PlainLogip>().is_none()))
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "no server key")]
+    fn server_key_missing() {
+        server_key::<PlainLogip>();
+    }
+}
@@ -0,0 +1,220 @@
+/*!
+式を評価する前に、かかる量を見積もる。
+
+`CostModel`はゲートごとの時間・bootstrapの回数・出力の誤差を持ち、`Logip::cost_model`で評価器ごとに決まる。
+`LogicExpr::estimate_cost`は式をたどって、bootstrapの回数、最も長い経路のゲートの数と時間、全体の時間を足し合わせる。
+TFHEの時間は目安なので、手元の環境に合わせるには`CostModel::measure`で測り直す。
+
+```ignore
+let cost = exp.estimate_cost::<TFHE<N, M>>();
+println!("{}", cost); // bootstraps=1532 depth=41 critical_path=615ms wall_time=22.98s max_noise=1
+```
+ */
+use crate::{LogicExpr, Logip};
+use hom_nand::stats::Gate;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+use utils::traits::AsLogic;
+
+/// TFHE(TRLWEのN=1024)でbootstrap1回にかかる時間の目安
+pub const TFHE_BOOTSTRAP_LATENCY: Duration = Duration::from_millis(15);
+
+/// ゲート1つの重さ
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GateCost {
+    pub latency: Duration,
+    pub bootstraps: usize,
+    /// 出力の誤差の分散。新しく暗号化したものを1とする。
+    /// bootstrapするゲートは出力の誤差がこの値になり、しないゲートは入力の誤差の和にこの値を足す
+    pub noise: f64,
+}
+
+/// 評価器ごとのゲートの重さ
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostModel {
+    gates: [GateCost; Gate::ALL.len()],
+}
+impl CostModel {
+    /// bootstrapするゲートはbootstrap1回ごとにlatencyかかり、notは符号を変えるだけなので0
+    pub fn tfhe(latency: Duration) -> Self {
+        let bootstrapped = |bootstraps: usize| GateCost {
+            latency: latency * bootstraps as u32,
+            bootstraps,
+            noise: 1.0,
+        };
+        CostModel::default()
+            .with_gate(Gate::Nand, bootstrapped(1))
+            .with_gate(Gate::And, bootstrapped(1))
+            .with_gate(Gate::Or, bootstrapped(1))
+            .with_gate(Gate::Xor, bootstrapped(1))
+            .with_gate(Gate::Not, GateCost::default())
+            .with_gate(Gate::Mux, bootstrapped(3))
+    }
+    pub fn with_gate(mut self, gate: Gate, cost: GateCost) -> Self {
+        self.gates[gate as usize] = cost;
+        self
+    }
+    pub fn gate(&self, gate: Gate) -> GateCost {
+        self.gates[gate as usize]
+    }
+    /// 各ゲートを定数の入力でsamples回ずつ評価し、その平均を時間にする。時間以外はそのまま
+    pub fn measure<P: Logip>(mut self, pros: &P, samples: u32) -> Self {
+        let samples = samples.max(1);
+        let (t, f) = (P::R::logic_true, P::R::logic_false);
+        for gate in Gate::ALL {
+            let start = Instant::now();
+            for _ in 0..samples {
+                match gate {
+                    Gate::Nand => pros.nand(t(), f()),
+                    Gate::And => pros.and(t(), f()),
+                    Gate::Or => pros.or(t(), f()),
+                    Gate::Xor => pros.xor(t(), f()),
+                    Gate::Not => pros.not(t()),
+                    Gate::Mux => pros.mux(t(), f(), t()),
+                };
+            }
+            self.gates[gate as usize].latency = start.elapsed() / samples;
+        }
+        self
+    }
+}
+
+/// 式の見積もり
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostEstimate {
+    pub gates: usize,
+    pub bootstraps: usize,
+    /// 入力から出力までの経路で、ゲートの最も多いものの数
+    pub depth: usize,
+    /// 入力から出力までの経路で、最も時間のかかるものの時間。並列に評価したときの下限
+    pub critical_path: Duration,
+    /// 1つずつ評価したときの時間
+    pub wall_time: Duration,
+    /// 出力の誤差の分散
+    pub max_noise: f64,
+}
+impl Display for CostEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bootstraps={} depth={} critical_path={:?} wall_time={:?} max_noise={}",
+            self.bootstraps, self.depth, self.critical_path, self.wall_time, self.max_noise
+        )
+    }
+}
+
+/// 部分式の、(深さ, 最も時間のかかる経路, 誤差)
+type PathCost = (usize, Duration, f64);
+
+impl<R: AsLogic> LogicExpr<R> {
+    /// Pの`cost_model`で見積もる
+    pub fn estimate_cost<P: Logip<R = R>>(&self) -> CostEstimate
+    where
+        R: Clone,
+    {
+        self.estimate_cost_with(&P::cost_model())
+    }
+    pub fn estimate_cost_with(&self, model: &CostModel) -> CostEstimate {
+        let mut estimate = CostEstimate::default();
+        // 子を見積もってから親を見積もる
+        let mut stack = vec![(self, false)];
+        let mut paths: Vec<PathCost> = Vec::new();
+        while let Some((exp, ready)) = stack.pop() {
+            let (gate, children): (Gate, Vec<&LogicExpr<R>>) = match exp {
+                LogicExpr::Nand(l, r) => (Gate::Nand, vec![l, r]),
+                LogicExpr::And(l, r) => (Gate::And, vec![l, r]),
+                LogicExpr::Or(l, r) => (Gate::Or, vec![l, r]),
+                LogicExpr::Xor(l, r) => (Gate::Xor, vec![l, r]),
+                LogicExpr::Not(e) => (Gate::Not, vec![e]),
+                LogicExpr::Leaf(r) => {
+                    let noise = if r.as_const().is_some() { 0.0 } else { 1.0 };
+                    paths.push((0, Duration::ZERO, noise));
+                    continue;
+                }
+                LogicExpr::Var(_) => {
+                    paths.push((0, Duration::ZERO, 1.0));
+                    continue;
+                }
+            };
+            if !ready {
+                stack.push((exp, true));
+                stack.extend(children.into_iter().map(|e| (e, false)));
+                continue;
+            }
+            let inputs = paths.split_off(paths.len() - children.len());
+            let cost = model.gate(gate);
+            let depth = inputs.iter().map(|p| p.0).max().unwrap() + 1;
+            let critical = inputs.iter().map(|p| p.1).max().unwrap() + cost.latency;
+            let noise = if cost.bootstraps > 0 {
+                cost.noise
+            } else {
+                inputs.iter().map(|p| p.2).sum::<f64>() + cost.noise
+            };
+            estimate.gates += 1;
+            estimate.bootstraps += cost.bootstraps;
+            estimate.wall_time += cost.latency;
+            paths.push((depth, critical, noise));
+        }
+        let (depth, critical_path, noise) = paths.pop().unwrap();
+        CostEstimate {
+            depth,
+            critical_path,
+            max_noise: noise,
+            ..estimate
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_logic_expr, PlainLogip};
+    use hom_nand::{tfhe::TFHE, tlwe::TLWERep};
+    use utils::math::Binary;
+
+    #[test]
+    fn estimate_cost() {
+        let exp = parse_logic_expr::<Binary>("((a & b) ^ !c) | d").unwrap();
+        let plain = exp.estimate_cost::<PlainLogip>();
+        assert_eq!((plain.gates, plain.bootstraps, plain.depth), (4, 0, 3));
+        assert_eq!(plain.wall_time, Duration::ZERO);
+
+        let ms = Duration::from_millis;
+        let tfhe = exp.estimate_cost_with(&CostModel::tfhe(ms(10)));
+        assert_eq!((tfhe.gates, tfhe.bootstraps, tfhe.depth), (4, 3, 3));
+        assert_eq!((tfhe.critical_path, tfhe.wall_time), (ms(30), ms(30)));
+        assert_eq!(tfhe.max_noise, 1.0);
+        // 並列に評価できる枝は足さない
+        let exp = parse_logic_expr::<Binary>("(a & b) ^ (c & d)").unwrap();
+        let tfhe = exp.estimate_cost_with(&CostModel::tfhe(ms(10)));
+        assert_eq!((tfhe.critical_path, tfhe.wall_time), (ms(20), ms(30)));
+        let exp = parse_logic_expr::<TLWERep<16>>("(a & b) ^ (c & d)").unwrap();
+        assert_eq!(
+            exp.estimate_cost::<TFHE<16, 1024>>().wall_time,
+            TFHE_BOOTSTRAP_LATENCY * 3
+        );
+    }
+
+    #[test]
+    fn estimate_noise() {
+        // bootstrapしないnotは誤差を足していく
+        let model = CostModel::default().with_gate(
+            Gate::Not,
+            GateCost {
+                noise: 0.5,
+                ..GateCost::default()
+            },
+        );
+        let exp = parse_logic_expr::<Binary>("!!a").unwrap();
+        assert_eq!(exp.estimate_cost_with(&model).max_noise, 2.0);
+        let exp = parse_logic_expr::<Binary>("!1").unwrap();
+        assert_eq!(exp.estimate_cost_with(&model).max_noise, 0.5);
+    }
+
+    #[test]
+    fn measure_cost_model() {
+        let model = CostModel::tfhe(Duration::from_secs(1)).measure(&PlainLogip, 10);
+        assert!(model.gate(Gate::Nand).latency < Duration::from_secs(1));
+        assert_eq!(model.gate(Gate::Mux).bootstraps, 3);
+    }
+}
@@ -0,0 +1,233 @@
+/*!
+途中のワイヤを全て復号して確かめる評価器。
+
+`DebugLogip`は秘密鍵(で復号する関数)と本物の評価器を持ち、ゲートを評価するたびに入力と出力を復号して記録する。
+入力を復号した値で平文のまま評価した結果とも比べるので、大きな回路で最初に値が壊れたゲートを探せる。
+`panic_on_mismatch`にすると、食い違ったところでpanicする。
+
+```ignore
+let debug = DebugLogip::with_key(tfhe, s_key_tlwelv0);
+let res = circuit.eval(&debug, &inputs);
+if let Some(gate) = debug.first_mismatch() {
+    println!("{}", gate); // #1532 xor(1, 0) = 0 (expected 1)
+}
+```
+ */
+use crate::{CostModel, Logip, PlainLogip};
+use hom_nand::{
+    digest::Cryptor,
+    stats::Gate,
+    tfhe::{BootstrappingKeyRows, TFHE},
+    tlwe::TLWE,
+};
+use std::fmt::Display;
+use std::sync::Mutex;
+use utils::math::Binary;
+
+/// 評価したゲート1つ分の記録
+#[derive(Debug, Clone, PartialEq)]
+pub struct GateTrace {
+    /// 評価した順の番号
+    pub index: usize,
+    pub gate: Gate,
+    /// 入力を復号した値
+    pub inputs: Vec<Binary>,
+    /// 出力を復号した値
+    pub output: Binary,
+    /// 入力を平文のまま評価した値
+    pub expected: Binary,
+}
+impl GateTrace {
+    pub fn is_correct(&self) -> bool {
+        self.output == self.expected
+    }
+}
+impl Display for GateTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inputs: Vec<String> = self.inputs.iter().map(|b| b.to_string()).collect();
+        write!(
+            f,
+            "#{} {}({}) = {}",
+            self.index,
+            self.gate.name(),
+            inputs.join(", "),
+            self.output
+        )?;
+        if !self.is_correct() {
+            write!(f, " (expected {})", self.expected)?;
+        }
+        Ok(())
+    }
+}
+
+/// 値を復号する関数
+type Decrypt<R> = Box<dyn Fn(&R) -> Binary + Send + Sync>;
+
+pub struct DebugLogip<P: Logip> {
+    inner: P,
+    decrypt: Decrypt<P::R>,
+    panic_on_mismatch: bool,
+    trace: Mutex<Vec<GateTrace>>,
+}
+impl<P: Logip> DebugLogip<P> {
+    /// decryptは値を復号する関数
+    pub fn new(inner: P, decrypt: impl Fn(&P::R) -> Binary + Send + Sync + 'static) -> Self {
+        DebugLogip {
+            inner,
+            decrypt: Box::new(decrypt),
+            panic_on_mismatch: false,
+            trace: Mutex::new(Vec::new()),
+        }
+    }
+    /// 平文で評価した値と食い違ったらpanicする
+    pub fn panic_on_mismatch(mut self) -> Self {
+        self.panic_on_mismatch = true;
+        self
+    }
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+    /// これまでに評価したゲートの記録
+    pub fn trace(&self) -> Vec<GateTrace> {
+        self.trace.lock().unwrap().clone()
+    }
+    /// 最初に平文で評価した値と食い違ったゲート
+    pub fn first_mismatch(&self) -> Option<GateTrace> {
+        let trace = self.trace.lock().unwrap();
+        trace.iter().find(|t| !t.is_correct()).cloned()
+    }
+    pub fn clear(&self) {
+        self.trace.lock().unwrap().clear();
+    }
+
+    fn record(&self, gate: Gate, inputs: &[&P::R], output: P::R) -> P::R {
+        let inputs: Vec<Binary> = inputs.iter().map(|r| (self.decrypt)(r)).collect();
+        let expected = match (gate, &inputs[..]) {
+            (Gate::Nand, &[l, r]) => PlainLogip.nand(l, r),
+            (Gate::And, &[l, r]) => PlainLogip.and(l, r),
+            (Gate::Or, &[l, r]) => PlainLogip.or(l, r),
+            (Gate::Xor, &[l, r]) => PlainLogip.xor(l, r),
+            (Gate::Not, &[b]) => PlainLogip.not(b),
+            _ => unreachable!(),
+        };
+        let mut trace = self.trace.lock().unwrap();
+        let t = GateTrace {
+            index: trace.len(),
+            gate,
+            inputs,
+            output: (self.decrypt)(&output),
+            expected,
+        };
+        trace.push(t.clone());
+        // ロックを外してからpanicする
+        drop(trace);
+        if self.panic_on_mismatch && !t.is_correct() {
+            panic!("wrong value at gate {}", t);
+        }
+        output
+    }
+}
+impl<const N: usize, const M: usize, BK: BootstrappingKeyRows<M>> DebugLogip<TFHE<N, M, BK>> {
+    /// s_keyはTLWE(lv0)の秘密鍵
+    pub fn with_key(tfhe: TFHE<N, M, BK>, s_key: [Binary; N]) -> Self {
+        Self::new(tfhe, move |r| Cryptor::decrypto(TLWE, &s_key, r.clone()))
+    }
+}
+impl<P: Logip> Logip for DebugLogip<P> {
+    type R = P::R;
+
+    fn nand(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        let res = self.inner.nand(lhs.clone(), rhs.clone());
+        self.record(Gate::Nand, &[&lhs, &rhs], res)
+    }
+
+    fn not(&self, b: Self::R) -> Self::R {
+        let res = self.inner.not(b.clone());
+        self.record(Gate::Not, &[&b], res)
+    }
+
+    fn and(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        let res = self.inner.and(lhs.clone(), rhs.clone());
+        self.record(Gate::And, &[&lhs, &rhs], res)
+    }
+
+    fn or(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        let res = self.inner.or(lhs.clone(), rhs.clone());
+        self.record(Gate::Or, &[&lhs, &rhs], res)
+    }
+
+    fn xor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        let res = self.inner.xor(lhs.clone(), rhs.clone());
+        self.record(Gate::Xor, &[&lhs, &rhs], res)
+    }
+
+    fn cost_model() -> CostModel {
+        P::cost_model()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_logic_expr_with, parse_logic_expr};
+    use std::collections::HashMap;
+    use utils::math::{BinaryDistribution, Random};
+
+    /// orを間違える評価器
+    struct BrokenOr;
+    impl Logip for BrokenOr {
+        type R = Binary;
+        fn nand(&self, lhs: Binary, rhs: Binary) -> Binary {
+            PlainLogip.nand(lhs, rhs)
+        }
+        fn or(&self, lhs: Binary, rhs: Binary) -> Binary {
+            PlainLogip.and(lhs, rhs)
+        }
+    }
+
+    #[test]
+    fn debug_logip_mismatch() {
+        let debug = DebugLogip::new(BrokenOr, |b: &Binary| *b);
+        let exp = parse_logic_expr("(a & b) | (a ^ b)").unwrap();
+        let vars: HashMap<String, Binary> = [("a", Binary::One), ("b", Binary::Zero)]
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v))
+            .collect();
+        assert_eq!(eval_logic_expr_with(&debug, exp, &vars), Ok(Binary::Zero));
+        let trace = debug.trace();
+        assert_eq!(trace.len(), 3);
+        let wrong = debug.first_mismatch().unwrap();
+        assert_eq!(wrong.index, 2);
+        assert_eq!(wrong.to_string(), "#2 or(0, 1) = 0 (expected 1)");
+        assert!(trace[..2].iter().all(GateTrace::is_correct));
+
+        debug.clear();
+        assert!(debug.trace().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong value at gate #0 or(1, 0) = 0 (expected 1)")]
+    fn debug_logip_panic() {
+        let debug = DebugLogip::new(BrokenOr, |b: &Binary| *b).panic_on_mismatch();
+        debug.or(Binary::One, Binary::Zero);
+    }
+
+    #[test]
+    fn debug_logip_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let debug = DebugLogip::with_key(TFHE::new(s_key_tlwelv0, s_key_tlwelv1), s_key_tlwelv0)
+            .panic_on_mismatch();
+        let enc = |b: Binary| Cryptor::encrypto(TLWE, &s_key_tlwelv0, b);
+        let res = debug.xor(enc(Binary::One), debug.not(enc(Binary::One)));
+        let res: Binary = Cryptor::decrypto(TLWE, &s_key_tlwelv0, res);
+        assert_eq!(res, Binary::One);
+        let trace = debug.trace();
+        assert_eq!(trace[0].to_string(), "#0 not(1) = 0");
+        assert_eq!(trace[1].to_string(), "#1 xor(1, 0) = 1");
+        assert_eq!(debug.inner().stats().total_gates(), 2);
+    }
+}
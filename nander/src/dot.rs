@@ -0,0 +1,192 @@
+/*!
+論理式(`LogicExpr`,`Circuit`)をGraphvizのDOT形式で書き出す。
+
+ゲートは種類(AND,OR,...)を書いた箱、値は丸、変数は名前を書いた楕円になる。
+同じ名前の変数は1つのノードにまとめ、辺は入力からゲートへ向ける。出力のゲートは二重線で囲む。
+
+```text
+$ dot -Tsvg expr.dot -o expr.svg
+```
+ */
+use crate::circuit::{Circuit, Node};
+use crate::LogicExpr;
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use utils::traits::AsLogic;
+
+impl<R: AsLogic> LogicExpr<R> {
+    pub fn to_dot(&self) -> String
+    where
+        R: Display,
+    {
+        self.to_dot_with(|r| r.to_string())
+    }
+    /// 値(Leaf)のラベルはleafで書く
+    pub fn to_dot_with(&self, leaf: impl Fn(&R) -> String) -> String {
+        let mut dot = DotWriter {
+            out: String::from("digraph logic_expr {\n"),
+            leaf: &leaf,
+            nodes: 0,
+            vars: HashMap::new(),
+        };
+        let root = dot.write(self);
+        writeln!(dot.out, "    {} [peripheries=2];", root).unwrap();
+        dot.out.push_str("}\n");
+        dot.out
+    }
+}
+
+impl<R: AsLogic> Circuit<R> {
+    pub fn to_dot(&self) -> String
+    where
+        R: Display,
+    {
+        self.to_dot_with(|r| r.to_string())
+    }
+    /// ノードiは`ni`になる。値(Leaf)のラベルはleafで書く
+    pub fn to_dot_with(&self, leaf: impl Fn(&R) -> String) -> String {
+        let mut out = String::from("digraph circuit {\n");
+        for (i, node) in self.nodes().iter().enumerate() {
+            let (label, shape, inputs) = match node {
+                Node::Leaf(r) => (leaf(r), "circle", vec![]),
+                Node::Var(name) => (name.clone(), "ellipse", vec![]),
+                Node::Not(e) => ("NOT".to_string(), "box", vec![e]),
+                Node::Nand(l, r) => ("NAND".to_string(), "box", vec![l, r]),
+                Node::And(l, r) => ("AND".to_string(), "box", vec![l, r]),
+                Node::Or(l, r) => ("OR".to_string(), "box", vec![l, r]),
+                Node::Xor(l, r) => ("XOR".to_string(), "box", vec![l, r]),
+            };
+            writeln!(
+                out,
+                "    n{} [label=\"{}\", shape={}];",
+                i,
+                escape(&label),
+                shape
+            )
+            .unwrap();
+            for input in inputs {
+                writeln!(out, "    n{} -> n{};", input, i).unwrap();
+            }
+        }
+        for output in self.outputs() {
+            writeln!(out, "    n{} [peripheries=2];", output).unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+struct DotWriter<'a, R> {
+    out: String,
+    leaf: &'a dyn Fn(&R) -> String,
+    nodes: usize,
+    /// 変数名 -> ノード
+    vars: HashMap<&'a str, String>,
+}
+impl<'a, R: AsLogic> DotWriter<'a, R> {
+    /// expのノードを書いて、その名前を返す
+    fn write(&mut self, exp: &'a LogicExpr<R>) -> String {
+        let (label, inputs): (_, [Option<&LogicExpr<R>>; 2]) = match exp {
+            LogicExpr::Nand(l, r) => ("NAND", [Some(l), Some(r)]),
+            LogicExpr::And(l, r) => ("AND", [Some(l), Some(r)]),
+            LogicExpr::Or(l, r) => ("OR", [Some(l), Some(r)]),
+            LogicExpr::Xor(l, r) => ("XOR", [Some(l), Some(r)]),
+            LogicExpr::Not(e) => ("NOT", [Some(e), None]),
+            LogicExpr::Leaf(r) => {
+                let label = (self.leaf)(r);
+                return self.node(&label, "circle");
+            }
+            LogicExpr::Var(name) => {
+                if let Some(node) = self.vars.get(name.as_str()) {
+                    return node.clone();
+                }
+                let node = self.node(name, "ellipse");
+                self.vars.insert(name, node.clone());
+                return node;
+            }
+        };
+        let inputs: Vec<String> = inputs.iter().flatten().map(|e| self.write(e)).collect();
+        let node = self.node(label, "box");
+        for input in inputs {
+            writeln!(self.out, "    {} -> {};", input, node).unwrap();
+        }
+        node
+    }
+    fn node(&mut self, label: &str, shape: &str) -> String {
+        let node = format!("n{}", self.nodes);
+        self.nodes += 1;
+        writeln!(
+            self.out,
+            "    {} [label=\"{}\", shape={}];",
+            node,
+            escape(label),
+            shape
+        )
+        .unwrap();
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_logic_expr, Circuit};
+    use utils::math::Binary;
+
+    #[test]
+    fn logic_expr_to_dot() {
+        let exp = parse_logic_expr::<Binary>("!(a & x[0]) $ (a | 1)").unwrap();
+        let dot = exp.to_dot();
+        assert_eq!(
+            dot,
+            "digraph logic_expr {
+    n0 [label=\"a\", shape=ellipse];
+    n1 [label=\"x[0]\", shape=ellipse];
+    n2 [label=\"AND\", shape=box];
+    n0 -> n2;
+    n1 -> n2;
+    n3 [label=\"NOT\", shape=box];
+    n2 -> n3;
+    n4 [label=\"1\", shape=circle];
+    n5 [label=\"OR\", shape=box];
+    n0 -> n5;
+    n4 -> n5;
+    n6 [label=\"NAND\", shape=box];
+    n3 -> n6;
+    n5 -> n6;
+    n6 [peripheries=2];
+}
+"
+        );
+
+        let dot = parse_logic_expr::<Binary>("0")
+            .unwrap()
+            .to_dot_with(|_| "\"secret\"".to_string());
+        assert!(dot.contains("n0 [label=\"\\\"secret\\\"\", shape=circle];"));
+    }
+
+    #[test]
+    fn circuit_to_dot() {
+        let exp = parse_logic_expr::<Binary>("(a & b) | !(a & b)").unwrap();
+        assert_eq!(
+            Circuit::from_expr(exp).to_dot(),
+            "digraph circuit {
+    n0 [label=\"a\", shape=ellipse];
+    n1 [label=\"b\", shape=ellipse];
+    n2 [label=\"AND\", shape=box];
+    n0 -> n2;
+    n1 -> n2;
+    n3 [label=\"NOT\", shape=box];
+    n2 -> n3;
+    n4 [label=\"OR\", shape=box];
+    n2 -> n4;
+    n3 -> n4;
+    n4 [peripheries=2];
+}
+"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+/*!
+2つの式が平文で同じ関数かを確かめる。
+
+最適化や`lower_to_nand`で書き換えた式を、暗号文で評価する前に元の式と比べるためのもの。
+変数が`EXHAUSTIVE_VARS`個以下なら全ての入力で評価して比べ、それより多ければBDD(`Bdd`)で調べる。
+BDDの実装とは別の方法で確かめるので、BDDを使った書き換え(`simplify_with_bdd`)の確認にも使える。
+
+値が分からない値(Leaf)は、`equivalent`と同じくそれぞれを1つの変数として扱う(このときはBDDで調べる)。
+
+```ignore
+let (nand, _) = lower_to_nand(exp.clone(), NandForm::NandNot);
+assert_eq!(counterexample(&exp, &nand), None);
+```
+ */
+use crate::bdd::Bdd;
+use crate::circuit::{Circuit, Node};
+use crate::LogicExpr;
+use std::collections::HashMap;
+use utils::traits::AsLogic;
+
+/// 全ての入力を試す変数の数の上限
+pub const EXHAUSTIVE_VARS: usize = 16;
+
+/// 同じ論理関数か
+pub fn logic_equivalent<R: AsLogic + Clone>(lhs: &LogicExpr<R>, rhs: &LogicExpr<R>) -> bool {
+    counterexample(lhs, rhs).is_none()
+}
+
+/// 2つの式が違う値になる変数の値の組。同じ関数ならNone。
+/// 両方の式の変数を全て含む
+pub fn counterexample<R: AsLogic + Clone>(
+    lhs: &LogicExpr<R>,
+    rhs: &LogicExpr<R>,
+) -> Option<HashMap<String, bool>> {
+    let circuit = Circuit::from_exprs(vec![lhs.clone(), rhs.clone()]);
+    let all_const = circuit.nodes().iter().all(|node| match node {
+        Node::Leaf(r) => r.as_const().is_some(),
+        _ => true,
+    });
+    if all_const && circuit.inputs().len() <= EXHAUSTIVE_VARS {
+        exhaustive(&circuit)
+    } else {
+        let diff = LogicExpr::Xor(Box::new(lhs.clone()), Box::new(rhs.clone()));
+        Bdd::from_expr(&diff).satisfy()
+    }
+}
+
+/// 入力を全て試す。Leafは全て定数
+fn exhaustive<R: AsLogic>(circuit: &Circuit<R>) -> Option<HashMap<String, bool>> {
+    let inputs = circuit.inputs();
+    let mut values = vec![false; circuit.nodes().len()];
+    for bits in 0u64..(1 << inputs.len()) {
+        let mut next_input = 0;
+        for (w, node) in circuit.nodes().iter().enumerate() {
+            values[w] = match *node {
+                Node::Leaf(ref r) => r.as_const().unwrap(),
+                Node::Var(_) => {
+                    next_input += 1;
+                    (bits >> (next_input - 1)) & 1 == 1
+                }
+                Node::Not(e) => !values[e],
+                Node::Nand(l, r) => !(values[l] && values[r]),
+                Node::And(l, r) => values[l] && values[r],
+                Node::Or(l, r) => values[l] || values[r],
+                Node::Xor(l, r) => values[l] ^ values[r],
+            };
+        }
+        let outputs = circuit.outputs();
+        if values[outputs[0]] != values[outputs[1]] {
+            let assign = inputs
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (name.clone(), (bits >> i) & 1 == 1))
+                .collect();
+            return Some(assign);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::{fold_constants, lower_to_nand, NandForm};
+    use crate::{eval_logic_expr_with_env, parse_logic_expr, simplify_with_bdd, PlainLogip};
+    use utils::math::Binary;
+
+    fn exp(s: &str) -> LogicExpr<Binary> {
+        parse_logic_expr(s).unwrap()
+    }
+    fn eval(exp: &LogicExpr<Binary>, assign: &HashMap<String, bool>) -> Binary {
+        let env = assign
+            .iter()
+            .map(|(k, &v)| (k.clone(), Binary::from(v as u32)))
+            .collect();
+        eval_logic_expr_with_env(&PlainLogip, exp, &env).unwrap()
+    }
+
+    #[test]
+    fn logic_equivalent_test() {
+        for s in [
+            "(a & b) | (c ^ !d)",
+            "a ^ b ^ c ^ d",
+            "(a | 1) & (b $ 0) | c & a",
+        ] {
+            let e = exp(s);
+            assert!(logic_equivalent(
+                &e,
+                &lower_to_nand(e.clone(), NandForm::NandOnly).0
+            ));
+            assert!(logic_equivalent(&e, &fold_constants(e.clone())));
+            assert!(logic_equivalent(&e, &simplify_with_bdd(&e)));
+        }
+        assert!(logic_equivalent(&exp("!(a & b)"), &exp("!a | !b")));
+        // 変数の集合が違っても関数が同じならよい
+        assert!(logic_equivalent(&exp("a | (b & !b)"), &exp("a")));
+
+        let (l, r) = (exp("a & (b | c)"), exp("a & b | c"));
+        assert!(!logic_equivalent(&l, &r));
+        let assign = counterexample(&l, &r).unwrap();
+        assert_eq!(assign.len(), 3);
+        assert_ne!(eval(&l, &assign), eval(&r, &assign));
+    }
+
+    #[test]
+    fn counterexample_bdd() {
+        // 変数が多いときはBDDで調べる
+        let n = EXHAUSTIVE_VARS + 4;
+        let chain = |op: &str| {
+            let vars: Vec<String> = (0..n).map(|i| format!("x{}", i)).collect();
+            exp(&vars.join(op))
+        };
+        let (xor, or) = (chain(" ^ "), chain(" | "));
+        assert!(logic_equivalent(
+            &or,
+            &lower_to_nand(or.clone(), NandForm::NandNot).0
+        ));
+        let assign = counterexample(&xor, &or).unwrap();
+        assert_eq!(assign.len(), n);
+        assert_ne!(eval(&xor, &assign), eval(&or, &assign));
+    }
+}
@@ -0,0 +1,454 @@
+/*!
+スレッドプールでゲートや論理式を評価する。
+
+評価器(評価鍵を持つ`TFHE`など)は`Arc`で全スレッドから共有する。
+投げた計算は`Handle`で受け取り、`join`で待つか、`Future`として`.await`する。
+非同期ランタイムのスレッドで重いbootstrapを走らせずに、多くのリクエストを並行に評価するためのもの。
+
+`eval_batch`は(式, 変数の値)の組をまとめて投げ、終わった順に結果を受け取る。失敗は仕事ごとに`JobError`で返す。
+
+```ignore
+let exec = Executor::new(Arc::new(tfhe), 4);
+let h = exec.eval(parse_logic_expr("1&0|1")?);
+let res = h.join().unwrap(); // または h.await
+
+for (i, res) in exec.eval_batch(jobs) {
+    println!("job {}: {:?}", i, res);
+}
+```
+ */
+use crate::{
+    eval_logic_expr, eval_logic_expr_with, eval_logic_exprs_with, EvalError, LogicExpr, Logip,
+};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct Executor<P> {
+    logip: Arc<P>,
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+impl<P> Executor<P>
+where
+    P: Logip + Send + Sync + 'static,
+    P::R: Send + 'static,
+{
+    /// # Panic
+    /// - 'threads == 0'
+    pub fn new(logip: Arc<P>, threads: usize) -> Self {
+        assert!(threads > 0, "threads must be greater than 0");
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("nander-executor-{}", i))
+                    .spawn(move || Self::work(&receiver))
+                    .expect("failed to spawn executor thread")
+            })
+            .collect();
+        Executor {
+            logip,
+            sender: Some(sender),
+            workers,
+        }
+    }
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            // 受け取ったらすぐにロックを外してから実行する
+            let job = receiver.lock().unwrap().recv();
+            match job {
+                Ok(job) => job(),
+                Err(_) => return, // Executorがdropされた
+            }
+        }
+    }
+    pub fn logip(&self) -> &Arc<P> {
+        &self.logip
+    }
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// 評価器を使う任意の計算を投げる
+    pub fn spawn<T, F>(&self, f: F) -> Handle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&P) -> T + Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        let (logip, result) = (self.logip.clone(), shared.clone());
+        self.send(Box::new(move || {
+            let res = panic::catch_unwind(AssertUnwindSafe(|| f(&logip)));
+            result.complete(res);
+        }));
+        Handle { shared }
+    }
+    /// (式, 変数の値)の組をまとめて投げる。結果は`Batch`から終わった順に受け取る
+    pub fn eval_batch<I>(&self, jobs: I) -> Batch<P::R>
+    where
+        I: IntoIterator<Item = (LogicExpr<P::R>, HashMap<String, P::R>)>,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let mut len = 0;
+        for (i, (exp, vars)) in jobs.into_iter().enumerate() {
+            let (logip, sender) = (self.logip.clone(), sender.clone());
+            self.send(Box::new(move || {
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    eval_logic_expr_with(&*logip, exp, &vars)
+                }));
+                let res = match res {
+                    Ok(res) => res.map_err(JobError::Eval),
+                    Err(payload) => Err(JobError::Panicked(panic_message(&*payload))),
+                };
+                // Batchを捨てていれば結果も捨てる
+                let _ = sender.send((i, res));
+            }));
+            len += 1;
+        }
+        Batch {
+            receiver,
+            len,
+            received: 0,
+        }
+    }
+    fn send(&self, job: Job) {
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .expect("executor threads are stopped");
+    }
+    pub fn eval(&self, exp: LogicExpr<P::R>) -> Handle<P::R> {
+        self.spawn(move |p| eval_logic_expr(p, exp))
+    }
+    /// 変数にはvarsの値を使う
+    pub fn eval_with(
+        &self,
+        exp: LogicExpr<P::R>,
+        vars: HashMap<String, P::R>,
+    ) -> Handle<Result<P::R, EvalError>> {
+        self.spawn(move |p| eval_logic_expr_with(p, exp, &vars))
+    }
+    /// 複数の式を共通部分をまとめて評価する
+    pub fn eval_many(
+        &self,
+        exps: Vec<LogicExpr<P::R>>,
+        vars: HashMap<String, P::R>,
+    ) -> Handle<Result<Vec<P::R>, EvalError>> {
+        self.spawn(move |p| eval_logic_exprs_with(p, exps, &vars))
+    }
+    pub fn nand(&self, lhs: P::R, rhs: P::R) -> Handle<P::R> {
+        self.spawn(move |p| p.nand(lhs, rhs))
+    }
+    pub fn and(&self, lhs: P::R, rhs: P::R) -> Handle<P::R> {
+        self.spawn(move |p| p.and(lhs, rhs))
+    }
+    pub fn or(&self, lhs: P::R, rhs: P::R) -> Handle<P::R> {
+        self.spawn(move |p| p.or(lhs, rhs))
+    }
+    pub fn xor(&self, lhs: P::R, rhs: P::R) -> Handle<P::R> {
+        self.spawn(move |p| p.xor(lhs, rhs))
+    }
+    pub fn not(&self, b: P::R) -> Handle<P::R> {
+        self.spawn(move |p| p.not(b))
+    }
+}
+impl<P> Drop for Executor<P> {
+    /// 投げてある計算を全て終えてからスレッドを止める
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// バッチの1つの仕事の失敗
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobError {
+    Eval(EvalError),
+    /// 評価中にpanicした。panicのメッセージ
+    Panicked(String),
+}
+impl Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::Eval(err) => write!(f, "{}", err),
+            JobError::Panicked(msg) => write!(f, "job panicked: {}", msg),
+        }
+    }
+}
+impl std::error::Error for JobError {}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// `eval_batch`で投げた仕事の結果。(投げた順の番号, 結果)を終わった順に返すIterator
+pub struct Batch<T> {
+    receiver: Receiver<(usize, Result<T, JobError>)>,
+    len: usize,
+    received: usize,
+}
+impl<T> Batch<T> {
+    /// 投げた仕事の数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// まだ受け取っていない結果の数
+    pub fn remaining(&self) -> usize {
+        self.len - self.received
+    }
+    /// 全て終わるまで待ち、投げた順に並べて返す
+    pub fn wait_all(self) -> Vec<Result<T, JobError>> {
+        let mut results: Vec<Option<Result<T, JobError>>> = (0..self.len).map(|_| None).collect();
+        for (i, res) in self {
+            results[i] = Some(res);
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+impl<T> Iterator for Batch<T> {
+    type Item = (usize, Result<T, JobError>);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.received == self.len {
+            return None;
+        }
+        // 仕事は必ず結果を送るので、Executorが生きている限り待てば届く
+        let item = self.receiver.recv().expect("executor threads are stopped");
+        self.received += 1;
+        Some(item)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining(), Some(self.remaining()))
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+}
+struct State<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Shared {
+            state: Mutex::new(State {
+                result: None,
+                waker: None,
+            }),
+            done: Condvar::new(),
+        }
+    }
+}
+impl<T> Shared<T> {
+    fn complete(&self, res: thread::Result<T>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(res);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.done.notify_all();
+    }
+}
+
+/// 投げた計算の結果。計算がpanicしたときは`thread::JoinHandle`と同じくErrになる
+pub struct Handle<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T> Handle<T> {
+    /// 結果が出るまで待つ
+    pub fn join(self) -> thread::Result<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(res) = state.result.take() {
+                return res;
+            }
+            state = self.shared.done.wait(state).unwrap();
+        }
+    }
+    pub fn is_finished(&self) -> bool {
+        self.shared.state.lock().unwrap().result.is_some()
+    }
+}
+impl<T> Future for Handle<T> {
+    type Output = thread::Result<T>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(res) => Poll::Ready(res),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_logic_expr, PlainLogip};
+    use hom_nand::{digest::Cryptor, tfhe::TFHE, tlwe::TLWE};
+    use std::task::Wake;
+    use utils::math::{Binary, BinaryDistribution, Random};
+
+    /// Handleを待つだけの最小のblock_on
+    fn block_on<F: Future>(f: F) -> F::Output {
+        struct Unpark(thread::Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            if let Poll::Ready(res) = f.as_mut().poll(&mut cx) {
+                return res;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn executor_plain() {
+        let exec = Executor::new(Arc::new(PlainLogip), 3);
+        assert_eq!(exec.threads(), 3);
+        let handles: Vec<_> = ["1&1", "1$1", "!(1|0)$0", "1^1^1", "0|0"]
+            .iter()
+            .map(|l| exec.eval(parse_logic_expr(l).unwrap()))
+            .collect();
+        let res: Vec<Binary> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        use Binary::{One, Zero};
+        assert_eq!(res, vec![One, Zero, One, One, Zero]);
+
+        let vars: HashMap<_, _> = vec![("a".to_string(), One), ("b".to_string(), Zero)]
+            .into_iter()
+            .collect();
+        let h = exec.eval_with(parse_logic_expr("a & !b").unwrap(), vars.clone());
+        assert_eq!(h.join().unwrap(), Ok(One));
+        let exps = vec![
+            parse_logic_expr("a ^ b").unwrap(),
+            parse_logic_expr("a & b").unwrap(),
+        ];
+        assert_eq!(
+            exec.eval_many(exps, vars).join().unwrap(),
+            Ok(vec![One, Zero])
+        );
+
+        assert_eq!(block_on(exec.xor(One, Zero)).unwrap(), One);
+        assert_eq!(block_on(exec.not(One)).unwrap(), Zero);
+    }
+
+    #[test]
+    fn executor_panic() {
+        let exec = Executor::new(Arc::new(PlainLogip), 1);
+        let h = exec.spawn(|_| -> Binary { panic!("in job") });
+        assert!(h.join().is_err());
+        // panicしてもスレッドは残る
+        assert_eq!(
+            exec.and(Binary::One, Binary::One).join().unwrap(),
+            Binary::One
+        );
+    }
+
+    #[test]
+    fn executor_batch() {
+        /// orでpanicする評価器
+        struct PanicOnOr;
+        impl Logip for PanicOnOr {
+            type R = Binary;
+            fn nand(&self, lhs: Binary, rhs: Binary) -> Binary {
+                PlainLogip.nand(lhs, rhs)
+            }
+            fn or(&self, _: Binary, _: Binary) -> Binary {
+                panic!("or is not supported")
+            }
+        }
+        let exec = Executor::new(Arc::new(PanicOnOr), 3);
+        let vars = |a: u32, b: u32| -> HashMap<String, Binary> {
+            [("a", a), ("b", b)]
+                .iter()
+                .map(|&(k, v)| (k.to_string(), Binary::from(v)))
+                .collect()
+        };
+        let mut jobs: Vec<_> = (0..8)
+            .map(|i| (parse_logic_expr("a ^ b").unwrap(), vars(i & 1, i >> 1 & 1)))
+            .collect();
+        jobs.push((parse_logic_expr("a & c").unwrap(), vars(1, 1)));
+        jobs.push((parse_logic_expr("a | b").unwrap(), vars(1, 1)));
+
+        let mut batch = exec.eval_batch(jobs.clone());
+        assert_eq!(batch.len(), 10);
+        let (i, _) = batch.next().unwrap();
+        assert!(i < 10);
+        assert_eq!(batch.remaining(), 9);
+        let mut seen: Vec<usize> = batch.map(|(i, _)| i).chain([i]).collect();
+        seen.sort_unstable();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+
+        let res = exec.eval_batch(jobs).wait_all();
+        for (i, r) in res[..8].iter().enumerate() {
+            assert_eq!(*r, Ok(Binary::from((i & 1) ^ (i >> 1 & 1))));
+        }
+        assert_eq!(
+            res[8],
+            Err(JobError::Eval(EvalError::UnboundVariable("c".to_string())))
+        );
+        assert_eq!(
+            res[9],
+            Err(JobError::Panicked("or is not supported".to_string()))
+        );
+        assert!(exec.eval_batch(Vec::new()).wait_all().is_empty());
+    }
+
+    #[test]
+    fn executor_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let exec = Executor::new(Arc::new(TFHE::new(s_key_tlwelv0, s_key_tlwelv1)), 2);
+
+        let enc = |b: Binary| Cryptor::encrypto(TLWE, &s_key_tlwelv0, b);
+        let handles: Vec<_> = [(0, 0), (0, 1), (1, 0), (1, 1)]
+            .iter()
+            .map(|&(x, y)| exec.nand(enc(Binary::from(x)), enc(Binary::from(y))))
+            .collect();
+        let res: Vec<Binary> = handles
+            .into_iter()
+            .map(|h| Cryptor::decrypto(TLWE, &s_key_tlwelv0, h.join().unwrap()))
+            .collect();
+        assert_eq!(
+            res,
+            vec![Binary::One, Binary::One, Binary::One, Binary::Zero]
+        );
+        assert_eq!(exec.logip().stats().total_gates(), 4);
+    }
+}
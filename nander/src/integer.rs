@@ -0,0 +1,445 @@
+/*!
+暗号化した整数の型。
+
+`FheUint<P, BITS>`(符号なし)と`FheInt<P, BITS>`(2の補数の符号付き)は、bitごとの暗号文と評価器(TFHEならサーバー鍵)への参照を持ち、`+`や`&`などの演算子で`arith`の回路を評価する。
+演算はどれも`BITS`bitで折り返す(`u8`の`wrapping_add`などと同じ)。
+`>>`は`FheUint`なら論理シフト、`FheInt`なら算術シフトで、比較(`lt`など)も符号に合わせる。
+
+```ignore
+let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+let a = FheUint::<_, 8>::encrypt(&tfhe, &s_key_tlwelv0, 100);
+let b = FheUint::<_, 8>::encrypt(&tfhe, &s_key_tlwelv0, 27);
+let c = (&a + &b) * &a;
+assert_eq!(c.decrypt(&s_key_tlwelv0), (127 * 100) & 0xff);
+```
+ */
+use crate::{arith, Logip};
+use hom_nand::{
+    digest::Cryptor,
+    tfhe::{BootstrappingKeyRows, TFHE},
+    tlwe::TLWE,
+};
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Shl, Shr, Sub};
+use utils::{math::Binary, mem, traits::AsLogic};
+
+/// 符号なし整数。0番目のbitが最下位
+pub struct FheUint<'a, P: Logip, const BITS: usize> {
+    pros: &'a P,
+    bits: [P::R; BITS],
+}
+/// 2の補数の符号付き整数。0番目のbitが最下位で、`BITS-1`番目が符号
+pub struct FheInt<'a, P: Logip, const BITS: usize> {
+    pros: &'a P,
+    bits: [P::R; BITS],
+}
+
+/// 符号拡張したvalueのibit目
+fn bit_at(value: i128, i: usize) -> bool {
+    (value >> i.min(127)) & 1 == 1
+}
+
+/// 2つの型で同じもの
+macro_rules! impl_common {
+    ($T:ident, $Int:ty) => {
+        impl<'a, P: Logip, const BITS: usize> $T<'a, P, BITS> {
+            pub fn new(pros: &'a P, bits: [P::R; BITS]) -> Self {
+                $T { pros, bits }
+            }
+            /// 暗号化していない値。`BITS`bitに入らない上位bitは捨てる
+            pub fn trivial(pros: &'a P, value: $Int) -> Self {
+                Self::new(
+                    pros,
+                    mem::array_create_enumerate(|i| {
+                        if bit_at(value as i128, i) {
+                            P::R::logic_true()
+                        } else {
+                            P::R::logic_false()
+                        }
+                    }),
+                )
+            }
+            pub fn bits(&self) -> &[P::R; BITS] {
+                &self.bits
+            }
+            pub fn into_bits(self) -> [P::R; BITS] {
+                self.bits
+            }
+            pub fn pros(&self) -> &'a P {
+                self.pros
+            }
+            /// condが1ならthen、0ならelse_
+            pub fn select(cond: &P::R, then: &Self, else_: &Self) -> Self {
+                then.with_bits(mem::array_create_enumerate(|i| {
+                    then.pros
+                        .mux(cond.clone(), else_.bits[i].clone(), then.bits[i].clone())
+                }))
+            }
+            pub fn is_zero(&self) -> P::R {
+                arith::is_zero(self.pros, &self.bits)
+            }
+            pub fn eq(&self, rhs: &Self) -> P::R {
+                arith::eq(self.pros, &self.bits, &rhs.bits)
+            }
+            pub fn ne(&self, rhs: &Self) -> P::R {
+                self.pros.not(self.eq(rhs))
+            }
+            pub fn gt(&self, rhs: &Self) -> P::R {
+                rhs.lt(self)
+            }
+            pub fn ge(&self, rhs: &Self) -> P::R {
+                rhs.le(self)
+            }
+
+            fn with_bits(&self, bits: [P::R; BITS]) -> Self {
+                Self::new(self.pros, bits)
+            }
+            /// 空いたbitをfillで埋めてshiftだけ右にずらす
+            fn shift_right(&self, shift: usize, fill: P::R) -> Self {
+                self.with_bits(mem::array_create_enumerate(|i| {
+                    match i.checked_add(shift) {
+                        Some(j) if j < BITS => self.bits[j].clone(),
+                        _ => fill.clone(),
+                    }
+                }))
+            }
+        }
+        impl<
+                'a,
+                const N: usize,
+                const M: usize,
+                BK: BootstrappingKeyRows<M>,
+                const BITS: usize,
+            > $T<'a, TFHE<N, M, BK>, BITS>
+        {
+            /// s_keyはTLWE(lv0)の秘密鍵
+            pub fn encrypt(tfhe: &'a TFHE<N, M, BK>, s_key: &[Binary; N], value: $Int) -> Self {
+                Self::new(
+                    tfhe,
+                    mem::array_create_enumerate(|i| {
+                        let bit = Binary::from(bit_at(value as i128, i) as u32);
+                        Cryptor::encrypto(TLWE, s_key, bit)
+                    }),
+                )
+            }
+            pub fn decrypt(&self, s_key: &[Binary; N]) -> $Int {
+                self.decrypt_with(|b| Cryptor::decrypto(TLWE, s_key, b.clone()))
+            }
+        }
+        impl<P: Logip, const BITS: usize> Clone for $T<'_, P, BITS> {
+            fn clone(&self) -> Self {
+                self.with_bits(self.bits.clone())
+            }
+        }
+        /// 平文の量だけ左にずらす。ゲートは使わない
+        impl<'a, P: Logip, const BITS: usize> Shl<usize> for &$T<'a, P, BITS> {
+            type Output = $T<'a, P, BITS>;
+            fn shl(self, shift: usize) -> Self::Output {
+                self.with_bits(mem::array_create_enumerate(|i| {
+                    if i >= shift {
+                        self.bits[i - shift].clone()
+                    } else {
+                        P::R::logic_false()
+                    }
+                }))
+            }
+        }
+        impl<'a, P: Logip, const BITS: usize> Shl<usize> for $T<'a, P, BITS> {
+            type Output = $T<'a, P, BITS>;
+            fn shl(self, shift: usize) -> Self::Output {
+                &self << shift
+            }
+        }
+        impl<'a, P: Logip, const BITS: usize> Shr<usize> for $T<'a, P, BITS> {
+            type Output = $T<'a, P, BITS>;
+            fn shr(self, shift: usize) -> Self::Output {
+                &self >> shift
+            }
+        }
+        impl_binary_op!($T, Add, add, |pros, a, b| arith::add(pros, a, b).0);
+        impl_binary_op!($T, Sub, sub, |pros, a, b| arith::sub(pros, a, b).0);
+        // 下位BITSbitは符号があってもなくても同じ
+        impl_binary_op!($T, Mul, mul, |pros, a, b| arith::mul(pros, a, b));
+        impl_binary_op!($T, BitAnd, bitand, |pros, a, b| bitwise(pros, a, b, P::and));
+        impl_binary_op!($T, BitOr, bitor, |pros, a, b| bitwise(pros, a, b, P::or));
+        impl_binary_op!($T, BitXor, bitxor, |pros, a, b| bitwise(pros, a, b, P::xor));
+    };
+}
+
+/// `&a op &b`を実装し、値を取るものはそれに任せる
+macro_rules! impl_binary_op {
+    ($T:ident, $Op:ident, $op:ident, |$pros:ident, $a:ident, $b:ident| $body:expr) => {
+        impl<'a, P: Logip, const BITS: usize> $Op<&$T<'a, P, BITS>> for &$T<'a, P, BITS> {
+            type Output = $T<'a, P, BITS>;
+            fn $op(self, rhs: &$T<'a, P, BITS>) -> Self::Output {
+                debug_assert!(
+                    std::ptr::eq(self.pros, rhs.pros),
+                    "operands use different evaluators"
+                );
+                let ($pros, $a, $b) = (self.pros, &self.bits, &rhs.bits);
+                self.with_bits($body)
+            }
+        }
+        impl<'a, P: Logip, const BITS: usize> $Op<$T<'a, P, BITS>> for &$T<'a, P, BITS> {
+            type Output = $T<'a, P, BITS>;
+            fn $op(self, rhs: $T<'a, P, BITS>) -> Self::Output {
+                self.$op(&rhs)
+            }
+        }
+        impl<'a, P: Logip, const BITS: usize> $Op<&$T<'a, P, BITS>> for $T<'a, P, BITS> {
+            type Output = $T<'a, P, BITS>;
+            fn $op(self, rhs: &$T<'a, P, BITS>) -> Self::Output {
+                (&self).$op(rhs)
+            }
+        }
+        impl<'a, P: Logip, const BITS: usize> $Op for $T<'a, P, BITS> {
+            type Output = $T<'a, P, BITS>;
+            fn $op(self, rhs: $T<'a, P, BITS>) -> Self::Output {
+                (&self).$op(&rhs)
+            }
+        }
+    };
+}
+
+/// bitごとにゲートを1つ使う
+fn bitwise<P: Logip, const BITS: usize>(
+    pros: &P,
+    a: &[P::R; BITS],
+    b: &[P::R; BITS],
+    gate: fn(&P, P::R, P::R) -> P::R,
+) -> [P::R; BITS] {
+    mem::array_create_enumerate(|i| gate(pros, a[i].clone(), b[i].clone()))
+}
+
+impl_common!(FheUint, u64);
+impl<P: Logip, const BITS: usize> FheUint<'_, P, BITS> {
+    /// decryptで1bitずつ復号する
+    /// # Panic
+    /// - `BITS > 64`
+    pub fn decrypt_with(&self, decrypt: impl Fn(&P::R) -> Binary) -> u64 {
+        assert!(BITS <= 64, "{} bits do not fit in u64", BITS);
+        self.bits
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (decrypt(b) as u64) << i)
+            .sum()
+    }
+    /// (和, 桁あふれ)
+    pub fn overflowing_add(&self, rhs: &Self) -> (Self, P::R) {
+        let (sum, carry) = arith::add(self.pros, &self.bits, &rhs.bits);
+        (self.with_bits(sum), carry)
+    }
+    /// (差, `self < rhs`)
+    pub fn overflowing_sub(&self, rhs: &Self) -> (Self, P::R) {
+        let (diff, borrow) = arith::sub(self.pros, &self.bits, &rhs.bits);
+        (self.with_bits(diff), borrow)
+    }
+    pub fn overflowing_mul(&self, rhs: &Self) -> (Self, P::R) {
+        let (product, overflow) = arith::overflowing_mul(self.pros, &self.bits, &rhs.bits);
+        (self.with_bits(product), overflow)
+    }
+    pub fn lt(&self, rhs: &Self) -> P::R {
+        arith::lt(self.pros, &self.bits, &rhs.bits)
+    }
+    pub fn le(&self, rhs: &Self) -> P::R {
+        arith::le(self.pros, &self.bits, &rhs.bits)
+    }
+}
+/// 論理シフト。上のbitは0で埋める
+impl<'a, P: Logip, const BITS: usize> Shr<usize> for &FheUint<'a, P, BITS> {
+    type Output = FheUint<'a, P, BITS>;
+    fn shr(self, shift: usize) -> Self::Output {
+        self.shift_right(shift, P::R::logic_false())
+    }
+}
+
+impl_common!(FheInt, i64);
+impl<P: Logip, const BITS: usize> FheInt<'_, P, BITS> {
+    /// decryptで1bitずつ復号し、符号拡張する
+    /// # Panic
+    /// - `BITS > 64`
+    pub fn decrypt_with(&self, decrypt: impl Fn(&P::R) -> Binary) -> i64 {
+        assert!(BITS <= 64, "{} bits do not fit in i64", BITS);
+        let value: u64 = self
+            .bits
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (decrypt(b) as u64) << i)
+            .sum();
+        // 符号bitを最上位に移してから戻す
+        ((value << (64 - BITS)) as i64) >> (64 - BITS)
+    }
+    /// (和, 符号付きであふれたか)
+    pub fn overflowing_add(&self, rhs: &Self) -> (Self, P::R) {
+        let (sum, overflow) = arith::overflowing_add_signed(self.pros, &self.bits, &rhs.bits);
+        (self.with_bits(sum), overflow)
+    }
+    pub fn overflowing_sub(&self, rhs: &Self) -> (Self, P::R) {
+        let (diff, overflow) = arith::overflowing_sub_signed(self.pros, &self.bits, &rhs.bits);
+        (self.with_bits(diff), overflow)
+    }
+    pub fn overflowing_mul(&self, rhs: &Self) -> (Self, P::R) {
+        let (product, overflow) = arith::overflowing_mul_signed(self.pros, &self.bits, &rhs.bits);
+        (self.with_bits(product), overflow)
+    }
+    /// 最小値のときだけあふれる
+    pub fn overflowing_neg(&self) -> (Self, P::R) {
+        let (res, overflow) = arith::neg(self.pros, &self.bits);
+        (self.with_bits(res), overflow)
+    }
+    pub fn lt(&self, rhs: &Self) -> P::R {
+        arith::lt_signed(self.pros, &self.bits, &rhs.bits)
+    }
+    pub fn le(&self, rhs: &Self) -> P::R {
+        arith::le_signed(self.pros, &self.bits, &rhs.bits)
+    }
+    /// 負なら1
+    pub fn is_negative(&self) -> P::R {
+        self.bits.last().cloned().unwrap_or_else(P::R::logic_false)
+    }
+}
+/// 算術シフト。上のbitは符号で埋める
+impl<'a, P: Logip, const BITS: usize> Shr<usize> for &FheInt<'a, P, BITS> {
+    type Output = FheInt<'a, P, BITS>;
+    fn shr(self, shift: usize) -> Self::Output {
+        self.shift_right(shift, self.is_negative())
+    }
+}
+/// 最小値の符号を変えると最小値のまま(`i8::wrapping_neg`と同じ)
+impl<'a, P: Logip, const BITS: usize> Neg for &FheInt<'a, P, BITS> {
+    type Output = FheInt<'a, P, BITS>;
+    fn neg(self) -> Self::Output {
+        self.with_bits(arith::neg(self.pros, &self.bits).0)
+    }
+}
+impl<'a, P: Logip, const BITS: usize> Neg for FheInt<'a, P, BITS> {
+    type Output = FheInt<'a, P, BITS>;
+    fn neg(self) -> Self::Output {
+        -&self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlainLogip;
+    use utils::math::{BinaryDistribution, Random};
+
+    type U8<'a> = FheUint<'a, PlainLogip, 8>;
+    type I8<'a> = FheInt<'a, PlainLogip, 8>;
+
+    fn value(x: &U8) -> u64 {
+        x.decrypt_with(|b| *b)
+    }
+    fn signed(x: &I8) -> i64 {
+        x.decrypt_with(|b| *b)
+    }
+
+    #[test]
+    fn fhe_uint_ops() {
+        let p = &PlainLogip;
+        for (a, b) in [(0u64, 0u64), (100, 27), (200, 100), (3, 250), (255, 255)] {
+            let (x, y) = (U8::trivial(p, a), U8::trivial(p, b));
+            assert_eq!(value(&(&x + &y)), (a + b) & 0xff, "{}+{}", a, b);
+            assert_eq!(value(&(&x - &y)), a.wrapping_sub(b) & 0xff, "{}-{}", a, b);
+            assert_eq!(value(&(&x * &y)), (a * b) & 0xff, "{}*{}", a, b);
+            assert_eq!(value(&(&x & &y)), a & b);
+            assert_eq!(value(&(&x | &y)), a | b);
+            assert_eq!(value(&(&x ^ &y)), a ^ b);
+            for shift in [0, 1, 7, 8, 20] {
+                assert_eq!(
+                    value(&(&x << shift)),
+                    (a << shift) & 0xff,
+                    "{}<<{}",
+                    a,
+                    shift
+                );
+            }
+            for shift in [0, 1, 7, 8, 20] {
+                assert_eq!(
+                    value(&(x.clone() >> shift)),
+                    a.checked_shr(shift as u32).unwrap_or(0)
+                );
+            }
+            assert_eq!(x.lt(&y), Binary::from((a < b) as u32));
+            assert_eq!(x.ge(&y), Binary::from((a >= b) as u32));
+            assert_eq!(x.eq(&y), Binary::from((a == b) as u32));
+            let flag = |(x, o): (U8, Binary)| (value(&x), o == Binary::One);
+            let (ua, ub) = (a as u8, b as u8);
+            let wide = |(x, o): (u8, bool)| (x as u64, o);
+            assert_eq!(flag(x.overflowing_add(&y)), wide(ua.overflowing_add(ub)));
+            assert_eq!(flag(x.overflowing_sub(&y)), wide(ua.overflowing_sub(ub)));
+            assert_eq!(flag(x.overflowing_mul(&y)), wide(ua.overflowing_mul(ub)));
+            assert_eq!(x.is_zero(), Binary::from((a == 0) as u32));
+            assert_eq!(value(&U8::select(&x.lt(&y), &x, &y)), a.min(b));
+            // 値を取る演算子も同じ
+            assert_eq!(value(&(x.clone() + y.clone())), (a + b) & 0xff);
+            assert_eq!(value(&(x ^ y)), a ^ b);
+        }
+        assert_eq!(value(&U8::trivial(p, 0x1234)), 0x34);
+    }
+
+    #[test]
+    fn fhe_int_ops() {
+        let p = &PlainLogip;
+        let values = [0i8, 1, -1, 5, -7, 100, -100, 127, -128];
+        for &a in &values {
+            for &b in &values {
+                let (x, y) = (I8::trivial(p, a as i64), I8::trivial(p, b as i64));
+                assert_eq!(signed(&(&x + &y)), a.wrapping_add(b) as i64, "{}+{}", a, b);
+                assert_eq!(signed(&(&x - &y)), a.wrapping_sub(b) as i64, "{}-{}", a, b);
+                assert_eq!(signed(&(&x * &y)), a.wrapping_mul(b) as i64, "{}*{}", a, b);
+                assert_eq!(signed(&(&x ^ &y)), (a ^ b) as i64);
+                assert_eq!(x.lt(&y), Binary::from((a < b) as u32), "{}<{}", a, b);
+                assert_eq!(x.le(&y), Binary::from((a <= b) as u32), "{}<={}", a, b);
+                assert_eq!(x.gt(&y), Binary::from((a > b) as u32), "{}>{}", a, b);
+                let flag = |(x, o): (I8, Binary)| (signed(&x), o == Binary::One);
+                let wide = |(x, o): (i8, bool)| (x as i64, o);
+                assert_eq!(flag(x.overflowing_add(&y)), wide(a.overflowing_add(b)));
+                assert_eq!(flag(x.overflowing_sub(&y)), wide(a.overflowing_sub(b)));
+                assert_eq!(flag(x.overflowing_mul(&y)), wide(a.overflowing_mul(b)));
+            }
+            assert_eq!(signed(&-&I8::trivial(p, a as i64)), a.wrapping_neg() as i64);
+            let x = I8::trivial(p, a as i64);
+            assert_eq!(x.is_negative(), Binary::from((a < 0) as u32));
+            let (res, overflow) = x.overflowing_neg();
+            assert_eq!((signed(&res), overflow == Binary::One), {
+                let (r, o) = a.overflowing_neg();
+                (r as i64, o)
+            });
+            for shift in [0usize, 1, 3, 7, 8, 20] {
+                // 8bit以上ずらすと符号だけが残る
+                let expect = (a as i64) >> shift.min(7);
+                assert_eq!(signed(&(&x >> shift)), expect, "{}>>{}", a, shift);
+                assert_eq!(
+                    signed(&(&x << shift)),
+                    a.checked_shl(shift as u32).unwrap_or(0) as i64,
+                    "{}<<{}",
+                    a,
+                    shift
+                );
+            }
+        }
+        assert_eq!(signed(&I8::trivial(p, -300)), -300i64 as i8 as i64);
+    }
+
+    #[test]
+    fn fhe_uint_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+        let a = FheUint::<_, 4>::encrypt(&tfhe, &s_key_tlwelv0, 11);
+        let b = FheUint::<_, 4>::encrypt(&tfhe, &s_key_tlwelv0, 6);
+        assert_eq!((&a + &b).decrypt(&s_key_tlwelv0), (11 + 6) & 0xf);
+        assert_eq!((&a - &b).decrypt(&s_key_tlwelv0), 11 - 6);
+        assert_eq!(
+            ((a ^ b) << 1).decrypt(&s_key_tlwelv0),
+            ((11 ^ 6) << 1) & 0xf
+        );
+        let c = FheInt::<_, 4>::encrypt(&tfhe, &s_key_tlwelv0, -3);
+        assert_eq!((&c >> 1).decrypt(&s_key_tlwelv0), -2);
+        assert_eq!((-c).decrypt(&s_key_tlwelv0), 3);
+    }
+}
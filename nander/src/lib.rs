@@ -4,12 +4,64 @@
 extern crate hom_nand;
 extern crate utils;
 
+pub mod arena;
+pub mod arith;
+pub mod bdd;
+pub mod bits;
+pub mod blif;
+pub mod boolean;
+pub mod circuit;
+pub mod context;
+pub mod cost;
+pub mod debug;
+pub mod dot;
+pub mod equiv;
+pub mod executor;
+pub mod integer;
+pub mod optimize;
+pub mod parser;
+pub mod printer;
+pub mod protocol;
+pub mod random;
+pub mod repl;
+pub mod shared;
+pub mod stream;
+pub mod synth;
+pub mod trivium;
+pub mod verilog;
+
+pub use arena::ExprArena;
+pub use bdd::{equivalent, simplify_with_bdd, Bdd};
+pub use bits::{BitOrder, FheBits};
+pub use blif::{import_blif, BlifError, BlifModel};
+pub use boolean::FheBool;
+pub use circuit::Circuit;
+pub use context::{server_key, set_server_key, try_server_key, with_server_key};
+pub use cost::{CostEstimate, CostModel, GateCost};
+pub use debug::{DebugLogip, GateTrace};
+pub use equiv::{counterexample, logic_equivalent};
+pub use executor::{Batch, Executor, Handle, JobError};
+pub use integer::{FheInt, FheUint};
+pub use optimize::{count_gates, fold_constants, lower_to_nand, GateCount, NandForm};
+pub use parser::{
+    bus_bit_name, parse_bus_expr, parse_bus_expr_with, parse_logic_expr, parse_logic_expr_with,
+    ParseError, ParserConfig, Syntax,
+};
+pub use printer::Printer;
+pub use protocol::{Client, ProtocolError, Request, Response, Server};
+pub use random::{ExprGenConfig, ExprGenerator, OpWeights};
+pub use repl::{Repl, ReplError};
+pub use shared::{SharedExpr, SharedNode};
+pub use stream::{eval_stream_with, Instr, StreamError, StreamEvaluator};
+pub use verilog::VerilogError;
+
 use hom_nand::{
     digest::Cryptor,
-    tfhe::{TFHEHelper, TFHE},
+    tfhe::{BootstrappingKeyRows, TFHEHelper, TFHE},
     tlwe::{TLWEHelper, TLWERep, TLWE},
 };
-use std::str::Chars;
+use std::collections::HashMap;
+use std::fmt::Display;
 use utils::{mem,math::{Binary, BinaryDistribution, Random}, timeit, traits::AsLogic};
 use std::time;
 
@@ -35,9 +87,42 @@ where
         let x = self.nand(lhs.clone(), rhs.clone());
         self.nand(self.nand(lhs, x.clone()), self.nand(x, rhs))
     }
+    /// control ? input_1 : input_0
+    fn mux(&self, control: Self::R, input_0: Self::R, input_1: Self::R) -> Self::R {
+        let i_1 = self.and(control.clone(), input_1);
+        let i_0 = self.and(self.not(control), input_0);
+        self.or(i_1, i_0)
+    }
+    fn nor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        self.not(self.or(lhs, rhs))
+    }
+    fn xnor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        self.not(self.xor(lhs, rhs))
+    }
+    /// !lhs | rhs
+    fn implies(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        self.or(self.not(lhs), rhs)
+    }
+    /// 半加算器。(和, 桁上げ)
+    fn half_add(&self, a: Self::R, b: Self::R) -> (Self::R, Self::R) {
+        (self.xor(a.clone(), b.clone()), self.and(a, b))
+    }
+    /// 全加算器。(和, 桁上げ)
+    fn full_add(&self, a: Self::R, b: Self::R, cin: Self::R) -> (Self::R, Self::R) {
+        let (s, c_ab) = self.half_add(a, b);
+        let (sum, c_s) = self.half_add(s, cin);
+        (sum, self.or(c_ab, c_s))
+    }
+    /// ゲートごとの重さ。`LogicExpr::estimate_cost`で使う。既定では全て0
+    fn cost_model() -> CostModel
+    where
+        Self: Sized,
+    {
+        CostModel::default()
+    }
 }
 
-impl<const N: usize, const M: usize> Logip for TFHE<N, M> {
+impl<const N: usize, const M: usize, BK: BootstrappingKeyRows<M>> Logip for TFHE<N, M, BK> {
     type R = TLWERep<N>;
 
     fn nand(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
@@ -59,8 +144,133 @@ impl<const N: usize, const M: usize> Logip for TFHE<N, M> {
     fn xor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
         self.hom_xor(lhs, rhs)
     }
+
+    fn mux(&self, control: Self::R, input_0: Self::R, input_1: Self::R) -> Self::R {
+        self.hom_mux(control, input_0, input_1)
+    }
+
+    // 符号を反転した入力を使うと1回のbootstrapで済む
+    fn nor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        self.hom_and(-lhs, -rhs)
+    }
+
+    fn xnor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        self.hom_xor(-lhs, rhs)
+    }
+
+    fn implies(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        self.hom_or(-lhs, rhs)
+    }
+
+    fn cost_model() -> CostModel {
+        CostModel::tfhe(cost::TFHE_BOOTSTRAP_LATENCY)
+    }
+}
+
+/// 暗号化せずに平文のまま評価する。鍵ストリームの生成や結果の確認に使う
+pub struct PlainLogip;
+impl Logip for PlainLogip {
+    type R = Binary;
+
+    fn nand(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        match (lhs, rhs) {
+            (Binary::One, Binary::One) => Binary::Zero,
+            _ => Binary::One,
+        }
+    }
+
+    fn xor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        if lhs == rhs {
+            Binary::Zero
+        } else {
+            Binary::One
+        }
+    }
+}
+
+/// `bool`のまま評価する。鍵なしで回路を組み立てて試すのに使う
+pub struct BoolLogip;
+impl Logip for BoolLogip {
+    type R = bool;
+
+    fn nand(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        !(lhs && rhs)
+    }
+
+    fn not(&self, b: Self::R) -> Self::R {
+        !b
+    }
+
+    fn and(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        lhs && rhs
+    }
+
+    fn or(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        lhs || rhs
+    }
+
+    fn xor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        lhs ^ rhs
+    }
+}
+
+/// 評価器を借りたまま`DebugLogip`などで包めるようにする
+impl<P: Logip> Logip for &P {
+    type R = P::R;
+
+    fn nand(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        (*self).nand(lhs, rhs)
+    }
+
+    fn not(&self, b: Self::R) -> Self::R {
+        (*self).not(b)
+    }
+
+    fn and(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        (*self).and(lhs, rhs)
+    }
+
+    fn or(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        (*self).or(lhs, rhs)
+    }
+
+    fn xor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        (*self).xor(lhs, rhs)
+    }
+
+    fn mux(&self, control: Self::R, input_0: Self::R, input_1: Self::R) -> Self::R {
+        (*self).mux(control, input_0, input_1)
+    }
+
+    fn nor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        (*self).nor(lhs, rhs)
+    }
+
+    fn xnor(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        (*self).xnor(lhs, rhs)
+    }
+
+    fn implies(&self, lhs: Self::R, rhs: Self::R) -> Self::R {
+        (*self).implies(lhs, rhs)
+    }
+
+    fn half_add(&self, a: Self::R, b: Self::R) -> (Self::R, Self::R) {
+        (*self).half_add(a, b)
+    }
+
+    fn full_add(&self, a: Self::R, b: Self::R, cin: Self::R) -> (Self::R, Self::R) {
+        (*self).full_add(a, b, cin)
+    }
+
+    fn cost_model() -> CostModel {
+        P::cost_model()
+    }
 }
 
+/// `serialize`featureでserdeに対応する。JSONでは`{"and":[{"var":"a"},{"leaf":1}]}`のようになる
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "snake_case"))]
 pub enum LogicExpr<R: AsLogic> {
     Nand(Box<Self>, Box<Self>),
     Not(Box<Self>),
@@ -68,109 +278,167 @@ pub enum LogicExpr<R: AsLogic> {
     Or(Box<Self>, Box<Self>),
     Xor(Box<Self>, Box<Self>),
     Leaf(R),
+    /// 名前で参照する入力。評価するときに値を渡す
+    Var(String),
 }
-pub fn eval_logic_expr<P: Logip>(pros: &P, exp: LogicExpr<<P as Logip>::R>) -> <P as Logip>::R {
-    match exp {
-        LogicExpr::<<P as Logip>::R>::Nand(rhs, lhs) => {
-            pros.nand(eval_logic_expr(pros, *lhs), eval_logic_expr(pros, *rhs))
-        }
-        LogicExpr::<<P as Logip>::R>::Not(lhs) => pros.not(eval_logic_expr(pros, *lhs)),
-        LogicExpr::<<P as Logip>::R>::And(lhs, rhs) => {
-            pros.and(eval_logic_expr(pros, *lhs), eval_logic_expr(pros, *rhs))
-        }
-        LogicExpr::<<P as Logip>::R>::Or(lhs, rhs) => {
-            pros.or(eval_logic_expr(pros, *lhs), eval_logic_expr(pros, *rhs))
-        }
-        LogicExpr::<<P as Logip>::R>::Xor(lhs, rhs) => {
-            pros.xor(eval_logic_expr(pros, *lhs), eval_logic_expr(pros, *rhs))
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// 値が渡されていない変数
+    UnboundVariable(String),
+}
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::UnboundVariable(name) => write!(f, "unbound variable: {}", name),
         }
-        LogicExpr::<<P as Logip>::R>::Leaf(elem) => elem,
     }
 }
-pub fn parse_logic_expr<R: AsLogic>(l: &str) -> Result<LogicExpr<R>, &str> {
-    const ZERO: char = '0';
-    const ONE: char = '1';
-    const AND: char = '&';
-    const OR: char = '|';
-    const XOR: char = '^';
-    const NOT: char = '!';
-    const NAND: char = '$';
-    const LEFT: char = '(';
-    const RIGHT: char = ')';
-    let mut l = l.trim().to_string();
-    l.retain(|c| !c.is_whitespace());
-    let mut l = l.as_str().chars();
-
-    return match parse_binary_op::<R>(&mut l) {
-        Result::Ok(item) => Ok(*item),
-        Result::Err(err) => Err(err),
-    };
+impl std::error::Error for EvalError {}
 
-    fn parse_binary_op<R: AsLogic>(l: &mut Chars) -> Result<Box<LogicExpr<R>>, &'static str> {
-        let mut lhs = parse_mono_op::<R>(l)?;
-        loop {
-            match l.clone().next() {
-                Option::Some(c) => match c {
-                    AND => {
-                        l.next();
-                        lhs = Box::new(LogicExpr::And(lhs, parse_mono_op(l)?));
-                    }
-                    OR => {
-                        l.next();
-                        lhs = Box::new(LogicExpr::Or(lhs, parse_mono_op(l)?));
-                    }
-                    XOR => {
-                        l.next();
-                        lhs = Box::new(LogicExpr::Xor(lhs, parse_mono_op(l)?));
-                    }
-                    NAND => {
-                        l.next();
-                        lhs = Box::new(LogicExpr::Nand(lhs, parse_mono_op(l)?));
-                    }
-                    _ => {
-                        return Ok(lhs);
+/// # Panic
+/// - 式に変数(LogicExpr::Var)が含まれる。変数を使うときは`eval_logic_expr_with`を使う
+pub fn eval_logic_expr<P: Logip>(pros: &P, exp: LogicExpr<<P as Logip>::R>) -> <P as Logip>::R {
+    match eval_logic_expr_with(pros, exp, &HashMap::new()) {
+        Ok(res) => res,
+        Err(err) => panic!("{}", err),
+    }
+}
+/// 変数にはvarsの値を使って評価する。
+///
+/// 再帰せずに自前のスタックで評価するので、深い式でもスタックは溢れない。
+/// 評価し終えたノードから順に捨てていくので、式を捨てるときも再帰しない。
+pub fn eval_logic_expr_with<P: Logip>(
+    pros: &P,
+    exp: LogicExpr<<P as Logip>::R>,
+    vars: &HashMap<String, <P as Logip>::R>,
+) -> Result<<P as Logip>::R, EvalError> {
+    enum Task<R: AsLogic> {
+        Eval(LogicExpr<R>),
+        Apply(Op),
+    }
+    let mut tasks = vec![Task::Eval(exp)];
+    let mut values = Vec::new();
+    // エラーの後も、式を少しずつ捨てるためにノードの分解だけは続ける
+    let mut error = None;
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Eval(exp) => match exp {
+                LogicExpr::Leaf(elem) => values.push(elem),
+                LogicExpr::Var(name) => match vars.get(&name) {
+                    Some(elem) => values.push(elem.clone()),
+                    None => {
+                        error.get_or_insert(EvalError::UnboundVariable(name));
                     }
                 },
-                Option::None => {
-                    l.next();
-                    return Ok(lhs);
+                LogicExpr::Not(e) => tasks.extend([Task::Apply(Op::Not), Task::Eval(*e)]),
+                // 左辺から評価する
+                LogicExpr::Nand(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::Nand), Task::Eval(*rhs), Task::Eval(*lhs)])
                 }
-            }
+                LogicExpr::And(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::And), Task::Eval(*rhs), Task::Eval(*lhs)])
+                }
+                LogicExpr::Or(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::Or), Task::Eval(*rhs), Task::Eval(*lhs)])
+                }
+                LogicExpr::Xor(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::Xor), Task::Eval(*rhs), Task::Eval(*lhs)])
+                }
+            },
+            Task::Apply(_) if error.is_some() => {}
+            Task::Apply(op) => op.apply(pros, &mut values),
         }
     }
-    fn parse_mono_op<R: AsLogic>(l: &mut Chars) -> Result<Box<LogicExpr<R>>, &'static str> {
-        if let Some(c) = l.clone().next() {
-            if c == NOT {
-                l.next();
-                return Ok(Box::new(LogicExpr::Not(parse_mono_op(l)?)));
-            }
-        }
-        Ok(parse_elem(l)?)
-    }
-    fn parse_elem<R: AsLogic>(l: &mut Chars) -> Result<Box<LogicExpr<R>>, &'static str> {
-        match l.next() {
-            Option::Some(c) => match c {
-                ZERO => Ok(Box::new(LogicExpr::Leaf(R::logic_false()))),
-                ONE => Ok(Box::new(LogicExpr::Leaf(R::logic_true()))),
-                LEFT => {
-                    let e = parse_binary_op::<R>(l)?;
-                    if let Some(c) = l.next() {
-                        if c == RIGHT {
-                            Ok(e)
-                        } else {
-                            Err("braket is not closed")
-                        }
-                    } else {
-                        Err("braket is not closed")
-                    }
+    match error {
+        Some(err) => Err(err),
+        None => Ok(values.pop().unwrap()),
+    }
+}
+/// `eval_logic_expr_with`の式を借りる版。同じ式を入力を変えて何度も評価するときに使う
+///
+/// 変数はenvの値を使い、envにない変数があれば`EvalError::UnboundVariable`を返す。
+pub fn eval_logic_expr_with_env<P: Logip>(
+    pros: &P,
+    exp: &LogicExpr<<P as Logip>::R>,
+    env: &HashMap<String, <P as Logip>::R>,
+) -> Result<<P as Logip>::R, EvalError> {
+    enum Task<'e, R: AsLogic> {
+        Eval(&'e LogicExpr<R>),
+        Apply(Op),
+    }
+    let mut tasks = vec![Task::Eval(exp)];
+    let mut values = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Eval(exp) => match exp {
+                LogicExpr::Leaf(elem) => values.push(elem.clone()),
+                LogicExpr::Var(name) => match env.get(name) {
+                    Some(elem) => values.push(elem.clone()),
+                    None => return Err(EvalError::UnboundVariable(name.clone())),
+                },
+                LogicExpr::Not(e) => tasks.extend([Task::Apply(Op::Not), Task::Eval(e)]),
+                // 左辺から評価する
+                LogicExpr::Nand(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::Nand), Task::Eval(rhs), Task::Eval(lhs)])
+                }
+                LogicExpr::And(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::And), Task::Eval(rhs), Task::Eval(lhs)])
+                }
+                LogicExpr::Or(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::Or), Task::Eval(rhs), Task::Eval(lhs)])
+                }
+                LogicExpr::Xor(lhs, rhs) => {
+                    tasks.extend([Task::Apply(Op::Xor), Task::Eval(rhs), Task::Eval(lhs)])
                 }
-                _ => Err("invalid element"),
             },
-            Option::None => Err("invalid element. this is none"),
+            Task::Apply(op) => op.apply(pros, &mut values),
         }
     }
+    Ok(values.pop().unwrap())
+}
+/// 評価のスタックで使うゲート
+enum Op {
+    Nand,
+    Not,
+    And,
+    Or,
+    Xor,
+}
+impl Op {
+    /// 入力をvaluesの末尾から取り出し、出力を積む
+    fn apply<P: Logip>(self, pros: &P, values: &mut Vec<P::R>) {
+        if let Op::Not = self {
+            let e = values.pop().unwrap();
+            values.push(pros.not(e));
+            return;
+        }
+        let rhs = values.pop().unwrap();
+        let lhs = values.pop().unwrap();
+        values.push(match self {
+            Op::Nand => pros.nand(lhs, rhs),
+            Op::And => pros.and(lhs, rhs),
+            Op::Or => pros.or(lhs, rhs),
+            Op::Xor => pros.xor(lhs, rhs),
+            Op::Not => unreachable!(),
+        });
+    }
+}
+/// 複数の式をまとめて評価する。式の間で同じ部分式は1回だけ評価する(和と桁上げなど)
+pub fn eval_logic_exprs_with<P: Logip>(
+    pros: &P,
+    exps: Vec<LogicExpr<<P as Logip>::R>>,
+    vars: &HashMap<String, <P as Logip>::R>,
+) -> Result<Vec<<P as Logip>::R>, EvalError> {
+    Circuit::from_exprs(exps).eval_with(pros, vars)
+}
+/// バスの式をbitごとに評価する。0番目が最下位bit
+pub fn eval_bus_expr_with<P: Logip>(
+    pros: &P,
+    exps: Vec<LogicExpr<<P as Logip>::R>>,
+    vars: &HashMap<String, <P as Logip>::R>,
+) -> Result<Vec<<P as Logip>::R>, EvalError> {
+    eval_logic_exprs_with(pros, exps, vars)
 }
-
 #[cfg(feature = "profile")]
 pub fn hom_nand_profile() {
 
@@ -346,3 +614,245 @@ where [();N/2]:,
     }
 }
  */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(items: &[(&str, Binary)]) -> HashMap<String, Binary> {
+        items.iter().map(|&(k, v)| (k.to_string(), v)).collect()
+    }
+
+    #[test]
+    fn parse_logic_expr_var() {
+        use Binary::{One, Zero};
+        let exp = parse_logic_expr::<Binary>("a & !b | c").unwrap();
+        let res = eval_logic_expr_with(
+            &PlainLogip,
+            exp,
+            &vars(&[("a", One), ("b", Zero), ("c", Zero)]),
+        );
+        assert_eq!(res, Ok(One));
+
+        let exp = parse_logic_expr::<Binary>("( x_1 ^ in2 ) $ 1").unwrap();
+        let res = eval_logic_expr_with(&PlainLogip, exp, &vars(&[("x_1", One), ("in2", One)]));
+        assert_eq!(res, Ok(One));
+
+        let exp = parse_logic_expr::<Binary>("a & b").unwrap();
+        let res = eval_logic_expr_with(&PlainLogip, exp, &vars(&[("a", One)]));
+        assert_eq!(res, Err(EvalError::UnboundVariable("b".to_string())));
+
+        // 変数がなければ今まで通り
+        let exp = parse_logic_expr::<Binary>("!(1 | 0) $ 0").unwrap();
+        assert_eq!(eval_logic_expr(&PlainLogip, exp), One);
+    }
+
+    #[test]
+    fn parse_logic_expr_precedence() {
+        let plain = |l: &str, a: Binary, b: Binary, c: Binary| {
+            let exp = parse_logic_expr::<Binary>(l).unwrap();
+            eval_logic_expr_with(&PlainLogip, exp, &vars(&[("a", a), ("b", b), ("c", c)])).unwrap()
+        };
+        let p = PlainLogip;
+        for i in 0..8 {
+            let (a, b, c) = (
+                Binary::from(i & 1),
+                Binary::from(i & 2),
+                Binary::from(i & 4),
+            );
+            let cases = [
+                ("a | b & c", p.or(a, p.and(b, c))),
+                ("a & b | c", p.or(p.and(a, b), c)),
+                ("a ^ b & c", p.xor(a, p.and(b, c))),
+                ("a | b ^ c", p.or(a, p.xor(b, c))),
+                ("a $ b | c", p.nand(a, p.or(b, c))),
+                ("!a & b", p.and(p.not(a), b)),
+                ("(a | b) & c", p.and(p.or(a, b), c)),
+                // 同じ強さなら左結合
+                ("a $ b $ c", p.nand(p.nand(a, b), c)),
+            ];
+            for (l, expect) in cases.iter() {
+                assert_eq!(
+                    plain(l, a, b, c),
+                    *expect,
+                    "{} (a={},b={},c={})",
+                    l,
+                    a,
+                    b,
+                    c
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serialize")]
+    fn logic_expr_json() {
+        let exp = parse_logic_expr::<Binary>("!(a & 1) | b").unwrap();
+        let json = serde_json::to_string(&exp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"or":[{"not":{"and":[{"var":"a"},{"leaf":1}]}},{"var":"b"}]}"#
+        );
+        let exp: LogicExpr<Binary> = serde_json::from_str(&json).unwrap();
+        assert_eq!(exp.to_string(), "!(a & 1) | b");
+
+        // 外で作ったJSONを評価する
+        let exp: LogicExpr<Binary> =
+            serde_json::from_str(r#"{"nand": [{"var": "x"}, {"leaf": 0}]}"#).unwrap();
+        let res = eval_logic_expr_with(&PlainLogip, exp, &vars(&[("x", Binary::One)]));
+        assert_eq!(res, Ok(Binary::One));
+
+        assert!(serde_json::from_str::<LogicExpr<Binary>>(r#"{"leaf": 2}"#).is_err());
+        assert!(serde_json::from_str::<LogicExpr<Binary>>(r#"{"xnor": []}"#).is_err());
+    }
+
+    #[test]
+    fn eval_logic_expr_deep() {
+        // 再帰して評価するとスタックが溢れる深さ
+        const DEPTH: usize = 300_000;
+        let deep = || {
+            let mut exp = LogicExpr::Var("a".to_string());
+            for i in 0..DEPTH {
+                exp = match i % 3 {
+                    0 => LogicExpr::Not(Box::new(exp)),
+                    1 => LogicExpr::And(Box::new(exp), Box::new(LogicExpr::Leaf(Binary::One))),
+                    _ => LogicExpr::Xor(Box::new(LogicExpr::Leaf(Binary::Zero)), Box::new(exp)),
+                };
+            }
+            exp
+        };
+        // notはDEPTH/3回なので偶数回
+        let res = eval_logic_expr_with(&PlainLogip, deep(), &vars(&[("a", Binary::One)]));
+        assert_eq!(res, Ok(Binary::One));
+        let res = eval_logic_expr_with(&PlainLogip, deep(), &vars(&[]));
+        assert_eq!(res, Err(EvalError::UnboundVariable("a".to_string())));
+    }
+
+    #[test]
+    fn eval_logic_expr_env() {
+        use Binary::{One, Zero};
+        let exp = parse_logic_expr::<Binary>("(a ^ b) & !c | 0").unwrap();
+        // 同じ式を入力を変えて評価する
+        for bits in 0..8u32 {
+            let (a, b, c) = (bits & 1, (bits >> 1) & 1, (bits >> 2) & 1);
+            let env = vars(&[("a", a.into()), ("b", b.into()), ("c", c.into())]);
+            let res = eval_logic_expr_with_env(&PlainLogip, &exp, &env);
+            assert_eq!(res, Ok(Binary::from((a ^ b) & (1 - c))));
+            assert_eq!(res, eval_logic_expr_with(&PlainLogip, exp.clone(), &env));
+        }
+        let res = eval_logic_expr_with_env(&PlainLogip, &exp, &vars(&[("a", One), ("c", Zero)]));
+        assert_eq!(res, Err(EvalError::UnboundVariable("b".to_string())));
+    }
+
+    #[test]
+    fn eval_logic_exprs_shared() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        // 全加算器の和と桁上げ。a ^ bを共有する
+        let exps = vec![
+            parse_logic_expr("a ^ b ^ c").unwrap(),
+            parse_logic_expr("a & b | c & (a ^ b)").unwrap(),
+        ];
+        let vars: HashMap<String, _> =
+            [("a", Binary::One), ("b", Binary::Zero), ("c", Binary::One)]
+                .iter()
+                .map(|&(k, v)| (k.to_string(), Cryptor::encrypto(TLWE, &s_key_tlwelv0, v)))
+                .collect();
+        let res = eval_logic_exprs_with(&tfhe, exps, &vars).unwrap();
+        let res: Vec<Binary> = res
+            .into_iter()
+            .map(|r| Cryptor::decrypto(TLWE, &s_key_tlwelv0, r))
+            .collect();
+        assert_eq!(res, vec![Binary::Zero, Binary::One]);
+        // 別々に評価すると6ゲート
+        assert_eq!(tfhe.stats().total_gates(), 5);
+    }
+
+    #[test]
+    fn bool_logip() {
+        for s in ["1&1", "1$1", "!(1|0)$0", "1^1^1", "0|0"] {
+            let exp = parse_logic_expr::<bool>(s).unwrap();
+            let expect = eval_logic_expr(&PlainLogip, parse_logic_expr(s).unwrap());
+            let res = eval_logic_expr(&BoolLogip, exp);
+            assert_eq!(res, expect == Binary::One, "{}", s);
+        }
+        let circuit = Circuit::from_exprs(parse_bus_expr::<bool>("x[1:0] ^ 2'b10").unwrap());
+        assert_eq!(circuit.eval(&BoolLogip, &[true, true]), vec![true, false]);
+    }
+
+    #[test]
+    fn logip_derived_gates() {
+        let table = |p: &dyn Fn(Binary, Binary, Binary) -> Binary| -> Vec<Binary> {
+            (0..8u32)
+                .map(|i| {
+                    p(
+                        Binary::from(i & 1),
+                        Binary::from(i >> 1 & 1),
+                        Binary::from(i >> 2),
+                    )
+                })
+                .collect()
+        };
+        let expect = |f: fn(bool, bool, bool) -> bool| {
+            table(&|a, b, c| {
+                let res = f(a == Binary::One, b == Binary::One, c == Binary::One);
+                Binary::from(res as u32)
+            })
+        };
+        let p = PlainLogip;
+        assert_eq!(
+            table(&|a, b, c| p.mux(a, b, c)),
+            expect(|a, b, c| if a { c } else { b })
+        );
+        assert_eq!(table(&|a, b, _| p.nor(a, b)), expect(|a, b, _| !(a | b)));
+        assert_eq!(table(&|a, b, _| p.xnor(a, b)), expect(|a, b, _| a == b));
+        assert_eq!(table(&|a, b, _| p.implies(a, b)), expect(|a, b, _| !a | b));
+
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+        let enc = |b: Binary| Cryptor::encrypto(TLWE, &s_key_tlwelv0, b);
+        let dec = |r: TLWERep<TLWE_N>| -> Binary { Cryptor::decrypto(TLWE, &s_key_tlwelv0, r) };
+        let hom =
+            |f: &dyn Fn(TLWERep<TLWE_N>, TLWERep<TLWE_N>, TLWERep<TLWE_N>) -> TLWERep<TLWE_N>| {
+                table(&|a, b, c| dec(f(enc(a), enc(b), enc(c))))
+            };
+        assert_eq!(
+            hom(&|a, b, c| tfhe.mux(a, b, c)),
+            expect(|a, b, c| if a { c } else { b })
+        );
+        tfhe.reset_stats();
+        assert_eq!(hom(&|a, b, _| tfhe.nor(a, b)), expect(|a, b, _| !(a | b)));
+        assert_eq!(hom(&|a, b, _| tfhe.xnor(a, b)), expect(|a, b, _| a == b));
+        assert_eq!(hom(&|a, b, _| tfhe.implies(a, b)), expect(|a, b, _| !a | b));
+        // どれも1ゲート
+        assert_eq!(tfhe.stats().total_gates(), 3 * 8);
+        // 借りた評価器(&P)からも同じゲートを使う
+        tfhe.reset_stats();
+        let by_ref = &tfhe;
+        assert_eq!(hom(&|a, b, _| Logip::nor(&by_ref, a, b)), expect(|a, b, _| !(a | b)));
+        assert_eq!(hom(&|a, b, _| Logip::xnor(&by_ref, a, b)), expect(|a, b, _| a == b));
+        assert_eq!(hom(&|a, b, _| Logip::implies(&by_ref, a, b)), expect(|a, b, _| !a | b));
+        assert_eq!(tfhe.stats().total_gates(), 3 * 8);
+    }
+
+    #[test]
+    fn logip_adders() {
+        for i in 0..8u32 {
+            let (a, b, c) = (i & 1, i >> 1 & 1, i >> 2);
+            let (s, carry) = PlainLogip.half_add(Binary::from(a), Binary::from(b));
+            assert_eq!((s as u32) + 2 * (carry as u32), a + b);
+            let (s, carry) = BoolLogip.full_add(a == 1, b == 1, c == 1);
+            assert_eq!((s as u32) + 2 * (carry as u32), a + b + c);
+        }
+    }
+}
@@ -3,9 +3,10 @@ use hom_nand::{
     tfhe::{TFHEHelper, TFHE},
     tlwe::{TLWEHelper, TLWERep, TLWE},
 };
-use nander::{eval_logic_expr, parse_logic_expr, Logip};
+use nander::{eval_logic_expr_with, parse_logic_expr_with, Logip, ParserConfig, Repl, Syntax};
 use std::{
     array,
+    collections::HashMap,
     io::{self, BufRead, Write},
 };
 use utils::{
@@ -24,11 +25,23 @@ where
     CONVERT: Fn(P::R) -> Binary,
 {
     println!("Hello nander!!");
-    println!("[Rule]1:true,0:false,&:and,$:nand,!:not,|:or,^:xor");
+    println!("[Rule]1(true):true,0(false):false,&:and,$:nand,!:not,|:or,^:xor");
     println!("[Example]");
     println!("- 1&1 => 1");
     println!("- !(1|0)$0 => 1");
     println!("- 1&1$0 => (1&1)$0");
+    println!("- 1|0&0 => 1|(0&0)");
+    println!("[Precedence] ! > & > ^ > | > $");
+    // --c-likeで && || ^ !& を使う
+    let config = ParserConfig {
+        syntax: if std::env::args().any(|a| a == "--c-like") {
+            println!("[Syntax] &&:and, ||:or, !&:nand");
+            Syntax::CLike
+        } else {
+            Syntax::Symbol
+        },
+        ..Default::default()
+    };
 
     let pros = f();
 
@@ -45,8 +58,11 @@ where
         match res {
             Ok(_) => {
                 let exp = {
-                    let res = parse_logic_expr(&buffer);
+                    let res = parse_logic_expr_with(&buffer, &config);
                     if let Err(err) = res {
+                        // エラーの位置(バイト数)を文字数に直して^を出す
+                        let column = buffer[..err.position()].chars().count();
+                        println!("{}^", " ".repeat("nander>".len() + column));
                         println!("[Parse Error] {}", err);
                         continue;
                     }
@@ -54,7 +70,13 @@ where
                 };
 
                 let start = std::time::Instant::now();
-                let z_ = eval_logic_expr(&pros, exp);
+                let z_ = match eval_logic_expr_with(&pros, exp, &HashMap::new()) {
+                    Ok(z_) => z_,
+                    Err(err) => {
+                        println!("[Eval Error] {}", err);
+                        continue;
+                    }
+                };
                 let time_ms = start.elapsed().as_millis();
 
                 let z: Binary = g(z_);
@@ -84,6 +106,16 @@ fn main() {
     let create_tfhe = || TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
     let convert = |rep: TLWERep<TLWE_N>| Cryptor::decrypto(TLWE, &s_key_tlwelv0, rep);
 
+    // --replで変数を束縛しながら評価する
+    if std::env::args().any(|a| a == "--repl") {
+        let tfhe = create_tfhe();
+        println!("Hello nander!! (:help for usage)");
+        Repl::with_key(&tfhe, s_key_tlwelv0)
+            .run(io::stdin().lock(), io::stdout())
+            .unwrap();
+        return;
+    }
+
     nander_console(create_tfhe, convert);
 }
 
@@ -0,0 +1,288 @@
+/*!
+論理式の最適化。
+
+TFHEではゲートを1つ減らすごとにbootstrapが1回減るので、評価する前に式を小さくしておく。
+ */
+use crate::LogicExpr;
+use std::fmt::Display;
+use utils::traits::AsLogic;
+
+/// 式に含まれるゲートの数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GateCount {
+    pub nand: usize,
+    pub and: usize,
+    pub or: usize,
+    pub xor: usize,
+    pub not: usize,
+}
+impl GateCount {
+    pub fn total(&self) -> usize {
+        self.nand + self.and + self.or + self.xor + self.not
+    }
+    /// TFHEで評価したときのbootstrapの回数。notはbootstrapしない
+    pub fn bootstraps(&self) -> usize {
+        self.total() - self.not
+    }
+}
+impl Display for GateCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "nand={} and={} or={} xor={} not={} total={}",
+            self.nand,
+            self.and,
+            self.or,
+            self.xor,
+            self.not,
+            self.total()
+        )
+    }
+}
+pub fn count_gates<R: AsLogic>(exp: &LogicExpr<R>) -> GateCount {
+    let mut count = GateCount::default();
+    let mut stack = vec![exp];
+    while let Some(exp) = stack.pop() {
+        match exp {
+            LogicExpr::Nand(l, r) => {
+                count.nand += 1;
+                stack.extend([&**l, &**r]);
+            }
+            LogicExpr::And(l, r) => {
+                count.and += 1;
+                stack.extend([&**l, &**r]);
+            }
+            LogicExpr::Or(l, r) => {
+                count.or += 1;
+                stack.extend([&**l, &**r]);
+            }
+            LogicExpr::Xor(l, r) => {
+                count.xor += 1;
+                stack.extend([&**l, &**r]);
+            }
+            LogicExpr::Not(e) => {
+                count.not += 1;
+                stack.push(e);
+            }
+            LogicExpr::Leaf(_) | LogicExpr::Var(_) => {}
+        }
+    }
+    count
+}
+
+/// 値が分かっている定数(`AsLogic::as_const`)を畳み込む。
+///
+/// `x & 0 -> 0`,`x & 1 -> x`,`x | 1 -> 1`,`x ^ 1 -> !x`,`x $ 0 -> 1`,`!!x -> x`など。
+/// 定数で結果が決まる演算の他方の式は捨てるので、その中の変数には値を渡さなくてもよくなる。
+pub fn fold_constants<R: AsLogic>(exp: LogicExpr<R>) -> LogicExpr<R> {
+    let fold = |e: Box<LogicExpr<R>>| fold_constants(*e);
+    match exp {
+        LogicExpr::Not(e) => not(fold(e)),
+        LogicExpr::And(l, r) => fold_binary(fold(l), fold(r), LogicExpr::And, |c, e| {
+            if c {
+                e
+            } else {
+                constant(false)
+            }
+        }),
+        LogicExpr::Or(l, r) => fold_binary(fold(l), fold(r), LogicExpr::Or, |c, e| {
+            if c {
+                constant(true)
+            } else {
+                e
+            }
+        }),
+        LogicExpr::Xor(l, r) => {
+            fold_binary(
+                fold(l),
+                fold(r),
+                LogicExpr::Xor,
+                |c, e| {
+                    if c {
+                        not(e)
+                    } else {
+                        e
+                    }
+                },
+            )
+        }
+        LogicExpr::Nand(l, r) => fold_binary(fold(l), fold(r), LogicExpr::Nand, |c, e| {
+            if c {
+                not(e)
+            } else {
+                constant(true)
+            }
+        }),
+        LogicExpr::Leaf(_) | LogicExpr::Var(_) => exp,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NandForm {
+    /// NANDだけ。`!x`は`x $ x`にする
+    NandOnly,
+    /// NANDとNOT。TFHEではNOTはbootstrapしないので、こちらの方が速い
+    NandNot,
+}
+
+/// NANDだけ(またはNANDとNOT)の同じ値の式に書き換えて、そのゲートの数と一緒に返す。
+///
+/// `a & b -> !(a $ b)`,`a | b -> !a $ !b`,`a ^ b -> (a $ t) $ (b $ t)  (t = a $ b)`。
+/// 木のままなので、xorや`NandOnly`の`!x`では部分式を複製する。
+pub fn lower_to_nand<R: AsLogic + Clone>(
+    exp: LogicExpr<R>,
+    form: NandForm,
+) -> (LogicExpr<R>, GateCount) {
+    let exp = lower(exp, form);
+    let count = count_gates(&exp);
+    (exp, count)
+}
+fn lower<R: AsLogic + Clone>(exp: LogicExpr<R>, form: NandForm) -> LogicExpr<R> {
+    let nand = |l, r| LogicExpr::Nand(Box::new(l), Box::new(r));
+    let not = |e: LogicExpr<R>| match (e, form) {
+        (LogicExpr::Not(e), _) => *e,
+        (e, NandForm::NandNot) => LogicExpr::Not(Box::new(e)),
+        (e, NandForm::NandOnly) => nand(e.clone(), e),
+    };
+    let lower = |e: Box<LogicExpr<R>>| lower(*e, form);
+    match exp {
+        LogicExpr::Nand(l, r) => nand(lower(l), lower(r)),
+        LogicExpr::Not(e) => not(lower(e)),
+        LogicExpr::And(l, r) => not(nand(lower(l), lower(r))),
+        LogicExpr::Or(l, r) => nand(not(lower(l)), not(lower(r))),
+        LogicExpr::Xor(l, r) => {
+            let (l, r) = (lower(l), lower(r));
+            let t = nand(l.clone(), r.clone());
+            nand(nand(l, t.clone()), nand(r, t))
+        }
+        LogicExpr::Leaf(_) | LogicExpr::Var(_) => exp,
+    }
+}
+
+type BinaryCtor<R> = fn(Box<LogicExpr<R>>, Box<LogicExpr<R>>) -> LogicExpr<R>;
+
+/// 片方が定数ならwith_const(定数の値, 他方)にする。演算はどれも可換
+fn fold_binary<R: AsLogic>(
+    lhs: LogicExpr<R>,
+    rhs: LogicExpr<R>,
+    op: BinaryCtor<R>,
+    with_const: fn(bool, LogicExpr<R>) -> LogicExpr<R>,
+) -> LogicExpr<R> {
+    match (as_const(&lhs), as_const(&rhs)) {
+        (Some(c), _) => with_const(c, rhs),
+        (_, Some(c)) => with_const(c, lhs),
+        _ => op(Box::new(lhs), Box::new(rhs)),
+    }
+}
+fn not<R: AsLogic>(exp: LogicExpr<R>) -> LogicExpr<R> {
+    if let Some(c) = as_const(&exp) {
+        return constant(!c);
+    }
+    match exp {
+        LogicExpr::Not(e) => *e,
+        exp => LogicExpr::Not(Box::new(exp)),
+    }
+}
+fn as_const<R: AsLogic>(exp: &LogicExpr<R>) -> Option<bool> {
+    match exp {
+        LogicExpr::Leaf(r) => r.as_const(),
+        _ => None,
+    }
+}
+pub(crate) fn constant<R: AsLogic>(c: bool) -> LogicExpr<R> {
+    LogicExpr::Leaf(if c { R::logic_true() } else { R::logic_false() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_logic_expr_with, parse_logic_expr, PlainLogip};
+    use hom_nand::tlwe::{TLWEHelper, TLWERep};
+    use std::collections::HashMap;
+    use utils::math::Binary;
+
+    fn fold(l: &str) -> String {
+        fold_constants(parse_logic_expr::<Binary>(l).unwrap()).to_string()
+    }
+
+    #[test]
+    fn fold_constants_rules() {
+        assert_eq!(fold("x & 0"), "0");
+        assert_eq!(fold("1 & x"), "x");
+        assert_eq!(fold("x | 1"), "1");
+        assert_eq!(fold("0 | x"), "x");
+        assert_eq!(fold("x ^ 0"), "x");
+        assert_eq!(fold("1 ^ x"), "!x");
+        assert_eq!(fold("x $ 0"), "1");
+        assert_eq!(fold("x $ 1"), "!x");
+        assert_eq!(fold("!1"), "0");
+        assert_eq!(fold("!!x"), "x");
+        assert_eq!(fold("!(x ^ 1)"), "x");
+        assert_eq!(fold("(a & (1 | b)) ^ (c & !1)"), "a");
+        assert_eq!(fold("a & b | !0 & c"), "a & b | c");
+        assert_eq!(fold("1 ^ 1 $ 0 | 1"), "1");
+
+        // 畳み込んでも値は変わらない
+        let l = "(a ^ 1) & (b | 0) $ !(c & 1) ^ (a $ 1)";
+        for i in 0..8 {
+            let vars: HashMap<String, Binary> = ["a", "b", "c"]
+                .iter()
+                .enumerate()
+                .map(|(j, name)| (name.to_string(), Binary::from((i >> j) & 1)))
+                .collect();
+            let eval = |exp| eval_logic_expr_with(&PlainLogip, exp, &vars);
+            let exp = || parse_logic_expr::<Binary>(l).unwrap();
+            assert_eq!(eval(fold_constants(exp())), eval(exp()));
+        }
+    }
+
+    #[test]
+    fn fold_constants_tlwe() {
+        // 暗号文の定数(自明な暗号文)も畳み込める
+        const N: usize = TLWEHelper::N;
+        let exp = parse_logic_expr::<TLWERep<N>>("(a | 0) & !0").unwrap();
+        assert!(matches!(fold_constants(exp), LogicExpr::Var(name) if name == "a"));
+    }
+
+    #[test]
+    fn lower_to_nand_equivalent() {
+        let count = |l: &str, form| lower_to_nand(parse_logic_expr::<Binary>(l).unwrap(), form).1;
+        let gates = |nand, not| GateCount {
+            nand,
+            not,
+            ..Default::default()
+        };
+        assert_eq!(count("a & b", NandForm::NandNot), gates(1, 1));
+        // (a $ b) $ (a $ b)。木なので複製したa $ bも数える
+        assert_eq!(count("a & b", NandForm::NandOnly), gates(3, 0));
+        assert_eq!(count("!a | !b", NandForm::NandNot), gates(1, 0));
+        assert_eq!(count("!a", NandForm::NandOnly), gates(1, 0));
+        assert_eq!(count("a ^ b", NandForm::NandNot), gates(5, 0));
+        assert_eq!(count("a ^ b", NandForm::NandNot).bootstraps(), 5);
+
+        let l = "(a ^ b) & !c | (a $ c) ^ !(b | 1)";
+        for form in [NandForm::NandOnly, NandForm::NandNot] {
+            let (lowered, count) = lower_to_nand(parse_logic_expr::<Binary>(l).unwrap(), form);
+            assert_eq!(count, count_gates(&lowered));
+            assert_eq!(count.total(), count.nand + count.not);
+            if form == NandForm::NandOnly {
+                assert_eq!(count.not, 0);
+            }
+            for i in 0..8 {
+                let vars: HashMap<String, Binary> = ["a", "b", "c"]
+                    .iter()
+                    .enumerate()
+                    .map(|(j, name)| (name.to_string(), Binary::from((i >> j) & 1)))
+                    .collect();
+                let eval = |exp| eval_logic_expr_with(&PlainLogip, exp, &vars);
+                assert_eq!(
+                    eval(lowered.clone()),
+                    eval(parse_logic_expr(l).unwrap()),
+                    "{:?} {}",
+                    form,
+                    i
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,851 @@
+/*!
+論理式の構文解析。
+
+```text
+expr := mono (op mono)*      op: & ^ | $ (強い順)
+mono := '!' mono | elem
+elem := '0' | '1' | 'true' | 'false' | literal | ident | ident '[' n (':' n)? ']' | '(' expr ')'
+literal := width "'b" [01_]+ | width "'h" [0-9a-fA-F_]+
+```
+空白は読み飛ばす。エラーの位置は入力の先頭からのバイト数。
+括弧と`!`は合わせて`MAX_DEPTH`段まで入れ子にできる。
+
+## バス
+`x[7:0]`や`8'b10110001`は複数bitの値(バス)になり、演算子はbitごとに適用する。
+`parse_bus_expr`はbitごとの式を、0番目を最下位bit(テキストでは一番右)として返す。
+`x[7:0]`の各bitは`x[7]`,...,`x[0]`という名前の変数(`bus_bit_name`)になる。
+二項演算子の両辺の幅は同じでなければならない。`parse_logic_expr`は1bitの式だけを受け付ける。
+
+`ParserConfig::define`で登録した名前は、その式を括弧で囲んだものとして読む。
+
+演算子の記号は`Syntax`で選ぶ。
+
+| 演算 | Symbol | CLike |
+|------|--------|-------|
+| not  | `!`    | `!`   |
+| and  | `&`    | `&&`  |
+| xor  | `^`    | `^`   |
+| or   | `\|`   | `\|\|`  |
+| nand | `$`    | `!&`  |
+ */
+use crate::LogicExpr;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::Chars;
+use utils::traits::AsLogic;
+
+const ZERO: char = '0';
+const ONE: char = '1';
+const NOT: char = '!';
+const LEFT: char = '(';
+const RIGHT: char = ')';
+const BUS_LEFT: char = '[';
+const BUS_RIGHT: char = ']';
+const BUS_RANGE: char = ':';
+const LITERAL: char = '\'';
+/// バスの範囲やリテラルで書ける幅の上限。`x[4294967295:0]`のような入力で
+/// 大きなVecを確保しないようにする
+pub const MAX_BUS_WIDTH: usize = 1 << 16;
+/// 括弧と`!`を入れ子にできる深さの上限。構文解析も出来た式の評価や破棄も
+/// 深さの分だけ再帰するので、`((((...`のような入力でスタックを溢れさせないようにする
+pub const MAX_DEPTH: usize = 1 << 8;
+
+/// 演算子の記号の組
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// 1文字の記号 `& | ^ $`
+    #[default]
+    Symbol,
+    /// C/Verilogの条件式に近い記号 `&& || ^ !&`
+    CLike,
+}
+#[derive(Debug, Clone, Default)]
+pub struct ParserConfig {
+    pub syntax: Syntax,
+    /// `T`,`F`も`true`,`false`として読む。変数名には使えなくなる
+    pub short_bool: bool,
+    /// 名前 -> 展開する式
+    pub constants: HashMap<String, String>,
+}
+impl ParserConfig {
+    /// nameを式exprの別名にする。exprは使うときに同じ設定で読む
+    pub fn define(&mut self, name: &str, expr: &str) -> &mut Self {
+        self.constants.insert(name.to_string(), expr.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    And,
+    Xor,
+    Or,
+    Nand,
+}
+impl BinaryOp {
+    /// 結合の強さ。大きいほど先に結合する (! > & > ^ > | > $)
+    pub(crate) fn precedence(self) -> u32 {
+        match self {
+            BinaryOp::And => 4,
+            BinaryOp::Xor => 3,
+            BinaryOp::Or => 2,
+            BinaryOp::Nand => 1,
+        }
+    }
+    fn tokens(syntax: Syntax) -> [(&'static str, BinaryOp); 4] {
+        match syntax {
+            Syntax::Symbol => [
+                ("&", BinaryOp::And),
+                ("^", BinaryOp::Xor),
+                ("|", BinaryOp::Or),
+                ("$", BinaryOp::Nand),
+            ],
+            Syntax::CLike => [
+                ("&&", BinaryOp::And),
+                ("^", BinaryOp::Xor),
+                ("||", BinaryOp::Or),
+                ("!&", BinaryOp::Nand),
+            ],
+        }
+    }
+    pub(crate) fn token(self, syntax: Syntax) -> &'static str {
+        Self::tokens(syntax)
+            .iter()
+            .find(|&&(_, op)| op == self)
+            .unwrap()
+            .0
+    }
+    fn apply<R: AsLogic>(self, lhs: LogicExpr<R>, rhs: LogicExpr<R>) -> LogicExpr<R> {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match self {
+            BinaryOp::And => LogicExpr::And(lhs, rhs),
+            BinaryOp::Xor => LogicExpr::Xor(lhs, rhs),
+            BinaryOp::Or => LogicExpr::Or(lhs, rhs),
+            BinaryOp::Nand => LogicExpr::Nand(lhs, rhs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// 値(0,1,変数,括弧)が来るはずの位置に別の文字がある
+    InvalidElement { found: char, position: usize },
+    /// 値が来る前に式が終わった
+    UnexpectedEnd { position: usize },
+    /// ')'が来るはずの位置に別の文字がある。Noneは式の終わり
+    UnclosedParen {
+        found: Option<char>,
+        position: usize,
+    },
+    /// 式を読み終えた後に文字が残っている
+    TrailingInput { found: char, position: usize },
+    /// 名前付きの定数の式が読めない。errorの位置は定数の式の中での位置
+    InConstant {
+        name: String,
+        position: usize,
+        error: Box<ParseError>,
+    },
+    /// 名前付きの定数が自分自身を含む
+    RecursiveConstant { name: String, position: usize },
+    /// ']'が来るはずの位置に別の文字がある。Noneは式の終わり
+    UnclosedBracket {
+        found: Option<char>,
+        position: usize,
+    },
+    /// `8'b1011`のようなリテラルが読めない(基数・数字・幅が不正)
+    InvalidLiteral { position: usize },
+    /// 二項演算子の両辺のバスの幅が違う。positionは演算子の位置
+    WidthMismatch {
+        left: usize,
+        right: usize,
+        position: usize,
+    },
+    /// 1bitの式が必要な所にバスがある
+    NotABit { width: usize, position: usize },
+    /// バスの範囲やリテラルの幅がMAX_BUS_WIDTHを超える
+    BusTooWide { width: usize, position: usize },
+    /// 括弧と`!`の入れ子がMAX_DEPTHを超える。positionは超えた所の記号の位置
+    TooDeep { position: usize },
+}
+impl ParseError {
+    pub fn position(&self) -> usize {
+        match self {
+            ParseError::InvalidElement { position, .. }
+            | ParseError::UnexpectedEnd { position }
+            | ParseError::UnclosedParen { position, .. }
+            | ParseError::TrailingInput { position, .. }
+            | ParseError::InConstant { position, .. }
+            | ParseError::RecursiveConstant { position, .. }
+            | ParseError::UnclosedBracket { position, .. }
+            | ParseError::InvalidLiteral { position }
+            | ParseError::WidthMismatch { position, .. }
+            | ParseError::NotABit { position, .. }
+            | ParseError::BusTooWide { position, .. }
+            | ParseError::TooDeep { position } => *position,
+        }
+    }
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidElement { found, position } => {
+                write!(f, "invalid element '{}' at {}", found, position)
+            }
+            ParseError::UnexpectedEnd { position } => {
+                write!(f, "unexpected end of input at {}", position)
+            }
+            ParseError::UnclosedParen {
+                found: Some(c),
+                position,
+            } => write!(f, "expected ')' but found '{}' at {}", c, position),
+            ParseError::UnclosedParen {
+                found: None,
+                position,
+            } => write!(f, "expected ')' but input ended at {}", position),
+            ParseError::TrailingInput { found, position } => {
+                write!(f, "unexpected '{}' after expression at {}", found, position)
+            }
+            ParseError::InConstant {
+                name,
+                position,
+                error,
+            } => write!(f, "in constant '{}' at {}: {}", name, position, error),
+            ParseError::RecursiveConstant { name, position } => {
+                write!(f, "constant '{}' refers to itself at {}", name, position)
+            }
+            ParseError::UnclosedBracket {
+                found: Some(c),
+                position,
+            } => write!(f, "expected ']' but found '{}' at {}", c, position),
+            ParseError::UnclosedBracket {
+                found: None,
+                position,
+            } => write!(f, "expected ']' but input ended at {}", position),
+            ParseError::InvalidLiteral { position } => {
+                write!(f, "invalid literal at {}", position)
+            }
+            ParseError::WidthMismatch {
+                left,
+                right,
+                position,
+            } => write!(
+                f,
+                "bus width mismatch ({} and {}) at {}",
+                left, right, position
+            ),
+            ParseError::NotABit { width, position } => {
+                write!(f, "expected 1 bit but found {} bits at {}", width, position)
+            }
+            ParseError::BusTooWide { width, position } => write!(
+                f,
+                "bus width {} exceeds {} at {}",
+                width, MAX_BUS_WIDTH, position
+            ),
+            ParseError::TooDeep { position } => {
+                write!(f, "nesting deeper than {} at {}", MAX_DEPTH, position)
+            }
+        }
+    }
+}
+impl std::error::Error for ParseError {}
+
+pub fn parse_logic_expr<R: AsLogic>(l: &str) -> Result<LogicExpr<R>, ParseError> {
+    parse_logic_expr_with(l, &ParserConfig::default())
+}
+pub fn parse_logic_expr_with<R: AsLogic>(
+    l: &str,
+    config: &ParserConfig,
+) -> Result<LogicExpr<R>, ParseError> {
+    let mut bus = parse_bus_expr_with(l, config)?;
+    match bus.len() {
+        1 => Ok(bus.pop().unwrap()),
+        width => Err(ParseError::NotABit { width, position: 0 }),
+    }
+}
+/// bitごとの式を返す。0番目が最下位bit
+pub fn parse_bus_expr<R: AsLogic>(l: &str) -> Result<Vec<LogicExpr<R>>, ParseError> {
+    parse_bus_expr_with(l, &ParserConfig::default())
+}
+pub fn parse_bus_expr_with<R: AsLogic>(
+    l: &str,
+    config: &ParserConfig,
+) -> Result<Vec<LogicExpr<R>>, ParseError> {
+    Parser {
+        src: l,
+        chars: l.chars(),
+        config,
+        expanding: Vec::new(),
+        depth: 0,
+    }
+    .parse()
+}
+/// バス`name`のi番目のbitを表す変数名
+pub fn bus_bit_name(name: &str, i: usize) -> String {
+    format!("{}[{}]", name, i)
+}
+
+/// bitごとの式。0番目が最下位bit
+type Bus<R> = Vec<LogicExpr<R>>;
+
+struct Parser<'a> {
+    src: &'a str,
+    chars: Chars<'a>,
+    config: &'a ParserConfig,
+    /// 展開中の定数の名前
+    expanding: Vec<&'a str>,
+    /// 今いる位置を囲む括弧と`!`の数
+    depth: usize,
+}
+impl<'a> Parser<'a> {
+    fn parse<R: AsLogic>(mut self) -> Result<Bus<R>, ParseError> {
+        let exp = self.parse_binary_op(0)?;
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) => Err(ParseError::TrailingInput {
+                found: c,
+                position: self.position(),
+            }),
+            None => Ok(exp),
+        }
+    }
+    /// 次に読む文字の位置
+    fn position(&self) -> usize {
+        self.src.len() - self.chars.as_str().len()
+    }
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+    /// fを満たす間読み進めて、読んだ部分を返す
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.chars.as_str();
+        let len = rest.len() - rest.trim_start_matches(f).len();
+        self.chars = rest[len..].chars();
+        &rest[..len]
+    }
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.chars.next();
+        }
+    }
+    /// 次が二項演算子ならその種類と記号の長さ
+    fn peek_binary_op(&self) -> Option<(BinaryOp, usize)> {
+        let rest = self.chars.as_str();
+        BinaryOp::tokens(self.config.syntax)
+            .iter()
+            .find(|(token, _)| rest.starts_with(token))
+            .map(|&(token, op)| (op, token.len()))
+    }
+    /// 優先順位法(precedence climbing)。結合の強さがmin_prec以上の演算子だけを読む
+    fn parse_binary_op<R: AsLogic>(&mut self, min_prec: u32) -> Result<Bus<R>, ParseError> {
+        let mut lhs = self.parse_mono_op::<R>()?;
+        loop {
+            self.skip_whitespace();
+            let (op, len) = match self.peek_binary_op() {
+                Some((op, len)) if op.precedence() >= min_prec => (op, len),
+                _ => return Ok(lhs),
+            };
+            let position = self.position();
+            // 記号はASCIIなのでlen文字進める
+            self.chars.nth(len - 1);
+            // 左結合なので、右辺には自分より強い演算子だけを含める
+            let rhs = self.parse_binary_op(op.precedence() + 1)?;
+            if lhs.len() != rhs.len() {
+                return Err(ParseError::WidthMismatch {
+                    left: lhs.len(),
+                    right: rhs.len(),
+                    position,
+                });
+            }
+            lhs = lhs
+                .into_iter()
+                .zip(rhs)
+                .map(|(l, r)| op.apply(l, r))
+                .collect();
+        }
+    }
+    /// 入れ子を一段深くする。positionは入れ子を作る記号の位置
+    fn enter(&mut self, position: usize) -> Result<(), ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ParseError::TooDeep { position });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+    fn parse_mono_op<R: AsLogic>(&mut self) -> Result<Bus<R>, ParseError> {
+        // '!'の連続は再帰せずに数える
+        let mut nots = 0;
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(NOT) {
+                break;
+            }
+            self.enter(self.position())?;
+            self.chars.next();
+            nots += 1;
+        }
+        let bus = self.parse_elem()?;
+        self.depth -= nots;
+        Ok(bus
+            .into_iter()
+            .map(|e| (0..nots).fold(e, |e, _| LogicExpr::Not(Box::new(e))))
+            .collect())
+    }
+    fn parse_elem<R: AsLogic>(&mut self) -> Result<Bus<R>, ParseError> {
+        let position = self.position();
+        // 数字の後に'が続けばリテラル
+        let rest = self.chars.as_str();
+        let after_digits = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+        if after_digits.len() < rest.len() && after_digits.starts_with(LITERAL) {
+            return self.parse_literal(position);
+        }
+        match self.chars.next() {
+            Some(ZERO) => Ok(vec![LogicExpr::Leaf(R::logic_false())]),
+            Some(ONE) => Ok(vec![LogicExpr::Leaf(R::logic_true())]),
+            Some(LEFT) => {
+                self.enter(position)?;
+                let e = self.parse_binary_op::<R>(0)?;
+                self.depth -= 1;
+                self.skip_whitespace();
+                let position = self.position();
+                match self.chars.next() {
+                    Some(RIGHT) => Ok(e),
+                    found => Err(ParseError::UnclosedParen { found, position }),
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                // 変数名 [A-Za-z_][A-Za-z0-9_]*
+                let mut name = c.to_string();
+                while let Some(c) = self.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    self.chars.next();
+                }
+                if self.peek() == Some(BUS_LEFT) {
+                    self.chars.next();
+                    return self.parse_bus_range(&name, position);
+                }
+                self.parse_name(name, position)
+            }
+            Some(found) => Err(ParseError::InvalidElement { found, position }),
+            None => Err(ParseError::UnexpectedEnd { position }),
+        }
+    }
+    /// `[msb:lsb]`または`[i]`。'['は読んである。name_positionは名前の位置
+    fn parse_bus_range<R: AsLogic>(
+        &mut self,
+        name: &str,
+        name_position: usize,
+    ) -> Result<Bus<R>, ParseError> {
+        let msb = self.parse_index()?;
+        self.skip_whitespace();
+        let lsb = if self.peek() == Some(BUS_RANGE) {
+            self.chars.next();
+            self.parse_index()?
+        } else {
+            msb
+        };
+        self.skip_whitespace();
+        let position = self.position();
+        match self.chars.next() {
+            Some(BUS_RIGHT) => {}
+            found => return Err(ParseError::UnclosedBracket { found, position }),
+        }
+        let width = msb.abs_diff(lsb).saturating_add(1);
+        if width > MAX_BUS_WIDTH {
+            return Err(ParseError::BusTooWide {
+                width,
+                position: name_position,
+            });
+        }
+        // 右に書いた方を0番目にする
+        let indices: Vec<usize> = if msb >= lsb {
+            (lsb..=msb).collect()
+        } else {
+            (msb..=lsb).rev().collect()
+        };
+        Ok(indices
+            .into_iter()
+            .map(|i| LogicExpr::Var(bus_bit_name(name, i)))
+            .collect())
+    }
+    fn parse_index(&mut self) -> Result<usize, ParseError> {
+        self.skip_whitespace();
+        let position = self.position();
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        if digits.is_empty() {
+            return Err(match self.peek() {
+                Some(found) => ParseError::InvalidElement { found, position },
+                None => ParseError::UnexpectedEnd { position },
+            });
+        }
+        digits
+            .parse()
+            .map_err(|_| ParseError::InvalidLiteral { position })
+    }
+    /// `<幅>'b<2進数>`または`<幅>'h<16進数>`。数字の間の'_'は読み飛ばす
+    fn parse_literal<R: AsLogic>(&mut self, position: usize) -> Result<Bus<R>, ParseError> {
+        let err = ParseError::InvalidLiteral { position };
+        let width: usize = self
+            .take_while(|c| c.is_ascii_digit())
+            .parse()
+            .map_err(|_| err.clone())?;
+        if width > MAX_BUS_WIDTH {
+            return Err(ParseError::BusTooWide { width, position });
+        }
+        self.chars.next(); // '
+        let bits_per_digit = match self.chars.next() {
+            Some('b') | Some('B') => 1,
+            Some('h') | Some('H') => 4,
+            _ => return Err(err),
+        };
+        let digits = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        // 下位bitから詰める
+        let mut bits = Vec::new();
+        for c in digits.chars().rev().filter(|&c| c != '_') {
+            let d = c.to_digit(1 << bits_per_digit).ok_or_else(|| err.clone())?;
+            bits.extend((0..bits_per_digit).map(|i| (d >> i) & 1 == 1));
+        }
+        if width == 0 || bits.is_empty() || bits.iter().skip(width).any(|&b| b) {
+            return Err(err);
+        }
+        bits.resize(width, false);
+        Ok(bits
+            .into_iter()
+            .map(|b| LogicExpr::Leaf(if b { R::logic_true() } else { R::logic_false() }))
+            .collect())
+    }
+    /// キーワード、定数、変数の順に名前を解決する
+    fn parse_name<R: AsLogic>(&self, name: String, position: usize) -> Result<Bus<R>, ParseError> {
+        let short_bool = self.config.short_bool;
+        match name.as_str() {
+            "true" => return Ok(vec![LogicExpr::Leaf(R::logic_true())]),
+            "false" => return Ok(vec![LogicExpr::Leaf(R::logic_false())]),
+            "T" if short_bool => return Ok(vec![LogicExpr::Leaf(R::logic_true())]),
+            "F" if short_bool => return Ok(vec![LogicExpr::Leaf(R::logic_false())]),
+            _ => {}
+        }
+        let (name, expr) = match self.config.constants.get_key_value(&name) {
+            Some(constant) => constant,
+            None => return Ok(vec![LogicExpr::Var(name)]),
+        };
+        if self.expanding.contains(&name.as_str()) {
+            return Err(ParseError::RecursiveConstant {
+                name: name.clone(),
+                position,
+            });
+        }
+        let mut expanding = self.expanding.clone();
+        expanding.push(name);
+        let parser = Parser {
+            src: expr,
+            chars: expr.chars(),
+            config: self.config,
+            expanding,
+            depth: self.depth,
+        };
+        match parser.parse() {
+            Ok(bus) => Ok(bus),
+            // 自分自身を含むときは使った位置だけを返す
+            Err(ParseError::RecursiveConstant { name, .. }) => {
+                Err(ParseError::RecursiveConstant { name, position })
+            }
+            Err(error) => Err(ParseError::InConstant {
+                name: name.clone(),
+                position,
+                error: Box::new(error),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::math::Binary;
+
+    fn parse_err(l: &str) -> ParseError {
+        match parse_logic_expr::<Binary>(l) {
+            Ok(_) => panic!("{} should not be parsed", l),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn parse_logic_expr_error() {
+        assert_eq!(
+            parse_err("1 & & 0"),
+            ParseError::InvalidElement {
+                found: '&',
+                position: 4
+            }
+        );
+        assert_eq!(
+            parse_err("1 ) 0"),
+            ParseError::TrailingInput {
+                found: ')',
+                position: 2
+            }
+        );
+        assert_eq!(
+            parse_err("(1 | 0"),
+            ParseError::UnclosedParen {
+                found: None,
+                position: 6
+            }
+        );
+        assert_eq!(
+            parse_err("(1 0)"),
+            ParseError::UnclosedParen {
+                found: Some('0'),
+                position: 3
+            }
+        );
+        assert_eq!(
+            parse_err("1 |  "),
+            ParseError::UnexpectedEnd { position: 5 }
+        );
+        assert_eq!(parse_err(""), ParseError::UnexpectedEnd { position: 0 });
+        // 位置はバイト数で数える
+        assert_eq!(parse_err("1 & ?").position(), 4);
+        assert_eq!(parse_err("1\u{3000}& ?").position(), 6);
+        assert_eq!(parse_err("1 & & 0").to_string(), "invalid element '&' at 4");
+
+        assert!(parse_logic_expr::<Binary>("  (a | 1) & !b  ").is_ok());
+    }
+
+    #[test]
+    fn parse_logic_expr_depth() {
+        use crate::{eval_logic_expr, PlainLogip};
+        let nested = |open: &str, depth: usize, close: &str| {
+            format!("{}1{}", open.repeat(depth), close.repeat(depth))
+        };
+        assert_eq!(
+            parse_err(&nested("(", 100_000, ")")),
+            ParseError::TooDeep {
+                position: MAX_DEPTH
+            }
+        );
+        assert_eq!(
+            parse_err(&nested("!", 100_000, "")),
+            ParseError::TooDeep {
+                position: MAX_DEPTH
+            }
+        );
+        assert_eq!(
+            parse_err(&nested("!(", 50_000, ")")),
+            ParseError::TooDeep {
+                position: MAX_DEPTH
+            }
+        );
+        assert_eq!(
+            parse_err(&nested("(", MAX_DEPTH + 1, ")")).to_string(),
+            format!("nesting deeper than {} at {}", MAX_DEPTH, MAX_DEPTH)
+        );
+        // 上限ちょうどは読める
+        let exp = parse_logic_expr::<Binary>(&nested("(", MAX_DEPTH, ")")).unwrap();
+        assert_eq!(eval_logic_expr(&PlainLogip, exp), Binary::One);
+        let exp = parse_logic_expr::<Binary>(&nested("! ", MAX_DEPTH, "")).unwrap();
+        assert_eq!(eval_logic_expr(&PlainLogip, exp), Binary::One);
+        // 並べるだけなら深くならない
+        assert!(parse_logic_expr::<Binary>(&["(1)"; 2000].join("&")).is_ok());
+
+        // 定数は使った位置の深さで展開する
+        let mut config = ParserConfig::default();
+        config.define("c", &nested("!", MAX_DEPTH, ""));
+        assert!(parse_logic_expr_with::<Binary>("c", &config).is_ok());
+        assert_eq!(
+            parse_logic_expr_with::<Binary>("!c", &config).map(|_| ()),
+            Err(ParseError::InConstant {
+                name: "c".to_string(),
+                position: 1,
+                error: Box::new(ParseError::TooDeep {
+                    position: MAX_DEPTH - 1
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_logic_expr_clike() {
+        use crate::{eval_logic_expr, PlainLogip};
+        let config = ParserConfig {
+            syntax: Syntax::CLike,
+            ..Default::default()
+        };
+        let eval = |l: &str| {
+            eval_logic_expr(
+                &PlainLogip,
+                parse_logic_expr_with::<Binary>(l, &config).unwrap(),
+            )
+        };
+        assert_eq!(eval("1 && 0 || 1"), Binary::One);
+        assert_eq!(eval("1 || 1 && 0"), Binary::One, "&&が先");
+        assert_eq!(eval("!(1 ^ 0) || 0"), Binary::Zero);
+        assert_eq!(eval("1 !& 1"), Binary::Zero);
+        assert_eq!(eval("1 !& !1"), Binary::One, "!&の右の!は否定");
+        assert!(parse_logic_expr_with::<Binary>("a && (b || !c)", &config).is_ok());
+
+        // 1文字の記号はCLikeでは使えない
+        assert_eq!(
+            parse_logic_expr_with::<Binary>("1 & 0", &config).err(),
+            Some(ParseError::TrailingInput {
+                found: '&',
+                position: 2
+            })
+        );
+        assert!(parse_logic_expr_with::<Binary>("1 $ 0", &config).is_err());
+        // Symbolでは&&は&の後に値がないのでエラー
+        assert!(parse_logic_expr::<Binary>("1 && 0").is_err());
+    }
+
+    #[test]
+    fn parse_logic_expr_constants() {
+        use crate::{eval_logic_expr, PlainLogip};
+        let eval = |l: &str, config: &ParserConfig| {
+            eval_logic_expr(
+                &PlainLogip,
+                parse_logic_expr_with::<Binary>(l, config).unwrap(),
+            )
+        };
+        let mut config = ParserConfig::default();
+        assert_eq!(eval("true & !false", &config), Binary::One);
+        assert!(matches!(
+            parse_logic_expr::<Binary>("T"),
+            Ok(LogicExpr::Var(name)) if name == "T"
+        ));
+        config.short_bool = true;
+        assert_eq!(eval("T ^ F", &config), Binary::One);
+
+        config.define("maj", "(1 & 0) | (0 & 1) | (1 & 1)");
+        config.define("nmaj", "!maj");
+        // 定数は括弧で囲んだものとして読む
+        assert_eq!(eval("nmaj | 1 & 0", &config), Binary::Zero);
+        assert_eq!(eval("maj & false", &config), Binary::Zero);
+
+        config.define("bad", "1 & ");
+        assert_eq!(
+            parse_logic_expr_with::<Binary>("1 | bad", &config).err(),
+            Some(ParseError::InConstant {
+                name: "bad".to_string(),
+                position: 4,
+                error: Box::new(ParseError::UnexpectedEnd { position: 4 })
+            })
+        );
+        config.define("loop_a", "1 & loop_b");
+        config.define("loop_b", "!loop_a");
+        assert_eq!(
+            parse_logic_expr_with::<Binary>("0 | loop_a", &config).err(),
+            Some(ParseError::RecursiveConstant {
+                name: "loop_a".to_string(),
+                position: 4
+            })
+        );
+    }
+
+    #[test]
+    fn parse_bus_expr_bytes() {
+        use crate::{eval_bus_expr_with, PlainLogip};
+        let byte = |b: u8| -> Vec<Binary> { (0..8).map(|i| Binary::from((b >> i) & 1)).collect() };
+        let eval = |l: &str, x: u8, y: u8| {
+            let mut vars = HashMap::new();
+            for (i, (x, y)) in byte(x).into_iter().zip(byte(y)).enumerate() {
+                vars.insert(bus_bit_name("x", i), x);
+                vars.insert(bus_bit_name("y", i), y);
+            }
+            eval_bus_expr_with(&PlainLogip, parse_bus_expr(l).unwrap(), &vars).unwrap()
+        };
+        assert_eq!(
+            eval("x[7:0] & y[7:0]", 0b1100_1010, 0b1010_0110),
+            byte(0b1000_0010)
+        );
+        assert_eq!(
+            eval("x[7:0] ^ 8'b10110001", 0b1111_0000, 0),
+            byte(0b0100_0001)
+        );
+        assert_eq!(eval("!(x[7:0] | 8'h0f)", 0b0101_0000, 0), byte(0b1010_0000));
+        // 右に書いた方が最下位bit
+        assert_eq!(eval("x[0:7]", 0b0000_0001, 0), byte(0b1000_0000));
+
+        // 幅を0で埋める
+        assert_eq!(eval("8'b1_01", 0, 0), byte(0b101));
+        assert_eq!(eval("x[3] & y[3]", 0b1000, 0b1000), vec![Binary::One]);
+        assert!(parse_logic_expr::<Binary>("x[3] | 1'b0").is_ok());
+
+        let err = |l: &str| parse_bus_expr::<Binary>(l).err();
+        assert_eq!(
+            err("x[7:0] & y[3:0]"),
+            Some(ParseError::WidthMismatch {
+                left: 8,
+                right: 4,
+                position: 7
+            })
+        );
+        assert_eq!(
+            err("4'b10201"),
+            Some(ParseError::InvalidLiteral { position: 0 })
+        );
+        assert_eq!(
+            err("1 | 2'b111"),
+            Some(ParseError::InvalidLiteral { position: 4 })
+        );
+        assert_eq!(
+            err("0'b0"),
+            Some(ParseError::InvalidLiteral { position: 0 })
+        );
+        assert_eq!(
+            err("8'o17"),
+            Some(ParseError::InvalidLiteral { position: 0 })
+        );
+        assert_eq!(
+            err("x[7:0"),
+            Some(ParseError::UnclosedBracket {
+                found: None,
+                position: 5
+            })
+        );
+        assert_eq!(
+            err("x[:0]"),
+            Some(ParseError::InvalidElement {
+                found: ':',
+                position: 2
+            })
+        );
+        assert_eq!(
+            parse_logic_expr::<Binary>("x[1:0]").err(),
+            Some(ParseError::NotABit {
+                width: 2,
+                position: 0
+            })
+        );
+        assert_eq!(
+            err("x[65536:0]"),
+            Some(ParseError::BusTooWide {
+                width: 65537,
+                position: 0
+            })
+        );
+        assert_eq!(
+            err("1 | x[0:18446744073709551615]"),
+            Some(ParseError::BusTooWide {
+                width: usize::MAX,
+                position: 4
+            })
+        );
+        assert_eq!(
+            err("99999999'b1"),
+            Some(ParseError::BusTooWide {
+                width: 99999999,
+                position: 0
+            })
+        );
+        assert_eq!(
+            parse_bus_expr::<Binary>("x[65535:0]").map(|b| b.len()),
+            Ok(MAX_BUS_WIDTH)
+        );
+        // 今までの1bitの値はそのまま
+        assert!(parse_bus_expr::<Binary>("10").is_err());
+    }
+}
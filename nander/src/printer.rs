@@ -0,0 +1,162 @@
+/*!
+論理式を構文解析器の書式で書き出す。
+
+括弧は読み直したときに同じ式になるのに必要な所にだけ付ける(! > & > ^ > | > $、左結合)。
+`LogicExpr`の`Display`は`Printer::default()`で書く。
+
+```ignore
+let exp = parse_logic_expr::<Binary>("((a & 1) | (b | c))")?;
+assert_eq!(exp.to_string(), "a & 1 | (b | c)");
+```
+ */
+use crate::parser::{BinaryOp, Syntax};
+use crate::LogicExpr;
+use std::fmt::Display;
+use utils::traits::AsLogic;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Printer {
+    pub syntax: Syntax,
+    /// 二項演算子の前後に空白を入れる
+    pub spaces: bool,
+}
+impl Default for Printer {
+    fn default() -> Self {
+        Printer {
+            syntax: Syntax::Symbol,
+            spaces: true,
+        }
+    }
+}
+impl Printer {
+    pub fn print<R: AsLogic + Display>(&self, exp: &LogicExpr<R>) -> String {
+        self.print_with(exp, |r| r.to_string())
+    }
+    /// 値(Leaf)はleafで書く。暗号文のように`Display`でない値に使う
+    pub fn print_with<R: AsLogic>(
+        &self,
+        exp: &LogicExpr<R>,
+        leaf: impl Fn(&R) -> String,
+    ) -> String {
+        let mut out = String::new();
+        self.write(&mut out, exp, &leaf);
+        out
+    }
+    fn write<R: AsLogic>(&self, out: &mut String, exp: &LogicExpr<R>, leaf: &dyn Fn(&R) -> String) {
+        match exp {
+            LogicExpr::Leaf(r) => out.push_str(&leaf(r)),
+            LogicExpr::Var(name) => out.push_str(name),
+            LogicExpr::Not(e) => {
+                out.push('!');
+                self.write_operand(out, e, precedence(e) < ATOM, leaf);
+            }
+            _ => {
+                let (op, lhs, rhs) = binary(exp).unwrap();
+                let prec = op.precedence();
+                // 左結合なので、右辺は同じ強さでも括弧が要る
+                self.write_operand(out, lhs, precedence(lhs) < prec, leaf);
+                if self.spaces {
+                    out.push(' ');
+                }
+                out.push_str(op.token(self.syntax));
+                if self.spaces {
+                    out.push(' ');
+                }
+                self.write_operand(out, rhs, precedence(rhs) <= prec, leaf);
+            }
+        }
+    }
+    fn write_operand<R: AsLogic>(
+        &self,
+        out: &mut String,
+        exp: &LogicExpr<R>,
+        paren: bool,
+        leaf: &dyn Fn(&R) -> String,
+    ) {
+        if paren {
+            out.push('(');
+        }
+        self.write(out, exp, leaf);
+        if paren {
+            out.push(')');
+        }
+    }
+}
+
+/// 否定・値・変数の結合の強さ。どの二項演算子よりも強い
+const ATOM: u32 = u32::MAX;
+
+fn precedence<R: AsLogic>(exp: &LogicExpr<R>) -> u32 {
+    binary(exp).map_or(ATOM, |(op, _, _)| op.precedence())
+}
+fn binary<R: AsLogic>(exp: &LogicExpr<R>) -> Option<(BinaryOp, &LogicExpr<R>, &LogicExpr<R>)> {
+    match exp {
+        LogicExpr::And(l, r) => Some((BinaryOp::And, l, r)),
+        LogicExpr::Xor(l, r) => Some((BinaryOp::Xor, l, r)),
+        LogicExpr::Or(l, r) => Some((BinaryOp::Or, l, r)),
+        LogicExpr::Nand(l, r) => Some((BinaryOp::Nand, l, r)),
+        LogicExpr::Not(_) | LogicExpr::Leaf(_) | LogicExpr::Var(_) => None,
+    }
+}
+
+impl<R: AsLogic + Display> Display for LogicExpr<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&Printer::default().print(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_logic_expr, parse_logic_expr_with, ParserConfig};
+    use utils::math::Binary;
+
+    fn pretty(l: &str) -> String {
+        parse_logic_expr::<Binary>(l).unwrap().to_string()
+    }
+
+    #[test]
+    fn printer_minimal_paren() {
+        assert_eq!(pretty("((a & 1) | (b | c))"), "a & 1 | (b | c)");
+        assert_eq!(pretty("(a | b) | c"), "a | b | c");
+        assert_eq!(pretty("a & (b ^ c)"), "a & (b ^ c)");
+        assert_eq!(pretty("!(a & b) $ !(!c)"), "!(a & b) $ !!c");
+        assert_eq!(pretty("(a $ b) ^ 0"), "(a $ b) ^ 0");
+        assert_eq!(pretty("true | x[3]"), "1 | x[3]");
+
+        // 書いたものを読み直すと同じ式になる
+        for l in [
+            "a $ (b $ c) $ d",
+            "!(a ^ !b) & (c | d & e)",
+            "(((a)))",
+            "a ^ b ^ (c ^ d)",
+        ] {
+            let once = pretty(l);
+            assert_eq!(pretty(&once), once);
+        }
+    }
+
+    #[test]
+    fn printer_config() {
+        let exp = parse_logic_expr::<Binary>("!(a & b) & (c | 1) $ d").unwrap();
+        let clike = Printer {
+            syntax: Syntax::CLike,
+            spaces: false,
+        };
+        let printed = clike.print(&exp);
+        assert_eq!(printed, "!(a&&b)&&(c||1)!&d");
+        let config = ParserConfig {
+            syntax: Syntax::CLike,
+            ..Default::default()
+        };
+        let reparsed = parse_logic_expr_with::<Binary>(&printed, &config).unwrap();
+        assert_eq!(reparsed.to_string(), "!(a & b) & (c | 1) $ d");
+
+        let leaf = |b: &Binary| match b {
+            Binary::One => "true".to_string(),
+            Binary::Zero => "false".to_string(),
+        };
+        let exp = parse_logic_expr::<Binary>("1 & !0").unwrap();
+        assert_eq!(Printer::default().print_with(&exp, leaf), "true & !false");
+    }
+}
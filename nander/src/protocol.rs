@@ -0,0 +1,543 @@
+/*!
+評価を頼むクライアントと、評価鍵を持って計算するサーバーの間のやりとり。
+
+クライアントは評価鍵を送り、回路(`stream`の命令列)と入力の暗号文を送って、後で結果の暗号文を受け取る。
+秘密鍵はクライアントから出ない。
+
+1つのメッセージは次の形で送る。数値はリトルエンディアン。
+```text
+tag : u8
+len : u64
+body: [u8; len]
+```
+
+| tag | メッセージ | body |
+|---|---|---|
+| 1 | `Request::UploadServerKey` | `wire`の`ServerKey`か`CompressedServerKey` |
+| 2 | `Request::Submit` | 命令列の長さ(u32), 命令列(1行に1命令), `CiphertextBundle` |
+| 3 | `Request::Fetch` | JobId(u64) |
+| 129 | `Response::KeyAccepted` | なし |
+| 130 | `Response::Submitted` | JobId(u64) |
+| 131 | `Response::Outputs` | `CiphertextBundle`(出力した順) |
+| 255 | `Response::Error` | メッセージ(UTF-8) |
+
+lenが`MAX_FRAME_LEN`を超えるメッセージは読まずにエラーにする。
+
+`Server`と`Client`はTCPで動く参考の実装。`Server`は接続を1つずつ順に処理し、回路は受け取ったときに評価する。
+評価鍵は接続をまたいで使うが、JobIdと結果は接続ごとに持ち、接続が閉じたら取りに来ていない結果は捨てる。
+
+```ignore
+// サーバー
+let listener = TcpListener::bind("0.0.0.0:7878")?;
+Server::<TLWE_N, TRLWE_N>::new().serve(&listener, |e| eprintln!("connection closed: {}", e))?;
+
+// クライアント
+let mut client = Client::<_, TLWE_N>::connect("server:7878")?;
+client.upload_server_key(&s_key.compressed_server_key())?;
+let job = client.submit(&circuit.to_stream(&["y"]), inputs)?;
+let outputs = client.fetch(job)?;
+```
+ */
+use crate::stream::{Instr, StreamEvaluator};
+use hom_nand::tfhe::TFHE;
+use hom_nand::wire::{read_server_key, CiphertextBundle, WireError, WireFormat};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// 投入した回路の番号。接続ごとに0から振る
+pub type JobId = u64;
+/// 1つのメッセージのbodyの上限。評価鍵(`TLWEHelper::N`で約150MB)が入る大きさにする
+pub const MAX_FRAME_LEN: u64 = 256 << 20;
+
+const TAG_UPLOAD_SERVER_KEY: u8 = 1;
+const TAG_SUBMIT: u8 = 2;
+const TAG_FETCH: u8 = 3;
+const TAG_KEY_ACCEPTED: u8 = 129;
+const TAG_SUBMITTED: u8 = 130;
+const TAG_OUTPUTS: u8 = 131;
+const TAG_ERROR: u8 = 255;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    Wire(WireError),
+    /// 読めないメッセージ
+    Malformed(String),
+    /// サーバーが返したエラー
+    Server(String),
+    /// 要求に合わない応答
+    UnexpectedResponse(u8),
+}
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        ProtocolError::Io(e)
+    }
+}
+impl From<WireError> for ProtocolError {
+    fn from(e: WireError) -> Self {
+        ProtocolError::Wire(e)
+    }
+}
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "io error: {}", e),
+            ProtocolError::Wire(e) => write!(f, "{}", e),
+            ProtocolError::Malformed(msg) => write!(f, "malformed message: {}", msg),
+            ProtocolError::Server(msg) => write!(f, "server error: {}", msg),
+            ProtocolError::UnexpectedResponse(tag) => {
+                write!(f, "unexpected response (tag {})", tag)
+            }
+        }
+    }
+}
+impl std::error::Error for ProtocolError {}
+
+pub enum Request<const N: usize> {
+    /// `wire`の形式の評価鍵。以後のSubmitはこの鍵で評価する
+    UploadServerKey(Vec<u8>),
+    /// 入力の変数の値はinputsから名前で引く
+    Submit {
+        circuit: Vec<Instr>,
+        inputs: CiphertextBundle<N>,
+    },
+    Fetch(JobId),
+}
+impl<const N: usize> Request<N> {
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Request::UploadServerKey(key) => write_frame(w, TAG_UPLOAD_SERVER_KEY, key),
+            Request::Submit { circuit, inputs } => {
+                let text: String = circuit.iter().map(|i| format!("{}\n", i)).collect();
+                let len: u32 = text.len().try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "circuit is too large")
+                })?;
+                let mut body = len.to_le_bytes().to_vec();
+                body.extend_from_slice(text.as_bytes());
+                inputs.write_to(&mut body)?;
+                write_frame(w, TAG_SUBMIT, &body)
+            }
+            Request::Fetch(job) => write_frame(w, TAG_FETCH, &job.to_le_bytes()),
+        }
+    }
+    /// 相手が閉じていればNone
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Option<Self>, ProtocolError> {
+        let (tag, body) = match read_frame(r)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let req = match tag {
+            TAG_UPLOAD_SERVER_KEY => Request::UploadServerKey(body),
+            TAG_SUBMIT => {
+                let (len, rest) = split_u32(&body)?;
+                if rest.len() < len {
+                    return Err(ProtocolError::Malformed("circuit is truncated".to_string()));
+                }
+                let (text, mut bundle) = rest.split_at(len);
+                let text = std::str::from_utf8(text)
+                    .map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+                let circuit = text
+                    .lines()
+                    .filter_map(|line| Instr::parse(line).transpose())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|text| {
+                        ProtocolError::Malformed(format!("invalid instruction '{}'", text))
+                    })?;
+                let inputs = CiphertextBundle::read_from(&mut bundle)?;
+                Request::Submit { circuit, inputs }
+            }
+            TAG_FETCH => Request::Fetch(read_job(&body)?),
+            tag => return Err(ProtocolError::Malformed(format!("unknown tag {}", tag))),
+        };
+        Ok(Some(req))
+    }
+}
+
+pub enum Response<const N: usize> {
+    KeyAccepted,
+    Submitted(JobId),
+    /// (出力の名前, 値)を出力した順に
+    Outputs(CiphertextBundle<N>),
+    Error(String),
+}
+impl<const N: usize> Response<N> {
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Response::KeyAccepted => write_frame(w, TAG_KEY_ACCEPTED, &[]),
+            Response::Submitted(job) => write_frame(w, TAG_SUBMITTED, &job.to_le_bytes()),
+            Response::Outputs(outputs) => write_frame(w, TAG_OUTPUTS, &outputs.to_bytes()),
+            Response::Error(msg) => write_frame(w, TAG_ERROR, msg.as_bytes()),
+        }
+    }
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, ProtocolError> {
+        let (tag, body) = read_frame(r)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))?;
+        Ok(match tag {
+            TAG_KEY_ACCEPTED => Response::KeyAccepted,
+            TAG_SUBMITTED => Response::Submitted(read_job(&body)?),
+            TAG_OUTPUTS => Response::Outputs(CiphertextBundle::from_bytes(&body)?),
+            TAG_ERROR => Response::Error(String::from_utf8_lossy(&body).into_owned()),
+            tag => return Err(ProtocolError::Malformed(format!("unknown tag {}", tag))),
+        })
+    }
+    fn tag(&self) -> u8 {
+        match self {
+            Response::KeyAccepted => TAG_KEY_ACCEPTED,
+            Response::Submitted(_) => TAG_SUBMITTED,
+            Response::Outputs(_) => TAG_OUTPUTS,
+            Response::Error(_) => TAG_ERROR,
+        }
+    }
+}
+
+fn write_frame<W: Write>(w: &mut W, tag: u8, body: &[u8]) -> io::Result<()> {
+    w.write_all(&[tag])?;
+    w.write_all(&(body.len() as u64).to_le_bytes())?;
+    w.write_all(body)?;
+    w.flush()
+}
+/// 最初のバイトの前で閉じていればNone
+fn read_frame<R: Read>(r: &mut R) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut tag = [0_u8; 1];
+    if r.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    let mut len = [0_u8; 8];
+    r.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message is too large ({} bytes)", len),
+        ));
+    }
+    // lenを信じて確保せず、届いた分だけ読む
+    let mut body = Vec::new();
+    r.take(len).read_to_end(&mut body)?;
+    if (body.len() as u64) < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "message is truncated",
+        ));
+    }
+    Ok(Some((tag[0], body)))
+}
+fn split_u32(body: &[u8]) -> Result<(usize, &[u8]), ProtocolError> {
+    if body.len() < 4 {
+        return Err(ProtocolError::Malformed("message is too short".to_string()));
+    }
+    let (len, rest) = body.split_at(4);
+    Ok((u32::from_le_bytes(len.try_into().unwrap()) as usize, rest))
+}
+fn read_job(body: &[u8]) -> Result<JobId, ProtocolError> {
+    body.try_into()
+        .map(JobId::from_le_bytes)
+        .map_err(|_| ProtocolError::Malformed("job id must be 8 bytes".to_string()))
+}
+
+/// 1つの接続で投入した回路の、取りに来ていない結果。
+/// 他の接続からはJobIdを当てても取れない
+#[derive(Default)]
+pub struct Session<const N: usize> {
+    results: HashMap<JobId, CiphertextBundle<N>>,
+    next_job: JobId,
+}
+impl<const N: usize> Session<N> {
+    pub fn new() -> Self {
+        Session::default()
+    }
+}
+
+/// 評価鍵を持つ。鍵は大きいので、スタックに置かずに持つ
+pub struct Server<const TLWE_N: usize, const TRLWE_N: usize> {
+    key: Option<Box<TFHE<TLWE_N, TRLWE_N>>>,
+}
+impl<const TLWE_N: usize, const TRLWE_N: usize> Server<TLWE_N, TRLWE_N> {
+    pub fn new() -> Self {
+        Server { key: None }
+    }
+    /// 評価鍵を持っているか
+    pub fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+    /// 結果はsessionに置く。1回だけ返し、返したら捨てる
+    pub fn handle(
+        &mut self,
+        session: &mut Session<TLWE_N>,
+        req: Request<TLWE_N>,
+    ) -> Response<TLWE_N> {
+        match req {
+            Request::UploadServerKey(bytes) => match read_server_key(&mut &bytes[..]) {
+                Ok(key) => {
+                    self.key = Some(Box::new(key));
+                    Response::KeyAccepted
+                }
+                Err(e) => Response::Error(e.to_string()),
+            },
+            Request::Submit { circuit, inputs } => {
+                let key = match &self.key {
+                    Some(key) => key,
+                    None => return Response::Error("server key is not uploaded".to_string()),
+                };
+                let vars: HashMap<_, _> = inputs
+                    .iter()
+                    .map(|(name, rep)| (name.to_string(), rep.clone()))
+                    .collect();
+                let mut eval = StreamEvaluator::new(&**key, &vars);
+                if let Err(e) = circuit.into_iter().try_for_each(|i| eval.step(i)) {
+                    return Response::Error(e.to_string());
+                }
+                let job = session.next_job;
+                session.next_job += 1;
+                session
+                    .results
+                    .insert(job, eval.finish().into_iter().collect());
+                Response::Submitted(job)
+            }
+            Request::Fetch(job) => match session.results.remove(&job) {
+                Some(outputs) => Response::Outputs(outputs),
+                None => Response::Error(format!("unknown job {}", job)),
+            },
+        }
+    }
+    /// 相手が閉じるまで要求に応える。取りに来なかった結果は捨てる
+    pub fn serve_connection<S: Read + Write>(
+        &mut self,
+        mut stream: S,
+    ) -> Result<(), ProtocolError> {
+        let mut session = Session::new();
+        loop {
+            let res = match Request::read_from(&mut stream) {
+                Ok(Some(req)) => self.handle(&mut session, req),
+                Ok(None) => return Ok(()),
+                // 読めなかったことを伝えてから切る
+                Err(ProtocolError::Io(e)) => return Err(ProtocolError::Io(e)),
+                Err(e) => {
+                    Response::<TLWE_N>::Error(e.to_string()).write_to(&mut stream)?;
+                    return Err(e);
+                }
+            };
+            res.write_to(&mut stream)?;
+        }
+    }
+    /// 接続を1つずつ順に処理する。1つの接続のエラーでは止まらず、on_errorに渡して次の接続を待つ
+    pub fn serve<F: FnMut(ProtocolError)>(
+        &mut self,
+        listener: &TcpListener,
+        mut on_error: F,
+    ) -> io::Result<()> {
+        for stream in listener.incoming() {
+            if let Err(e) = self.serve_connection(stream?) {
+                on_error(e);
+            }
+        }
+        Ok(())
+    }
+}
+impl<const TLWE_N: usize, const TRLWE_N: usize> Default for Server<TLWE_N, TRLWE_N> {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+pub struct Client<S: Read + Write, const N: usize> {
+    stream: S,
+}
+impl<const N: usize> Client<TcpStream, N> {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Client::new(TcpStream::connect(addr)?))
+    }
+}
+impl<S: Read + Write, const N: usize> Client<S, N> {
+    pub fn new(stream: S) -> Self {
+        Client { stream }
+    }
+    /// 要求を送って応答を待つ。`Response::Error`はそのまま返す
+    pub fn request(&mut self, req: &Request<N>) -> Result<Response<N>, ProtocolError> {
+        req.write_to(&mut self.stream)?;
+        Response::read_from(&mut self.stream)
+    }
+    /// `ServerKey`か`CompressedServerKey`を送る
+    pub fn upload_server_key<K: WireFormat>(&mut self, key: &K) -> Result<(), ProtocolError> {
+        match self.expect(&Request::UploadServerKey(key.to_bytes()))? {
+            Response::KeyAccepted => Ok(()),
+            res => Err(ProtocolError::UnexpectedResponse(res.tag())),
+        }
+    }
+    pub fn submit(
+        &mut self,
+        circuit: &[Instr],
+        inputs: CiphertextBundle<N>,
+    ) -> Result<JobId, ProtocolError> {
+        let req = Request::Submit {
+            circuit: circuit.to_vec(),
+            inputs,
+        };
+        match self.expect(&req)? {
+            Response::Submitted(job) => Ok(job),
+            res => Err(ProtocolError::UnexpectedResponse(res.tag())),
+        }
+    }
+    pub fn fetch(&mut self, job: JobId) -> Result<CiphertextBundle<N>, ProtocolError> {
+        match self.expect(&Request::Fetch(job))? {
+            Response::Outputs(outputs) => Ok(outputs),
+            res => Err(ProtocolError::UnexpectedResponse(res.tag())),
+        }
+    }
+    /// `Response::Error`をエラーにする
+    fn expect(&mut self, req: &Request<N>) -> Result<Response<N>, ProtocolError> {
+        match self.request(req)? {
+            Response::Error(msg) => Err(ProtocolError::Server(msg)),
+            res => Ok(res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_bus_expr, Circuit};
+    use hom_nand::tfhe::SecretKey;
+    use hom_nand::tlwe::TLWERep;
+    use utils::math::Binary;
+
+    const TLWE_N: usize = 16;
+    const TRLWE_N: usize = 1024;
+
+    #[test]
+    fn protocol_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut server = Server::<TLWE_N, TRLWE_N>::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                server.serve_connection(stream).unwrap();
+                assert!(server.has_key());
+            }
+        });
+
+        let s_key = SecretKey::<TLWE_N, TRLWE_N>::new();
+        let mut client = Client::<_, TLWE_N>::connect(addr).unwrap();
+        let exprs = parse_bus_expr::<TLWERep<TLWE_N>>("(a[1:0] & b[1:0]) ^ 2'b10").unwrap();
+        let circuit = Circuit::from_exprs(exprs).to_stream(&["y0", "y1"]);
+        let inputs = |a: u32, b: u32| -> CiphertextBundle<TLWE_N> {
+            ["a[0]", "a[1]", "b[0]", "b[1]"]
+                .iter()
+                .zip([a & 1, a >> 1, b & 1, b >> 1])
+                .map(|(n, x)| (n.to_string(), s_key.encrypt(Binary::from(x))))
+                .collect()
+        };
+
+        // 鍵がなければ評価できない
+        match client.submit(&circuit, inputs(0, 0)) {
+            Err(ProtocolError::Server(msg)) => assert_eq!(msg, "server key is not uploaded"),
+            _ => panic!("評価できてしまった"),
+        }
+        client
+            .upload_server_key(&s_key.compressed_server_key())
+            .unwrap();
+
+        let jobs: Vec<JobId> = [(0b11, 0b01), (0b10, 0b11)]
+            .iter()
+            .map(|&(a, b)| client.submit(&circuit, inputs(a, b)).unwrap())
+            .collect();
+        for (job, expect) in jobs.into_iter().zip([0b11, 0b00]) {
+            let outputs = client.fetch(job).unwrap();
+            let bits: Vec<(&str, Binary)> = outputs
+                .iter()
+                .map(|(n, r)| (n, s_key.decrypt(r.clone())))
+                .collect();
+            assert_eq!(
+                bits,
+                [
+                    ("y0", Binary::from(expect & 1)),
+                    ("y1", Binary::from(expect >> 1))
+                ]
+            );
+            // 2回は取れない
+            assert!(matches!(client.fetch(job), Err(ProtocolError::Server(_))));
+        }
+        // 足りない入力
+        let partial: CiphertextBundle<TLWE_N> = CiphertextBundle::new();
+        match client.submit(&circuit, partial) {
+            Err(ProtocolError::Server(msg)) => assert!(msg.contains("unbound variable"), "{}", msg),
+            _ => panic!("評価できてしまった"),
+        }
+        let left = client.submit(&circuit, inputs(0, 0)).unwrap();
+        assert_eq!(left, 2);
+        drop(client);
+
+        // 鍵は残るが、前の接続の結果は別の接続からは取れない
+        let mut client = Client::<_, TLWE_N>::connect(addr).unwrap();
+        match client.fetch(left) {
+            Err(ProtocolError::Server(msg)) => assert_eq!(msg, "unknown job 2"),
+            _ => panic!("前の接続の結果を取れてしまった"),
+        }
+        assert_eq!(client.submit(&circuit, inputs(0, 0)).unwrap(), 0);
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn protocol_malformed() {
+        let mut server = Server::<TLWE_N, TRLWE_N>::new();
+        // 評価鍵でないものを鍵として送る
+        let req = Request::UploadServerKey(CiphertextBundle::<TLWE_N>::new().to_bytes());
+        match server.handle(&mut Session::new(), req) {
+            Response::Error(msg) => assert!(msg.starts_with("kind mismatch"), "{}", msg),
+            _ => panic!("鍵として読めてしまった"),
+        }
+
+        let mut input = vec![42];
+        input.extend_from_slice(&0_u64.to_le_bytes());
+        let mut output = Vec::new();
+        let mut stream = io::Cursor::new(input);
+        let res = server.serve_connection(ReadWrite(&mut stream, &mut output));
+        assert!(matches!(res, Err(ProtocolError::Malformed(_))));
+        match Response::<TLWE_N>::read_from(&mut &output[..]).unwrap() {
+            Response::Error(msg) => assert_eq!(msg, "malformed message: unknown tag 42"),
+            _ => panic!(),
+        }
+
+        // 途中で切れたメッセージ
+        let mut bytes = Vec::new();
+        Request::<TLWE_N>::Fetch(3).write_to(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(matches!(
+            Request::<TLWE_N>::read_from(&mut &bytes[..]),
+            Err(ProtocolError::Io(_))
+        ));
+        assert!(matches!(
+            Request::<TLWE_N>::read_from(&mut &[][..]),
+            Ok(None)
+        ));
+
+        // 大きすぎるメッセージはbodyを読む前に断る
+        let mut bytes = vec![TAG_UPLOAD_SERVER_KEY];
+        bytes.extend_from_slice(&(MAX_FRAME_LEN + 1).to_le_bytes());
+        match Request::<TLWE_N>::read_from(&mut &bytes[..]) {
+            Err(ProtocolError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            _ => panic!("大きすぎるメッセージを読んでしまった"),
+        }
+    }
+
+    /// 読むものと書くものを別にした接続
+    struct ReadWrite<'a, R, W>(&'a mut R, &'a mut W);
+    impl<R: Read, W> Read for ReadWrite<'_, R, W> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+    impl<R, W: Write> Write for ReadWrite<'_, R, W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.1.flush()
+        }
+    }
+}
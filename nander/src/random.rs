@@ -0,0 +1,246 @@
+/*!
+ランダムな論理式・回路を作る。
+
+構文解析器や最適化のfuzzと、評価器のベンチマークに使う。
+ゲートの数・深さ・演算子の割合・変数の数は`ExprGenConfig`で決め、乱数は呼ぶ側が渡す(種を固定すれば同じ式になる)。
+
+```ignore
+let config = ExprGenConfig { gates: 200, max_depth: 12, vars: 8, ..Default::default() };
+let mut gen = ExprGenerator::new(config, ChaCha20Rng::seed_from_u64(1));
+let exp: LogicExpr<Binary> = gen.gen_expr();
+assert!(equivalent(&exp, &lower_to_nand(exp.clone(), NandForm::NandNot).0));
+```
+ */
+use crate::circuit::Circuit;
+use crate::LogicExpr;
+use rand::Rng;
+use utils::traits::AsLogic;
+
+/// 演算子を選ぶ重み。0の演算子は使わない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpWeights {
+    pub nand: u32,
+    pub not: u32,
+    pub and: u32,
+    pub or: u32,
+    pub xor: u32,
+}
+impl Default for OpWeights {
+    fn default() -> Self {
+        OpWeights {
+            nand: 1,
+            not: 1,
+            and: 1,
+            or: 1,
+            xor: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprGenConfig {
+    /// 1つの式のゲートの数。深さに収まらないときはそれより少なくなる
+    pub gates: usize,
+    /// 根から葉までのゲートの数の上限
+    pub max_depth: usize,
+    /// 変数の数。名前は`x0`,`x1`,...
+    pub vars: usize,
+    pub weights: OpWeights,
+    /// 葉を定数(0か1)にする確率。変数がなければ常に定数
+    pub const_prob: f64,
+}
+impl Default for ExprGenConfig {
+    fn default() -> Self {
+        ExprGenConfig {
+            gates: 32,
+            max_depth: 8,
+            vars: 4,
+            weights: OpWeights::default(),
+            const_prob: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Nand,
+    Not,
+    And,
+    Or,
+    Xor,
+}
+
+pub struct ExprGenerator<G: Rng> {
+    config: ExprGenConfig,
+    rng: G,
+}
+impl<G: Rng> ExprGenerator<G> {
+    /// # Panic
+    /// - 演算子の重みが全て0で、ゲートを1つ以上作る
+    pub fn new(config: ExprGenConfig, rng: G) -> Self {
+        let w = config.weights;
+        assert!(
+            config.gates == 0 || w.nand + w.not + w.and + w.or + w.xor > 0,
+            "at least one operator must have a positive weight"
+        );
+        ExprGenerator { config, rng }
+    }
+    pub fn config(&self) -> &ExprGenConfig {
+        &self.config
+    }
+    /// 使う変数の名前
+    pub fn var_names(&self) -> Vec<String> {
+        (0..self.config.vars).map(|i| format!("x{}", i)).collect()
+    }
+
+    pub fn gen_expr<R: AsLogic>(&mut self) -> LogicExpr<R> {
+        self.gen(self.config.gates, self.config.max_depth)
+    }
+    pub fn gen_exprs<R: AsLogic>(&mut self, n: usize) -> Vec<LogicExpr<R>> {
+        (0..n).map(|_| self.gen_expr()).collect()
+    }
+    /// 出力がoutputs個の回路。出力の式の間で同じ部分式はまとめる
+    pub fn gen_circuit<R: AsLogic>(&mut self, outputs: usize) -> Circuit<R> {
+        Circuit::from_exprs(self.gen_exprs(outputs))
+    }
+
+    /// ゲートがgates個で、深さdepth以下の式。再帰の深さはdepthまで
+    fn gen<R: AsLogic>(&mut self, gates: usize, depth: usize) -> LogicExpr<R> {
+        if gates == 0 || depth == 0 {
+            return self.gen_leaf();
+        }
+        // 深さdの式に入るゲートの数
+        let cap = |d: usize| 2usize.checked_pow(d as u32).map_or(usize::MAX, |x| x - 1);
+        let rest = (gates - 1).min(cap(depth - 1).saturating_mul(2));
+        let op = self.choose_op(rest <= cap(depth - 1));
+        let child = |gen: &mut Self, gates| Box::new(gen.gen(gates, depth - 1));
+        match op {
+            Op::Not => LogicExpr::Not(child(self, rest)),
+            op => {
+                let lo = rest.saturating_sub(cap(depth - 1));
+                let hi = rest.min(cap(depth - 1));
+                let l = self.rng.gen_range(lo..=hi);
+                let (lhs, rhs) = (child(self, l), child(self, rest - l));
+                match op {
+                    Op::Nand => LogicExpr::Nand(lhs, rhs),
+                    Op::And => LogicExpr::And(lhs, rhs),
+                    Op::Or => LogicExpr::Or(lhs, rhs),
+                    Op::Xor => LogicExpr::Xor(lhs, rhs),
+                    Op::Not => unreachable!(),
+                }
+            }
+        }
+    }
+    /// 重みに従って選ぶ。notが入りきらないときは、二項演算子があればそちらから選ぶ
+    fn choose_op(&mut self, not_fits: bool) -> Op {
+        let w = self.config.weights;
+        let binary = w.nand + w.and + w.or + w.xor;
+        let not = if not_fits || binary == 0 { w.not } else { 0 };
+        let mut x = self.rng.gen_range(0..binary + not);
+        for (op, weight) in [
+            (Op::Nand, w.nand),
+            (Op::Not, not),
+            (Op::And, w.and),
+            (Op::Or, w.or),
+            (Op::Xor, w.xor),
+        ] {
+            if x < weight {
+                return op;
+            }
+            x -= weight;
+        }
+        unreachable!()
+    }
+    fn gen_leaf<R: AsLogic>(&mut self) -> LogicExpr<R> {
+        if self.config.vars == 0 || self.rng.gen_bool(self.config.const_prob) {
+            if self.rng.gen() {
+                LogicExpr::Leaf(R::logic_true())
+            } else {
+                LogicExpr::Leaf(R::logic_false())
+            }
+        } else {
+            let i = self.rng.gen_range(0..self.config.vars);
+            LogicExpr::Var(format!("x{}", i))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::{count_gates, fold_constants, lower_to_nand, NandForm};
+    use crate::{equivalent, parse_logic_expr, CostModel};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use utils::math::Binary;
+
+    fn depth(exp: &LogicExpr<Binary>) -> usize {
+        exp.estimate_cost_with(&CostModel::default()).depth
+    }
+
+    #[test]
+    fn gen_expr_shape() {
+        let config = ExprGenConfig {
+            gates: 50,
+            max_depth: 7,
+            weights: OpWeights {
+                not: 0,
+                ..OpWeights::default()
+            },
+            ..ExprGenConfig::default()
+        };
+        let mut gen = ExprGenerator::new(config, ChaCha20Rng::seed_from_u64(0));
+        for _ in 0..20 {
+            let exp: LogicExpr<Binary> = gen.gen_expr();
+            assert_eq!(count_gates(&exp).total(), 50);
+            assert!(depth(&exp) <= 7);
+        }
+        // 深さに入りきらない分は作らない
+        gen.config.max_depth = 3;
+        let exp: LogicExpr<Binary> = gen.gen_expr();
+        assert_eq!(count_gates(&exp).total(), 7);
+
+        let config = ExprGenConfig {
+            weights: OpWeights {
+                nand: 0,
+                not: 1,
+                and: 0,
+                or: 0,
+                xor: 2,
+            },
+            ..ExprGenConfig::default()
+        };
+        let mut gen = ExprGenerator::new(config.clone(), ChaCha20Rng::seed_from_u64(1));
+        let exp: LogicExpr<Binary> = gen.gen_expr();
+        let count = count_gates(&exp);
+        assert_eq!(count.nand + count.and + count.or, 0);
+        assert!(count.total() <= config.gates);
+        // 同じ種なら同じ式
+        let mut again = ExprGenerator::new(config, ChaCha20Rng::seed_from_u64(1));
+        assert_eq!(again.gen_expr::<Binary>().to_string(), exp.to_string());
+    }
+
+    #[test]
+    fn gen_expr_fuzz() {
+        let config = ExprGenConfig {
+            gates: 40,
+            max_depth: 10,
+            vars: 5,
+            ..ExprGenConfig::default()
+        };
+        let mut gen = ExprGenerator::new(config, ChaCha20Rng::seed_from_u64(2));
+        for _ in 0..50 {
+            let exp: LogicExpr<Binary> = gen.gen_expr();
+            let printed = parse_logic_expr::<Binary>(&exp.to_string()).unwrap();
+            assert_eq!(printed.to_string(), exp.to_string());
+            assert!(equivalent(&exp, &fold_constants(exp.clone())));
+            assert!(equivalent(
+                &exp,
+                &lower_to_nand(exp.clone(), NandForm::NandNot).0
+            ));
+        }
+        let circuit = gen.gen_circuit::<Binary>(4);
+        assert_eq!(circuit.outputs().len(), 4);
+        assert!(circuit.inputs().len() <= 5);
+    }
+}
@@ -0,0 +1,373 @@
+/*!
+暗号文の変数を束縛しながら、式を1行ずつ評価する対話環境(REPL)。
+
+秘密鍵(で暗号化・復号する関数)を渡しておくと、結果を復号して表示する。
+`:trace`にすると、評価したゲートを1つずつ復号して表示する(`DebugLogip`)ので、回路のどこで値が壊れたかを探せる。
+
+```text
+nander> :enc a 1
+a = 1
+nander> let b = a ^ 1
+b = 0
+nander> :trace
+trace on
+nander> !(a & b)
+#0 and(1, 0) = 0
+#1 not(0) = 1
+> 1
+```
+
+| 入力 | |
+|---|---|
+| `<式>` | 評価して表示する |
+| `let <名前> = <式>` | 評価した結果を名前に束縛する |
+| `:enc <名前> <0\|1>` | 秘密鍵で暗号化した値を束縛する |
+| `:vars` | 束縛した変数の一覧 |
+| `:unset <名前>` | 束縛を外す |
+| `:trace` | ゲートごとの表示を切り替える |
+| `:help` | 使い方 |
+| `:quit` | 終わる(`run`のみ) |
+ */
+use crate::debug::{DebugLogip, GateTrace};
+use crate::parser::{parse_logic_expr_with, ParseError, ParserConfig};
+use crate::{eval_logic_expr_with, EvalError, Logip};
+use hom_nand::{
+    digest::Cryptor,
+    tfhe::{BootstrappingKeyRows, TFHE},
+    tlwe::{TLWERep, TLWE},
+};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use utils::math::Binary;
+
+pub const PROMPT: &str = "nander> ";
+
+const HELP: &str = "\
+<expr>               evaluate
+let <name> = <expr>  bind the result to name
+:enc <name> <0|1>    bind a freshly encrypted bit
+:vars                list bound variables
+:unset <name>        remove a binding
+:trace               toggle gate-by-gate trace
+:help                show this message
+:quit                exit";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplError {
+    /// 式が読めない。positionは行の中のバイト位置
+    Parse {
+        error: ParseError,
+        position: usize,
+    },
+    Eval(EvalError),
+    /// 秘密鍵がないとできない
+    NoKey,
+    UnknownCommand(String),
+    /// 引数が違う。正しい使い方
+    Usage(&'static str),
+}
+impl Display for ReplError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplError::Parse { error, .. } => write!(f, "{}", error),
+            ReplError::Eval(err) => write!(f, "{}", err),
+            ReplError::NoKey => write!(f, "secret key is not loaded"),
+            ReplError::UnknownCommand(cmd) => write!(f, "unknown command: {}", cmd),
+            ReplError::Usage(usage) => write!(f, "usage: {}", usage),
+        }
+    }
+}
+impl std::error::Error for ReplError {}
+impl From<EvalError> for ReplError {
+    fn from(err: EvalError) -> Self {
+        ReplError::Eval(err)
+    }
+}
+
+/// 暗号化・復号する関数
+struct Key<R> {
+    encrypt: Box<dyn Fn(Binary) -> R>,
+    decrypt: Arc<dyn Fn(&R) -> Binary + Send + Sync>,
+}
+
+pub struct Repl<'a, P: Logip> {
+    pros: &'a P,
+    config: ParserConfig,
+    vars: BTreeMap<String, P::R>,
+    key: Option<Key<P::R>>,
+    trace: bool,
+}
+impl<'a, P: Logip> Repl<'a, P>
+where
+    P::R: 'static,
+{
+    /// 秘密鍵なし。結果は復号しない
+    pub fn new(pros: &'a P) -> Self {
+        Repl {
+            pros,
+            config: ParserConfig::default(),
+            vars: BTreeMap::new(),
+            key: None,
+            trace: false,
+        }
+    }
+    /// 結果を復号して表示し、`:enc`で暗号化できるようにする
+    pub fn key(
+        mut self,
+        encrypt: impl Fn(Binary) -> P::R + 'static,
+        decrypt: impl Fn(&P::R) -> Binary + Send + Sync + 'static,
+    ) -> Self {
+        self.key = Some(Key {
+            encrypt: Box::new(encrypt),
+            decrypt: Arc::new(decrypt),
+        });
+        self
+    }
+    pub fn config(mut self, config: ParserConfig) -> Self {
+        self.config = config;
+        self
+    }
+    pub fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+    pub fn var(&self, name: &str) -> Option<&P::R> {
+        self.vars.get(name)
+    }
+    pub fn bind(&mut self, name: &str, value: P::R) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// 1行を実行して、表示する文字列を返す
+    pub fn exec(&mut self, line: &str) -> Result<String, ReplError> {
+        let trimmed = line.trim_start();
+        let offset = line.len() - trimmed.len();
+        let line = trimmed.trim_end();
+        if let Some(cmd) = line.strip_prefix(':') {
+            return self.command(cmd);
+        }
+        if let Some(rest) = line.strip_prefix("let ") {
+            let (name, src) = rest
+                .split_once('=')
+                .ok_or(ReplError::Usage("let <name> = <expr>"))?;
+            let name = valid_name(name.trim()).ok_or(ReplError::Usage("let <name> = <expr>"))?;
+            let offset = offset + line.len() - src.len();
+            let (value, trace) = self.eval(src, offset)?;
+            let shown = self.show(&value);
+            self.bind(name, value);
+            return Ok(with_trace(trace, format!("{} = {}", name, shown)));
+        }
+        if line.is_empty() {
+            return Ok(String::new());
+        }
+        let (value, trace) = self.eval(line, offset)?;
+        Ok(with_trace(trace, format!("> {}", self.show(&value))))
+    }
+
+    /// inputを読み終わるか`:quit`まで、1行ずつ実行して結果をoutputに書く
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 || line.trim() == ":quit" {
+                return Ok(());
+            }
+            match self.exec(&line) {
+                Ok(out) if out.is_empty() => {}
+                Ok(out) => writeln!(output, "{}", out)?,
+                Err(ReplError::Parse { error, position }) => {
+                    // エラーの位置(バイト数)を文字数に直して^を出す
+                    let column = line[..position].chars().count();
+                    writeln!(output, "{}^", " ".repeat(PROMPT.len() + column))?;
+                    writeln!(output, "[Parse Error] {}", error)?;
+                }
+                Err(err) => writeln!(output, "[Error] {}", err)?,
+            }
+        }
+    }
+
+    fn command(&mut self, cmd: &str) -> Result<String, ReplError> {
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+        match args[..] {
+            ["help"] => Ok(HELP.to_string()),
+            ["vars"] => {
+                let lines: Vec<String> = self
+                    .vars
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, self.show(value)))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            ["unset", name] => match self.vars.remove(name) {
+                Some(_) => Ok(String::new()),
+                None => Err(EvalError::UnboundVariable(name.to_string()).into()),
+            },
+            ["unset", ..] => Err(ReplError::Usage(":unset <name>")),
+            ["enc", name, bit] => {
+                let key = self.key.as_ref().ok_or(ReplError::NoKey)?;
+                let name = valid_name(name).ok_or(ReplError::Usage(":enc <name> <0|1>"))?;
+                let bit = match bit {
+                    "0" => Binary::Zero,
+                    "1" => Binary::One,
+                    _ => return Err(ReplError::Usage(":enc <name> <0|1>")),
+                };
+                self.bind(name, (key.encrypt)(bit));
+                Ok(format!("{} = {}", name, bit))
+            }
+            ["enc", ..] => Err(ReplError::Usage(":enc <name> <0|1>")),
+            ["trace"] => {
+                if self.key.is_none() {
+                    return Err(ReplError::NoKey);
+                }
+                self.trace = !self.trace;
+                Ok(format!("trace {}", if self.trace { "on" } else { "off" }))
+            }
+            _ => Err(ReplError::UnknownCommand(cmd.trim().to_string())),
+        }
+    }
+
+    /// offsetは行の中でのsrcの位置。`:trace`のときは評価したゲートの記録も返す
+    fn eval(&self, src: &str, offset: usize) -> Result<(P::R, Vec<GateTrace>), ReplError> {
+        let exp = parse_logic_expr_with(src, &self.config).map_err(|error| {
+            let position = offset + error.position();
+            ReplError::Parse { error, position }
+        })?;
+        // eval_logic_expr_withはHashMapで変数を受け取る
+        let vars = self
+            .vars
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        match &self.key {
+            Some(key) if self.trace => {
+                let decrypt = key.decrypt.clone();
+                let debug = DebugLogip::new(self.pros, move |r| decrypt(r));
+                let res = eval_logic_expr_with(&debug, exp, &vars)?;
+                Ok((res, debug.trace()))
+            }
+            _ => Ok((eval_logic_expr_with(self.pros, exp, &vars)?, Vec::new())),
+        }
+    }
+    fn show(&self, value: &P::R) -> String {
+        match &self.key {
+            Some(key) => (key.decrypt)(value).to_string(),
+            None => "(encrypted)".to_string(),
+        }
+    }
+}
+impl<'a, const N: usize, const M: usize, BK: BootstrappingKeyRows<M>> Repl<'a, TFHE<N, M, BK>> {
+    /// s_keyはTLWE(lv0)の秘密鍵
+    pub fn with_key(tfhe: &'a TFHE<N, M, BK>, s_key: [Binary; N]) -> Self {
+        Self::new(tfhe).key(
+            move |b| Cryptor::encrypto(TLWE, &s_key, b),
+            move |r: &TLWERep<N>| Cryptor::decrypto(TLWE, &s_key, r.clone()),
+        )
+    }
+}
+
+/// 変数名 [A-Za-z_][A-Za-z0-9_]*
+fn valid_name(name: &str) -> Option<&str> {
+    let mut chars = name.chars();
+    let head = chars.next()?;
+    let ok = (head.is_ascii_alphabetic() || head == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if ok {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn with_trace(trace: Vec<GateTrace>, last: String) -> String {
+    let mut lines: Vec<String> = trace.iter().map(|t| t.to_string()).collect();
+    lines.push(last);
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlainLogip;
+    use utils::math::{BinaryDistribution, Random};
+
+    #[test]
+    fn repl_exec() {
+        let mut repl = Repl::new(&PlainLogip).key(|b| b, |b: &Binary| *b);
+        assert_eq!(repl.exec(":enc a 1"), Ok("a = 1".to_string()));
+        assert_eq!(repl.exec("let b = a ^ 1"), Ok("b = 0".to_string()));
+        assert_eq!(repl.exec("  !(a & b)\n"), Ok("> 1".to_string()));
+        assert_eq!(repl.exec(""), Ok(String::new()));
+        assert_eq!(repl.exec(":vars"), Ok("a = 1\nb = 0".to_string()));
+
+        assert_eq!(repl.exec(":trace"), Ok("trace on".to_string()));
+        assert_eq!(
+            repl.exec("!(a & b)"),
+            Ok("#0 and(1, 0) = 0\n#1 not(0) = 1\n> 1".to_string())
+        );
+        assert_eq!(repl.exec(":trace"), Ok("trace off".to_string()));
+
+        assert_eq!(repl.exec(":unset a"), Ok(String::new()));
+        assert_eq!(
+            repl.exec("a | b"),
+            Err(ReplError::Eval(EvalError::UnboundVariable("a".to_string())))
+        );
+        // 位置は行の中で数える
+        match repl.exec("let c = b & ") {
+            Err(ReplError::Parse { position, .. }) => assert_eq!(position, 11),
+            res => panic!("{:?}", res),
+        }
+        assert_eq!(
+            repl.exec("let 1c = b"),
+            Err(ReplError::Usage("let <name> = <expr>"))
+        );
+        assert_eq!(
+            repl.exec(":enc c 2"),
+            Err(ReplError::Usage(":enc <name> <0|1>"))
+        );
+        assert_eq!(
+            repl.exec(":foo"),
+            Err(ReplError::UnknownCommand("foo".to_string()))
+        );
+
+        let mut repl = Repl::new(&PlainLogip);
+        assert_eq!(repl.exec(":enc a 1"), Err(ReplError::NoKey));
+        assert_eq!(repl.exec(":trace"), Err(ReplError::NoKey));
+        assert_eq!(
+            repl.exec("let a = 1 ^ 0"),
+            Ok("a = (encrypted)".to_string())
+        );
+        assert_eq!(repl.var("a"), Some(&Binary::One));
+    }
+
+    #[test]
+    fn repl_run_tfhe() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+        let mut repl = Repl::with_key(&tfhe, s_key_tlwelv0);
+
+        let input = ":enc x 1\n:enc y 0\nlet z = x $ y\nz ^ (x &\n:quit\nx\n";
+        let mut output = Vec::new();
+        repl.run(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "nander> x = 1");
+        assert_eq!(lines[1], "nander> y = 0");
+        assert_eq!(lines[2], "nander> z = 1");
+        assert_eq!(
+            lines[3],
+            format!("nander> {}^", " ".repeat(PROMPT.len() + 8))
+        );
+        assert!(lines[4].starts_with("[Parse Error]"));
+        // :quitの後は読まない
+        assert_eq!(lines[5], "nander> ");
+        assert_eq!(lines.len(), 6);
+        assert_eq!(tfhe.stats().total_gates(), 1);
+    }
+}
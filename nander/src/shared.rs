@@ -0,0 +1,261 @@
+/*!
+部分式を`Rc`で共有する論理式。
+
+`LogicExpr`は木なので、`lower_to_nand`のxorのように同じ部分式を複製すると、その回数だけ評価する(段を重ねると指数的に増える)。
+`SharedExpr`は同じノードを複数の親から指せて、`eval_with`は1回の評価の間、ノードごとに結果を覚えておく。
+`Circuit`のように前もって回路に変換せず、式のまま組み立てて評価したいときに使う。
+
+`from_expr`は同じ形の部分式を1つのノードにまとめる(hash consing)。
+
+```ignore
+let (a, b) = (SharedExpr::var("a"), SharedExpr::var("b"));
+let t = SharedExpr::nand(&a, &b);
+let xor = SharedExpr::nand(&SharedExpr::nand(&a, &t), &SharedExpr::nand(&b, &t)); // tは1回だけ評価する
+let res = xor.eval_with(&tfhe, &vars)?;
+```
+ */
+use crate::circuit::{Circuit, Node};
+use crate::{EvalError, LogicExpr, Logip};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use utils::traits::AsLogic;
+
+pub enum SharedNode<R: AsLogic> {
+    Leaf(R),
+    Var(String),
+    Not(SharedExpr<R>),
+    Nand(SharedExpr<R>, SharedExpr<R>),
+    And(SharedExpr<R>, SharedExpr<R>),
+    Or(SharedExpr<R>, SharedExpr<R>),
+    Xor(SharedExpr<R>, SharedExpr<R>),
+}
+
+/// cloneしてもノードは共有したまま
+pub struct SharedExpr<R: AsLogic>(Rc<SharedNode<R>>);
+impl<R: AsLogic> Clone for SharedExpr<R> {
+    fn clone(&self) -> Self {
+        SharedExpr(self.0.clone())
+    }
+}
+impl<R: AsLogic> SharedExpr<R> {
+    pub fn new(node: SharedNode<R>) -> Self {
+        SharedExpr(Rc::new(node))
+    }
+    pub fn leaf(r: R) -> Self {
+        Self::new(SharedNode::Leaf(r))
+    }
+    pub fn var(name: &str) -> Self {
+        Self::new(SharedNode::Var(name.to_string()))
+    }
+    pub fn not(e: &Self) -> Self {
+        Self::new(SharedNode::Not(e.clone()))
+    }
+    pub fn nand(lhs: &Self, rhs: &Self) -> Self {
+        Self::new(SharedNode::Nand(lhs.clone(), rhs.clone()))
+    }
+    pub fn and(lhs: &Self, rhs: &Self) -> Self {
+        Self::new(SharedNode::And(lhs.clone(), rhs.clone()))
+    }
+    pub fn or(lhs: &Self, rhs: &Self) -> Self {
+        Self::new(SharedNode::Or(lhs.clone(), rhs.clone()))
+    }
+    pub fn xor(lhs: &Self, rhs: &Self) -> Self {
+        Self::new(SharedNode::Xor(lhs.clone(), rhs.clone()))
+    }
+
+    pub fn node(&self) -> &SharedNode<R> {
+        &self.0
+    }
+    /// 同じノードを指しているか
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+    /// 子を左から
+    pub fn children(&self) -> Vec<&Self> {
+        match self.node() {
+            SharedNode::Leaf(_) | SharedNode::Var(_) => vec![],
+            SharedNode::Not(e) => vec![e],
+            SharedNode::Nand(l, r)
+            | SharedNode::And(l, r)
+            | SharedNode::Or(l, r)
+            | SharedNode::Xor(l, r) => vec![l, r],
+        }
+    }
+    /// 違うノードの数。評価するときのゲートと入力の数
+    pub fn node_count(&self) -> usize {
+        let mut seen = HashSet::new();
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            if seen.insert(Rc::as_ptr(&e.0)) {
+                stack.extend(e.children());
+            }
+        }
+        seen.len()
+    }
+    fn key(&self) -> *const SharedNode<R> {
+        Rc::as_ptr(&self.0)
+    }
+}
+impl<R: AsLogic + Clone> SharedExpr<R> {
+    /// 同じ形の部分式を1つのノードにまとめる。暗号文のように値が分からない値はまとめない
+    pub fn from_expr(exp: LogicExpr<R>) -> Self {
+        let circuit = Circuit::from_expr(exp);
+        let mut wires: Vec<Self> = Vec::with_capacity(circuit.nodes().len());
+        for node in circuit.nodes() {
+            let w = |i: &usize| &wires[*i];
+            let e = match node {
+                Node::Leaf(r) => Self::leaf(r.clone()),
+                Node::Var(name) => Self::var(name),
+                Node::Not(e) => Self::not(w(e)),
+                Node::Nand(l, r) => Self::nand(w(l), w(r)),
+                Node::And(l, r) => Self::and(w(l), w(r)),
+                Node::Or(l, r) => Self::or(w(l), w(r)),
+                Node::Xor(l, r) => Self::xor(w(l), w(r)),
+            };
+            wires.push(e);
+        }
+        wires[circuit.outputs()[0]].clone()
+    }
+    /// 共有している部分式を複製して木に戻す
+    pub fn to_expr(&self) -> LogicExpr<R> {
+        let mut memo: HashMap<*const SharedNode<R>, LogicExpr<R>> = HashMap::new();
+        self.fold(&mut memo, |node, value| {
+            let b = |e: &Self| Box::new(value(e));
+            Ok::<_, ()>(match node {
+                SharedNode::Leaf(r) => LogicExpr::Leaf(r.clone()),
+                SharedNode::Var(name) => LogicExpr::Var(name.clone()),
+                SharedNode::Not(e) => LogicExpr::Not(b(e)),
+                SharedNode::Nand(l, r) => LogicExpr::Nand(b(l), b(r)),
+                SharedNode::And(l, r) => LogicExpr::And(b(l), b(r)),
+                SharedNode::Or(l, r) => LogicExpr::Or(b(l), b(r)),
+                SharedNode::Xor(l, r) => LogicExpr::Xor(b(l), b(r)),
+            })
+        })
+        .unwrap()
+    }
+    /// 変数にはvarsの値を使って評価する。共有しているノードは1回だけ評価する
+    pub fn eval_with<P: Logip<R = R>>(
+        &self,
+        pros: &P,
+        vars: &HashMap<String, R>,
+    ) -> Result<R, EvalError> {
+        let mut memo: HashMap<*const SharedNode<R>, R> = HashMap::new();
+        self.fold(&mut memo, |node, value| {
+            Ok(match node {
+                SharedNode::Leaf(r) => r.clone(),
+                SharedNode::Var(name) => match vars.get(name) {
+                    Some(r) => r.clone(),
+                    None => return Err(EvalError::UnboundVariable(name.clone())),
+                },
+                SharedNode::Not(e) => pros.not(value(e)),
+                SharedNode::Nand(l, r) => pros.nand(value(l), value(r)),
+                SharedNode::And(l, r) => pros.and(value(l), value(r)),
+                SharedNode::Or(l, r) => pros.or(value(l), value(r)),
+                SharedNode::Xor(l, r) => pros.xor(value(l), value(r)),
+            })
+        })
+    }
+    /// # Panic
+    /// - 式に変数が含まれる
+    pub fn eval<P: Logip<R = R>>(&self, pros: &P) -> R {
+        match self.eval_with(pros, &HashMap::new()) {
+            Ok(res) => res,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// 子から順に、ノードごとに1回だけfを呼ぶ。子の値はvalueで読む。
+    /// 再帰せずに自前のスタックでたどる
+    fn fold<T: Clone, E>(
+        &self,
+        memo: &mut HashMap<*const SharedNode<R>, T>,
+        f: impl Fn(&SharedNode<R>, &dyn Fn(&Self) -> T) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut stack = vec![(self, false)];
+        while let Some((e, ready)) = stack.pop() {
+            if memo.contains_key(&e.key()) {
+                continue;
+            }
+            let children = e.children();
+            if !ready && !children.is_empty() {
+                stack.push((e, true));
+                // 左の子から評価する
+                stack.extend(children.into_iter().rev().map(|c| (c, false)));
+                continue;
+            }
+            let value = f(e.node(), &|c: &Self| memo[&c.key()].clone())?;
+            memo.insert(e.key(), value);
+        }
+        Ok(memo[&self.key()].clone())
+    }
+}
+impl<R: AsLogic + Clone> From<LogicExpr<R>> for SharedExpr<R> {
+    fn from(exp: LogicExpr<R>) -> Self {
+        Self::from_expr(exp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::{count_gates, lower_to_nand, NandForm};
+    use crate::{eval_logic_expr_with, parse_logic_expr, PlainLogip};
+    use std::cell::Cell;
+    use utils::math::Binary;
+
+    /// 評価したゲートを数える
+    struct Counting(Cell<usize>);
+    impl Logip for Counting {
+        type R = Binary;
+        fn nand(&self, lhs: Binary, rhs: Binary) -> Binary {
+            self.0.set(self.0.get() + 1);
+            PlainLogip.nand(lhs, rhs)
+        }
+    }
+
+    #[test]
+    fn shared_expr_eval() {
+        // xorをnandにすると部分式を複製するので、段を重ねるごとに3倍になる
+        let exp = parse_logic_expr::<Binary>("a ^ b ^ c ^ d ^ e ^ f").unwrap();
+        let (nand, count) = lower_to_nand(exp, NandForm::NandOnly);
+        assert!(count.total() > 200);
+        let shared = SharedExpr::from_expr(nand.clone());
+        // 変数6つとxor5つ分のnand
+        assert_eq!(shared.node_count(), 6 + 5 * 4);
+        assert_eq!(count_gates(&shared.to_expr()).total(), count.total());
+
+        let names = ["a", "b", "c", "d", "e", "f"];
+        for bits in 0..(1u32 << names.len()) {
+            let vars: HashMap<String, Binary> = names
+                .iter()
+                .enumerate()
+                .map(|(i, n)| (n.to_string(), Binary::from((bits >> i) & 1)))
+                .collect();
+            let pros = Counting(Cell::new(0));
+            let res = shared.eval_with(&pros, &vars).unwrap();
+            assert_eq!(pros.0.get(), 5 * 4);
+            let expect = eval_logic_expr_with(&PlainLogip, nand.clone(), &vars).unwrap();
+            assert_eq!(res, expect);
+            assert_eq!(res, Binary::from(bits.count_ones() & 1));
+        }
+    }
+
+    #[test]
+    fn shared_expr_build() {
+        let (a, b) = (SharedExpr::<Binary>::var("a"), SharedExpr::var("b"));
+        let t = SharedExpr::nand(&a, &b);
+        let xor = SharedExpr::nand(&SharedExpr::nand(&a, &t), &SharedExpr::nand(&b, &t));
+        assert_eq!(xor.node_count(), 6);
+        assert!(xor.children()[0].children()[1].ptr_eq(&t));
+        let vars = HashMap::from([("a".to_string(), Binary::One)]);
+        assert_eq!(
+            xor.eval_with(&PlainLogip, &vars).err(),
+            Some(EvalError::UnboundVariable("b".to_string()))
+        );
+        let xor = SharedExpr::xor(
+            &SharedExpr::leaf(Binary::One),
+            &SharedExpr::leaf(Binary::Zero),
+        );
+        assert_eq!(xor.eval(&PlainLogip), Binary::One);
+    }
+}
@@ -0,0 +1,388 @@
+/*!
+ゲートの命令列を流しながら評価する。
+
+式や回路を全部メモリに載せずに、ファイルやソケットから1命令ずつ読んで評価する。
+持っておくのは生きているワイヤの値だけで、`free`した(または上書きした)ワイヤはすぐに捨てる。
+数千万ゲートの回路でも、同時に生きているワイヤの分のメモリで済む。
+
+1行に1命令で、ワイヤは番号で指す。`#`から行末までは注釈。
+```text
+0 = in a          # 変数aの値
+1 = const 1
+2 = nand 0 1      # and, or, xor も同じ形
+3 = not 2
+out 3 y           # 出力。出力した順に返す
+free 0            # もう使わないワイヤ
+```
+`Circuit::to_stream`は最後に使ったところで`free`を入れた命令列を作る。
+
+```ignore
+let reader = BufReader::new(File::open("huge.gates")?);
+let outputs = eval_stream_with(&tfhe, reader, &vars)?; // Vec<(出力の名前, 値)>
+```
+ */
+use crate::circuit::{Circuit, Node, Wire};
+use crate::Logip;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::BufRead;
+use utils::traits::AsLogic;
+
+/// 1つの命令。最初のワイヤに結果を書く
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instr {
+    Input(Wire, String),
+    Const(Wire, bool),
+    Not(Wire, Wire),
+    Nand(Wire, Wire, Wire),
+    And(Wire, Wire, Wire),
+    Or(Wire, Wire, Wire),
+    Xor(Wire, Wire, Wire),
+    Output(Wire, String),
+    Free(Wire),
+}
+impl Instr {
+    /// 1行を読む。空行と注釈だけの行はNone。読めなければErr(行の内容)
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let code = line.split('#').next().unwrap();
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let wire = |w: &str| w.parse::<Wire>().map_err(|_| line.trim().to_string());
+        let instr = match words[..] {
+            [] => return Ok(None),
+            ["out", w, name] => Instr::Output(wire(w)?, name.to_string()),
+            ["free", w] => Instr::Free(wire(w)?),
+            [dst, "=", "in", name] => Instr::Input(wire(dst)?, name.to_string()),
+            [dst, "=", "const", "0"] => Instr::Const(wire(dst)?, false),
+            [dst, "=", "const", "1"] => Instr::Const(wire(dst)?, true),
+            [dst, "=", "not", e] => Instr::Not(wire(dst)?, wire(e)?),
+            [dst, "=", op, l, r] => {
+                let gate = match op {
+                    "nand" => Instr::Nand,
+                    "and" => Instr::And,
+                    "or" => Instr::Or,
+                    "xor" => Instr::Xor,
+                    _ => return Err(line.trim().to_string()),
+                };
+                gate(wire(dst)?, wire(l)?, wire(r)?)
+            }
+            _ => return Err(line.trim().to_string()),
+        };
+        Ok(Some(instr))
+    }
+}
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instr::Input(w, name) => write!(f, "{} = in {}", w, name),
+            Instr::Const(w, value) => write!(f, "{} = const {}", w, *value as u8),
+            Instr::Not(w, e) => write!(f, "{} = not {}", w, e),
+            Instr::Nand(w, l, r) => write!(f, "{} = nand {} {}", w, l, r),
+            Instr::And(w, l, r) => write!(f, "{} = and {} {}", w, l, r),
+            Instr::Or(w, l, r) => write!(f, "{} = or {} {}", w, l, r),
+            Instr::Xor(w, l, r) => write!(f, "{} = xor {} {}", w, l, r),
+            Instr::Output(w, name) => write!(f, "out {} {}", w, name),
+            Instr::Free(w) => write!(f, "free {}", w),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamError {
+    /// 読み込みの失敗
+    Io(String),
+    /// 読めない命令
+    Syntax { text: String, line: usize },
+    /// 値のない(まだ書いていないか、freeした)ワイヤ
+    UndefinedWire { wire: Wire, line: usize },
+    /// 値が渡されていない変数
+    UnboundVariable { name: String, line: usize },
+}
+impl Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::Io(err) => write!(f, "failed to read stream: {}", err),
+            StreamError::Syntax { text, line } => {
+                write!(f, "invalid instruction '{}' at line {}", text, line)
+            }
+            StreamError::UndefinedWire { wire, line } => {
+                write!(f, "wire {} has no value at line {}", wire, line)
+            }
+            StreamError::UnboundVariable { name, line } => {
+                write!(f, "unbound variable '{}' at line {}", name, line)
+            }
+        }
+    }
+}
+impl std::error::Error for StreamError {}
+
+/// 命令を1つずつ受け取って評価する
+pub struct StreamEvaluator<'a, P: Logip> {
+    pros: &'a P,
+    vars: &'a HashMap<String, P::R>,
+    wires: HashMap<Wire, P::R>,
+    outputs: Vec<(String, P::R)>,
+    line: usize,
+    peak: usize,
+}
+impl<'a, P: Logip> StreamEvaluator<'a, P> {
+    /// 変数にはvarsの値を使う
+    pub fn new(pros: &'a P, vars: &'a HashMap<String, P::R>) -> Self {
+        StreamEvaluator {
+            pros,
+            vars,
+            wires: HashMap::new(),
+            outputs: Vec::new(),
+            line: 0,
+            peak: 0,
+        }
+    }
+    /// 次の命令を評価する。エラーの行は、これまでに受け取った命令の数で数える
+    pub fn step(&mut self, instr: Instr) -> Result<(), StreamError> {
+        self.line += 1;
+        self.step_at(instr, self.line)
+    }
+    /// 生きているワイヤの数
+    pub fn live_wires(&self) -> usize {
+        self.wires.len()
+    }
+    /// これまでで最も多く生きていたワイヤの数
+    pub fn peak_live_wires(&self) -> usize {
+        self.peak
+    }
+    /// 出力した順の(名前, 値)
+    pub fn finish(self) -> Vec<(String, P::R)> {
+        self.outputs
+    }
+
+    fn step_at(&mut self, instr: Instr, line: usize) -> Result<(), StreamError> {
+        let get = |wire: Wire| match self.wires.get(&wire) {
+            Some(r) => Ok(r.clone()),
+            None => Err(StreamError::UndefinedWire { wire, line }),
+        };
+        let (wire, value) = match instr {
+            Instr::Input(w, name) => match self.vars.get(&name) {
+                Some(r) => (w, r.clone()),
+                None => return Err(StreamError::UnboundVariable { name, line }),
+            },
+            Instr::Const(w, value) => {
+                let r = if value {
+                    P::R::logic_true()
+                } else {
+                    P::R::logic_false()
+                };
+                (w, r)
+            }
+            Instr::Not(w, e) => (w, self.pros.not(get(e)?)),
+            Instr::Nand(w, l, r) => (w, self.pros.nand(get(l)?, get(r)?)),
+            Instr::And(w, l, r) => (w, self.pros.and(get(l)?, get(r)?)),
+            Instr::Or(w, l, r) => (w, self.pros.or(get(l)?, get(r)?)),
+            Instr::Xor(w, l, r) => (w, self.pros.xor(get(l)?, get(r)?)),
+            Instr::Output(w, name) => {
+                let r = get(w)?;
+                self.outputs.push((name, r));
+                return Ok(());
+            }
+            Instr::Free(w) => {
+                return match self.wires.remove(&w) {
+                    Some(_) => Ok(()),
+                    None => Err(StreamError::UndefinedWire { wire: w, line }),
+                };
+            }
+        };
+        // 生きているワイヤに書けば前の値は捨てる
+        self.wires.insert(wire, value);
+        self.peak = self.peak.max(self.wires.len());
+        Ok(())
+    }
+}
+
+/// readerから1行ずつ命令を読んで評価し、(出力の名前, 値)を出力した順に返す
+pub fn eval_stream_with<P: Logip>(
+    pros: &P,
+    reader: impl BufRead,
+    vars: &HashMap<String, P::R>,
+) -> Result<Vec<(String, P::R)>, StreamError> {
+    let mut eval = StreamEvaluator::new(pros, vars);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| StreamError::Io(err.to_string()))?;
+        match Instr::parse(&line) {
+            Ok(Some(instr)) => eval.step_at(instr, i + 1)?,
+            Ok(None) => {}
+            Err(text) => return Err(StreamError::Syntax { text, line: i + 1 }),
+        }
+    }
+    Ok(eval.finish())
+}
+
+impl<R: AsLogic> Circuit<R> {
+    /// 命令列にする。ワイヤは最後に使ったところで`free`し、出力は最後にoutputsの名前で出す
+    /// # Panic
+    /// - outputsの長さが出力の数と違う
+    /// - 暗号文のように値が分からない値を含む
+    pub fn to_stream(&self, outputs: &[&str]) -> Vec<Instr> {
+        assert_eq!(outputs.len(), self.outputs().len(), "number of outputs");
+        let inputs = |node: &Node<R>| match *node {
+            Node::Leaf(_) | Node::Var(_) => vec![],
+            Node::Not(e) => vec![e],
+            Node::Nand(l, r) | Node::And(l, r) | Node::Or(l, r) | Node::Xor(l, r) => vec![l, r],
+        };
+        // 出力するワイヤは最後まで残す
+        let mut last_use: Vec<Option<usize>> = vec![None; self.nodes().len()];
+        for (i, node) in self.nodes().iter().enumerate() {
+            for w in inputs(node) {
+                last_use[w] = Some(i);
+            }
+        }
+        for &o in self.outputs() {
+            last_use[o] = Some(usize::MAX);
+        }
+        let mut instrs = Vec::with_capacity(self.nodes().len() * 2 + outputs.len());
+        for (w, node) in self.nodes().iter().enumerate() {
+            instrs.push(match node {
+                Node::Leaf(r) => match r.as_const() {
+                    Some(value) => Instr::Const(w, value),
+                    None => panic!("wire {} holds a value that cannot be written", w),
+                },
+                Node::Var(name) => Instr::Input(w, name.clone()),
+                Node::Not(e) => Instr::Not(w, *e),
+                Node::Nand(l, r) => Instr::Nand(w, *l, *r),
+                Node::And(l, r) => Instr::And(w, *l, *r),
+                Node::Or(l, r) => Instr::Or(w, *l, *r),
+                Node::Xor(l, r) => Instr::Xor(w, *l, *r),
+            });
+            let mut dead = inputs(node);
+            dead.dedup();
+            instrs.extend(
+                dead.into_iter()
+                    .filter(|&e| last_use[e] == Some(w))
+                    .map(Instr::Free),
+            );
+            // どこからも使わないワイヤ
+            if last_use[w].is_none() {
+                instrs.push(Instr::Free(w));
+            }
+        }
+        instrs.extend(
+            self.outputs()
+                .iter()
+                .zip(outputs)
+                .map(|(&o, name)| Instr::Output(o, name.to_string())),
+        );
+        instrs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_bus_expr, PlainLogip};
+    use utils::math::Binary;
+
+    fn vars(items: &[(&str, Binary)]) -> HashMap<String, Binary> {
+        items.iter().map(|&(k, v)| (k.to_string(), v)).collect()
+    }
+
+    #[test]
+    fn eval_stream_text() {
+        use Binary::{One, Zero};
+        let src = "\
+            # a & !b
+            0 = in a
+            1 = in b
+            2 = not 1
+            free 1
+            3 = and 0 2   # 0と2はもう使わない
+            free 0
+            free 2
+            out 3 y
+            4 = const 1
+            3 = xor 3 4   # 上書き
+            out 3 ny
+        ";
+        let res = eval_stream_with(
+            &PlainLogip,
+            src.as_bytes(),
+            &vars(&[("a", One), ("b", Zero)]),
+        );
+        assert_eq!(
+            res,
+            Ok(vec![("y".to_string(), One), ("ny".to_string(), Zero)])
+        );
+
+        let res = eval_stream_with(
+            &PlainLogip,
+            "0 = in a\nfree 0\n1 = not 0".as_bytes(),
+            &vars(&[("a", One)]),
+        );
+        assert_eq!(res, Err(StreamError::UndefinedWire { wire: 0, line: 3 }));
+        let res = eval_stream_with(&PlainLogip, "0 = in a".as_bytes(), &vars(&[]));
+        assert_eq!(
+            res,
+            Err(StreamError::UnboundVariable {
+                name: "a".to_string(),
+                line: 1
+            })
+        );
+        let res = eval_stream_with(&PlainLogip, "\n0 = nor 1 2".as_bytes(), &vars(&[]));
+        assert_eq!(
+            res,
+            Err(StreamError::Syntax {
+                text: "0 = nor 1 2".to_string(),
+                line: 2
+            })
+        );
+    }
+
+    #[test]
+    fn circuit_to_stream() {
+        let circuit = Circuit::from_exprs(
+            parse_bus_expr::<Binary>("(x[7:0] & y[7:0]) ^ (x[7:0] | 8'h5a)").unwrap(),
+        );
+        let names: Vec<String> = (0..8).map(|i| format!("z{}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let instrs = circuit.to_stream(&names);
+        // 文字列にしても同じ命令に戻る
+        let text: String = instrs.iter().map(|i| format!("{}\n", i)).collect();
+        let parsed: Vec<Instr> = text
+            .lines()
+            .filter_map(|l| Instr::parse(l).unwrap())
+            .collect();
+        assert_eq!(parsed, instrs);
+
+        for (x, y) in [(0u32, 0u32), (200, 100), (255, 1), (77, 91)] {
+            let inputs: Vec<Binary> = circuit
+                .inputs()
+                .iter()
+                .map(|name| {
+                    let (v, i) = name.split_once('[').unwrap();
+                    let i: u32 = i.trim_end_matches(']').parse().unwrap();
+                    Binary::from((if v == "x" { x } else { y } >> i) & 1)
+                })
+                .collect();
+            let vars: HashMap<String, Binary> = circuit
+                .inputs()
+                .iter()
+                .cloned()
+                .zip(inputs.iter().cloned())
+                .collect();
+            let mut eval = StreamEvaluator::new(&PlainLogip, &vars);
+            for instr in instrs.iter().cloned() {
+                eval.step(instr).unwrap();
+            }
+            // 生きているのは出力だけ
+            assert_eq!(eval.live_wires(), 8);
+            assert!(eval.peak_live_wires() < circuit.nodes().len());
+            let res = eval.finish();
+            assert_eq!(
+                res.iter().map(|(_, r)| *r).collect::<Vec<_>>(),
+                circuit.eval(&PlainLogip, &inputs)
+            );
+            let sum: u32 = res
+                .iter()
+                .enumerate()
+                .map(|(i, (_, r))| (*r as u32) << i)
+                .sum();
+            assert_eq!(sum, (x & y) ^ (x | 0x5a));
+        }
+    }
+}
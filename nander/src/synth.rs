@@ -0,0 +1,211 @@
+/*!
+真理値表から論理式を作る。
+
+Quine-McCluskey法で主項を求め、必須主項と、まだ覆っていない行を多く覆う主項を順に選んで積和形にする。
+1の行を覆う式と0の行を覆う式の否定のうち、ゲートの少ない方を使う。
+入力が4〜6個程度の関数を手で式にする代わりに使う。
+
+```ignore
+// 多数決
+let maj = LogicExpr::<Binary>::from_truth_table(3, &[false, false, false, true, false, true, true, true]);
+// (x[0] & x[1]) | (x[0] & x[2]) | (x[1] & x[2])
+```
+ */
+use crate::optimize::constant;
+use crate::{bus_bit_name, LogicExpr};
+use std::collections::HashSet;
+use utils::traits::AsLogic;
+
+/// 入力の一部を決めた行の集合。maskの立っているbitはどちらでもよい
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Cube {
+    value: u32,
+    mask: u32,
+}
+impl Cube {
+    fn covers(self, row: u32) -> bool {
+        (row & !self.mask) == self.value
+    }
+    fn literals(self, inputs: usize) -> usize {
+        inputs - self.mask.count_ones() as usize
+    }
+}
+
+impl<R: AsLogic> LogicExpr<R> {
+    /// `table[i]`は入力の`j`bit目を`x[j]`としたときの値。変数は`x[0]`,...,`x[inputs-1]`
+    /// # Panic
+    /// - `table.len() != 1 << inputs`
+    /// - `inputs > 16`
+    pub fn from_truth_table(inputs: usize, table: &[bool]) -> Self {
+        assert!(inputs <= 16, "too many inputs: {}", inputs);
+        assert_eq!(table.len(), 1 << inputs, "table must have 2^inputs rows");
+        let rows = |value: bool| -> Vec<u32> {
+            (0..table.len() as u32)
+                .filter(|&i| table[i as usize] == value)
+                .collect()
+        };
+        let (ones, zeros) = (rows(true), rows(false));
+        if zeros.is_empty() {
+            return constant(true);
+        }
+        if ones.is_empty() {
+            return constant(false);
+        }
+        let on = cover(&ones, inputs);
+        let off = cover(&zeros, inputs);
+        if gates(&off, inputs) + 1 < gates(&on, inputs) {
+            LogicExpr::Not(Box::new(sum_of_products(&off, inputs)))
+        } else {
+            sum_of_products(&on, inputs)
+        }
+    }
+}
+
+/// 積和形にしたときのゲートの数
+fn gates(cubes: &[Cube], inputs: usize) -> usize {
+    let products: usize = cubes
+        .iter()
+        .map(|c| {
+            let negatives = (!c.value & !c.mask & ((1 << inputs) - 1)).count_ones() as usize;
+            c.literals(inputs).saturating_sub(1) + negatives
+        })
+        .sum();
+    products + cubes.len() - 1
+}
+
+/// rowsをちょうど覆う主項の組
+fn cover(rows: &[u32], inputs: usize) -> Vec<Cube> {
+    let primes = prime_implicants(rows);
+    let mut uncovered: HashSet<u32> = rows.iter().copied().collect();
+    let mut res = Vec::new();
+    // 1つの主項でしか覆えない行があれば、その主項は必ず使う
+    for &row in rows {
+        let mut covering = primes.iter().filter(|p| p.covers(row));
+        if let (Some(&p), None) = (covering.next(), covering.next()) {
+            if !res.contains(&p) {
+                res.push(p);
+            }
+        }
+    }
+    uncovered.retain(|&row| !res.iter().any(|p| p.covers(row)));
+    while !uncovered.is_empty() {
+        // 多くの行を覆い、リテラルの少ないもの
+        let &best = primes
+            .iter()
+            .max_by_key(|p| {
+                let count = uncovered.iter().filter(|&&row| p.covers(row)).count();
+                (count, usize::MAX - p.literals(inputs))
+            })
+            .unwrap();
+        uncovered.retain(|&row| !best.covers(row));
+        res.push(best);
+    }
+    res.sort_by_key(|c| (c.mask, c.value));
+    res
+}
+
+/// 1bitだけ違う項をまとめられなくなるまでまとめる
+fn prime_implicants(rows: &[u32]) -> Vec<Cube> {
+    let mut primes = Vec::new();
+    let mut cubes: Vec<Cube> = rows.iter().map(|&value| Cube { value, mask: 0 }).collect();
+    while !cubes.is_empty() {
+        let mut merged = vec![false; cubes.len()];
+        let mut next = HashSet::new();
+        for i in 0..cubes.len() {
+            for j in i + 1..cubes.len() {
+                let (a, b) = (cubes[i], cubes[j]);
+                let diff = a.value ^ b.value;
+                if a.mask == b.mask && diff.count_ones() == 1 {
+                    next.insert(Cube {
+                        value: a.value & !diff,
+                        mask: a.mask | diff,
+                    });
+                    merged[i] = true;
+                    merged[j] = true;
+                }
+            }
+        }
+        primes.extend(
+            cubes
+                .iter()
+                .zip(merged)
+                .filter(|(_, m)| !m)
+                .map(|(c, _)| *c),
+        );
+        cubes = next.into_iter().collect();
+        cubes.sort_by_key(|c| (c.mask, c.value));
+    }
+    primes
+}
+
+fn sum_of_products<R: AsLogic>(cubes: &[Cube], inputs: usize) -> LogicExpr<R> {
+    let product = |cube: &Cube| {
+        (0..inputs)
+            .filter(|i| cube.mask >> i & 1 == 0)
+            .map(|i| {
+                let var = LogicExpr::Var(bus_bit_name("x", i));
+                if cube.value >> i & 1 == 1 {
+                    var
+                } else {
+                    LogicExpr::Not(Box::new(var))
+                }
+            })
+            .reduce(|l, r| LogicExpr::And(Box::new(l), Box::new(r)))
+            .unwrap_or_else(|| constant(true))
+    };
+    cubes
+        .iter()
+        .map(product)
+        .reduce(|l, r| LogicExpr::Or(Box::new(l), Box::new(r)))
+        .unwrap_or_else(|| constant(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{count_gates, eval_logic_expr_with, LogicExpr, PlainLogip};
+    use std::collections::HashMap;
+    use utils::math::Binary;
+
+    fn check(inputs: usize, table: &[bool]) -> LogicExpr<Binary> {
+        let exp = LogicExpr::<Binary>::from_truth_table(inputs, table);
+        for (i, &expect) in table.iter().enumerate() {
+            let vars: HashMap<String, Binary> = (0..inputs)
+                .map(|j| (format!("x[{}]", j), Binary::from((i >> j) as u32 & 1)))
+                .collect();
+            let res = eval_logic_expr_with(&PlainLogip, exp.clone(), &vars).unwrap();
+            assert_eq!(res, Binary::from(expect as u32), "row {}", i);
+        }
+        exp
+    }
+
+    #[test]
+    fn from_truth_table() {
+        // 多数決は2リテラルの項が3つ
+        let maj = check(3, &[false, false, false, true, false, true, true, true]);
+        let count = count_gates(&maj);
+        assert_eq!((count.and, count.or, count.not), (3, 2, 0));
+
+        // 1の行が多いときは0の行を覆って否定する
+        let mut table = vec![true; 16];
+        table[7] = false;
+        table[15] = false;
+        let exp = check(4, &table);
+        assert!(matches!(exp, LogicExpr::Not(_)));
+        assert_eq!(count_gates(&exp).total(), 3);
+
+        // 1つの変数にまとまる
+        let exp = check(4, &(0..16).map(|i| i & 4 != 0).collect::<Vec<_>>());
+        assert!(matches!(exp, LogicExpr::Var(ref name) if name == "x[2]"));
+
+        check(2, &[false, true, true, false]);
+        check(1, &[true, true]);
+        check(0, &[false]);
+        // 6入力の適当な関数
+        check(
+            6,
+            &(0u64..64)
+                .map(|i| 0x8e3a_50f1_27c4_9bd6_u64 >> i & 1 == 1)
+                .collect::<Vec<_>>(),
+        );
+    }
+}
@@ -0,0 +1,200 @@
+/*!
+Trivium を準同型に評価して、共通鍵で暗号化されたデータを暗号文(Logip::R)に変換する(transciphering)。
+
+クライアントはTrivium鍵を暗号化したもの(80個)だけをアップロードし、
+データ自体は平文と同じ大きさの共通鍵暗号文 c = m ^ z で送る。
+サーバーは暗号化された鍵から鍵ストリームzを計算し、公開されているcとのxorで m の暗号文を得る。
+
+ビット列はeSTREAMの参照実装と同じく、各バイトの下位ビットから並べる。
+ */
+use crate::{Logip, PlainLogip};
+use std::collections::VecDeque;
+use utils::math::Binary;
+use utils::mem;
+use utils::traits::AsLogic;
+
+pub struct TriviumHelper;
+impl TriviumHelper {
+    pub const KEY_BITS: usize = 80;
+    pub const IV_BITS: usize = 80;
+    /// 出力を捨てる初期化のラウンド数
+    pub const WARMUP: usize = 4 * 288;
+}
+
+/// 内部状態 (s_1..s_93), (s_94..s_177), (s_178..s_288)
+pub struct Trivium<R> {
+    a: VecDeque<R>,
+    b: VecDeque<R>,
+    c: VecDeque<R>,
+}
+impl<R: AsLogic + Clone> Trivium<R> {
+    /// keyは暗号化された鍵、ivは公開の値
+    pub fn new<P: Logip<R = R>>(
+        p: &P,
+        key: [R; TriviumHelper::KEY_BITS],
+        iv: &[Binary; TriviumHelper::IV_BITS],
+    ) -> Self {
+        let constant = |b: Binary| match b {
+            Binary::One => R::logic_true(),
+            Binary::Zero => R::logic_false(),
+        };
+        let mut a = VecDeque::from(Vec::from(key));
+        a.resize(93, R::logic_false());
+        let mut b: VecDeque<R> = iv.iter().map(|&b| constant(b)).collect();
+        b.resize(84, R::logic_false());
+        let mut c: VecDeque<R> = (0..111).map(|_| R::logic_false()).collect();
+        (108..111).for_each(|i| c[i] = R::logic_true());
+
+        let mut trivium = Trivium { a, b, c };
+        for _ in 0..TriviumHelper::WARMUP {
+            trivium.clock(p, false);
+        }
+        trivium
+    }
+    /// 鍵ストリームをnビット取り出す
+    pub fn keystream<P: Logip<R = R>>(&mut self, p: &P, n: usize) -> Vec<R> {
+        (0..n).map(|_| self.clock(p, true).unwrap()).collect()
+    }
+    /// 公開されている共通鍵暗号文とのxorをとり、平文の暗号文にする
+    ///
+    /// cが定数なのでxorはnotで済む
+    pub fn transcipher<P: Logip<R = R>>(&mut self, p: &P, ciphertext: &[Binary]) -> Vec<R> {
+        ciphertext
+            .iter()
+            .map(|&c_i| {
+                let z = self.clock(p, true).unwrap();
+                match c_i {
+                    Binary::One => p.not(z),
+                    Binary::Zero => z,
+                }
+            })
+            .collect()
+    }
+
+    /// 1ラウンド進める。output=falseなら鍵ストリームを計算しない
+    fn clock<P: Logip<R = R>>(&mut self, p: &P, output: bool) -> Option<R> {
+        let (a, b, c) = (&self.a, &self.b, &self.c);
+        let t1 = p.xor(a[65].clone(), a[92].clone()); // s66 + s93
+        let t2 = p.xor(b[68].clone(), b[83].clone()); // s162 + s177
+        let t3 = p.xor(c[65].clone(), c[110].clone()); // s243 + s288
+        let z = if output {
+            Some(p.xor(p.xor(t1.clone(), t2.clone()), t3.clone()))
+        } else {
+            None
+        };
+        // t1 + s91*s92 + s171
+        let t1 = p.xor(
+            t1,
+            p.xor(p.and(a[90].clone(), a[91].clone()), b[77].clone()),
+        );
+        // t2 + s175*s176 + s264
+        let t2 = p.xor(
+            t2,
+            p.xor(p.and(b[81].clone(), b[82].clone()), c[86].clone()),
+        );
+        // t3 + s286*s287 + s69
+        let t3 = p.xor(
+            t3,
+            p.xor(p.and(c[108].clone(), c[109].clone()), a[68].clone()),
+        );
+
+        self.a.pop_back();
+        self.a.push_front(t3);
+        self.b.pop_back();
+        self.b.push_front(t1);
+        self.c.pop_back();
+        self.c.push_front(t2);
+        z
+    }
+}
+
+/// クライアント側の共通鍵暗号化。復号も同じ関数でできる
+pub fn symmetric_encrypt(
+    key: &[Binary; TriviumHelper::KEY_BITS],
+    iv: &[Binary; TriviumHelper::IV_BITS],
+    bits: &[Binary],
+) -> Vec<Binary> {
+    Trivium::new(&PlainLogip, *key, iv).transcipher(&PlainLogip, bits)
+}
+
+/// バイト列を下位ビットから並べる
+pub fn bits_from_bytes(bytes: &[u8]) -> Vec<Binary> {
+    bytes
+        .iter()
+        .flat_map(|&byte| (0..8).map(move |i| Binary::from(byte >> i & 1)))
+        .collect()
+}
+pub fn bytes_from_bits(bits: &[Binary]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0_u8, |byte, (i, &b)| byte | (b as u8) << i)
+        })
+        .collect()
+}
+/// 10バイトの鍵やIVをビット列にする
+pub fn bits80(bytes: &[u8; 10]) -> [Binary; 80] {
+    let bits = bits_from_bytes(bytes);
+    mem::array_create_enumerate(|i| bits[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hom_nand::{digest::Cryptor, tfhe::TFHE, tlwe::TLWE};
+    use utils::math::{BinaryDistribution, Random};
+
+    #[test]
+    fn trivium_keystream() {
+        // eSTREAM のテストベクトル (key = 0, iv = 0)
+        let mut trivium = Trivium::new(&PlainLogip, bits80(&[0; 10]), &bits80(&[0; 10]));
+        let z = bytes_from_bits(&trivium.keystream(&PlainLogip, 16 * 8));
+        let expect = [
+            0xFB, 0xE0, 0xBF, 0x26, 0x58, 0x59, 0x05, 0x1B, 0x51, 0x7A, 0x2E, 0x4E, 0x23, 0x9F,
+            0xC9, 0x7F,
+        ];
+        assert_eq!(z, expect);
+    }
+
+    #[test]
+    fn trivium_symmetric_roundtrip() {
+        let key = bits80(b"0123456789");
+        let iv = bits80(b"abcdefghij");
+        let plain = bits_from_bytes(b"hello homNand");
+        let cipher = symmetric_encrypt(&key, &iv, &plain);
+        assert_ne!(cipher, plain);
+        assert_eq!(symmetric_encrypt(&key, &iv, &cipher), plain);
+        assert_ne!(
+            symmetric_encrypt(&key, &bits80(b"abcdefghik"), &plain),
+            cipher,
+            "IVが違えば鍵ストリームも違う"
+        );
+    }
+
+    #[test]
+    fn trivium_transcipher() {
+        const TLWE_N: usize = 16;
+        const TRLWE_N: usize = 1024;
+        let mut unif = BinaryDistribution::uniform();
+        let s_key_tlwelv0 = unif.gen_n::<TLWE_N>();
+        let s_key_tlwelv1 = unif.gen_n::<TRLWE_N>();
+        let tfhe = TFHE::new(s_key_tlwelv0, s_key_tlwelv1);
+
+        let key: [Binary; 80] = unif.gen_n();
+        let iv: [Binary; 80] = unif.gen_n();
+        let plain: [Binary; 8] = unif.gen_n();
+        let cipher = symmetric_encrypt(&key, &iv, &plain);
+
+        // サーバーには暗号化された鍵と共通鍵暗号文だけを渡す
+        let key_enc =
+            mem::array_create_enumerate(|i| Cryptor::encrypto(TLWE, &s_key_tlwelv0, key[i]));
+        let res = Trivium::new(&tfhe, key_enc, &iv).transcipher(&tfhe, &cipher);
+        let res: Vec<Binary> = res
+            .into_iter()
+            .map(|rep| Cryptor::decrypto(TLWE, &s_key_tlwelv0, rep))
+            .collect();
+        assert_eq!(res, plain);
+    }
+}
@@ -0,0 +1,282 @@
+/*!
+論理式(`LogicExpr`,`Circuit`)を合成できるVerilogのモジュールとして書き出す。
+
+ハードウェアのシミュレーションで準同型回路の結果と突き合わせたり、FPGAのフローで使い回すためのもの。
+ゲートごとに1本の`wire`と`assign`を書く。
+
+`x[0]`,`x[1]`,...のような`parse_bus_expr`の変数名は`input [1:0] x`のようにまとめる。
+Verilogの識別子として使えない名前はエスケープした識別子(`\a.b `)にする。
+値(Leaf)は`1'b0`,`1'b1`にするので、暗号文のような値の分からないLeafは書けない。
+
+```ignore
+let circuit = Circuit::from_exprs(parse_bus_expr("x[3:0] ^ y[3:0]")?);
+std::fs::write("xor4.v", circuit.to_verilog_named("xor4", &["z[0]", "z[1]", "z[2]", "z[3]"])?)?;
+```
+ */
+use crate::circuit::{Circuit, Node, Wire};
+use crate::LogicExpr;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Write};
+use utils::traits::AsLogic;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerilogError {
+    /// 定数でない値(Leaf)
+    NonConstantLeaf { wire: Wire },
+}
+impl Display for VerilogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerilogError::NonConstantLeaf { wire } => {
+                write!(f, "wire {} is a value which is not a constant", wire)
+            }
+        }
+    }
+}
+impl std::error::Error for VerilogError {}
+
+impl<R: AsLogic + Clone> LogicExpr<R> {
+    /// 出力`y`を1つ持つモジュール
+    pub fn to_verilog(&self, module: &str) -> Result<String, VerilogError> {
+        Circuit::from_expr(self.clone()).to_verilog(module)
+    }
+}
+
+impl<R: AsLogic> Circuit<R> {
+    /// 出力は1つなら`y`、複数なら`y[0]`,`y[1]`,...
+    pub fn to_verilog(&self, module: &str) -> Result<String, VerilogError> {
+        let outputs: Vec<String> = match self.outputs().len() {
+            1 => vec!["y".to_string()],
+            n => (0..n).map(|i| format!("y[{}]", i)).collect(),
+        };
+        let outputs: Vec<&str> = outputs.iter().map(String::as_str).collect();
+        self.to_verilog_named(module, &outputs)
+    }
+    /// 出力の名前はoutputs。ノードiのワイヤは`ni`になる
+    /// # Panic
+    /// - `outputs.len() != self.outputs().len()`
+    pub fn to_verilog_named(&self, module: &str, outputs: &[&str]) -> Result<String, VerilogError> {
+        assert_eq!(
+            outputs.len(),
+            self.outputs().len(),
+            "the number of output names does not match"
+        );
+        let inputs: Vec<&str> = self.inputs().iter().map(String::as_str).collect();
+        let (input_decls, input_refs) = ports(&inputs);
+        let (output_decls, output_refs) = ports(outputs);
+
+        let mut out = String::new();
+        writeln!(out, "module {} (", identifier(module)).unwrap();
+        let decls: Vec<String> = input_decls
+            .iter()
+            .map(|d| format!("    input {}", d))
+            .chain(output_decls.iter().map(|d| format!("    output {}", d)))
+            .collect();
+        writeln!(out, "{}", decls.join(",\n")).unwrap();
+        out.push_str(");\n");
+
+        // ワイヤを参照するときの式
+        let mut refs: Vec<String> = Vec::with_capacity(self.nodes().len());
+        for (i, node) in self.nodes().iter().enumerate() {
+            let gate = match node {
+                Node::Leaf(r) => match r.as_const() {
+                    Some(c) => {
+                        refs.push(format!("1'b{}", c as u8));
+                        continue;
+                    }
+                    None => return Err(VerilogError::NonConstantLeaf { wire: i }),
+                },
+                Node::Var(name) => {
+                    refs.push(input_refs[name.as_str()].clone());
+                    continue;
+                }
+                Node::Not(e) => format!("~{}", refs[*e]),
+                Node::Nand(l, r) => format!("~({} & {})", refs[*l], refs[*r]),
+                Node::And(l, r) => format!("{} & {}", refs[*l], refs[*r]),
+                Node::Or(l, r) => format!("{} | {}", refs[*l], refs[*r]),
+                Node::Xor(l, r) => format!("{} ^ {}", refs[*l], refs[*r]),
+            };
+            writeln!(out, "    wire n{};", i).unwrap();
+            writeln!(out, "    assign n{} = {};", i, gate).unwrap();
+            refs.push(format!("n{}", i));
+        }
+        for (name, &wire) in outputs.iter().zip(self.outputs()) {
+            writeln!(out, "    assign {} = {};", output_refs[name], refs[wire]).unwrap();
+        }
+        out.push_str("endmodule\n");
+        Ok(out)
+    }
+}
+
+/// ポートの宣言(`[1:0] x`など)と、名前 -> 参照する式
+fn ports<'a>(names: &[&'a str]) -> (Vec<String>, HashMap<&'a str, String>) {
+    let scalars: HashSet<&str> = names
+        .iter()
+        .filter(|name| split_bit(name).is_none())
+        .copied()
+        .collect();
+    let bus_base = |name: &'a str| {
+        split_bit(name).filter(|(base, _)| is_identifier(base) && !scalars.contains(base))
+    };
+    // busの名前 -> (lsb, msb)
+    let mut ranges: HashMap<&str, (usize, usize)> = HashMap::new();
+    let mut order = Vec::new();
+    for &name in names {
+        match bus_base(name) {
+            Some((base, i)) => {
+                let range = ranges.entry(base).or_insert_with(|| {
+                    order.push(base);
+                    (i, i)
+                });
+                *range = (range.0.min(i), range.1.max(i));
+            }
+            None => order.push(name),
+        }
+    }
+    let decls = order
+        .into_iter()
+        .map(|name| match ranges.get(name) {
+            Some((lsb, msb)) => format!("[{}:{}] {}", msb, lsb, name),
+            None => identifier(name),
+        })
+        .collect();
+    let refs = names
+        .iter()
+        .map(|&name| match bus_base(name) {
+            Some((base, i)) => (name, format!("{}[{}]", base, i)),
+            None => (name, identifier(name)),
+        })
+        .collect();
+    (decls, refs)
+}
+
+/// `x[3]` -> `("x", 3)`
+fn split_bit(name: &str) -> Option<(&str, usize)> {
+    let (base, index) = name.strip_suffix(']')?.rsplit_once('[')?;
+    Some((base, index.parse().ok()?))
+}
+
+/// 使えない名前はエスケープする。ゲートのワイヤ(`n0`など)と同じ名前もエスケープする
+fn identifier(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        format!("\\{} ", name)
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "always",
+        "assign",
+        "begin",
+        "case",
+        "else",
+        "end",
+        "endmodule",
+        "for",
+        "if",
+        "inout",
+        "input",
+        "module",
+        "output",
+        "reg",
+        "wire",
+    ];
+    let mut chars = name.chars();
+    let head = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    let is_wire = name
+        .strip_prefix('n')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    head && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !is_wire
+        && !KEYWORDS.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_bus_expr, parse_logic_expr};
+    use hom_nand::tlwe::TLWERep;
+    use utils::{
+        math::{Binary, Torus32},
+        torus,
+    };
+
+    #[test]
+    fn logic_expr_to_verilog() {
+        let exp = parse_logic_expr::<Binary>("!(a & b) $ (a | 1)").unwrap();
+        assert_eq!(
+            exp.to_verilog("top").unwrap(),
+            "module top (
+    input a,
+    input b,
+    output y
+);
+    wire n2;
+    assign n2 = a & b;
+    wire n3;
+    assign n3 = ~n2;
+    wire n5;
+    assign n5 = a | 1'b1;
+    wire n6;
+    assign n6 = ~(n3 & n5);
+    assign y = n6;
+endmodule
+"
+        );
+    }
+
+    #[test]
+    fn circuit_to_verilog_buses() {
+        let mut exps = parse_bus_expr::<Binary>("x[1:0] ^ y[1:0]").unwrap();
+        let var = |name: &str| Box::new(LogicExpr::Var(name.to_string()));
+        // n1はゲートのワイヤと同じ名前、c.dは識別子に使えない名前
+        exps.push(LogicExpr::Or(
+            var("n1"),
+            Box::new(LogicExpr::Not(var("c.d"))),
+        ));
+        let circuit = Circuit::from_exprs(exps);
+        assert_eq!(
+            circuit
+                .to_verilog_named("xor2", &["z[0]", "z[1]", "w"])
+                .unwrap(),
+            "module xor2 (
+    input [1:0] x,
+    input [1:0] y,
+    input \\n1 ,
+    input \\c.d ,
+    output [1:0] z,
+    output w
+);
+    wire n2;
+    assign n2 = x[0] ^ y[0];
+    wire n5;
+    assign n5 = x[1] ^ y[1];
+    wire n8;
+    assign n8 = ~\\c.d ;
+    wire n9;
+    assign n9 = \\n1  | n8;
+    assign z[0] = n2;
+    assign z[1] = n5;
+    assign w = n9;
+endmodule
+"
+        );
+        assert!(circuit
+            .to_verilog("m")
+            .unwrap()
+            .contains("output [2:0] y\n"));
+    }
+
+    #[test]
+    fn verilog_non_constant_leaf() {
+        let leaf = LogicExpr::Leaf(TLWERep::<16>::trivial(torus!(0.5)));
+        assert_eq!(
+            leaf.to_verilog("m"),
+            Err(VerilogError::NonConstantLeaf { wire: 0 })
+        );
+    }
+}
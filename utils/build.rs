@@ -1,6 +1,15 @@
 extern crate cc;
 
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(spqlios_native)");
+    println!("cargo:rerun-if-changed=src/spqlios");
+
+    // spqliosはx86_64(AVX)向けのアセンブリを含むので、それ以外ではRust版のFFTを使う
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    if arch != "x86_64" || std::env::var_os("CARGO_FEATURE_PORTABLE_FFT").is_some() {
+        return;
+    }
+    println!("cargo:rustc-cfg=spqlios_native");
     println!("cargo:rustc-link-lib=spqlios");
 
     cc::Build::new()
@@ -17,4 +26,4 @@ fn main() {
         .flag("-DNDEBUG")
         .include("src")
         .compile("libspqlios.a");
-}
\ No newline at end of file
+}
@@ -0,0 +1,347 @@
+//! 長さを実行時に決める多項式。
+//! ファイルのヘッダから次数を読むときなど、`Polynomial`の`N`が決められないところで使う。
+//! 計算は係数の畳み込みなので遅い。速さが必要なら`Polynomial`に変換する。
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::ops::{Add, AddAssign, Index, Mul, Neg, Sub, SubAssign};
+
+use num::{traits::MulAdd, Zero};
+
+use crate::math::{Cross, Decimal, Polynomial};
+
+/// 長さが合わない
+#[derive(Debug, Clone, PartialEq)]
+pub struct LengthMismatch {
+    pub expected: usize,
+    pub found: usize,
+}
+impl Display for LengthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "length mismatch: expected {}, found {}",
+            self.expected, self.found
+        )
+    }
+}
+impl std::error::Error for LengthMismatch {}
+
+/**
+P(X) = SUM_{i=0}^{n-1} 0\[i\]X^i
+を表す。nは`len()`
+X^n+1を法とした剰余環上の値
+
+2つの多項式を使う演算は、長さが違うとpanicする
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynPolynomial<T>(Vec<T>);
+impl<T> DynPolynomial<T> {
+    pub fn new(coefs: Vec<T>) -> Self {
+        DynPolynomial(coefs)
+    }
+    /// 長さnで、i次の係数をf(i)とする
+    pub fn from_fn<F: FnMut(usize) -> T>(n: usize, f: F) -> Self {
+        DynPolynomial((0..n).map(f).collect())
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn coefs(&self) -> &[T] {
+        &self.0
+    }
+    pub fn coefs_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+    pub fn into_coefs(self) -> Vec<T> {
+        self.0
+    }
+    pub fn map<O, F: Fn(&T) -> O>(&self, f: F) -> DynPolynomial<O> {
+        DynPolynomial(self.0.iter().map(f).collect())
+    }
+    /// 係数ごとにfを適用する。最初に失敗したところで止めて、そのエラーを返す
+    pub fn try_map<O, E, F: FnMut(&T) -> Result<O, E>>(&self, f: F) -> Result<DynPolynomial<O>, E> {
+        self.0
+            .iter()
+            .map(f)
+            .collect::<Result<_, _>>()
+            .map(DynPolynomial)
+    }
+    /// 長さNの`Polynomial`にする
+    pub fn to_polynomial<const N: usize>(&self) -> Result<Polynomial<T, N>, LengthMismatch>
+    where
+        T: Clone,
+    {
+        Polynomial::try_from(self.clone())
+    }
+
+    fn assert_same_len<S>(&self, rhs: &DynPolynomial<S>) {
+        assert_eq!(
+            self.len(),
+            rhs.len(),
+            "DynPolynomial: length mismatch {} != {}",
+            self.len(),
+            rhs.len()
+        );
+    }
+}
+impl<T: Zero + Clone> DynPolynomial<T> {
+    pub fn zero(n: usize) -> Self {
+        DynPolynomial(vec![T::zero(); n])
+    }
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|t| t.is_zero())
+    }
+}
+impl<T> Index<usize> for DynPolynomial<T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.0[index]
+    }
+}
+impl<T: Copy> DynPolynomial<T> {
+    #[inline]
+    pub fn coef_(&self, i: usize) -> T {
+        self.0[i]
+    }
+}
+impl<T: Neg<Output = T> + Copy> DynPolynomial<T> {
+    /// X^nを掛ける。`Polynomial::rotate`と同じ
+    pub fn rotate(&self, n: i32) -> Self {
+        let len = self.len();
+        if len == 0 {
+            return self.clone();
+        }
+        let n = n.rem_euclid(2 * len as i32) as usize;
+        DynPolynomial::from_fn(len, |i| {
+            // X^n * X^j = X^{i}  (i = j + n mod 2len)
+            let j = (i + 2 * len - n) % (2 * len);
+            if j < len {
+                self.0[j]
+            } else {
+                -self.0[j - len]
+            }
+        })
+    }
+}
+impl<T: AddAssign + Copy> DynPolynomial<T> {
+    pub fn add_constant(&mut self, rhs: T) {
+        self.0[0] += rhs;
+    }
+}
+impl<S: Copy, T: Mul<S, Output = T> + Copy> Mul<S> for DynPolynomial<T> {
+    type Output = Self;
+    fn mul(mut self, rhs: S) -> Self::Output {
+        self.0.iter_mut().for_each(|x| *x = *x * rhs);
+        self
+    }
+}
+impl<S: Copy, T: Add<S, Output = T> + Copy> Add<DynPolynomial<S>> for DynPolynomial<T> {
+    type Output = Self;
+    fn add(self, rhs: DynPolynomial<S>) -> Self::Output {
+        self.add(&rhs)
+    }
+}
+impl<S: Copy, T: Add<S, Output = T> + Copy> Add<&DynPolynomial<S>> for DynPolynomial<T> {
+    type Output = Self;
+    fn add(mut self, rhs: &DynPolynomial<S>) -> Self::Output {
+        self.add_assign(rhs);
+        self
+    }
+}
+impl<S: Copy, T: Add<S, Output = T> + Copy> AddAssign<DynPolynomial<S>> for DynPolynomial<T> {
+    fn add_assign(&mut self, rhs: DynPolynomial<S>) {
+        self.add_assign(&rhs)
+    }
+}
+impl<S: Copy, T: Add<S, Output = T> + Copy> AddAssign<&DynPolynomial<S>> for DynPolynomial<T> {
+    fn add_assign(&mut self, rhs: &DynPolynomial<S>) {
+        self.assert_same_len(rhs);
+        self.0
+            .iter_mut()
+            .zip(rhs.0.iter())
+            .for_each(|(x, &y)| *x = *x + y);
+    }
+}
+impl<S: Copy, T: Sub<S, Output = T> + Copy> Sub<DynPolynomial<S>> for DynPolynomial<T> {
+    type Output = Self;
+    fn sub(self, rhs: DynPolynomial<S>) -> Self::Output {
+        self.sub(&rhs)
+    }
+}
+impl<S: Copy, T: Sub<S, Output = T> + Copy> Sub<&DynPolynomial<S>> for DynPolynomial<T> {
+    type Output = Self;
+    fn sub(mut self, rhs: &DynPolynomial<S>) -> Self::Output {
+        self.sub_assign(rhs);
+        self
+    }
+}
+impl<S: Copy, T: Sub<S, Output = T> + Copy> SubAssign<&DynPolynomial<S>> for DynPolynomial<T> {
+    fn sub_assign(&mut self, rhs: &DynPolynomial<S>) {
+        self.assert_same_len(rhs);
+        self.0
+            .iter_mut()
+            .zip(rhs.0.iter())
+            .for_each(|(x, &y)| *x = *x - y);
+    }
+}
+impl<T: Neg<Output = T> + Copy> Neg for DynPolynomial<T> {
+    type Output = Self;
+    fn neg(mut self) -> Self::Output {
+        self.0.iter_mut().for_each(|x| *x = -*x);
+        self
+    }
+}
+/// X^n+1を法とした多項式乗算
+impl<S: Copy, T: Sub<Output = T> + Copy + Zero + MulAdd<S, Output = T>> Cross<DynPolynomial<S>>
+    for DynPolynomial<T>
+{
+    type Output = Self;
+    fn cross(&self, rhs: &DynPolynomial<S>) -> Self::Output {
+        self.assert_same_len(rhs);
+        let n = self.len();
+        // X^{i+j} = -X^{i+j-n} (i+j >= n)
+        DynPolynomial::from_fn(n, |k| {
+            (0..n).fold(T::zero(), |acc, i| {
+                if i <= k {
+                    self.0[i].mul_add(rhs.0[k - i], acc)
+                } else {
+                    acc - self.0[i].mul_add(rhs.0[n + k - i], T::zero())
+                }
+            })
+        })
+    }
+}
+impl DynPolynomial<Decimal<u32>> {
+    /// `Polynomial::decomposition_i32`と同じ
+    pub fn decomposition_i32<const L: usize>(&self, bits: u32) -> [DynPolynomial<i32>; L] {
+        let digits: Vec<[i32; L]> = self.0.iter().map(|d| d.decomposition_i32(bits)).collect();
+        crate::mem::array_init(|i| DynPolynomial::from_fn(self.len(), |j| digits[j][i]))
+    }
+}
+
+impl<T, const N: usize> From<Polynomial<T, N>> for DynPolynomial<T> {
+    fn from(pol: Polynomial<T, N>) -> Self {
+        DynPolynomial(Vec::from(pol.into_coefs()))
+    }
+}
+impl<T: Clone, const N: usize> From<&Polynomial<T, N>> for DynPolynomial<T> {
+    fn from(pol: &Polynomial<T, N>) -> Self {
+        DynPolynomial(pol.coefs().to_vec())
+    }
+}
+impl<T, const N: usize> TryFrom<DynPolynomial<T>> for Polynomial<T, N> {
+    type Error = LengthMismatch;
+    fn try_from(pol: DynPolynomial<T>) -> Result<Self, Self::Error> {
+        let found = pol.len();
+        <[T; N]>::try_from(pol.0)
+            .map(Polynomial::new)
+            .map_err(|_| LengthMismatch { expected: N, found })
+    }
+}
+
+/// 係数を順に並べた列として読み書きする
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for DynPolynomial<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for DynPolynomial<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(DynPolynomial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{ModDistribution, Random, Torus32};
+    use crate::pol;
+
+    #[test]
+    fn dyn_polynomial_matches_polynomial() {
+        const N: usize = 16;
+        let mut unif = ModDistribution::uniform();
+        let l: Polynomial<Torus32, N> = pol!(unif.gen_n());
+        let r: Polynomial<i32, N> = Polynomial::from_fn(|i| i as i32 * 7 - 50);
+        let t: Polynomial<Torus32, N> = pol!(unif.gen_n());
+        let (dl, dr, dt) = (
+            DynPolynomial::from(&l),
+            DynPolynomial::from(&r),
+            DynPolynomial::from(t.clone()),
+        );
+
+        assert_eq!(dl.len(), N);
+        assert_eq!(dl.cross(&dr), DynPolynomial::from(l.cross(&r)));
+        assert_eq!(dl.clone() + &dt, DynPolynomial::from(l.clone() + &t));
+        assert_eq!(dl.clone() - dt.clone(), DynPolynomial::from(l.clone() - t));
+        assert_eq!(-dl.clone(), DynPolynomial::from(-l.clone()));
+        assert_eq!(dl.clone() * 3, DynPolynomial::from(l.clone() * 3));
+        for n in [-40, -16, -3, 0, 1, 5, 16, 17, 31, 100] {
+            assert_eq!(dr.rotate(n), DynPolynomial::from(r.rotate(n)), "{}", n);
+        }
+        let ds: [DynPolynomial<i32>; 3] = dl.decomposition_i32(6);
+        let ps: [Polynomial<i32, N>; 3] = l.decomposition_i32(6);
+        for (d, p) in ds.iter().zip(ps.iter()) {
+            assert_eq!(d, &DynPolynomial::from(p));
+        }
+
+        // 戻すときは長さを確かめる
+        assert_eq!(dl.to_polynomial::<N>(), Ok(l));
+        assert_eq!(
+            dl.to_polynomial::<8>(),
+            Err(LengthMismatch {
+                expected: 8,
+                found: N
+            })
+        );
+    }
+
+    #[test]
+    fn dyn_polynomial_api() {
+        let mut p = DynPolynomial::from_fn(5, |i| i as i32 + 1);
+        assert_eq!(p.coefs(), &[1, 2, 3, 4, 5]);
+        assert_eq!((p[2], p.coef_(4)), (3, 5));
+        p.add_constant(10);
+        assert_eq!(p.coefs(), &[11, 2, 3, 4, 5]);
+        assert_eq!(
+            p.map(|&x| x % 2 == 0).coefs(),
+            &[false, true, false, true, false]
+        );
+        assert_eq!(p.try_map(|&x| if x > 10 { Err(x) } else { Ok(x) }), Err(11));
+
+        let z = DynPolynomial::<i32>::zero(3);
+        assert!(z.is_zero() && z.len() == 3);
+        assert!(DynPolynomial::<i32>::new(vec![]).is_empty());
+        assert_eq!(
+            LengthMismatch {
+                expected: 1,
+                found: 2
+            }
+            .to_string(),
+            "length mismatch: expected 1, found 2"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn dyn_polynomial_json() {
+        let p = DynPolynomial::new(vec![Decimal::from_bits(1_u32), Decimal::from_bits(2)]);
+        let json = serde_json::to_string(&p).unwrap();
+        assert_eq!(json, "[1,2]");
+        assert_eq!(
+            serde_json::from_str::<DynPolynomial<Torus32>>(&json).unwrap(),
+            p
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn dyn_polynomial_length_mismatch() {
+        let _ = DynPolynomial::new(vec![1, 2]) + DynPolynomial::new(vec![1, 2, 3]);
+    }
+}
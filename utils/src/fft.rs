@@ -0,0 +1,686 @@
+//! spqliosのfft_model/ifft_model(C++の参照実装)をRustに移したもの。
+//! アセンブリを使わないので、x86_64以外(wasm32など)でも使える。
+//! 入出力のレイアウト(前半が実部、後半が虚部)とスケールはspqliosと同じ。
+//!
+//! x86_64でAVX2とFMAが使えるときは、実行時に判定してベクトル化した版(`avx`)を使う。
+//!
+//! 負巡回畳み込みのためのtwist(係数に1の2N乗根を掛ける)は別の段にせず、
+//! ifftでは最初の段、fftでは最後の段の中で掛ける。
+use std::f64::consts::PI;
+
+use crate::math::{count_fft, Torus32};
+use crate::spqlios::FrrSeries;
+
+type Model = fn(&[f64], &mut [f64]);
+
+pub struct PortableFft {
+    n: usize,
+    tables_direct: Vec<f64>,
+    tables_reverse: Vec<f64>,
+    buf: Vec<f64>,
+    fft_model: Model,
+    ifft_model: Model,
+    vectorized: bool,
+}
+
+impl PortableFft {
+    pub fn new(n: usize) -> Self {
+        assert!(n >= 16, "n must be >=16");
+        assert!(n.is_power_of_two(), "n must be a power of 2");
+        PortableFft {
+            n,
+            tables_direct: new_fft_table(n),
+            tables_reverse: new_ifft_table(n),
+            buf: vec![0.0; n],
+            fft_model,
+            ifft_model,
+            vectorized: false,
+        }
+        .with_best_kernel()
+    }
+    /// スカラー版のみを使う
+    pub fn scalar(n: usize) -> Self {
+        let mut fft = Self::new(n);
+        fft.fft_model = fft_model;
+        fft.ifft_model = ifft_model;
+        fft.vectorized = false;
+        fft
+    }
+    /// AVX2/FMA版を使っているか
+    pub fn is_vectorized(&self) -> bool {
+        self.vectorized
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn with_best_kernel(mut self) -> Self {
+        if avx::has_avx2_fma() {
+            self.fft_model = avx::fft_model;
+            self.ifft_model = avx::ifft_model;
+            self.vectorized = true;
+        }
+        self
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    fn with_best_kernel(self) -> Self {
+        self
+    }
+
+    pub fn ifft<const N: usize>(&mut self, input: &[f64; N]) -> FrrSeries<N> {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.buf.copy_from_slice(input);
+        self.execute_reverse()
+    }
+
+    pub fn ifft_torus<const N: usize>(&mut self, input: &[Torus32; N]) -> FrrSeries<N> {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.buf
+            .iter_mut()
+            .zip(input.iter())
+            .for_each(|(b, t)| *b = t.inner() as i32 as f64);
+        self.execute_reverse()
+    }
+
+    pub fn ifft_int<const N: usize>(&mut self, input: &[i32; N]) -> FrrSeries<N> {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.buf
+            .iter_mut()
+            .zip(input.iter())
+            .for_each(|(b, &i)| *b = i as f64);
+        self.execute_reverse()
+    }
+
+    pub fn ifft_to<const N: usize>(&mut self, input: &[f64; N], res: &mut FrrSeries<N>) {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.buf.copy_from_slice(input);
+        self.run_ifft();
+        res.coefs_mut().copy_from_slice(&self.buf);
+    }
+
+    pub fn ifft_torus_to<const N: usize>(&mut self, input: &[Torus32; N], res: &mut FrrSeries<N>) {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.buf
+            .iter_mut()
+            .zip(input.iter())
+            .for_each(|(b, t)| *b = t.inner() as i32 as f64);
+        self.run_ifft();
+        res.coefs_mut().copy_from_slice(&self.buf);
+    }
+
+    pub fn ifft_int_to<const N: usize>(&mut self, input: &[i32; N], res: &mut FrrSeries<N>) {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.buf
+            .iter_mut()
+            .zip(input.iter())
+            .for_each(|(b, &i)| *b = i as f64);
+        self.run_ifft();
+        res.coefs_mut().copy_from_slice(&self.buf);
+    }
+
+    pub fn fft<const N: usize>(&mut self, input: &FrrSeries<N>) -> [f64; N] {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.execute_direct(input);
+        let mut res = [0.0; N];
+        res.copy_from_slice(&self.buf);
+        res
+    }
+
+    pub fn fft_torus<const N: usize>(&mut self, input: &FrrSeries<N>) -> [Torus32; N] {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.execute_direct(input);
+        let mut res = [Torus32::from_bits(0); N];
+        res.iter_mut()
+            .zip(self.buf.iter())
+            .for_each(|(r, &d)| *r = Torus32::from_bits(d as i64 as u32));
+        res
+    }
+
+    pub fn fft_to<const N: usize>(&mut self, input: &FrrSeries<N>, res: &mut [f64; N]) {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.execute_direct(input);
+        res.copy_from_slice(&self.buf);
+    }
+
+    pub fn fft_torus_to<const N: usize>(&mut self, input: &FrrSeries<N>, res: &mut [Torus32; N]) {
+        debug_assert!(self.n == N, "fft: self.n={},N={}", self.n, N);
+        self.execute_direct(input);
+        res.iter_mut()
+            .zip(self.buf.iter())
+            .for_each(|(r, &d)| *r = Torus32::from_bits(d as i64 as u32));
+    }
+
+    pub fn poly_mul<const N: usize>(&mut self, a: &[Torus32; N], b: &[Torus32; N]) -> [Torus32; N] {
+        let a = self.ifft_torus(a);
+        let b = self.ifft_torus(b);
+        self.fft_torus(&a.hadamard(&b))
+    }
+
+    fn run_ifft(&mut self) {
+        count_fft(1);
+        (self.ifft_model)(&self.tables_reverse, &mut self.buf);
+    }
+    fn run_fft(&mut self) {
+        count_fft(1);
+        (self.fft_model)(&self.tables_direct, &mut self.buf);
+    }
+    fn execute_reverse<const N: usize>(&mut self) -> FrrSeries<N> {
+        self.run_ifft();
+        let mut res = [0.0; N];
+        res.copy_from_slice(&self.buf);
+        FrrSeries::new(res)
+    }
+    fn execute_direct<const N: usize>(&mut self, input: &FrrSeries<N>) {
+        let _2sn = 2.0 / N as f64;
+        self.buf
+            .iter_mut()
+            .zip(input.coefs().iter())
+            .for_each(|(b, &a)| *b = a * _2sn);
+        self.run_fft();
+    }
+}
+
+/// cos(2pi*i/n)
+fn accurate_cos(i: i64, n: i64) -> f64 {
+    let i = ((i % n) + n) % n;
+    let rad = |k: i64| 2. * PI * k as f64 / n as f64;
+    if i >= 3 * n / 4 {
+        rad(n - i).cos()
+    } else if i >= 2 * n / 4 {
+        -rad(i - n / 2).cos()
+    } else if i >= n / 4 {
+        -rad(n / 2 - i).cos()
+    } else {
+        rad(i).cos()
+    }
+}
+/// sin(2pi*i/n)
+fn accurate_sin(i: i64, n: i64) -> f64 {
+    let i = ((i % n) + n) % n;
+    let rad = |k: i64| 2. * PI * k as f64 / n as f64;
+    if i >= 3 * n / 4 {
+        -rad(n - i).sin()
+    } else if i >= 2 * n / 4 {
+        -rad(i - n / 2).sin()
+    } else if i >= n / 4 {
+        rad(n / 2 - i).sin()
+    } else {
+        rad(i).sin()
+    }
+}
+/// 4つずつ |cos..|sin..| の順に並べる
+fn push_trig(table: &mut Vec<f64>, n: i64, len: i64, angle: impl Fn(i64) -> i64) {
+    for i in (0..len).step_by(4) {
+        (0..4).for_each(|k| table.push(accurate_cos(angle(i + k), n)));
+        (0..4).for_each(|k| table.push(accurate_sin(angle(i + k), n)));
+    }
+}
+
+fn new_ifft_table(nn: usize) -> Vec<f64> {
+    let n = 2 * nn as i64;
+    let ns4 = n / 4;
+    let mut table = Vec::with_capacity(2 * nn);
+    push_trig(&mut table, n, ns4, |j| j);
+    let mut nn = ns4;
+    while nn >= 8 {
+        let j = n / nn;
+        push_trig(&mut table, n, nn / 2, |i| j * i);
+        nn /= 2;
+    }
+    table
+}
+
+fn new_fft_table(nn: usize) -> Vec<f64> {
+    let n = 2 * nn as i64;
+    let ns4 = n / 4;
+    let mut table = Vec::with_capacity(2 * nn);
+    let mut halfnn = 4;
+    while halfnn < ns4 {
+        let j = n / (2 * halfnn);
+        push_trig(&mut table, n, halfnn, |i| -j * i);
+        halfnn *= 2;
+    }
+    push_trig(&mut table, n, ns4, |i| -i);
+    table
+}
+
+/// (re + i im) * (cos + i sin)
+#[inline(always)]
+fn cmul(re: f64, im: f64, cos: f64, sin: f64) -> (f64, f64) {
+    (re * cos - im * sin, re * sin + im * cos)
+}
+/// [1 1; 1 -1] を隣り合う2つに
+fn butterfly2(d: &mut [f64]) {
+    for b in (0..d.len()).step_by(4) {
+        let x = [d[b], d[b + 1], d[b + 2], d[b + 3]];
+        d[b..b + 4].copy_from_slice(&[x[0] + x[1], x[0] - x[1], x[2] + x[3], x[2] - x[3]]);
+    }
+}
+
+fn ifft_model(table: &[f64], c: &mut [f64]) {
+    let ns4 = c.len() / 2;
+    let (are, aim) = c.split_at_mut(ns4);
+
+    // 最初の段(nn = ns4)でtwistもまとめて掛ける
+    let halfnn = ns4 / 2;
+    let mut cur = 2 * ns4;
+    for off in (0..halfnn).step_by(4) {
+        let (cos, sin) = (&table[cur + 2 * off..], &table[cur + 2 * off + 4..]);
+        let (tw0, tw1) = (&table[2 * off..], &table[2 * (halfnn + off)..]);
+        for k in 0..4 {
+            let (i0, i1) = (off + k, halfnn + off + k);
+            let (r0, m0) = cmul(are[i0], aim[i0], tw0[k], tw0[k + 4]);
+            let (r1, m1) = cmul(are[i1], aim[i1], tw1[k], tw1[k + 4]);
+            are[i0] = r0 + r1;
+            aim[i0] = m0 + m1;
+            let (r, i) = cmul(r0 - r1, m0 - m1, cos[k], sin[k]);
+            are[i1] = r;
+            aim[i1] = i;
+        }
+    }
+
+    let mut nn = halfnn;
+    while nn >= 8 {
+        let halfnn = nn / 2;
+        cur += 2 * nn;
+        for block in (0..ns4).step_by(nn) {
+            for off in (0..halfnn).step_by(4) {
+                let (cos, sin) = (&table[cur + 2 * off..], &table[cur + 2 * off + 4..]);
+                for k in 0..4 {
+                    let (i0, i1) = (block + off + k, block + halfnn + off + k);
+                    let (re_d, im_d) = (are[i0] - are[i1], aim[i0] - aim[i1]);
+                    are[i0] += are[i1];
+                    aim[i0] += aim[i1];
+                    are[i1] = re_d * cos[k] - im_d * sin[k];
+                    aim[i1] = re_d * sin[k] + im_d * cos[k];
+                }
+            }
+        }
+        nn /= 2;
+    }
+
+    ifft_tail(are, aim);
+}
+/// ifftの最後の size 4, size 2 の段
+#[inline(always)]
+fn ifft_tail(are: &mut [f64], aim: &mut [f64]) {
+    // size 4
+    for b in (0..are.len()).step_by(4) {
+        let r = [are[b], are[b + 1], are[b + 2], are[b + 3]];
+        let i = [aim[b], aim[b + 1], aim[b + 2], aim[b + 3]];
+        are[b..b + 4].copy_from_slice(&[r[0] + r[2], r[1] + r[3], r[0] - r[2], -i[1] + i[3]]);
+        aim[b..b + 4].copy_from_slice(&[i[0] + i[2], i[1] + i[3], i[0] - i[2], r[1] - r[3]]);
+    }
+    // size 2
+    butterfly2(are);
+    butterfly2(aim);
+}
+
+fn fft_model(table: &[f64], c: &mut [f64]) {
+    let ns4 = c.len() / 2;
+    let (pre, pim) = c.split_at_mut(ns4);
+
+    fft_head(pre, pim);
+
+    let mut cur = 0;
+    let mut halfnn = 4;
+    while 2 * halfnn < ns4 {
+        let nn = 2 * halfnn;
+        for block in (0..ns4).step_by(nn) {
+            for off in (0..halfnn).step_by(4) {
+                let (cos, sin) = (&table[cur + 2 * off..], &table[cur + 2 * off + 4..]);
+                for k in 0..4 {
+                    let (i0, i1) = (block + off + k, block + halfnn + off + k);
+                    let re2 = pre[i1] * cos[k] - pim[i1] * sin[k];
+                    let im2 = pre[i1] * sin[k] + pim[i1] * cos[k];
+                    pre[i1] = pre[i0] - re2;
+                    pim[i1] = pim[i0] - im2;
+                    pre[i0] += re2;
+                    pim[i0] += im2;
+                }
+            }
+        }
+        cur += nn;
+        halfnn *= 2;
+    }
+
+    // 最後の段(halfnn = ns4 / 2)でtwistもまとめて掛ける
+    let twist = cur + ns4;
+    for off in (0..halfnn).step_by(4) {
+        let (cos, sin) = (&table[cur + 2 * off..], &table[cur + 2 * off + 4..]);
+        let (tw0, tw1) = (
+            &table[twist + 2 * off..],
+            &table[twist + 2 * (halfnn + off)..],
+        );
+        for k in 0..4 {
+            let (i0, i1) = (off + k, halfnn + off + k);
+            let (re2, im2) = cmul(pre[i1], pim[i1], cos[k], sin[k]);
+            let (r0, m0) = (pre[i0], pim[i0]);
+            let (r, i) = cmul(r0 + re2, m0 + im2, tw0[k], tw0[k + 4]);
+            pre[i0] = r;
+            pim[i0] = i;
+            let (r, i) = cmul(r0 - re2, m0 - im2, tw1[k], tw1[k + 4]);
+            pre[i1] = r;
+            pim[i1] = i;
+        }
+    }
+}
+/// fftの最初の size 2, size 4 の段
+#[inline(always)]
+fn fft_head(pre: &mut [f64], pim: &mut [f64]) {
+    // size 2
+    butterfly2(pre);
+    butterfly2(pim);
+    // size 4
+    for b in (0..pre.len()).step_by(4) {
+        let r = [pre[b], pre[b + 1], pre[b + 2], pre[b + 3]];
+        let i = [pim[b], pim[b + 1], pim[b + 2], pim[b + 3]];
+        pre[b..b + 4].copy_from_slice(&[r[0] + r[2], r[1] + i[3], r[0] - r[2], r[1] - i[3]]);
+        pim[b..b + 4].copy_from_slice(&[i[0] + i[2], i[1] - r[3], i[0] - i[2], i[1] + r[3]]);
+    }
+}
+
+/// ifft_model/fft_modelの係数4つずつの計算を__m256dで行う。
+/// size 2, 4 の段は要素の並べ替えが必要なのでスカラー版を使う
+#[cfg(target_arch = "x86_64")]
+mod avx {
+    use std::arch::x86_64::*;
+
+    /// *_implはAVX2とFMAを確認してから呼ぶ。使えなければスカラー版で計算する
+    /// (判定結果はstdがキャッシュしているので毎回確認しても安い)
+    pub fn ifft_model(table: &[f64], c: &mut [f64]) {
+        if has_avx2_fma() {
+            unsafe { ifft_model_impl(table, c) }
+        } else {
+            super::ifft_model(table, c)
+        }
+    }
+    pub fn fft_model(table: &[f64], c: &mut [f64]) {
+        if has_avx2_fma() {
+            unsafe { fft_model_impl(table, c) }
+        } else {
+            super::fft_model(table, c)
+        }
+    }
+
+    pub fn has_avx2_fma() -> bool {
+        is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+    }
+
+    /// (re + i im) * (cos + i sin)
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn cmul(re: __m256d, im: __m256d, cos: __m256d, sin: __m256d) -> (__m256d, __m256d) {
+        (
+            _mm256_fmsub_pd(re, cos, _mm256_mul_pd(im, sin)),
+            _mm256_fmadd_pd(re, sin, _mm256_mul_pd(im, cos)),
+        )
+    }
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn load(p: *const f64, i: usize) -> __m256d {
+        _mm256_loadu_pd(p.add(i))
+    }
+    /// table[j..j+8]の|cos..|sin..|を掛ける
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn twist_at(
+        re: __m256d,
+        im: __m256d,
+        table: *const f64,
+        j: usize,
+    ) -> (__m256d, __m256d) {
+        cmul(re, im, load(table, j), load(table, j + 4))
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn ifft_model_impl(table: &[f64], c: &mut [f64]) {
+        let ns4 = c.len() / 2;
+        let (are, aim) = c.split_at_mut(ns4);
+
+        let (re, im) = (are.as_mut_ptr(), aim.as_mut_ptr());
+        let t = table.as_ptr();
+
+        // 最初の段(nn = ns4)でtwistもまとめて掛ける
+        let halfnn = ns4 / 2;
+        let mut cur = 2 * ns4;
+        assert!(table.len() >= cur + ns4);
+        for off in (0..halfnn).step_by(4) {
+            let (i0, i1) = (off, halfnn + off);
+            let (r0, m0) = twist_at(load(re, i0), load(im, i0), t, 2 * i0);
+            let (r1, m1) = twist_at(load(re, i1), load(im, i1), t, 2 * i1);
+            _mm256_storeu_pd(re.add(i0), _mm256_add_pd(r0, r1));
+            _mm256_storeu_pd(im.add(i0), _mm256_add_pd(m0, m1));
+            let (r, i) = twist_at(
+                _mm256_sub_pd(r0, r1),
+                _mm256_sub_pd(m0, m1),
+                t,
+                cur + 2 * off,
+            );
+            _mm256_storeu_pd(re.add(i1), r);
+            _mm256_storeu_pd(im.add(i1), i);
+        }
+
+        let mut nn = halfnn;
+        while nn >= 8 {
+            let halfnn = nn / 2;
+            cur += 2 * nn;
+            assert!(table.len() >= cur + nn);
+            for block in (0..ns4).step_by(nn) {
+                for off in (0..halfnn).step_by(4) {
+                    let cos = _mm256_loadu_pd(table.as_ptr().add(cur + 2 * off));
+                    let sin = _mm256_loadu_pd(table.as_ptr().add(cur + 2 * off + 4));
+                    let (i0, i1) = (block + off, block + halfnn + off);
+                    let (r0, r1) = (_mm256_loadu_pd(re.add(i0)), _mm256_loadu_pd(re.add(i1)));
+                    let (m0, m1) = (_mm256_loadu_pd(im.add(i0)), _mm256_loadu_pd(im.add(i1)));
+                    _mm256_storeu_pd(re.add(i0), _mm256_add_pd(r0, r1));
+                    _mm256_storeu_pd(im.add(i0), _mm256_add_pd(m0, m1));
+                    let (r, i) = cmul(_mm256_sub_pd(r0, r1), _mm256_sub_pd(m0, m1), cos, sin);
+                    _mm256_storeu_pd(re.add(i1), r);
+                    _mm256_storeu_pd(im.add(i1), i);
+                }
+            }
+            nn /= 2;
+        }
+
+        super::ifft_tail(are, aim);
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn fft_model_impl(table: &[f64], c: &mut [f64]) {
+        let ns4 = c.len() / 2;
+        let (pre, pim) = c.split_at_mut(ns4);
+
+        super::fft_head(pre, pim);
+
+        let (re, im) = (pre.as_mut_ptr(), pim.as_mut_ptr());
+        let mut cur = 0;
+        let mut halfnn = 4;
+        while 2 * halfnn < ns4 {
+            let nn = 2 * halfnn;
+            assert!(table.len() >= cur + nn);
+            for block in (0..ns4).step_by(nn) {
+                for off in (0..halfnn).step_by(4) {
+                    let cos = _mm256_loadu_pd(table.as_ptr().add(cur + 2 * off));
+                    let sin = _mm256_loadu_pd(table.as_ptr().add(cur + 2 * off + 4));
+                    let (i0, i1) = (block + off, block + halfnn + off);
+                    let (r0, m0) = (_mm256_loadu_pd(re.add(i0)), _mm256_loadu_pd(im.add(i0)));
+                    let (r2, m2) = cmul(
+                        _mm256_loadu_pd(re.add(i1)),
+                        _mm256_loadu_pd(im.add(i1)),
+                        cos,
+                        sin,
+                    );
+                    _mm256_storeu_pd(re.add(i1), _mm256_sub_pd(r0, r2));
+                    _mm256_storeu_pd(im.add(i1), _mm256_sub_pd(m0, m2));
+                    _mm256_storeu_pd(re.add(i0), _mm256_add_pd(r0, r2));
+                    _mm256_storeu_pd(im.add(i0), _mm256_add_pd(m0, m2));
+                }
+            }
+            cur += nn;
+            halfnn *= 2;
+        }
+
+        // 最後の段(halfnn = ns4 / 2)でtwistもまとめて掛ける
+        let twist = cur + ns4;
+        assert!(table.len() >= twist + 2 * ns4);
+        let t = table.as_ptr();
+        for off in (0..halfnn).step_by(4) {
+            let (i0, i1) = (off, halfnn + off);
+            let (r0, m0) = (load(re, i0), load(im, i0));
+            let (r2, m2) = twist_at(load(re, i1), load(im, i1), t, cur + 2 * off);
+            let (r, i) = twist_at(
+                _mm256_add_pd(r0, r2),
+                _mm256_add_pd(m0, m2),
+                t,
+                twist + 2 * i0,
+            );
+            _mm256_storeu_pd(re.add(i0), r);
+            _mm256_storeu_pd(im.add(i0), i);
+            let (r, i) = twist_at(
+                _mm256_sub_pd(r0, r2),
+                _mm256_sub_pd(m0, m2),
+                t,
+                twist + 2 * i1,
+            );
+            _mm256_storeu_pd(re.add(i1), r);
+            _mm256_storeu_pd(im.add(i1), i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{ModDistribution, Random};
+
+    fn close(a: Torus32, b: Torus32) -> bool {
+        (a.inner().wrapping_sub(b.inner()) as i32).abs() <= 1
+    }
+
+    #[test]
+    fn portable_fft_poly_mul() {
+        const N: usize = 64;
+        let mut fft = PortableFft::new(N);
+        let mut a = [Torus32::from_bits(0); N];
+        let mut b = [Torus32::from_bits(0); N];
+        a[1] = Torus32::from_bits(3);
+        a[N - 1] = Torus32::from_bits(5);
+        b[2] = Torus32::from_bits(7);
+        b[0] = Torus32::from_bits(1);
+
+        // (3X + 5X^{N-1})(1 + 7X^2) = 3X + 21X^3 + 5X^{N-1} - 35X
+        // spqliosと同じく切り捨てなので、1ずれることがある
+        let res = fft.poly_mul(&a, &b);
+        let mut expect = [Torus32::from_bits(0); N];
+        expect[1] = Torus32::from_bits(3_u32.wrapping_sub(35));
+        expect[3] = Torus32::from_bits(21);
+        expect[N - 1] = Torus32::from_bits(5);
+        assert!(
+            res.iter().zip(expect.iter()).all(|(r, e)| close(*r, *e)),
+            "{:?}",
+            res
+        );
+
+        let mut unif = ModDistribution::uniform();
+        let x: [Torus32; N] = unif.gen_n();
+        let x_f = fft.ifft_torus(&x);
+        let y = fft.fft_torus(&x_f);
+        assert!(
+            x.iter().zip(y.iter()).all(|(x, y)| close(*x, *y)),
+            "ifft->fft"
+        );
+    }
+
+    #[test]
+    fn portable_fft_vectorized_matches_scalar() {
+        const N: usize = 1024;
+        let mut fft = PortableFft::new(N);
+        let mut scalar = PortableFft::scalar(N);
+        assert!(!scalar.is_vectorized());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            fft.is_vectorized(),
+            is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+        );
+
+        let mut unif = ModDistribution::uniform();
+        let x: [Torus32; N] = unif.gen_n();
+        let (lhs, rhs) = (fft.ifft_torus(&x), scalar.ifft_torus(&x));
+        for (l, r) in lhs.coefs().iter().zip(rhs.coefs().iter()) {
+            assert!((l - r).abs() <= 1e-6 * r.abs().max(1.0), "{} vs {}", l, r);
+        }
+        let (lhs, rhs) = (fft.fft_torus(&rhs), scalar.fft_torus(&rhs));
+        assert!(lhs.iter().zip(rhs.iter()).all(|(l, r)| close(*l, *r)));
+    }
+
+    /// 段が1つだけ(N=16)のときも、twistを入れた最初/最後の段が正しいか
+    #[test]
+    fn portable_fft_small_n() {
+        fn check<const N: usize>(mut fft: PortableFft) {
+            let mut unif = ModDistribution::uniform();
+            let a: [Torus32; N] = unif.gen_n();
+            let mut b = [0i32; N];
+            b.iter_mut()
+                .enumerate()
+                .for_each(|(i, x)| *x = (i as i32 * 7) % 17 - 8);
+
+            let mut expect = [0u32; N];
+            for (i, a_i) in a.iter().enumerate() {
+                for (j, &b_j) in b.iter().enumerate() {
+                    let m = a_i.inner().wrapping_mul(b_j as u32);
+                    let k = (i + j) % N;
+                    expect[k] = if i + j < N {
+                        expect[k].wrapping_add(m)
+                    } else {
+                        expect[k].wrapping_sub(m)
+                    };
+                }
+            }
+            let (a_f, b_f) = (fft.ifft_torus(&a), fft.ifft_int(&b));
+            let res = fft.fft_torus(&a_f.hadamard(&b_f));
+            assert!(
+                res.iter()
+                    .zip(expect.iter())
+                    .all(|(r, &e)| close(*r, Torus32::from_bits(e))),
+                "N={}",
+                N
+            );
+        }
+        check::<16>(PortableFft::new(16));
+        check::<16>(PortableFft::scalar(16));
+        check::<32>(PortableFft::new(32));
+        check::<32>(PortableFft::scalar(32));
+        check::<64>(PortableFft::scalar(64));
+    }
+
+    #[bench]
+    fn bench_portable_fft_scalar(b: &mut test::Bencher) {
+        bench_poly_mul(b, PortableFft::scalar(1024));
+    }
+    #[bench]
+    fn bench_portable_fft_vectorized(b: &mut test::Bencher) {
+        bench_poly_mul(b, PortableFft::new(1024));
+    }
+    fn bench_poly_mul(b: &mut test::Bencher, mut fft: PortableFft) {
+        const N: usize = 1024;
+        let mut unif = ModDistribution::uniform();
+        let x: [Torus32; N] = unif.gen_n();
+        let y: [Torus32; N] = unif.gen_n();
+        b.iter(|| fft.poly_mul(&x, &y));
+    }
+
+    #[cfg(spqlios_native)]
+    #[test]
+    fn portable_fft_matches_spqlios() {
+        const N: usize = 1024;
+        let mut fft = PortableFft::scalar(N);
+        let mut spq = crate::spqlios::Spqlios::new(N);
+        let mut unif = ModDistribution::uniform();
+        let x: [Torus32; N] = unif.gen_n();
+
+        let (lhs, rhs) = (fft.ifft_torus(&x), spq.ifft_torus(&x));
+        for (l, r) in lhs.coefs().iter().zip(rhs.coefs().iter()) {
+            assert!((l - r).abs() <= 1e-6 * r.abs().max(1.0), "{} vs {}", l, r);
+        }
+        assert_eq!(fft.fft_torus(&rhs), spq.fft_torus(&rhs));
+    }
+}
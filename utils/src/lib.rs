@@ -3,9 +3,12 @@
 #![feature(test)]
 extern crate test;
 
+pub mod dyn_polynomial;
+pub mod fft;
 pub mod macros;
 pub mod math;
 pub mod mem;
+pub mod ntt;
 pub mod spqlios;
 pub mod traits;
 
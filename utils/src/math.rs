@@ -1,17 +1,25 @@
+pub use crate::dyn_polynomial::{DynPolynomial, LengthMismatch};
 use crate::mem;
+use crate::ntt::{self, NttCoef};
+pub use crate::spqlios::FourierPolynomial;
 use crate::spqlios::FrrSeries;
 use crate::spqlios::Spqlios;
+use crate::traits::AsLogic;
 use num::{
     traits::{MulAdd, WrappingAdd, WrappingSub},
-    Complex, Float, Integer, One, ToPrimitive, Unsigned, Zero,
+    Complex, Float, Integer, One, Unsigned, Zero,
 };
-use rand::{prelude::ThreadRng, Rng};
+use rand::{CryptoRng, Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
 use rand_distr::{Distribution, Normal, Uniform};
-use std::{cell::RefCell, ops::Index};
+use std::rc::Rc;
+use std::{
+    cell::{Cell, RefCell},
+    ops::Index,
+};
 use std::{
     collections::HashMap,
     fmt::Display,
-    mem::MaybeUninit,
     ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign},
 };
 
@@ -38,7 +46,7 @@ P(X) = SUM_{i=0}^{N-1} 0\[i\]X^i
 を表す。
 X^N+1を法とした剰余環上の値
  */
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct Polynomial<T, const N: usize>([T; N]);
 impl<T, const N: usize> Polynomial<T, N> {
     pub fn new(coeffis: [T; N]) -> Self {
@@ -50,13 +58,95 @@ impl<T, const N: usize> Polynomial<T, N> {
     pub fn coefs_mut(&mut self) -> &mut [T; N] {
         &mut self.0
     }
-    pub fn map<O, F: Fn(&T) -> O>(&self, f: F) -> Polynomial<O, N> {
-        let mut arr: [MaybeUninit<O>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+    pub fn into_coefs(self) -> [T; N] {
         self.0
-            .iter()
-            .zip(arr.iter_mut())
-            .for_each(|(t, x)| *x = MaybeUninit::new(f(t)));
-        pol!(crate::mem::transmute::<_, [O; N]>(arr))
+    }
+    /// i次の係数をf(i)とする
+    /// ```
+    /// use utils::{pol,math::Polynomial};
+    /// assert_eq!(Polynomial::<_, 4>::from_fn(|i| i * i), pol!([0, 1, 4, 9]));
+    /// ```
+    pub fn from_fn<F: FnMut(usize) -> T>(f: F) -> Self {
+        pol!(mem::array_create_enumerate(f))
+    }
+    pub fn map<O, F: Fn(&T) -> O>(&self, f: F) -> Polynomial<O, N> {
+        Polynomial::from_fn(|i| f(&self.0[i]))
+    }
+    /// 係数ごとにfを適用する。最初に失敗したところで止めて、そのエラーを返す
+    pub fn try_map<O, E, F: FnMut(&T) -> Result<O, E>>(
+        &self,
+        mut f: F,
+    ) -> Result<Polynomial<O, N>, E> {
+        let mut err = None;
+        let res: Polynomial<Option<O>, N> = Polynomial::from_fn(|i| match err {
+            Some(_) => None,
+            None => f(&self.0[i]).map_err(|e| err = Some(e)).ok(),
+        });
+        match err {
+            Some(e) => Err(e),
+            None => Ok(pol!(res.0.map(Option::unwrap))),
+        }
+    }
+}
+/// Displayで表示する項の数。`{:#}`なら全て表示する
+pub const DISPLAY_TERMS: usize = 8;
+/// Debugで全ての係数を表示する最大のN。`{:#?}`なら全て表示する
+pub const DEBUG_FULL_LEN: usize = 16;
+/// 0でない項を次数の低い順に`1 + 0.5x + 3x^2`のように表示する。
+/// 精度(`{:.3}`)は係数に使う
+impl<T: Display + Zero, const N: usize> Display for Polynomial<T, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut terms = self.0.iter().enumerate().filter(|(_, c)| !c.is_zero());
+        let limit = if f.alternate() { N } else { DISPLAY_TERMS };
+        let mut shown = 0;
+        for (i, c) in terms.by_ref().take(limit) {
+            if shown > 0 {
+                write!(f, " + ")?;
+            }
+            match f.precision() {
+                Some(p) => write!(f, "{:.*}", p, c)?,
+                None => write!(f, "{}", c)?,
+            }
+            match i {
+                0 => {}
+                1 => write!(f, "x")?,
+                _ => write!(f, "x^{}", i)?,
+            }
+            shown += 1;
+        }
+        let rest = terms.count();
+        if shown == 0 {
+            write!(f, "0")
+        } else if rest > 0 {
+            write!(f, " + ... ({} more terms)", rest)
+        } else {
+            Ok(())
+        }
+    }
+}
+/// Nが大きいときは先頭と末尾の係数だけを表示する
+impl<T: std::fmt::Debug, const N: usize> std::fmt::Debug for Polynomial<T, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Compact<'a, T>(&'a [T]);
+        impl<T: std::fmt::Debug> std::fmt::Debug for Compact<'_, T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                const SIDE: usize = DEBUG_FULL_LEN / 4;
+                let len = self.0.len();
+                let omitted = format!("...({} more)", len - 2 * SIDE);
+                f.debug_list()
+                    .entries(&self.0[..SIDE])
+                    .entry(&format_args!("{}", omitted))
+                    .entries(&self.0[len - SIDE..])
+                    .finish()
+            }
+        }
+        if N <= DEBUG_FULL_LEN || f.alternate() {
+            f.debug_tuple("Polynomial").field(&self.0).finish()
+        } else {
+            f.debug_tuple("Polynomial")
+                .field(&Compact(&self.0))
+                .finish()
+        }
     }
 }
 impl<T, const N: usize> Index<usize> for Polynomial<T, N> {
@@ -84,7 +174,8 @@ impl<T: Neg<Output = T> + Copy, const N: usize> Polynomial<T, N> {
     /// ```
     pub fn rotate(&self, n: i32) -> Self {
         let n = n.mod_floor(&(2 * N as i32)) as usize;
-        let mut arr: [MaybeUninit<T>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        // Copyなので、全体を上書きする前提でそのまま複製しておく
+        let mut arr = *self.coefs();
         if n <= N {
             let n: usize = n as usize;
             let (arr_m, arr_p) = arr.split_at_mut(n);
@@ -92,11 +183,8 @@ impl<T: Neg<Output = T> + Copy, const N: usize> Polynomial<T, N> {
             arr_m
                 .iter_mut()
                 .zip(coef_m.iter().map(|&t| -t))
-                .for_each(|(x, c)| *x = MaybeUninit::new(c));
-            arr_p
-                .iter_mut()
-                .zip(coef_p.iter())
-                .for_each(|(x, &c)| *x = MaybeUninit::new(c));
+                .for_each(|(x, c)| *x = c);
+            arr_p.copy_from_slice(coef_p);
         } else {
             let n: usize = n as usize - N;
             let (arr_p, arr_m) = arr.split_at_mut(n);
@@ -104,13 +192,10 @@ impl<T: Neg<Output = T> + Copy, const N: usize> Polynomial<T, N> {
             arr_m
                 .iter_mut()
                 .zip(coef_m.iter().map(|&t| -t))
-                .for_each(|(x, c)| *x = MaybeUninit::new(c));
-            arr_p
-                .iter_mut()
-                .zip(coef_p.iter())
-                .for_each(|(x, &c)| *x = MaybeUninit::new(c));
+                .for_each(|(x, c)| *x = c);
+            arr_p.copy_from_slice(coef_p);
         }
-        pol!(mem::transmute(arr))
+        pol!(arr)
         /*
         let n = n.mod_floor(&(2 * N as i32)) as usize;
         if n <= N {
@@ -130,6 +215,115 @@ impl<T: Neg<Output = T> + Copy, const N: usize> Polynomial<T, N> {
         }
         */
     }
+    /// P(X) -> P(X^k)。X^{2N} = 1 なので、X^i は X^{ik mod 2N} に移り、N以上なら符号が反転する
+    /// # Panic
+    /// - kが偶数(X^N+1を法とする環の自己同型にならない)
+    /// # Example
+    /// ```
+    /// use utils::{pol,math::Polynomial};
+    /// assert_eq!(pol!([1,2,3,4]).automorphism(3),pol!([1,4,-3,2]));
+    /// assert_eq!(pol!([1,2,3,4]).automorphism(7),pol!([1,-4,-3,-2]));
+    /// assert_eq!(pol!([1,2,3,4]).automorphism(1),pol!([1,2,3,4]));
+    /// ```
+    pub fn automorphism(&self, k: usize) -> Self {
+        assert!(k % 2 == 1, "automorphism: k must be odd, k={}", k);
+        let k = k % (2 * N);
+        let mut arr = *self.coefs();
+        for (i, &c) in self.coefs().iter().enumerate() {
+            // kは奇数なので、iからjへの対応は全単射
+            let j = i * k % (2 * N);
+            if j < N {
+                arr[j] = c;
+            } else {
+                arr[j - N] = -c;
+            }
+        }
+        pol!(arr)
+    }
+}
+impl<T: Copy, const N: usize> Polynomial<T, N> {
+    /// P(x)をHorner法で求める。係数の環で計算するので、X^N+1の剰余はとらない
+    /// ```
+    /// use utils::{pol,math::Polynomial};
+    /// assert_eq!(pol!([1, 2, 3]).eval(2), 1 + 2 * 2 + 3 * 4);
+    /// ```
+    pub fn eval<X: Copy>(&self, x: X) -> T
+    where
+        T: Mul<X, Output = T> + Add<Output = T> + Zero,
+    {
+        self.0.iter().rev().fold(T::zero(), |acc, &c| acc * x + c)
+    }
+}
+impl<T: Centered, const N: usize> Polynomial<T, N> {
+    /// 複素数zでのP(z)。係数は`Centered`で実数にする
+    pub fn eval_complex(&self, z: Complex<f64>) -> Complex<f64> {
+        self.0
+            .iter()
+            .rev()
+            .fold(Complex::new(0.0, 0.0), |acc, c| acc * z + c.centered())
+    }
+    /// X^N+1の根 ζ^{2k+1} (ζ = e^{iπ/N}) でのP。k < N
+    ///
+    /// `to_fourier`のj番目(実部j、虚部j+N/2)は、jをlog2(N)ビットで反転したkでの値
+    pub fn eval_at_root(&self, k: usize) -> Complex<f64> {
+        let theta = std::f64::consts::PI * (2 * k + 1) as f64 / N as f64;
+        self.eval_complex(Complex::from_polar(1.0, theta))
+    }
+}
+/// 係数を実数として見た値。ノルムや平均に使う
+pub trait Centered {
+    fn centered(&self) -> f64;
+}
+macro_rules! impl_centered {
+    ($($t:ty),*) => {
+        $(
+            impl Centered for $t {
+                fn centered(&self) -> f64 {
+                    *self as f64
+                }
+            }
+        )*
+    };
+}
+impl_centered!(i8, i16, i32, i64, f32, f64);
+impl Centered for Binary {
+    fn centered(&self) -> f64 {
+        *self as u32 as f64
+    }
+}
+/// ノルムと統計量。トーラスは[-1/2,1/2)の代表元で考える
+impl<T: Centered, const N: usize> Polynomial<T, N> {
+    /// max |c_i|
+    pub fn linf_norm(&self) -> f64 {
+        self.0
+            .iter()
+            .map(|c| c.centered().abs())
+            .fold(0.0, f64::max)
+    }
+    /// sqrt(Σ c_i^2)
+    pub fn l2_norm(&self) -> f64 {
+        self.0
+            .iter()
+            .map(|c| c.centered().powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+    /// 係数の平均
+    pub fn mean(&self) -> f64 {
+        self.0.iter().map(Centered::centered).sum::<f64>() / N as f64
+    }
+    /// 係数の(標本ではなく母集団の)分散
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.0
+            .iter()
+            .map(|c| (c.centered() - mean).powi(2))
+            .sum::<f64>()
+            / N as f64
+    }
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
 }
 impl<T: AddAssign + Copy, const N: usize> Polynomial<T, N> {
     pub fn add_constant(&mut self, rhs: T) {
@@ -240,19 +434,15 @@ impl<S: Copy, T: Sub<Output = T> + Copy + Zero + MulAdd<S, Output = T>, const N:
 {
     type Output = Self;
     fn cross(&self, rhs: &Polynomial<S, N>) -> Self::Output {
-        let mut arr: [MaybeUninit<T>; N] = unsafe { MaybeUninit::uninit().assume_init() };
-        for (sum, arr_i) in arr.iter_mut().enumerate() {
+        Polynomial::from_fn(|sum| {
             // p(x)*q(x) = \sum_{s=0}^{2*(n-1)} \sum_{i=max(0,sum-(n-1))^{min(sum,n-1)} p_i * q_{sum-i} mod X^N+1
             if sum < N - 1 {
-                *arr_i = MaybeUninit::new(
-                    convolution(self.coefs(), rhs.coefs(), sum)
-                        - convolution(self.coefs(), rhs.coefs(), N + sum),
-                );
+                convolution(self.coefs(), rhs.coefs(), sum)
+                    - convolution(self.coefs(), rhs.coefs(), N + sum)
             } else {
-                *arr_i = MaybeUninit::new(convolution(self.coefs(), rhs.coefs(), sum));
+                convolution(self.coefs(), rhs.coefs(), sum)
             }
-        }
-        Polynomial(mem::transmute::<_, [T; N]>(arr))
+        })
     }
 }
 impl<const N: usize> From<&Polynomial<i32, N>> for FrrSeries<N> {
@@ -302,62 +492,248 @@ impl<const N: usize> Polynomial<Decimal<u32>, N> {
         bits: u32,
         decomp_mask: u32,
     ) -> [Polynomial<i32, N>; L] {
-        let mut res: [[MaybeUninit<i32>; N]; L] = unsafe { MaybeUninit::uninit().assume_init() };
-        let masked_coefs: [Decimal<u32>; N] = unsafe {
-            mem::array_create(
-                self.coefs()
-                    .iter()
-                    .map(|d| Decimal::from_bits(d.inner().wrapping_add(decomp_mask) ^ decomp_mask)),
-            )
-        };
+        let mut res = mem::array_init(|_| pol!([0; N]));
+        self.decomposition_i32_to(bits, decomp_mask, &mut res);
+        res
+    }
+    /// decomposition_i32_の結果をresに書き込む
+    pub fn decomposition_i32_to<const L: usize>(
+        &self,
+        bits: u32,
+        decomp_mask: u32,
+        res: &mut [Polynomial<i32, N>; L],
+    ) {
         let mask: u32 = (1 << bits) - 1;
         for (i, res_i) in res.iter_mut().enumerate() {
-            for (coef_i, res_i_j) in masked_coefs.iter().zip(res_i.iter_mut()) {
-                let u = (coef_i.inner() >> (u32::BITS - bits * ((i + 1) as u32))) & mask;
+            for (coef, res_i_j) in self.coefs().iter().zip(res_i.0.iter_mut()) {
+                let u = coef.inner().wrapping_add(decomp_mask) ^ decomp_mask;
+                let u = (u >> (u32::BITS - bits * ((i + 1) as u32))) & mask;
                 // uはbits桁の符号付き表現になっている。bits -> 32へ符号拡張する
-                *res_i_j = MaybeUninit::new(
-                    (u & (1 << (bits - 1)))
-                        .wrapping_mul(0xfffffffe_u32)
-                        .wrapping_add(u) as i32,
-                );
+                *res_i_j = (u & (1 << (bits - 1)))
+                    .wrapping_mul(0xfffffffe_u32)
+                    .wrapping_add(u) as i32;
             }
         }
-        mem::transmute(res)
     }
     pub fn decomposition_i32<const L: usize>(&self, bits: u32) -> [Polynomial<i32, N>; L] {
-        let res_: [[i32; L]; N] =
-            unsafe { mem::array_create(self.coefs().iter().map(|d| d.decomposition_i32(bits))) };
+        let res_: [[i32; L]; N] = mem::array_init(|j| self.coef_(j).decomposition_i32(bits));
 
         mem::array_create_enumerate(|i| pol!(mem::array_create_enumerate(|j| res_[j][i])))
     }
 }
 impl<T, const N: usize> Polynomial<T, N> {
-    /// # Panic
-    /// - 'self.len() % 2 > 0'
+    /// 周波数領域に変換する。Nは`is_fft_size`であること
+    pub fn to_fourier<'a>(&'a self) -> FrrSeries<N>
+    where
+        FrrSeries<N>: From<&'a Self>,
+    {
+        FrrSeries::from(self)
+    }
+    /// to_fourierの逆変換
+    pub fn from_fourier(s: &FrrSeries<N>) -> Self
+    where
+        Self: for<'a> From<&'a FrrSeries<N>>,
+    {
+        Self::from(s)
+    }
+}
+impl<T, const N: usize> Polynomial<T, N> {
+    /// FFTで掛ける。Nが`is_fft_size`でなければcross(筆算)で掛ける
     pub fn fft_cross<'a, S>(&'a self, rhs: &'a Polynomial<S, N>) -> Self
     where
         FrrSeries<N>: From<&'a Polynomial<T, N>> + From<&'a Polynomial<S, N>>,
-        Polynomial<T, N>: From<FrrSeries<N>>,
+        Polynomial<T, N>: From<FrrSeries<N>> + Cross<Polynomial<S, N>, Output = Self>,
     {
+        if !is_fft_size(N) {
+            return self.cross(rhs);
+        }
         let lhs_f = FrrSeries::<N>::from(self);
         let rhs_f = FrrSeries::<N>::from(rhs);
         // 要素積
         let res_f = lhs_f.hadamard(&rhs_f);
         res_f.into()
     }
+    /// selfを1回だけ変換して、rhsのそれぞれと掛ける。結果はrhsの順。
+    /// k個と掛けるとき、FFTはfft_crossをk回呼ぶ3k回に対して2k+1回
+    pub fn fft_cross_many<'a, S: 'a>(
+        &'a self,
+        rhs: impl IntoIterator<Item = &'a Polynomial<S, N>>,
+    ) -> Vec<Self>
+    where
+        FrrSeries<N>: From<&'a Polynomial<T, N>> + From<&'a Polynomial<S, N>>,
+        Polynomial<T, N>: From<FrrSeries<N>> + Cross<Polynomial<S, N>, Output = Self>,
+    {
+        if !is_fft_size(N) {
+            return rhs.into_iter().map(|r| self.cross(r)).collect();
+        }
+        let lhs_f = FrrSeries::<N>::from(self);
+        rhs.into_iter()
+            .map(|r| lhs_f.hadamard(&FrrSeries::<N>::from(r)).into())
+            .collect()
+    }
+}
+/// これより短い多項式は、karatsuba_crossでも筆算で掛ける
+pub const KARATSUBA_THRESHOLD: usize = 32;
+impl<T, const N: usize> Polynomial<T, N> {
+    /// X^N+1を法とした多項式乗算をKaratsuba法で行う。O(N^1.58)
+    /// Nが2の冪でなくてもよい。Nが`KARATSUBA_THRESHOLD`より小さければcrossと同じく筆算
+    pub fn karatsuba_cross<S>(&self, rhs: &Polynomial<S, N>) -> Self
+    where
+        T: Add<Output = T> + Sub<Output = T> + MulAdd<S, Output = T> + Zero + Copy,
+        S: Add<Output = S> + Zero + Copy,
+    {
+        let mut prod = vec![T::zero(); 2 * N];
+        karatsuba(self.coefs(), rhs.coefs(), &mut prod);
+        // X^N = -1 で折り返す
+        pol!(mem::array_create_enumerate(|i| prod[i] - prod[i + N]))
+    }
+}
+/// a*bをoutに足す。a.len() == b.len() かつ out.len() >= 2 * a.len()
+fn karatsuba<T, S>(a: &[T], b: &[S], out: &mut [T])
+where
+    T: Add<Output = T> + Sub<Output = T> + MulAdd<S, Output = T> + Zero + Copy,
+    S: Add<Output = S> + Zero + Copy,
+{
+    let n = a.len();
+    if n < KARATSUBA_THRESHOLD {
+        for (i, &a_i) in a.iter().enumerate() {
+            for (o, &b_j) in out[i..].iter_mut().zip(b.iter()) {
+                *o = a_i.mul_add(b_j, *o);
+            }
+        }
+        return;
+    }
+    // a = a0 + a1 X^m, b = b0 + b1 X^m
+    let m = n.div_ceil(2);
+    let (a0, a1) = a.split_at(m);
+    let (b0, b1) = b.split_at(m);
+    let mut z0 = vec![T::zero(); 2 * m];
+    let mut z1 = vec![T::zero(); 2 * m];
+    let mut z2 = vec![T::zero(); 2 * m];
+    karatsuba(a0, b0, &mut z0);
+    karatsuba(a1, b1, &mut z2);
+    let a01: Vec<T> = (0..m)
+        .map(|i| a1.get(i).map_or(a0[i], |&x| a0[i] + x))
+        .collect();
+    let b01: Vec<S> = (0..m)
+        .map(|i| b1.get(i).map_or(b0[i], |&x| b0[i] + x))
+        .collect();
+    karatsuba(&a01, &b01, &mut z1);
+
+    // a*b = z0 + (z1 - z0 - z2) X^m + z2 X^2m
+    for (i, &z) in z0.iter().enumerate() {
+        out[i] = out[i] + z;
+        out[i + m] = out[i + m] - z;
+    }
+    for (i, &z) in z1.iter().enumerate() {
+        out[i + m] = out[i + m] + z;
+    }
+    for (i, &z) in z2[..2 * (n - m)].iter().enumerate() {
+        out[i + m] = out[i + m] - z;
+        out[i + 2 * m] = out[i + 2 * m] + z;
+    }
+}
+impl<const N: usize> Polynomial<Torus32, N> {
+    /// fft_crossと同じ積を、NTTで誤差なく求める。遅いが係数が大きくても正確
+    /// # Panic
+    /// - Nが2の冪でない
+    pub fn ntt_cross<S: NttCoef>(&self, rhs: &Polynomial<S, N>) -> Self {
+        pol!(ntt::with_ntt(N, |ntt| ntt.poly_mul(self.coefs(), rhs.coefs())))
+    }
+}
+impl<const N: usize> Polynomial<Torus64, N> {
+    /// rhsとの積をFFTで求める。selfの係数を16bitずつ4つに分けて掛けるので、FFTは9回。
+    /// rhsの係数の絶対値が2^16以下(分解した係数など)なら、結果は正確
+    pub fn fft_cross_i32(&self, rhs: &Polynomial<i32, N>) -> Self {
+        if !is_fft_size(N) {
+            return self.cross(rhs);
+        }
+        let rhs_f = FrrSeries::<N>::from(rhs);
+        let mut res = [0_u64; N];
+        for k in 0..4 {
+            let limb: Polynomial<f64, N> = self.map(|t| ((t.inner() >> (16 * k)) & 0xffff) as f64);
+            let prod: Polynomial<f64, N> = FrrSeries::from(&limb).hadamard(&rhs_f).into();
+            res.iter_mut().zip(prod.coefs().iter()).for_each(|(r, &p)| {
+                *r = r.wrapping_add((p.round() as i64 as u64).wrapping_shl(16 * k))
+            });
+        }
+        pol!(res.map(Torus64::from_bits))
+    }
+}
+impl<const N: usize> Polynomial<Torus32, N> {
+    /// self += sの逆変換。Polynomialを作らずに足す
+    pub fn add_assign_fft(&mut self, s: &FrrSeries<N>) {
+        let mut buf = [Torus32::zero(); N];
+        FFT_MAP.with(|m| m.borrow_mut().get_fft_proc(N).fft_torus_to(s, &mut buf));
+        self.coefs_mut()
+            .iter_mut()
+            .zip(buf.iter())
+            .for_each(|(x, &y)| *x += y);
+    }
+    /// self += lhs * rhs をFFTで
+    pub fn fft_mul_add_assign<'a, S>(&mut self, lhs: &'a Self, rhs: &'a Polynomial<S, N>)
+    where
+        FrrSeries<N>: From<&'a Self> + From<&'a Polynomial<S, N>>,
+        Self: Cross<Polynomial<S, N>, Output = Self>,
+    {
+        self.fft_mul_add_assign_many([(lhs, rhs)])
+    }
+    /// self += sum(lhs * rhs)。積の和は周波数領域でとるので、逆変換は1回
+    pub fn fft_mul_add_assign_many<'a, S: 'a>(
+        &mut self,
+        terms: impl IntoIterator<Item = (&'a Self, &'a Polynomial<S, N>)>,
+    ) where
+        FrrSeries<N>: From<&'a Self> + From<&'a Polynomial<S, N>>,
+        Self: Cross<Polynomial<S, N>, Output = Self>,
+    {
+        if !is_fft_size(N) {
+            terms
+                .into_iter()
+                .for_each(|(lhs, rhs)| *self += lhs.cross(rhs));
+            return;
+        }
+        let mut acc = FrrSeries::<N>::zero();
+        let mut any = false;
+        for (lhs, rhs) in terms {
+            acc.hadamard_add(&FrrSeries::from(lhs), &FrrSeries::from(rhs));
+            any = true;
+        }
+        if any {
+            self.add_assign_fft(&acc);
+        }
+    }
 }
+// スレッドごとに持つので、並列に評価してもロックで待たない
 thread_local! {
     pub static FFT_MAP: RefCell<FftMap> = Default::default();
 }
+// 変換の回数。Spqliosの変換のたびに足す
+thread_local! {
+    static FFT_COUNT: Cell<u64> = const { Cell::new(0) };
+}
 #[derive(Default)]
-pub struct FftMap(HashMap<usize, Spqlios>);
+pub struct FftMap {
+    procs: HashMap<usize, Spqlios>,
+}
 impl FftMap {
     pub fn get_fft_proc(&mut self, n: usize) -> &mut Spqlios {
-        self.0
+        self.procs
             .entry(n)
             .or_insert_with(|| *Box::new(Spqlios::new(n)))
     }
 }
+/// FFT(spqlios)で扱える多項式の長さか。16以上の2の冪
+pub fn is_fft_size(n: usize) -> bool {
+    n >= 16 && n.is_power_of_two()
+}
+/// このスレッドで実行したFFT,IFFTの回数
+pub fn fft_count() -> u64 {
+    FFT_COUNT.with(|c| c.get())
+}
+/// Spqliosが変換をtimes回したときに呼ぶ
+pub(crate) fn count_fft(times: u64) {
+    FFT_COUNT.with(|c| c.set(c.get() + times));
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Binary {
@@ -373,6 +749,14 @@ impl<T: Zero + PartialEq> From<T> for Binary {
         }
     }
 }
+impl Binary {
+    /// 下位1bitから作る。値による分岐をしない
+    #[inline]
+    pub fn from_bit(bit: u32) -> Self {
+        const TABLE: [Binary; 2] = [Binary::Zero, Binary::One];
+        TABLE[(bit & 1) as usize]
+    }
+}
 macro_rules! binary_into {
     ($t:ty) => {
         impl Into<$t> for Binary {
@@ -390,11 +774,130 @@ binary_into!(f32);
 binary_into!(i32);
 binary_into!(u32);
 
+impl AsLogic for Binary {
+    fn logic_true() -> Self {
+        Binary::One
+    }
+    fn logic_false() -> Self {
+        Binary::Zero
+    }
+    fn as_const(&self) -> Option<bool> {
+        Some(*self == Binary::One)
+    }
+}
 impl Display for Binary {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         (*self as u32).fmt(f)
     }
 }
+#[cfg(feature = "serde")]
+impl serde::Serialize for Binary {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Binary {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, Unexpected};
+        match u8::deserialize(deserializer)? {
+            0 => Ok(Binary::Zero),
+            1 => Ok(Binary::One),
+            b => Err(D::Error::invalid_value(
+                Unexpected::Unsigned(b as u64),
+                &"0 or 1",
+            )),
+        }
+    }
+}
+
+/// 係数を順に並べた長さNのtupleとして読み書きする
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize, const N: usize> serde::Serialize for Polynomial<T, N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+        let mut tup = serializer.serialize_tuple(N)?;
+        for t in self.0.iter() {
+            tup.serialize_element(t)?;
+        }
+        tup.end()
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>, const N: usize> serde::Deserialize<'de> for Polynomial<T, N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, SeqAccess, Visitor};
+        struct PolynomialVisitor<T, const N: usize>(std::marker::PhantomData<T>);
+        impl<'de, T: serde::Deserialize<'de>, const N: usize> Visitor<'de> for PolynomialVisitor<T, N> {
+            type Value = Polynomial<T, N>;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{} coefficients", N)
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let coefs = mem::array_try_init(|i| {
+                    seq.next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(i, &self))
+                })?;
+                // 余分な係数があれば失敗する
+                if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                    return Err(A::Error::invalid_length(N + 1, &self));
+                }
+                Ok(pol!(coefs))
+            }
+        }
+        deserializer.deserialize_tuple(N, PolynomialVisitor(std::marker::PhantomData))
+    }
+}
+/// 中の整数として読み書きする
+#[cfg(feature = "serde")]
+impl<U: Unsigned + serde::Serialize> serde::Serialize for Decimal<U> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de, U: Unsigned + serde::Deserialize<'de>> serde::Deserialize<'de> for Decimal<U> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        U::deserialize(deserializer).map(Decimal)
+    }
+}
+
+thread_local! {
+    static SECURE_RNG: Rc<RefCell<ChaCha20Rng>> =
+        Rc::new(RefCell::new(ChaCha20Rng::from_entropy()));
+}
+/// スレッドごとに1つ持つChaCha20の乱数生成器。鍵や誤差はこれから作る
+///
+/// ThreadRngと同じく、最初に使ったときにOSの乱数でseedを決める
+#[derive(Clone, Debug)]
+pub struct SecureRng(Rc<RefCell<ChaCha20Rng>>);
+pub fn secure_rng() -> SecureRng {
+    SECURE_RNG.with(|rng| SecureRng(rng.clone()))
+}
+/// このスレッドのSecureRngをseedから作り直す
+///
+/// 以降の鍵生成・暗号化はseedだけで決まるので、テストの再現に使う。
+/// 他のスレッドには影響しない。秘密鍵を作る用途では使わないこと
+/// (テストか`insecure-seeded-rng` featureのときだけ使える)
+#[cfg(any(test, feature = "insecure-seeded-rng"))]
+pub fn seed_secure_rng(seed: u64) {
+    SECURE_RNG.with(|rng| *rng.borrow_mut() = ChaCha20Rng::seed_from_u64(seed));
+}
+impl RngCore for SecureRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.borrow_mut().next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.0.borrow_mut().next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.borrow_mut().fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.borrow_mut().try_fill_bytes(dest)
+    }
+}
+impl CryptoRng for SecureRng {}
 
 pub trait Random<T> {
     fn gen(&mut self) -> T;
@@ -404,7 +907,7 @@ pub trait Random<T> {
     }
 }
 #[derive(Debug)]
-pub struct ModDistribution<X: Distribution<f32>, R: Rng> {
+pub struct ModDistribution<X, R: Rng> {
     distr: X,
     rng: R,
 }
@@ -414,19 +917,38 @@ impl<X: Distribution<f32>, R: Rng> Random<Decimal<u32>> for ModDistribution<X, R
         torus!(r)
     }
 }
-impl ModDistribution<Normal<f32>, ThreadRng> {
+/// トーラス上の一様分布。f32を経由せず、乱数の32bitをそのまま値のbitにする
+#[derive(Debug, Clone, Copy)]
+pub struct UniformTorus;
+impl<R: Rng> Random<Decimal<u32>> for ModDistribution<UniformTorus, R> {
+    fn gen(&mut self) -> Decimal<u32> {
+        Decimal::from_bits(self.rng.next_u32())
+    }
+}
+impl ModDistribution<Normal<f32>, SecureRng> {
     pub fn gaussian(std_dev: f32) -> Self {
+        Self::gaussian_with(std_dev, secure_rng())
+    }
+}
+impl<R: Rng> ModDistribution<Normal<f32>, R> {
+    /// 呼び出し側の乱数生成器を使う
+    pub fn gaussian_with(std_dev: f32, rng: R) -> Self {
         ModDistribution {
             distr: Normal::new(f32::neg_zero(), std_dev).unwrap(),
-            rng: rand::thread_rng(),
+            rng,
         }
     }
 }
-impl ModDistribution<Uniform<f32>, ThreadRng> {
+impl ModDistribution<UniformTorus, SecureRng> {
     pub fn uniform() -> Self {
+        Self::uniform_with(secure_rng())
+    }
+}
+impl<R: Rng> ModDistribution<UniformTorus, R> {
+    pub fn uniform_with(rng: R) -> Self {
         ModDistribution {
-            distr: Uniform::new(0.0, 1.0),
-            rng: rand::thread_rng(),
+            distr: UniformTorus,
+            rng,
         }
     }
 }
@@ -442,19 +964,29 @@ impl<X: Distribution<f64>, R: Rng> Random<Complex<f64>> for ComplexDistribution<
         Complex::new(r, i)
     }
 }
-impl ComplexDistribution<Normal<f64>, ThreadRng> {
+impl ComplexDistribution<Normal<f64>, SecureRng> {
     pub fn gaussian(std_dev: f64) -> Self {
+        Self::gaussian_with(std_dev, secure_rng())
+    }
+}
+impl<R: Rng> ComplexDistribution<Normal<f64>, R> {
+    pub fn gaussian_with(std_dev: f64, rng: R) -> Self {
         ComplexDistribution {
             distr: Normal::new(f64::neg_zero(), std_dev).unwrap(),
-            rng: rand::thread_rng(),
+            rng,
         }
     }
 }
-impl ComplexDistribution<Uniform<f64>, ThreadRng> {
+impl ComplexDistribution<Uniform<f64>, SecureRng> {
     pub fn uniform() -> Self {
+        Self::uniform_with(secure_rng())
+    }
+}
+impl<R: Rng> ComplexDistribution<Uniform<f64>, R> {
+    pub fn uniform_with(rng: R) -> Self {
         ComplexDistribution {
             distr: Uniform::new(0.0, 1.0),
-            rng: rand::thread_rng(),
+            rng,
         }
     }
 }
@@ -468,12 +1000,17 @@ impl<X: Distribution<i32>, R: Rng> Random<Binary> for BinaryDistribution<X, R> {
         Binary::from(self.uniform.sample(&mut self.rng))
     }
 }
-impl BinaryDistribution<Uniform<i32>, ThreadRng> {
+impl BinaryDistribution<Uniform<i32>, SecureRng> {
     #[allow(dead_code)]
-    pub fn uniform() -> BinaryDistribution<Uniform<i32>, ThreadRng> {
+    pub fn uniform() -> BinaryDistribution<Uniform<i32>, SecureRng> {
+        Self::uniform_with(secure_rng())
+    }
+}
+impl<R: Rng> BinaryDistribution<Uniform<i32>, R> {
+    pub fn uniform_with(rng: R) -> Self {
         BinaryDistribution {
             uniform: Uniform::new(0, 2),
-            rng: rand::thread_rng(),
+            rng,
         }
     }
 }
@@ -536,174 +1073,233 @@ impl<U: Unsigned + Zero + WrappingAdd> Zero for Decimal<U> {
     }
 }
 // 以下 Torus
+pub type Torus16 = Decimal<u16>;
 pub type Torus32 = Decimal<u32>;
-impl Decimal<u32> {
-    #[allow(dead_code)]
-    pub const fn make_decomp_mask(l: u32, bits: u32) -> u32 {
-        let total = u32::BITS;
-        let mut u = 0_u32;
-        if (total - l * bits) != 0 {
-            u = u.wrapping_add(1 << (total - l * bits - 1));
-            let mut i = l;
-            while i >= 1 {
-                u += 1 << (total - i * bits - 1);
-                i -= 1;
+pub type Torus64 = Decimal<u64>;
+pub type Torus128 = Decimal<u128>;
+
+/// Decimal<$u>をトーラスとして使うための実装。
+/// - $decomp_u, $decomp_iは同じ幅の符号なし、符号付きの桁を返す分解の名前
+/// - $iは掛けられる符号付き整数
+///
+/// # 分解の丸め
+/// xをbg = 2^bits進でL桁に分解する。L*bits桁より下は最も近い値に丸める(ちょうど半分なら切り上げ)。
+/// つまり桁d_iは Σ d_i 2^{-i*bits} = round(x * 2^{L*bits}) / 2^{L*bits} を満たし、
+/// 元の値との差は 2^{-L*bits-1} 以下になる。
+/// - 符号なしの桁は[0,bg)。最上位の桁からの繰り上がりは捨てる
+/// - 符号付きの桁は[-bg/2,bg/2)。最上位の桁もトーラスとして[-bg/2,bg/2)に折り返す
+macro_rules! impl_torus {
+    ($u:ty, $decomp_u:ident, $s:ty, $decomp_i:ident; $($i:ty),*) => {
+        impl Decimal<$u> {
+            /// 符号付きの分解に使うマスク。下位の桁をずらす分と、丸めの分
+            pub const fn make_decomp_mask(l: u32, bits: u32) -> $u {
+                let total = <$u>::BITS;
+                let mut u: $u = 0;
+                // i = l の項が丸めの分になる。L*bits = BITSなら丸める桁がない
+                let mut i = if total == l * bits { l - 1 } else { l };
+                while i >= 1 {
+                    u |= 1 << (total - i * bits - 1);
+                    i -= 1;
+                }
+                u
             }
-        } else {
-            let mut i = l - 1;
-            while i >= 1 {
-                u += 1 << (total - i * bits - 1);
-                i -= 1;
+            /// bits < 32。decomp_maskは`make_decomp_mask(L, bits)`
+            pub fn decomposition_i32_<const L: usize>(self, bits: u32, decomp_mask: $u) -> [i32; L] {
+                const TOTAL: u32 = <$u>::BITS;
+                let u = self.inner().wrapping_add(decomp_mask) ^ decomp_mask;
+
+                let mask: $u = (1 << bits) - 1;
+                mem::array_create_enumerate(|i| {
+                    let u = ((u >> (TOTAL - bits * ((i + 1) as u32))) & mask) as i32;
+                    // uはbits桁の符号付き表現になっている。bits -> 32へ符号拡張する
+                    (u & (1 << (bits - 1))).wrapping_mul(-2).wrapping_add(u)
+                })
             }
-        }
-        u
-    }
-    pub fn decomposition_i32_<const L: usize>(self, bits: u32, decomp_mask: u32) -> [i32; L] {
-        const TOTAL: u32 = u32::BITS;
-        let u = self.inner().wrapping_add(decomp_mask) ^ decomp_mask;
-
-        let mask: u32 = (1 << bits) - 1;
-        let mut res: [MaybeUninit<i32>; L] = unsafe { MaybeUninit::uninit().assume_init() };
-        res.iter_mut().enumerate().for_each(|(i, res_i)| {
-            let u = (u >> (TOTAL - bits * ((i + 1) as u32))) & mask;
-            // uはbits桁の符号付き表現になっている。bits -> 32へ符号拡張する
-            *res_i = MaybeUninit::new(
-                (u & (1 << (bits - 1)))
-                    .wrapping_mul(0xfffffffe_u32)
-                    .wrapping_add(u) as i32,
-            );
-        });
-        mem::transmute::<_, [i32; L]>(res)
-    }
-    /// 2進表現から2^bits進表現に変換
-    /// - res\[i\] in [-bg/2,bg/2) where bg = 2^bits
-    /// - N=u32::BITSを2^bitsで表現したときの有効桁数
-    pub fn decomposition_i32<const L: usize>(self, bits: u32) -> [i32; L] {
-        let decomp_mask = {
-            // inlined make_decomp_mask(L,bits) const Value
-            const TOTAL: u32 = u32::BITS;
-            if (TOTAL - L as u32 * bits) != 0 {
-                // with round
-                (1..=L as u32).fold(0_u32, |s, i| s | 1 << (TOTAL - i * bits - 1))
-            } else {
-                (1..L as u32).fold(0_u32, |s, i| s | 1 << (TOTAL - i * bits - 1))
+            /// 2進表現から2^bits進表現に変換
+            /// - res\[i\] in [-bg/2,bg/2) where bg = 2^bits
+            /// - N=BITSを2^bitsで表現したときの有効桁数
+            /// - bits < BITS
+            pub fn $decomp_i<const L: usize>(self, bits: u32) -> [$s; L] {
+                debug_assert!((L as u32) * bits <= <$u>::BITS, "Wrong array size");
+                const TOTAL: u32 = <$u>::BITS;
+                let decomp_mask = Self::make_decomp_mask(L as u32, bits);
+                let u = self.inner().wrapping_add(decomp_mask) ^ decomp_mask;
+
+                let mask: $u = (1 << bits) - 1;
+                mem::array_create_enumerate(|i| {
+                    let u = ((u >> (TOTAL - bits * ((i + 1) as u32))) & mask) as $s;
+                    // uはbits桁の符号付き表現になっている。bits -> 全体へ符号拡張する
+                    (u & (1 << (bits - 1))).wrapping_mul(-2).wrapping_add(u)
+                })
             }
-        };
-        self.decomposition_i32_(bits, decomp_mask)
-    }
-
-    /// 2進表現から2^bits進表現に変換
-    /// - res\[i\] in [0,bg) where bg = 2^{bits}
-    /// - N=u32::BITSを2^bitsで表現したときの有効桁数
-    pub fn decomposition_u32<const L: usize>(self, bits: u32) -> [u32; L] {
-        debug_assert!((L as u32) * bits <= u32::BITS, "Wrong array size");
-        const TOTAL: u32 = u32::BITS;
-
-        let Decimal(u) = self;
-        // 丸める
-        let u = u.wrapping_add(if (TOTAL - (L as u32) * bits) != 0 {
-            1 << (TOTAL - (L as u32) * bits - 1)
-        } else {
-            0
-        });
+            /// 符号付きの桁から戻す。Σ digits\[i\] * bg^{-(i+1)}
+            pub fn recompose_i32(digits: &[i32], bits: u32) -> Self {
+                debug_assert!(digits.len() as u32 * bits <= <$u>::BITS, "Wrong array size");
+                let u = digits.iter().enumerate().fold(0 as $u, |s, (i, &d)| {
+                    let shift = <$u>::BITS - bits * (i as u32 + 1);
+                    s.wrapping_add((d as $u).wrapping_shl(shift))
+                });
+                Decimal(u)
+            }
+            /// `decomposition_i32`と、丸めで生じた誤差(元の値 - 戻した値)。
+            /// 誤差は2^-BITSを1とした符号付き整数で、絶対値は`decomposition_error_bound`以下
+            pub fn decomposition_i32_with_error<const L: usize>(self, bits: u32) -> ([i32; L], $s) {
+                let digits: [i32; L] = self.decomposition_i32(bits);
+                let err = self.inner().wrapping_sub(Self::recompose_i32(&digits, bits).inner());
+                (digits, err as $s)
+            }
+            /// l桁に分解したときの誤差の最大値。2^-BITSを1とする
+            pub const fn decomposition_error_bound(l: u32, bits: u32) -> $s {
+                let rest = <$u>::BITS - l * bits;
+                if rest == 0 {
+                    0
+                } else {
+                    1 << (rest - 1)
+                }
+            }
+            /// 2進表現から2^bits進表現に変換
+            /// - res\[i\] in [0,bg) where bg = 2^{bits}
+            /// - N=BITSを2^bitsで表現したときの有効桁数
+            pub fn $decomp_u<const L: usize>(self, bits: u32) -> [$u; L] {
+                debug_assert!((L as u32) * bits <= <$u>::BITS, "Wrong array size");
+                const TOTAL: u32 = <$u>::BITS;
+
+                let Decimal(u) = self;
+                // 丸める
+                let u = u.wrapping_add(if (TOTAL - (L as u32) * bits) != 0 {
+                    1 << (TOTAL - (L as u32) * bits - 1)
+                } else {
+                    0
+                });
 
-        let mask = (1 << bits) - 1;
-        // res={a_i}, a_i in [0,bg)
-        let u_res =
-            mem::array_create_enumerate(|i| (u >> (TOTAL - bits * ((i + 1) as u32))) & mask);
-        u_res
-    }
+                let mask = (1 << bits) - 1;
+                // res={a_i}, a_i in [0,bg)
+                mem::array_create_enumerate(|i| (u >> (TOTAL - bits * ((i + 1) as u32))) & mask)
+            }
 
-    pub fn is_in(&self, p: Self, acc: f32) -> bool {
-        let x: f32 = self.into();
-        let p: f32 = p.into();
-        (x - p).abs() < acc
-    }
-    /// ```
-    /// use std::convert::From;
-    /// use utils::math::Torus32;
-    /// assert!(Torus32::pow_two_minus(1).is_in(Torus32::from(0.5),1e-6));
-    /// assert!(Torus32::pow_two_minus(0).is_in(Torus32::from(1.0),1e-6));
-    /// assert!(Torus32::pow_two_minus(31).is_in(Torus32::from(0.5_f32.powi(31)),1e-6));
-    /// assert!(Torus32::pow_two_minus(32).is_in(Torus32::from(0.0),1e-6));
-    /// ```
-    pub fn pow_two_minus(n: u32) -> Self {
-        if n == 0 {
-            return Torus32::from_bits(0);
+            pub fn is_in(&self, p: Self, acc: f64) -> bool {
+                let x: f64 = self.into();
+                let p: f64 = p.into();
+                (x - p).abs() < acc
+            }
+            /// 2^-n。nがBITS以上なら最小の正の値
+            pub fn pow_two_minus(n: u32) -> Self {
+                if n == 0 {
+                    return Decimal(0);
+                }
+                let n = n.min(<$u>::BITS);
+                Decimal(1 << (<$u>::BITS - n))
+            }
         }
-        let n = n.min(32);
-        Torus32::from_bits((1 << (32 - n)))
-    }
-}
-impl Mul<u32> for Decimal<u32> {
-    type Output = Self;
-    fn mul(self, rhs: u32) -> Self::Output {
-        Decimal(self.0.wrapping_mul(rhs.to_u32().unwrap()))
-    }
-}
-impl Mul<i32> for Decimal<u32> {
-    type Output = Self;
-    fn mul(self, rhs: i32) -> Self::Output {
-        if rhs.is_negative() {
-            -(self * rhs.abs() as u32)
-        } else {
-            self * rhs as u32
+        /// [-1/2,1/2)の代表元
+        impl Centered for Decimal<$u> {
+            fn centered(&self) -> f64 {
+                self.0 as $s as f64 / 2f64.powi(<$u>::BITS as i32)
+            }
         }
-    }
-}
-impl Mul<Binary> for Decimal<u32> {
-    type Output = Self;
-    fn mul(self, rhs: Binary) -> Self::Output {
-        self * rhs as u32
-    }
-}
-impl<T> MulAdd<T> for Decimal<u32>
-where
-    Self: Mul<T, Output = Self>,
-{
-    type Output = Self;
-    fn mul_add(self, a: T, b: Self) -> Self::Output {
-        self * a + b
-    }
-}
-impl Into<f64> for Decimal<u32> {
-    fn into(self) -> f64 {
-        (&self).into()
-    }
-}
-impl Into<f64> for &Decimal<u32> {
-    fn into(self) -> f64 {
-        const X: f64 = 1.0 / (u32::MAX as f64);
-        (self.0 as f64) * X
-    }
-}
-impl Into<f32> for Decimal<u32> {
-    fn into(self) -> f32 {
-        (&self).into()
-    }
-}
-impl Into<f32> for &Decimal<u32> {
-    fn into(self) -> f32 {
-        const X: f32 = 1.0 / (u32::MAX as f32);
-        (self.0 as f32) * X
-    }
+        impl Mul<$u> for Decimal<$u> {
+            type Output = Self;
+            fn mul(self, rhs: $u) -> Self::Output {
+                Decimal(self.0.wrapping_mul(rhs))
+            }
+        }
+        $(
+            impl Mul<$i> for Decimal<$u> {
+                type Output = Self;
+                fn mul(self, rhs: $i) -> Self::Output {
+                    // 2^BITSの剰余では、符号を変えて掛けるのと同じ
+                    Decimal(self.0.wrapping_mul(rhs as $u))
+                }
+            }
+        )*
+        impl Mul<Binary> for Decimal<$u> {
+            type Output = Self;
+            fn mul(self, rhs: Binary) -> Self::Output {
+                self * rhs as $u
+            }
+        }
+        impl<T> MulAdd<T> for Decimal<$u>
+        where
+            Self: Mul<T, Output = Self>,
+        {
+            type Output = Self;
+            fn mul_add(self, a: T, b: Self) -> Self::Output {
+                self * a + b
+            }
+        }
+        impl From<Decimal<$u>> for f64 {
+            fn from(t: Decimal<$u>) -> f64 {
+                (&t).into()
+            }
+        }
+        impl From<&Decimal<$u>> for f64 {
+            fn from(t: &Decimal<$u>) -> f64 {
+                let x: f64 = 1.0 / (<$u>::MAX as f64);
+                (t.0 as f64) * x
+            }
+        }
+        impl From<Decimal<$u>> for f32 {
+            fn from(t: Decimal<$u>) -> f32 {
+                (&t).into()
+            }
+        }
+        impl From<&Decimal<$u>> for f32 {
+            fn from(t: &Decimal<$u>) -> f32 {
+                let x: f32 = 1.0 / (<$u>::MAX as f32);
+                (t.0 as f32) * x
+            }
+        }
+        impl From<f32> for Decimal<$u> {
+            fn from(val: f32) -> Self {
+                let x: f32 = <$u>::MAX as f32;
+                Decimal(((val - val.floor()).fract() * x) as $u)
+            }
+        }
+        impl From<f64> for Decimal<$u> {
+            fn from(val: f64) -> Self {
+                let x: f64 = <$u>::MAX as f64;
+                Decimal(((val - val.floor()).fract() * x) as $u)
+            }
+        }
+        impl Display for Decimal<$u> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let v: f64 = (*self).into();
+                v.fmt(f)
+            }
+        }
+    };
 }
-impl From<f32> for Decimal<u32> {
-    fn from(val: f32) -> Self {
-        const X: f32 = u32::MAX as f32;
-        Decimal(((val - val.floor()).fract() * X) as u32)
-    }
+impl_torus!(u16, decomposition_u16, i16, decomposition_i16; i16, i32);
+impl_torus!(u32, decomposition_u32, i32, decomposition_i32; i32);
+impl_torus!(u64, decomposition_u64, i64, decomposition_i64; i64, i32);
+impl_torus!(u128, decomposition_u128, i128, decomposition_i128; i128, i32);
+
+/// Torus32以外でも桁をi32で受け取る。TRGSWなどで使う
+macro_rules! impl_decomposition_i32 {
+    ($($u:ty),*) => {
+        $(
+            impl Decimal<$u> {
+                /// 2進表現から2^bits進表現に変換
+                /// - res\[i\] in [-bg/2,bg/2) where bg = 2^bits
+                /// - bits < 32
+                pub fn decomposition_i32<const L: usize>(self, bits: u32) -> [i32; L] {
+                    self.decomposition_i32_(bits, Self::make_decomp_mask(L as u32, bits))
+                }
+            }
+        )*
+    };
 }
-impl From<f64> for Decimal<u32> {
-    fn from(val: f64) -> Self {
-        const X: f64 = u32::MAX as f64;
-        Decimal(((val - val.floor()).fract() * X) as u32)
+impl_decomposition_i32!(u16, u64, u128);
+
+impl Torus64 {
+    /// 下位32bitを丸めてTorus32にする
+    pub fn to_torus32(self) -> Torus32 {
+        Torus32::from_bits((self.0.wrapping_add(1 << 31) >> 32) as u32)
     }
 }
-impl Display for Decimal<u32> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let v: f64 = (*self).into();
-        v.fmt(f)
+impl From<Torus32> for Torus64 {
+    fn from(t: Torus32) -> Self {
+        Decimal((t.0 as u64) << 32)
     }
 }
 
@@ -726,6 +1322,31 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn secure_rng_seeded() {
+        let sample = || {
+            let b: [Binary; 32] = BinaryDistribution::uniform().gen_n();
+            let t: [Torus32; 4] = ModDistribution::gaussian(1e-3).gen_n();
+            (b, t)
+        };
+        seed_secure_rng(42);
+        let first = sample();
+        seed_secure_rng(42);
+        assert_eq!(sample(), first, "同じseedなら同じ乱数列");
+        seed_secure_rng(43);
+        assert_ne!(sample(), first);
+    }
+    #[test]
+    fn mod_distribution_uniform_full_width() {
+        // f32を経由すると上位24bitしか乱数にならず、0.5以上の値では下位8bitが常に0になる
+        let mut unif = ModDistribution::uniform_with(ChaCha20Rng::seed_from_u64(7));
+        let high: Vec<u32> = (0..256)
+            .map(|_| unif.gen().inner())
+            .filter(|&x| x >= 1 << 31)
+            .collect();
+        assert!(!high.is_empty());
+        assert_ne!(high.iter().fold(0, |acc, x| acc | x) & 0xff, 0);
+    }
     #[test]
     fn polynomial_new() {
         let _interger_pol = pol!([2, 3, 4, 5]);
@@ -733,6 +1354,190 @@ mod tests {
         let _decimal_pol = pol!([Decimal(2_u32), Decimal(5_u32)]);
     }
     #[test]
+    #[cfg(feature = "serde")]
+    fn polynomial_json() {
+        let pol: Polynomial<Torus32, 3> = pol!([0, 1, u32::MAX].map(Decimal::from_bits));
+        let json = serde_json::to_string(&pol).unwrap();
+        assert_eq!(json, "[0,1,4294967295]");
+        assert_eq!(
+            serde_json::from_str::<Polynomial<Torus32, 3>>(&json).unwrap(),
+            pol
+        );
+
+        let pol = pol!([Binary::One, Binary::Zero]);
+        let json = serde_json::to_string(&pol).unwrap();
+        assert_eq!(json, "[1,0]");
+        assert_eq!(
+            serde_json::from_str::<Polynomial<Binary, 2>>(&json).unwrap(),
+            pol
+        );
+        assert_eq!(serde_json::to_string(&Torus64::from_bits(7)).unwrap(), "7");
+
+        // 係数の数が合わない、値が読めないときは失敗する
+        assert!(serde_json::from_str::<Polynomial<i32, 3>>("[1,2]").is_err());
+        assert!(serde_json::from_str::<Polynomial<i32, 3>>("[1,2,3,4]").is_err());
+        assert!(serde_json::from_str::<Polynomial<Binary, 2>>("[1,2]").is_err());
+        assert!(serde_json::from_str::<Polynomial<String, 3>>(r#"["a","b",3]"#).is_err());
+    }
+    #[test]
+    fn polynomial_display() {
+        assert_eq!(pol!([1, 0, -2, 3]).to_string(), "1 + -2x^2 + 3x^3");
+        assert_eq!(pol!([0, 5]).to_string(), "5x");
+        assert_eq!(pol!([0; 4]).to_string(), "0");
+        let pol = pol!([0.0, 0.5, 0.25]);
+        assert_eq!(format!("{:.1}", pol), "0.5x + 0.2x^2");
+
+        // 項が多いときは省略する
+        let pol: Polynomial<i32, 1024> = Polynomial::from_fn(|i| i as i32);
+        let s = pol.to_string();
+        assert!(s.starts_with("1x + 2x^2 + "), "{}", s);
+        assert!(s.ends_with("8x^8 + ... (1015 more terms)"), "{}", s);
+        assert!(format!("{:#}", pol).ends_with(" + 1023x^1023"));
+
+        // Nが小さいときはderiveと同じ
+        assert_eq!(format!("{:?}", pol!([1, 2, 3])), "Polynomial([1, 2, 3])");
+        assert_eq!(
+            format!("{:?}", pol),
+            "Polynomial([0, 1, 2, 3, ...(1016 more), 1020, 1021, 1022, 1023])"
+        );
+        assert_eq!(format!("{:#?}", pol).lines().count(), 1024 + 4);
+    }
+    #[test]
+    fn polynomial_norm() {
+        let pol = pol!([3, -4, 0, 1]);
+        assert_eq!(pol.linf_norm(), 4.0);
+        assert_eq!(pol.l2_norm(), 26f64.sqrt());
+        assert_eq!(pol.mean(), 0.0);
+        assert_eq!(pol.variance(), 6.5);
+        assert_eq!(pol!([Binary::One, Binary::Zero]).mean(), 0.5);
+
+        // トーラスは0の近くで符号付きとして見る。[0.75, 0.25, 0.5, 0]
+        let pol = pol!([0xc000_0000, 0x4000_0000, 0x8000_0000, 0].map(Torus32::from_bits));
+        assert_eq!(pol.linf_norm(), 0.5);
+        assert_eq!(pol.mean(), -0.125);
+        assert_eq!(Torus64::from_bits(u64::MAX).centered(), -(0.5f64.powi(64)));
+        assert_eq!(Torus16::from_bits(1 << 14).centered(), 0.25);
+
+        // ガウス分布の誤差は標準偏差に近い
+        const N: usize = 1 << 14;
+        let std_dev = 2f32.powi(-15);
+        let pol: Polynomial<Torus32, N> = pol!(ModDistribution::gaussian(std_dev).gen_n());
+        assert!(pol.mean().abs() < 1e-6, "{}", pol.mean());
+        assert!(
+            (pol.std_dev() / std_dev as f64 - 1.0).abs() < 0.05,
+            "{}",
+            pol.std_dev()
+        );
+        assert!(pol.linf_norm() < 6.0 * std_dev as f64);
+    }
+    #[test]
+    fn polynomial_eval() {
+        let pol = pol!([1, -2, 0, 3]);
+        assert_eq!(pol.eval(2), 1 - 4 + 24);
+        assert_eq!(pol.eval(0), 1);
+        let pol = pol!([0.5, 0.25]);
+        assert_eq!(pol.eval(2.0), 1.0);
+        // トーラス係数に整数を代入する
+        let pol = pol!([torus!(0.25), torus!(0.5)]);
+        assert!(pol.eval(3).is_in(torus!(0.75), 1e-6));
+        assert_eq!(
+            pol!([1, 2]).eval_complex(Complex::new(0.0, 1.0)),
+            Complex::new(1.0, 2.0)
+        );
+
+        // X^N+1の根では、剰余環の積が値の積になる
+        const N: usize = 64;
+        let l: Polynomial<i32, N> = Polynomial::from_fn(|i| i as i32 % 5 - 2);
+        let r: Polynomial<i32, N> = Polynomial::from_fn(|i| (i as i32 * 37) % 11 - 5);
+        let prod = l.cross(&r);
+        for k in 0..N {
+            let diff = prod.eval_at_root(k) - l.eval_at_root(k) * r.eval_at_root(k);
+            assert!(diff.norm() < 1e-9, "{}", diff);
+        }
+
+        // FFTのj番目はζ^{2k+1}での値。kはjのビット反転
+        fn check_fft<const N: usize>() {
+            let bits = N.trailing_zeros();
+            let pol: Polynomial<i32, N> = Polynomial::from_fn(|i| (i * i % 7) as i32 - 3);
+            let f = pol.to_fourier();
+            for j in 0..N / 2 {
+                let k = j.reverse_bits() >> (usize::BITS - bits);
+                let v = pol.eval_at_root(k);
+                let c = Complex::new(f.coefs()[j], f.coefs()[j + N / 2]);
+                assert!((v - c).norm() < 1e-9, "N={} j={} {} {}", N, j, v, c);
+            }
+        }
+        check_fft::<16>();
+        check_fft::<64>();
+        check_fft::<1024>();
+    }
+    #[test]
+    fn polynomial_automorphism() {
+        const N: usize = 64;
+        let l: Polynomial<i32, N> = Polynomial::from_fn(|i| i as i32 % 5 - 2);
+        let r: Polynomial<i32, N> = Polynomial::from_fn(|i| (i as i32 * 37) % 11 - 5);
+        assert_eq!(l.automorphism(1), l);
+        assert_eq!(l.automorphism(2 * N + 1), l);
+        for k in [3, 5, 2 * N - 1, 77] {
+            // 環準同型
+            assert_eq!(
+                l.cross(&r).automorphism(k),
+                l.automorphism(k).cross(&r.automorphism(k))
+            );
+            assert_eq!(
+                (l.clone() + r.clone()).automorphism(k),
+                l.automorphism(k) + r.automorphism(k)
+            );
+            // 合成は指数の積
+            assert_eq!(l.automorphism(k).automorphism(5), l.automorphism(k * 5));
+            // 根ζ^{2j+1}での値は、P(ζ^{k(2j+1)})
+            for j in 0..N {
+                let z =
+                    Complex::from_polar(1.0, std::f64::consts::PI * (2 * j + 1) as f64 / N as f64);
+                let diff = l.automorphism(k).eval_at_root(j) - l.eval_complex(z.powu(k as u32));
+                assert!(diff.norm() < 1e-9, "k={} j={} {}", k, j, diff);
+            }
+        }
+        // X^{-1}の逆はX^{-1}
+        let inv = 2 * N - 1;
+        assert_eq!(l.automorphism(inv).automorphism(inv), l);
+    }
+    #[test]
+    #[should_panic]
+    fn polynomial_automorphism_even() {
+        pol!([1, 2, 3, 4]).automorphism(2);
+    }
+    #[test]
+    fn polynomial_from_fn() {
+        use std::convert::TryFrom;
+        let pol: Polynomial<i32, 5> = Polynomial::from_fn(|i| i as i32 - 2);
+        assert_eq!(pol, pol!([-2, -1, 0, 1, 2]));
+        assert_eq!(pol.map(|&x| x * 2), pol!([-4, -2, 0, 2, 4]));
+
+        let res: Result<Polynomial<u32, 5>, _> = pol.try_map(|&x| u32::try_from(x + 2));
+        assert_eq!(res, Ok(pol!([0, 1, 2, 3, 4])));
+        // 最初の失敗で止める
+        let mut count = 0;
+        let res = pol.try_map(|&x| {
+            count += 1;
+            if x < 0 {
+                Err(x)
+            } else {
+                Ok(x)
+            }
+        });
+        assert_eq!(res, Err(-2));
+        assert_eq!(count, 1);
+
+        // Dropがある型でも使える
+        let pol: Polynomial<String, 3> = Polynomial::from_fn(|i| i.to_string());
+        assert_eq!(pol.try_map(|s| s.parse::<i32>()), Ok(pol!([0, 1, 2])));
+        assert!(pol
+            .map(|s| s.clone() + "x")
+            .try_map(|s| s.parse::<i32>())
+            .is_err());
+    }
+    #[test]
     fn polynomial_add() {
         let l_integer = pol!([2, 3, 4, 5]);
         let r_integer = pol!([4, 5, 6, 7]);
@@ -847,7 +1652,7 @@ mod tests {
         let r_i = pol!([4, 5, 6]);
         let a_i = pol!([1, 1, 1]);
 
-        assert_eq!((&l_f).mul_add(&r_i, a_i), pol!([-29, -1, 44]));
+        assert_eq!((&l_f).mul_add(&r_i, a_i.clone()), pol!([-29, -1, 44]));
         assert_eq!(l_f.mul_add(&r_i, a_i), pol!([-29, -1, 44]));
 
         // decimal * i32
@@ -890,6 +1695,17 @@ mod tests {
         let pol = pol!([Decimal(0b000001_000010_000011_100000_000000_00u32)]);
         let res = pol.decomposition_i32::<3>(6);
         assert_eq!(res, [pol!([1]), pol!([2]), pol!([4])], "パート３");
+
+        let mut unif = ModDistribution::uniform();
+        let pol = pol!(unif.gen_n::<16>());
+        let decomp_mask = Torus32::make_decomp_mask(3, 6);
+        let mut res: [Polynomial<i32, 16>; 3] = mem::array_create_enumerate(|_| pol!([0; 16]));
+        pol.decomposition_i32_to::<3>(6, decomp_mask, &mut res);
+        assert_eq!(
+            res,
+            pol.decomposition_i32_::<3>(6, decomp_mask),
+            "作業領域に書き込む"
+        );
     }
     #[test]
     fn polynomial_rotate() {
@@ -951,6 +1767,294 @@ mod tests {
         }
     }
 
+    #[test]
+    fn fft_count_per_thread() {
+        let l = pol!([torus!(0.5); 16]);
+        let r = pol!([Binary::One; 16]);
+        let before = fft_count();
+        let _ = l.fft_cross(&r);
+        // IFFT 2回とFFT 1回
+        assert_eq!(fft_count() - before, 3);
+        // Spqliosを1度取り出して何回変換しても、変換の回数だけ数える
+        let before = fft_count();
+        FFT_MAP.with(|m| {
+            let mut m = m.borrow_mut();
+            let spq = m.get_fft_proc(16);
+            let l_f = spq.ifft_torus(l.coefs());
+            let _ = spq.fft_torus(&l_f);
+            let _ = spq.poly_mul(l.coefs(), l.coefs());
+        });
+        assert_eq!(fft_count() - before, 2 + 3);
+    }
+
+    #[test]
+    fn fft_map_per_thread() {
+        let before = fft_count();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    let l = pol!([torus!(0.5); 1024]);
+                    let r = pol!([Binary::One; 1024]);
+                    for _ in 0..10 {
+                        let _ = l.fft_cross(&r);
+                    }
+                    (fft_count(), FFT_MAP.with(|m| m.borrow().procs.len()))
+                })
+            })
+            .collect();
+        for h in handles {
+            // 他のスレッドの変換は数えず、FFTの準備もスレッドごと
+            assert_eq!(h.join().unwrap(), (30, 1));
+        }
+        assert_eq!(fft_count(), before);
+    }
+
+    #[test]
+    fn polynomial_fft_cross_any_size() {
+        fn check<const N: usize>() {
+            let mut unif = ModDistribution::uniform();
+            let mut unifb = BinaryDistribution::uniform();
+            let l = pol!(unif.gen_n::<N>());
+            let r = pol!(unifb.gen_n::<N>());
+            let before = fft_count();
+            assert_eq!(l.fft_cross(&r), l.cross(&r), "N={}", N);
+            assert_eq!(fft_count(), before, "N={}はFFTを使わない", N);
+        }
+        check::<1>();
+        check::<7>();
+        check::<8>();
+        check::<24>();
+        check::<100>();
+        assert!(is_fft_size(16) && is_fft_size(1024));
+        assert!(!is_fft_size(8) && !is_fft_size(48));
+    }
+
+    #[test]
+    fn polynomial_fft_cross_many() {
+        let mut unift = ModDistribution::uniform();
+        let mut unifb = BinaryDistribution::uniform();
+        const N: usize = 1024;
+        let l = pol!(unift.gen_n::<N>());
+        let rs: Vec<Polynomial<Binary, N>> = (0..4).map(|_| pol!(unifb.gen_n::<N>())).collect();
+        let before = fft_count();
+        let res = l.fft_cross_many(&rs);
+        assert_eq!(fft_count() - before, 2 * 4 + 1);
+        assert_eq!(res.len(), 4);
+        for (res, r) in res.iter().zip(rs.iter()) {
+            assert_eq!(res, &l.fft_cross(r));
+        }
+        assert!(l.fft_cross_many(&rs[..0]).is_empty());
+
+        let l = pol!([torus!(0.25); 8]);
+        let rs = [pol!([1; 8]), pol!([-3; 8])];
+        let res = l.fft_cross_many(&rs);
+        assert_eq!(res, vec![l.cross(&rs[0]), l.cross(&rs[1])]);
+    }
+
+    #[test]
+    fn polynomial_fft_mul_add_assign() {
+        let mut unift = ModDistribution::uniform();
+        let mut unifb = BinaryDistribution::uniform();
+        const N: usize = 1024;
+        let ls: Vec<Polynomial<Torus32, N>> = (0..3).map(|_| pol!(unift.gen_n::<N>())).collect();
+        let rs: Vec<Polynomial<Binary, N>> = (0..3).map(|_| pol!(unifb.gen_n::<N>())).collect();
+        let init = pol!(unift.gen_n::<N>());
+
+        let mut acc = init.clone();
+        acc.fft_mul_add_assign(&ls[0], &rs[0]);
+        let expect = init.clone() + ls[0].cross(&rs[0]);
+        for i in 0..N {
+            assert!(torus_range_eq(acc.coef_(i), expect.coef_(i), 1e-6));
+        }
+
+        let mut acc = init.clone();
+        let before = fft_count();
+        acc.fft_mul_add_assign_many(ls.iter().zip(rs.iter()));
+        assert_eq!(fft_count() - before, 2 * 3 + 1, "逆変換は1回");
+        let expect = ls
+            .iter()
+            .zip(rs.iter())
+            .fold(init.clone(), |acc, (l, r)| acc + l.cross(r));
+        for i in 0..N {
+            assert!(torus_range_eq(acc.coef_(i), expect.coef_(i), 1e-6));
+        }
+
+        // 項がなければ変わらない
+        let mut acc = init.clone();
+        acc.fft_mul_add_assign_many(ls.iter().zip(rs.iter()).take(0));
+        assert_eq!(acc, init);
+
+        // FFTを使えない長さ
+        let mut acc = pol!([torus!(0.5); 8]);
+        acc.fft_mul_add_assign(&pol!([torus!(0.25); 8]), &pol!([2; 8]));
+        assert_eq!(
+            acc,
+            pol!([torus!(0.5); 8]) + pol!([torus!(0.25); 8]).cross(&pol!([2; 8]))
+        );
+    }
+
+    #[test]
+    fn polynomial_fourier() {
+        let mut unift = ModDistribution::uniform();
+        let mut unifb = BinaryDistribution::uniform();
+        const N: usize = 1024;
+        let l: Polynomial<Torus32, N> = pol!(unift.gen_n());
+        let r: Polynomial<Binary, N> = pol!(unifb.gen_n());
+        let (l_f, r_f) = (l.to_fourier(), r.to_fourier());
+
+        let back = Polynomial::<Torus32, N>::from_fourier(&l_f);
+        for i in 0..N {
+            assert!(torus_range_eq(back.coef_(i), l.coef_(i), 1e-6));
+        }
+        let expect = l.cross(&r);
+        let res = Polynomial::<Torus32, N>::from_fourier(&(l_f.clone() * &r_f));
+        for i in 0..N {
+            assert!(torus_range_eq(res.coef_(i), expect.coef_(i), 1e-6));
+        }
+        // 変換したまま足してから戻す
+        let sum = Polynomial::<Torus32, N>::from_fourier(&(l_f.clone() * &r_f + &l_f));
+        let expect = expect + &l;
+        for i in 0..N {
+            assert!(torus_range_eq(sum.coef_(i), expect.coef_(i), 1e-6));
+        }
+        let mut prod = l_f.clone();
+        prod *= &r_f;
+        assert_eq!(prod, l_f * r_f);
+    }
+
+    #[test]
+    fn torus64() {
+        let x = Torus64::from(0.375);
+        assert_eq!(x.inner(), 3 << 61);
+        assert_eq!(Into::<f64>::into(x), 0.375);
+        assert_eq!(Torus64::from(-0.25), Torus64::from(0.75));
+        assert_eq!(Torus64::from(1.0 - 1e-18).inner(), 0);
+        assert_eq!(x * 3_i32, Torus64::from(0.125));
+        assert_eq!(x * -1_i64, -x);
+        assert_eq!(x * Binary::One + x * Binary::Zero, x);
+        assert_eq!(x.to_string(), "0.375");
+        assert_eq!(Torus64::pow_two_minus(3), Torus64::from(0.125));
+
+        let t = Torus32::from_bits(0x8000_0001);
+        assert_eq!(Torus64::from(t).inner(), 0x8000_0001_0000_0000);
+        assert_eq!(Torus64::from(t).to_torus32(), t);
+        assert_eq!(
+            Torus64::from_bits(0x8000_0000_8000_0000)
+                .to_torus32()
+                .inner(),
+            0x8000_0001
+        );
+
+        // 分解した桁から元の値を(丸めて)戻せる
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let x = Torus64::from_bits(rng.gen());
+            let d: [i32; 3] = x.decomposition_i32(10);
+            assert!(d.iter().all(|&d| (-512..512).contains(&d)));
+            let back = d.iter().enumerate().fold(0_u64, |s, (i, &d)| {
+                s.wrapping_add((d as i64 as u64).wrapping_shl(64 - 10 * (i as u32 + 1)))
+            });
+            let err = x.inner().wrapping_sub(back) as i64;
+            assert!(err.abs() <= 1 << (64 - 30 - 1), "{:?} {:?}", x, d);
+
+            let u: [u64; 4] = x.decomposition_u64(16);
+            let back = u.iter().fold(0_u64, |s, &u| (s << 16) | u);
+            assert_eq!(back, x.inner());
+        }
+    }
+
+    #[test]
+    fn torus_widths() {
+        assert!(Torus32::pow_two_minus(1).is_in(Torus32::from(0.5), 1e-6));
+        assert!(Torus32::pow_two_minus(0).is_in(Torus32::from(1.0), 1e-6));
+        assert!(Torus32::pow_two_minus(31).is_in(Torus32::from(0.5_f32.powi(31)), 1e-6));
+        assert!(Torus32::pow_two_minus(32).is_in(Torus32::from(0.0), 1e-6));
+
+        let x = Torus16::from_bits(3 << 13);
+        assert!(x.is_in(Torus16::from(0.375), 1e-4));
+        assert_eq!(x * 3_i32 + x * -1_i16, x + x);
+        assert_eq!(Torus16::pow_two_minus(20).inner(), 1);
+        assert_eq!(x.to_string(), Into::<f64>::into(x).to_string());
+        let d: [i32; 2] = Torus16::from_bits(0x80ff).decomposition_i32(8);
+        assert_eq!(d, [-127, -1]);
+        let u: [u16; 4] = Torus16::from_bits(0x1234).decomposition_u16(4);
+        assert_eq!(u, [1, 2, 3, 4]);
+
+        let x = Torus128::from(0.375);
+        assert!(x.is_in(Torus128::from_bits(3 << 125), 1e-12));
+        assert_eq!(x * 3_i32 + x * -1_i128, x + x);
+        assert_eq!(Torus128::pow_two_minus(2), Torus128::from_bits(1 << 126));
+
+        // 分解した桁から元の値を(丸めて)戻せる
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let x = Torus128::from_bits(rng.gen());
+            let d: [i32; 4] = x.decomposition_i32(20);
+            assert!(d.iter().all(|&d| (-(1 << 19)..1 << 19).contains(&d)));
+            let back = d.iter().enumerate().fold(0_u128, |s, (i, &d)| {
+                s.wrapping_add((d as i128 as u128).wrapping_shl(128 - 20 * (i as u32 + 1)))
+            });
+            let err = x.inner().wrapping_sub(back) as i128;
+            assert!(err.abs() <= 1 << (128 - 80 - 1), "{:?} {:?}", x, d);
+
+            let u: [u128; 8] = x.decomposition_u128(16);
+            let back = u.iter().fold(0_u128, |s, &u| (s << 16) | u);
+            assert_eq!(back, x.inner());
+        }
+    }
+
+    #[test]
+    fn polynomial_torus64_fft_cross() {
+        fn check<const N: usize>() {
+            let mut rng = rand::thread_rng();
+            let l: Polynomial<Torus64, N> = pol!(mem::array_create_enumerate(|_| {
+                Torus64::from_bits(rng.gen())
+            }));
+            let r: Polynomial<i32, N> = pol!(mem::array_create_enumerate(
+                |_| rng.gen_range(-(1 << 16)..=1 << 16)
+            ));
+            assert_eq!(l.fft_cross_i32(&r), l.cross(&r), "N={}", N);
+        }
+        check::<8>();
+        check::<1024>();
+        check::<2048>();
+    }
+
+    #[test]
+    fn polynomial_karatsuba_cross() {
+        fn check<const N: usize>() {
+            let mut unif = ModDistribution::uniform();
+            let l = pol!(unif.gen_n::<N>());
+            let r: Polynomial<i32, N> =
+                pol!(mem::array_create_enumerate(|i| (i as i32 * 37) % 1001 - 500));
+            assert_eq!(l.karatsuba_cross(&r), l.cross(&r), "N={}", N);
+            let l: Polynomial<f64, N> = pol!(mem::array_create_enumerate(|i| (i % 7) as f64 - 3.0));
+            let r: Polynomial<f64, N> = pol!(mem::array_create_enumerate(|i| (i % 5) as f64));
+            assert_eq!(l.karatsuba_cross(&r), l.cross(&r), "N={}", N);
+        }
+        check::<1>();
+        check::<7>();
+        check::<31>();
+        check::<32>();
+        check::<33>();
+        check::<100>();
+        check::<1024>();
+    }
+
+    #[test]
+    fn polynomial_ntt_cross() {
+        let mut unift = ModDistribution::uniform();
+        let mut unifb = BinaryDistribution::uniform();
+        const N: usize = 1024;
+        let l = pol!(unift.gen_n::<N>());
+        let r = pol!(unifb.gen_n::<N>());
+        assert_eq!(l.ntt_cross(&r), l.cross(&r));
+        let before = fft_count();
+        let r = pol!([i32::MIN; N]);
+        assert_eq!(l.ntt_cross(&r), l.cross(&r));
+        assert_eq!(fft_count(), before, "FFTは使わない");
+    }
+
     #[test]
     fn mod_guassian_run() {
         let mut mg = ModDistribution::gaussian(1.0);
@@ -1272,6 +2376,132 @@ mod tests {
         assert_eq!(res, [-32, -31, -32], "test5: 繰り上がりも桁上がりもある");
     }
 
+    #[test]
+    fn decomposition_rounding() {
+        // 全てのTorus16で、桁の範囲と丸めた値に戻ることを確かめる
+        fn check<const L: usize>(bits: u32) {
+            let rest = 16 - L as u32 * bits;
+            let bg = 1_i32 << bits;
+            for x in 0..=u16::MAX {
+                let dec = Torus16::from_bits(x);
+                // 最も近い値。ちょうど半分なら切り上げ
+                let round = if rest == 0 {
+                    x
+                } else {
+                    x.wrapping_add(1 << (rest - 1)) >> rest << rest
+                };
+                let err = x.wrapping_sub(round) as i16 as i32;
+                assert!(err.abs() <= (1 << rest) / 2);
+
+                let u: [u16; L] = dec.decomposition_u16(bits);
+                assert!(u.iter().all(|&u| (u as i32) < bg));
+                let back = u.iter().fold(0_u16, |s, &u| s.wrapping_shl(bits) | u);
+                assert_eq!(back.wrapping_shl(rest), round, "u16 {:x}", x);
+
+                let d: [i16; L] = dec.decomposition_i16(bits);
+                assert!(d.iter().all(|&d| (-bg / 2..bg / 2).contains(&(d as i32))));
+                let back = d
+                    .iter()
+                    .fold(0_u16, |s, &d| s.wrapping_shl(bits).wrapping_add(d as u16));
+                assert_eq!(back.wrapping_shl(rest), round, "i16 {:x}", x);
+                assert_eq!(dec.decomposition_i32::<L>(bits), d.map(|d| d as i32));
+            }
+        }
+        check::<1>(4);
+        check::<2>(3);
+        check::<3>(5);
+        check::<4>(4);
+        check::<2>(8);
+        check::<5>(3);
+
+        // make_decomp_maskを使う分解と同じ
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let x = Torus32::from_bits(rng.gen());
+            let mask = Torus32::make_decomp_mask(3, 6);
+            assert_eq!(
+                x.decomposition_i32::<3>(6),
+                x.decomposition_i32_::<3>(6, mask)
+            );
+            let mask = Torus32::make_decomp_mask(2, 16);
+            assert_eq!(
+                x.decomposition_i32::<2>(16),
+                x.decomposition_i32_::<2>(16, mask)
+            );
+
+            let x = Torus64::from_bits(rng.gen());
+            let d: [i64; 2] = x.decomposition_i64(24);
+            assert_eq!(x.decomposition_i32::<2>(24), d.map(|d| d as i32));
+            let back = (d[0] << 24).wrapping_add(d[1]) as u64;
+            let err = x.inner().wrapping_sub(back << 16) as i64;
+            assert!(err.abs() <= 1 << 15);
+        }
+        // 2,3桁目の最上位ビットと、丸めのビット
+        assert_eq!(Torus32::make_decomp_mask(3, 6), 0x0208_2000);
+        assert_eq!(Torus32::make_decomp_mask(2, 16), 0x0000_8000);
+    }
+
+    #[test]
+    fn decomposition_with_error() {
+        // 桁は[1, 2, 3]で、残りの14bitは2^13 + 1
+        let x = Torus32::from_bits(0x0420_e001);
+        let (d, err) = x.decomposition_i32_with_error::<3>(6);
+        assert_eq!(d, [1, 2, 4]);
+        assert_eq!(
+            Torus32::recompose_i32(&d, 6).inner(),
+            0b000001_000010_000100 << 14
+        );
+        assert_eq!(err, -(1 << 13) + 1);
+        assert_eq!(Torus32::decomposition_error_bound(3, 6), 1 << 13);
+        assert_eq!(Torus32::decomposition_error_bound(2, 16), 0);
+        assert_eq!(Torus64::decomposition_error_bound(3, 10), 1 << 33);
+
+        // 誤差は上限以下で、上限は実際に届く
+        fn check<const L: usize>(bits: u32) {
+            let bound = Torus16::decomposition_error_bound(L as u32, bits);
+            let mut max = 0;
+            for x in 0..=u16::MAX {
+                let x = Torus16::from_bits(x);
+                let (d, err) = x.decomposition_i32_with_error::<L>(bits);
+                assert_eq!(
+                    Torus16::recompose_i32(&d, bits) + Torus16::from_bits(err as u16),
+                    x
+                );
+                assert!(err.abs() <= bound);
+                max = max.max(err.abs());
+            }
+            assert_eq!(max, bound);
+        }
+        check::<3>(4);
+        check::<2>(7);
+        check::<4>(4);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let x = Torus64::from_bits(rng.gen());
+            let (d, err) = x.decomposition_i32_with_error::<3>(10);
+            assert_eq!(
+                Torus64::recompose_i32(&d, 10) + Torus64::from_bits(err as u64),
+                x
+            );
+            assert!(err.abs() <= Torus64::decomposition_error_bound(3, 10));
+        }
+    }
+
+    #[bench]
+    fn bench_cross_naive(b: &mut test::Bencher) {
+        let mut unif = ModDistribution::uniform();
+        let l = pol!(unif.gen_n::<1024>());
+        let r = pol!([3i32; 1024]);
+        b.iter(|| l.cross(&r));
+    }
+    #[bench]
+    fn bench_cross_karatsuba(b: &mut test::Bencher) {
+        let mut unif = ModDistribution::uniform();
+        let l = pol!(unif.gen_n::<1024>());
+        let r = pol!([3i32; 1024]);
+        b.iter(|| l.karatsuba_cross(&r));
+    }
     #[bench]
     fn bench_decimal_to_f32(b: &mut test::Bencher) {
         let x = Decimal(0x8000_0000_u32);
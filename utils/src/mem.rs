@@ -8,30 +8,103 @@ pub fn transmute<I: Sized, O: Sized>(mut item: I) -> O {
     res
 }
 
+/// f(i)をi番目の要素とする配列を作る
 #[inline]
-/// # Caution
-/// ## item_iter's length must be more than N
-pub unsafe fn array_create<T, I, const N: usize>(item_iter: I) -> [T; N]
+pub fn array_init<T, F, const N: usize>(f: F) -> [T; N]
 where
-    I: std::iter::Iterator<Item = T>,
+    F: FnMut(usize) -> T,
 {
-    let mut arr: [MaybeUninit<T>; N] = MaybeUninit::uninit().assume_init();
-    arr.iter_mut()
-        .zip(item_iter)
-        .for_each(|(arr_i, item)| *arr_i = MaybeUninit::new(item));
-    transmute::<_, [T; N]>(arr)
+    std::array::from_fn(f)
 }
 
+/// f(i)をi番目の要素とする配列を作る。
+/// 失敗したらそこで止めてエラーを返す。それまでに作った要素はdropする
+pub fn array_try_init<T, E, F, const N: usize>(mut f: F) -> Result<[T; N], E>
+where
+    F: FnMut(usize) -> Result<T, E>,
+{
+    /// 途中で抜けたときに、初期化済みの要素だけをdropする
+    struct Guard<'a, T, const N: usize> {
+        arr: &'a mut [MaybeUninit<T>; N],
+        len: usize,
+    }
+    impl<T, const N: usize> Drop for Guard<'_, T, N> {
+        fn drop(&mut self) {
+            self.arr[..self.len]
+                .iter_mut()
+                .for_each(|x| unsafe { x.assume_init_drop() });
+        }
+    }
+
+    let mut arr: [MaybeUninit<T>; N] = [const { MaybeUninit::uninit() }; N];
+    let mut guard = Guard {
+        arr: &mut arr,
+        len: 0,
+    };
+    for i in 0..N {
+        guard.arr[i] = MaybeUninit::new(f(i)?);
+        guard.len += 1;
+    }
+    core::mem::forget(guard);
+    // 全ての要素が初期化されている
+    Ok(transmute::<_, [T; N]>(arr))
+}
+
+/// item_iterの先頭N個を配列にする
+/// # Panic
+/// ## item_iter's length is less than N
+#[inline]
+pub fn array_create<T, I, const N: usize>(item_iter: I) -> [T; N]
+where
+    I: std::iter::IntoIterator<Item = T>,
+{
+    let mut item_iter = item_iter.into_iter();
+    array_init(|_| item_iter.next().expect("item_iter is too short"))
+}
+
+/// `array_init`と同じ
 #[inline]
-/// # Caution
-/// ## item_iter's length must be more than N
 pub fn array_create_enumerate<T, F, const N: usize>(init: F) -> [T; N]
 where
     F: FnMut(usize) -> T,
 {
-    let mut arr: [MaybeUninit<T>; N] = unsafe { MaybeUninit::uninit().assume_init() };
-    arr.iter_mut()
-        .zip((0..N).map(init))
-        .for_each(|(arr_i, item)| *arr_i = MaybeUninit::new(item));
-    transmute::<_, [T; N]>(arr)
+    array_init(init)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn array_init_test() {
+        assert_eq!(array_init(|i| i * 2), [0, 2, 4, 6]);
+        assert_eq!(array_create(1..), [1, 2, 3]);
+        let strs: [String; 3] = array_init(|i| i.to_string());
+        assert_eq!(strs, ["0", "1", "2"]);
+
+        assert_eq!(array_try_init(|i| Ok::<_, ()>(i + 1)), Ok([1, 2, 3]));
+        // 失敗したら、作った要素だけがdropされる
+        struct Counted<'a>(&'a Cell<usize>);
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+        let dropped = Cell::new(0);
+        let res: Result<[Counted; 8], usize> =
+            array_try_init(|i| if i < 5 { Ok(Counted(&dropped)) } else { Err(i) });
+        assert_eq!(res.err(), Some(5));
+        assert_eq!(dropped.get(), 5);
+
+        let res: Result<[Counted; 8], ()> = array_try_init(|_| Ok(Counted(&dropped)));
+        drop(res);
+        assert_eq!(dropped.get(), 13);
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_create_short() {
+        let _: [i32; 4] = array_create(0..3);
+    }
 }
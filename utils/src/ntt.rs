@@ -0,0 +1,333 @@
+//! 数論変換(NTT)による X^N+1 を法とした多項式乗算。
+//! 62bit以下の2つの素数で変換し、中国剰余定理で戻すので、FFTと違って丸め誤差がない。
+//! 係数が32bitに収まれば、Nがどれだけ大きくても結果は正確(2^32の剰余)。
+//!
+//! 負巡回畳み込みのためのtwist(1の2N乗根psiの冪)はバタフライの回転因子に含める。
+//! FFT(`spqlios`)よりは遅いので、精度が足りないときの代わりとして使う。
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::math::{Binary, Torus32};
+
+/// NTTに渡せる係数。絶対値は2^32未満
+pub trait NttCoef: Copy {
+    fn to_i64(self) -> i64;
+}
+impl NttCoef for i32 {
+    fn to_i64(self) -> i64 {
+        self as i64
+    }
+}
+impl NttCoef for Binary {
+    fn to_i64(self) -> i64 {
+        self as i64
+    }
+}
+/// [-1/2, 1/2)の代表元として扱う
+impl NttCoef for Torus32 {
+    fn to_i64(self) -> i64 {
+        self.inner() as i32 as i64
+    }
+}
+
+/// 1つの素数での変換
+struct NttPrime {
+    p: u64,
+    /// psi^bitrev(i)
+    psi_rev: Vec<u64>,
+    /// psi^-bitrev(i)
+    psi_inv_rev: Vec<u64>,
+    n_inv: u64,
+}
+
+pub struct Ntt {
+    n: usize,
+    primes: [NttPrime; 2],
+    /// primes[0].pのprimes[1].pでの逆元
+    p0_inv: u64,
+}
+
+impl Ntt {
+    /// # Panic
+    /// - nが2の冪でない
+    pub fn new(n: usize) -> Self {
+        assert!(n.is_power_of_two(), "n must be a power of 2");
+        let p0 = find_prime(n, 1 << 62);
+        let p1 = find_prime(n, p0);
+        Ntt {
+            n,
+            primes: [NttPrime::new(n, p0), NttPrime::new(n, p1)],
+            p0_inv: pow_mod(p0 % p1, p1 - 2, p1),
+        }
+    }
+    pub fn n(&self) -> usize {
+        self.n
+    }
+    /// 使っている素数
+    pub fn primes(&self) -> [u64; 2] {
+        [self.primes[0].p, self.primes[1].p]
+    }
+
+    /// a * b mod X^N+1 を正確に求める
+    pub fn poly_mul<S: NttCoef, T: NttCoef, const N: usize>(
+        &self,
+        a: &[S; N],
+        b: &[T; N],
+    ) -> [Torus32; N] {
+        debug_assert!(self.n == N, "ntt: self.n={},N={}", self.n, N);
+        let [r0, r1] = [0, 1].map(|k| {
+            let prime = &self.primes[k];
+            let mut a_n: Vec<u64> = a.iter().map(|x| prime.reduce(x.to_i64())).collect();
+            let mut b_n: Vec<u64> = b.iter().map(|x| prime.reduce(x.to_i64())).collect();
+            prime.forward(&mut a_n);
+            prime.forward(&mut b_n);
+            a_n.iter_mut()
+                .zip(b_n.iter())
+                .for_each(|(x, &y)| *x = mul_mod(*x, y, prime.p));
+            prime.inverse(&mut a_n);
+            a_n
+        });
+        let mut res = [Torus32::from_bits(0); N];
+        res.iter_mut()
+            .zip(r0.iter().zip(r1.iter()))
+            .for_each(|(r, (&x0, &x1))| *r = Torus32::from_bits(self.crt(x0, x1)));
+        res
+    }
+
+    /// 2つの剰余から[-p0p1/2, p0p1/2)の値を戻し、2^32の剰余をとる
+    fn crt(&self, x0: u64, x1: u64) -> u32 {
+        let (p0, p1) = (self.primes[0].p, self.primes[1].p);
+        let t = mul_mod((x1 + p1 - x0 % p1) % p1, self.p0_inv, p1);
+        let x = x0 as u128 + p0 as u128 * t as u128;
+        let m = p0 as u128 * p1 as u128;
+        // 2^32は2^128を割るので、負の値はwrappingのまま切り捨ててよい
+        if x > m / 2 {
+            x.wrapping_sub(m) as u32
+        } else {
+            x as u32
+        }
+    }
+}
+
+impl NttPrime {
+    fn new(n: usize, p: u64) -> Self {
+        let psi = root_of_unity(n, p);
+        let psi_inv = pow_mod(psi, p - 2, p);
+        let bits = n.trailing_zeros();
+        let rev = |i: usize| {
+            if bits == 0 {
+                0
+            } else {
+                i.reverse_bits() >> (usize::BITS - bits)
+            }
+        };
+        let powers = |x: u64| {
+            let mut pows = vec![1; n];
+            for i in 1..n {
+                pows[i] = mul_mod(pows[i - 1], x, p);
+            }
+            (0..n).map(|i| pows[rev(i)]).collect::<Vec<_>>()
+        };
+        NttPrime {
+            p,
+            psi_rev: powers(psi),
+            psi_inv_rev: powers(psi_inv),
+            n_inv: pow_mod(n as u64, p - 2, p),
+        }
+    }
+    fn reduce(&self, x: i64) -> u64 {
+        x.rem_euclid(self.p as i64) as u64
+    }
+
+    /// Cooley-Tukey。出力はビット反転順
+    fn forward(&self, a: &mut [u64]) {
+        let (n, p) = (a.len(), self.p);
+        let mut t = n;
+        let mut m = 1;
+        while m < n {
+            t /= 2;
+            for i in 0..m {
+                let s = self.psi_rev[m + i];
+                for j in 2 * i * t..2 * i * t + t {
+                    let (u, v) = (a[j], mul_mod(a[j + t], s, p));
+                    a[j] = add_mod(u, v, p);
+                    a[j + t] = add_mod(u, p - v, p);
+                }
+            }
+            m *= 2;
+        }
+    }
+    /// Gentleman-Sande。入力はビット反転順
+    fn inverse(&self, a: &mut [u64]) {
+        let (n, p) = (a.len(), self.p);
+        let mut t = 1;
+        let mut m = n;
+        while m > 1 {
+            let h = m / 2;
+            for i in 0..h {
+                let s = self.psi_inv_rev[h + i];
+                for j in 2 * i * t..2 * i * t + t {
+                    let (u, v) = (a[j], a[j + t]);
+                    a[j] = add_mod(u, v, p);
+                    a[j + t] = mul_mod(add_mod(u, p - v, p), s, p);
+                }
+            }
+            t *= 2;
+            m = h;
+        }
+        a.iter_mut().for_each(|x| *x = mul_mod(*x, self.n_inv, p));
+    }
+}
+
+fn add_mod(a: u64, b: u64, p: u64) -> u64 {
+    let s = a + b;
+    if s >= p {
+        s - p
+    } else {
+        s
+    }
+}
+fn mul_mod(a: u64, b: u64, p: u64) -> u64 {
+    (a as u128 * b as u128 % p as u128) as u64
+}
+fn pow_mod(mut x: u64, mut e: u64, p: u64) -> u64 {
+    let mut res = 1;
+    while e > 0 {
+        if e & 1 == 1 {
+            res = mul_mod(res, x, p);
+        }
+        x = mul_mod(x, x, p);
+        e >>= 1;
+    }
+    res
+}
+/// u64で決定的なMiller-Rabin
+fn is_prime(p: u64) -> bool {
+    const BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if p < 2 {
+        return false;
+    }
+    if let Some(&b) = BASES.iter().find(|&&b| p.is_multiple_of(b)) {
+        return p == b;
+    }
+    let (s, d) = (
+        (p - 1).trailing_zeros(),
+        (p - 1) >> (p - 1).trailing_zeros(),
+    );
+    BASES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, p);
+        if x == 1 || x == p - 1 {
+            return true;
+        }
+        (1..s).any(|_| {
+            x = mul_mod(x, x, p);
+            x == p - 1
+        })
+    })
+}
+/// below未満で、p = 1 mod 2n となる最大の素数
+fn find_prime(n: usize, below: u64) -> u64 {
+    let step = 2 * n as u64;
+    let mut p = (below - 2) / step * step + 1;
+    while !is_prime(p) {
+        p -= step;
+    }
+    p
+}
+/// 1の原始2n乗根
+fn root_of_unity(n: usize, p: u64) -> u64 {
+    let e = (p - 1) / (2 * n as u64);
+    (2..)
+        .map(|g| pow_mod(g, e, p))
+        .find(|&psi| pow_mod(psi, n as u64, p) == p - 1)
+        .unwrap()
+}
+
+thread_local! {
+    static NTT_MAP: RefCell<HashMap<usize, Rc<Ntt>>> = Default::default();
+}
+/// このスレッドで作ったNを法とするNttを使う。なければ作る
+pub fn with_ntt<R>(n: usize, f: impl FnOnce(&Ntt) -> R) -> R {
+    let ntt = NTT_MAP.with(|m| {
+        m.borrow_mut()
+            .entry(n)
+            .or_insert_with(|| Rc::new(Ntt::new(n)))
+            .clone()
+    });
+    f(&ntt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{BinaryDistribution, Cross, ModDistribution, Polynomial, Random};
+    use crate::pol;
+
+    #[test]
+    fn ntt_primes() {
+        for n in [1, 2, 16, 1024, 1 << 16] {
+            let ntt = Ntt::new(n);
+            let [p0, p1] = ntt.primes();
+            assert!(p0 > p1 && p1 > 1 << 61);
+            for p in [p0, p1] {
+                assert!(is_prime(p));
+                assert_eq!(p % (2 * n as u64), 1);
+            }
+        }
+        assert!(is_prime((1 << 61) - 1));
+        assert!(!is_prime(3215031751)); // 2,3,5,7の強擬素数
+    }
+
+    #[test]
+    fn ntt_poly_mul_exact() {
+        const N: usize = 1024;
+        let ntt = Ntt::new(N);
+        let mut unif = ModDistribution::uniform();
+        let mut unifb = BinaryDistribution::uniform();
+        for _ in 0..3 {
+            let l = pol!(unif.gen_n::<N>());
+            // 係数が大きいとFFTでは誤差が出るが、NTTは正確
+            let r = pol!(unif.gen_n::<N>().map(|t: Torus32| t.inner() as i32));
+            assert_eq!(pol!(ntt.poly_mul(l.coefs(), r.coefs())), l.cross(&r));
+            let r = pol!(unifb.gen_n::<N>());
+            assert_eq!(pol!(ntt.poly_mul(l.coefs(), r.coefs())), l.cross(&r));
+        }
+
+        // (3X + 5X^{N-1})(1 + 7X^2) = 3X + 21X^3 + 5X^{N-1} - 35X
+        let mut a = [Torus32::from_bits(0); 16];
+        let mut b = [0i32; 16];
+        a[1] = Torus32::from_bits(3);
+        a[15] = Torus32::from_bits(5);
+        b[0] = 1;
+        b[2] = 7;
+        let mut expect = [Torus32::from_bits(0); 16];
+        expect[1] = Torus32::from_bits(3_u32.wrapping_sub(35));
+        expect[3] = Torus32::from_bits(21);
+        expect[15] = Torus32::from_bits(5);
+        assert_eq!(with_ntt(16, |ntt| ntt.poly_mul(&a, &b)), expect);
+
+        // N=1,2 でも使える
+        let one = [Torus32::from_bits(7)];
+        assert_eq!(
+            Ntt::new(1).poly_mul(&one, &[-3]),
+            [Torus32::from_bits(-21i32 as u32)]
+        );
+        let two = [Torus32::from_bits(1), Torus32::from_bits(2)];
+        assert_eq!(
+            Ntt::new(2).poly_mul(&two, &[3, 4]),
+            [Torus32::from_bits(-5i32 as u32), Torus32::from_bits(10)]
+        );
+    }
+
+    #[bench]
+    fn bench_ntt_poly_mul(b: &mut test::Bencher) {
+        const N: usize = 1024;
+        let ntt = Ntt::new(N);
+        let mut unif = ModDistribution::uniform();
+        let mut unifb = BinaryDistribution::uniform();
+        let x: [Torus32; N] = unif.gen_n();
+        let y = unifb.gen_n::<N>();
+        b.iter(|| ntt.poly_mul(&x, &y));
+    }
+}
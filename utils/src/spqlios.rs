@@ -1,20 +1,27 @@
 use num::Zero;
-use std::mem::MaybeUninit;
 use std::ops::Add;
 use std::ops::AddAssign;
+use std::ops::Mul;
+use std::ops::MulAssign;
 use std::ops::Sub;
 use std::ops::SubAssign;
-use std::os::raw::c_double;
-use std::os::raw::c_int;
-use std::os::raw::c_uint;
+#[cfg(spqlios_native)]
+use std::os::raw::{c_double, c_int, c_uint};
 
+#[cfg(spqlios_native)]
+use crate::math::count_fft;
 use crate::math::Polynomial;
 use crate::math::Torus32;
-use crate::mem;
 use crate::pol;
 
+/// x86_64以外や`portable-fft`が有効なときは、Rustで書いたFFTを使う
+#[cfg(not(spqlios_native))]
+pub use crate::fft::PortableFft as Spqlios;
+
+#[cfg(spqlios_native)]
 pub enum SpqliosImpl {}
 
+#[cfg(spqlios_native)]
 extern "C" {
     fn Spqlios_new(N: c_int) -> *mut SpqliosImpl;
     fn Spqlios_destructor(spqlios: *mut SpqliosImpl);
@@ -31,15 +38,19 @@ extern "C" {
     );
 }
 
+#[cfg(spqlios_native)]
 pub struct Spqlios {
     raw: *mut SpqliosImpl,
     n: usize,
 }
 
+#[cfg(spqlios_native)]
 impl Spqlios {
+    /// # Panic
+    /// - nが16未満か、2の冪でない
     pub fn new(n: usize) -> Self {
-        debug_assert!(n >= 16);
-        debug_assert!(n.is_power_of_two());
+        assert!(n >= 16, "n must be >=16");
+        assert!(n.is_power_of_two(), "n must be a power of 2");
 
         unsafe {
             Spqlios {
@@ -50,51 +61,82 @@ impl Spqlios {
     }
 
     pub fn ifft<const N: usize>(&mut self, input: &[f64; N]) -> FrrSeries<N> {
+        let mut res = FrrSeries([0.0; N]);
+        self.ifft_to(input, &mut res);
+        res
+    }
+
+    pub fn ifft_torus<const N: usize>(&mut self, input: &[Torus32; N]) -> FrrSeries<N> {
+        let mut res = FrrSeries([0.0; N]);
+        self.ifft_torus_to(input, &mut res);
+        res
+    }
+
+    pub fn ifft_int<const N: usize>(&mut self, input: &[i32; N]) -> FrrSeries<N> {
+        let mut res = FrrSeries([0.0; N]);
+        self.ifft_int_to(input, &mut res);
+        res
+    }
+
+    /// ifftの結果をresに書き込む
+    pub fn ifft_to<const N: usize>(&mut self, input: &[f64; N], res: &mut FrrSeries<N>) {
         debug_assert!(self.n == N, "spqlios: self.n={},N={}", self.n, N);
 
-        let mut res: [MaybeUninit<f64>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        count_fft(1);
         unsafe {
             Spqlios_ifft(
                 self.raw,
-                res.as_mut_ptr() as *mut _,
+                res.0.as_mut_ptr() as *mut _,
                 input.as_ptr() as *const _,
             );
         }
-        FrrSeries(crate::mem::transmute::<_, [f64; N]>(res))
     }
 
-    pub fn ifft_torus<const N: usize>(&mut self, input: &[Torus32; N]) -> FrrSeries<N> {
+    /// ifft_torusの結果をresに書き込む
+    pub fn ifft_torus_to<const N: usize>(&mut self, input: &[Torus32; N], res: &mut FrrSeries<N>) {
         debug_assert!(self.n == N, "spqlios: self.n={},N={}", self.n, N);
 
-        let mut res: [MaybeUninit<f64>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        count_fft(1);
         unsafe {
             Spqlios_ifft_u32(
                 self.raw,
-                res.as_mut_ptr() as *mut _,
+                res.0.as_mut_ptr() as *mut _,
                 input.as_ptr() as *const _,
             );
         }
-        FrrSeries(crate::mem::transmute::<_, [f64; N]>(res))
     }
 
-    pub fn ifft_int<const N: usize>(&mut self, input: &[i32; N]) -> FrrSeries<N> {
+    /// ifft_intの結果をresに書き込む。作業領域を使い回すときに使う
+    pub fn ifft_int_to<const N: usize>(&mut self, input: &[i32; N], res: &mut FrrSeries<N>) {
         debug_assert!(self.n == N, "spqlios: self.n={},N={}", self.n, N);
 
-        let mut res: [MaybeUninit<f64>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        count_fft(1);
         unsafe {
             Spqlios_ifft_i32(
                 self.raw,
-                res.as_mut_ptr() as *mut _,
+                res.0.as_mut_ptr() as *mut _,
                 input.as_ptr() as *const _,
             );
         }
-        FrrSeries(crate::mem::transmute::<_, [f64; N]>(res))
     }
 
     pub fn fft<const N: usize>(&mut self, input: &FrrSeries<N>) -> [f64; N] {
+        let mut res = [0.0; N];
+        self.fft_to(input, &mut res);
+        res
+    }
+
+    pub fn fft_torus<const N: usize>(&mut self, input: &FrrSeries<N>) -> [Torus32; N] {
+        let mut res = [Torus32::zero(); N];
+        self.fft_torus_to(input, &mut res);
+        res
+    }
+
+    /// fftの結果をresに書き込む
+    pub fn fft_to<const N: usize>(&mut self, input: &FrrSeries<N>, res: &mut [f64; N]) {
         debug_assert!(self.n == N, "spqlios: self.n={},N={}", self.n, N);
 
-        let mut res: [MaybeUninit<f64>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        count_fft(1);
         unsafe {
             Spqlios_fft(
                 self.raw,
@@ -102,13 +144,13 @@ impl Spqlios {
                 input.0.as_ptr() as *const _,
             );
         }
-        crate::mem::transmute::<_, [f64; N]>(res)
     }
 
-    pub fn fft_torus<const N: usize>(&mut self, input: &FrrSeries<N>) -> [Torus32; N] {
+    /// fft_torusの結果をresに書き込む
+    pub fn fft_torus_to<const N: usize>(&mut self, input: &FrrSeries<N>, res: &mut [Torus32; N]) {
         debug_assert!(self.n == N, "spqlios: self.n={},N={}", self.n, N);
 
-        let mut res: [MaybeUninit<Torus32>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        count_fft(1);
         unsafe {
             Spqlios_fft_u32(
                 self.raw,
@@ -116,13 +158,14 @@ impl Spqlios {
                 input.0.as_ptr() as *const _,
             );
         }
-        crate::mem::transmute::<_, [Torus32; N]>(res)
     }
 
     pub fn poly_mul<const N: usize>(&mut self, a: &[Torus32; N], b: &[Torus32; N]) -> [Torus32; N] {
         debug_assert!(self.n == N, "spqlios: self.n={},N={}", self.n, N);
 
-        let mut res: [MaybeUninit<Torus32>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        // IFFT 2回とFFT 1回
+        count_fft(3);
+        let mut res = [Torus32::zero(); N];
         unsafe {
             Spqlios_poly_mul(
                 self.raw,
@@ -131,11 +174,11 @@ impl Spqlios {
                 b.as_ptr() as *const _,
             );
         }
-
-        crate::mem::transmute::<_, [Torus32; N]>(res)
+        res
     }
 }
 
+#[cfg(spqlios_native)]
 impl Drop for Spqlios {
     fn drop(&mut self) {
         unsafe {
@@ -144,7 +187,28 @@ impl Drop for Spqlios {
     }
 }
 
+/// X^N+1を法とした多項式を、X^N=-1の根(N/2個)での値で表したもの。
+/// 積は要素積(hadamard)になるので、同じ多項式と何度も掛けるときは変換したまま持つ
+#[derive(Clone, Debug, PartialEq)]
 pub struct FrrSeries<const N: usize>([f64; N]);
+pub type FourierPolynomial<const N: usize> = FrrSeries<N>;
+impl<const N: usize> Mul<&Self> for FrrSeries<N> {
+    type Output = Self;
+    fn mul(self, rhs: &Self) -> Self::Output {
+        self.hadamard(rhs)
+    }
+}
+impl<const N: usize> Mul for FrrSeries<N> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.hadamard(&rhs)
+    }
+}
+impl<const N: usize> MulAssign<&Self> for FrrSeries<N> {
+    fn mul_assign(&mut self, rhs: &Self) {
+        *self = self.hadamard(rhs);
+    }
+}
 impl<const N: usize> Add<&Self> for FrrSeries<N> {
     type Output = Self;
     fn add(mut self, rhs: &Self) -> Self::Output {
@@ -200,6 +264,16 @@ impl<const N: usize> Zero for FrrSeries<N> {
     }
 }
 impl<const N: usize> FrrSeries<N> {
+    /// spqliosのレイアウト(前半が実部、後半が虚部)のまま受け取る
+    pub fn new(coefs: [f64; N]) -> Self {
+        FrrSeries(coefs)
+    }
+    pub fn coefs(&self) -> &[f64; N] {
+        &self.0
+    }
+    pub fn coefs_mut(&mut self) -> &mut [f64; N] {
+        &mut self.0
+    }
     #[inline]
     pub fn hadamard(&self, rhs: &Self) -> Self {
         let l_re = &self.0[0..N / 2];
@@ -207,18 +281,33 @@ impl<const N: usize> FrrSeries<N> {
         let r_re = &rhs.0[0..N / 2];
         let r_im = &rhs.0[N / 2..N];
 
-        let mut res: [MaybeUninit<f64>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        let mut res = [0.0; N];
         let (res_re, res_im) = res.split_at_mut(N / 2);
         for i in 0..N / 2 {
             let ii = l_im[i] * r_im[i];
             let rr = l_re[i] * r_re[i];
             let ri = l_re[i] * r_im[i];
             let ir = l_im[i] * r_re[i];
-            res_re[i] = MaybeUninit::new(rr - ii);
-            res_im[i] = MaybeUninit::new(ir + ri);
+            res_re[i] = rr - ii;
+            res_im[i] = ir + ri;
         }
 
-        FrrSeries(mem::transmute::<_, [f64; N]>(res))
+        FrrSeries(res)
+    }
+    /// self += lhs*rhs (要素積)。hadamardの結果を一時的に確保しない
+    #[inline]
+    pub fn hadamard_add(&mut self, lhs: &Self, rhs: &Self) {
+        let (l_re, l_im) = lhs.0.split_at(N / 2);
+        let (r_re, r_im) = rhs.0.split_at(N / 2);
+        let (res_re, res_im) = self.0.split_at_mut(N / 2);
+        for i in 0..N / 2 {
+            let ii = l_im[i] * r_im[i];
+            let rr = l_re[i] * r_re[i];
+            let ri = l_re[i] * r_im[i];
+            let ir = l_im[i] * r_re[i];
+            res_re[i] += rr - ii;
+            res_im[i] += ir + ri;
+        }
     }
     pub fn culc_poly_torus(&self, spq: &mut Spqlios) -> Polynomial<Torus32, N> {
         pol!(spq.fft_torus(&self))
@@ -274,4 +363,32 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn fft_to_matches_fft() {
+        use crate::math::{ModDistribution, Random};
+        use crate::spqlios::FrrSeries;
+        const N: usize = 64;
+        let mut spq = Spqlios::new(N);
+        let mut unif = ModDistribution::uniform();
+        let x: [Torus32; N] = unif.gen_n();
+        let x_i: [i32; N] = x.map(|t| t.inner() as i32 >> 8);
+        let x_f: [f64; N] = x_i.map(|i| i as f64);
+
+        // 作業領域は前の内容が残っていても上書きする
+        let mut buf = FrrSeries::new([1.0; N]);
+        spq.ifft_torus_to(&x, &mut buf);
+        assert_eq!(buf.coefs(), spq.ifft_torus(&x).coefs());
+        spq.ifft_int_to(&x_i, &mut buf);
+        assert_eq!(buf.coefs(), spq.ifft_int(&x_i).coefs());
+        spq.ifft_to(&x_f, &mut buf);
+        assert_eq!(buf.coefs(), spq.ifft(&x_f).coefs());
+
+        let mut res_t = [Torus32::from_bits(1); N];
+        spq.fft_torus_to(&buf, &mut res_t);
+        assert_eq!(res_t, spq.fft_torus(&buf));
+        let mut res_f = [1.0; N];
+        spq.fft_to(&buf, &mut res_f);
+        assert_eq!(res_f, spq.fft(&buf));
+    }
 }
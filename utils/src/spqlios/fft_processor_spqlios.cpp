@@ -107,7 +107,7 @@ void FFT_Processor_Spqlios::execute_reverse_torus32(double *res, const Torus32 *
 
 void FFT_Processor_Spqlios::execute_direct(double *res,const double *a){
     //TODO: parallelization
-    static const double _2sN = double(2) / double(N);
+    const double _2sN = double(2) / double(N);
     //for (int32_t i=0; i<N; i++) real_inout_direct[i]=a[i]*_2sn;
     {
         double *dst = real_inout_direct;
@@ -155,7 +155,7 @@ void FFT_Processor_Spqlios::execute_direct(double *res,const double *a){
 
 void FFT_Processor_Spqlios::execute_direct_torus32(Torus32 *res, const double *a) {
     //TODO: parallelization
-    static const double _2sN = double(2) / double(N);
+    const double _2sN = double(2) / double(N);
     //for (int32_t i=0; i<N; i++) real_inout_direct[i]=a[i]*_2sn;
     {
         double *dst = real_inout_direct;
@@ -1,4 +1,19 @@
 pub trait AsLogic {
     fn logic_true() -> Self;
     fn logic_false() -> Self;
+    /// 値が分かっている定数ならその値。暗号文のように分からなければNone
+    fn as_const(&self) -> Option<bool> {
+        None
+    }
+}
+impl AsLogic for bool {
+    fn logic_true() -> Self {
+        true
+    }
+    fn logic_false() -> Self {
+        false
+    }
+    fn as_const(&self) -> Option<bool> {
+        Some(*self)
+    }
 }